@@ -0,0 +1,58 @@
+/*
+ * C interface to ssh-copy-id-rs, built with the `ffi` feature:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * The library runs the system's OpenSSH client, as the binary does.
+ */
+
+#ifndef SSH_COPY_ID_RS_H
+#define SSH_COPY_ID_RS_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return values of sci_install_key */
+#define SCI_OK 0
+#define SCI_ERR_INVALID -1 /* an argument was missing or invalid; nothing was attempted */
+#define SCI_ERR_FAILED -2  /* the install failed */
+
+/* Events passed to the callback, with their message */
+#define SCI_EVENT_CONNECTING 1              /* the destination */
+#define SCI_EVENT_AUTHENTICATED 2           /* empty */
+#define SCI_EVENT_KEY_ADDED 3               /* a key line that was appended */
+#define SCI_EVENT_KEY_ALREADY_INSTALLED 4   /* a key line that was there already */
+#define SCI_EVENT_INSTALLED 5               /* the host key ssh was shown, or empty */
+#define SCI_EVENT_INFO 6                    /* a progress message */
+#define SCI_EVENT_ERROR 7                   /* why an error is returned */
+
+/* `message` is only valid during the call. */
+typedef void (*sci_callback)(int event, const char *message, void *user_data);
+
+/* All zero (or NULL) for the defaults. */
+typedef struct sci_options {
+    uint16_t port;         /* 0 for ssh's default */
+    uint64_t timeout_secs; /* 0 for no limit */
+    int force;             /* non-zero: append without checking for keys already installed */
+    int sftp;              /* non-zero: install over SFTP */
+    const char *profile;   /* "posix" (NULL), "busybox", "csh", "powershell" or "device" */
+    const char *remote_home; /* the directory holding .ssh; NULL for the home directory */
+    void *user_data;       /* passed to the callback */
+} sci_options;
+
+/*
+ * Installs the public key(s) in `key`, the content of a .pub file, on
+ * `host` (user@host[:port] or an ssh:// URI). `opts` and `callback` may be
+ * NULL. Blocks until done; the callback is called on the calling thread.
+ */
+int sci_install_key(const char *host, const char *key, const sci_options *opts,
+                    sci_callback callback);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
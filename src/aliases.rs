@@ -0,0 +1,102 @@
+use crate::fsutil::{self, FileLock};
+use crate::paths::LocalFile;
+use anyhow::{Result, bail};
+
+/// A named destination together with the flags it should be invoked with.
+pub struct Alias {
+    pub name: String,
+    /// Command-line arguments substituted for the alias, e.g.
+    /// `["user@10.1.2.3", "-p", "2202"]`
+    pub args: Vec<String>,
+}
+
+/// Reads all aliases. The file holds one alias per line: the name followed by
+/// its arguments, separated by tabs. Blank lines and `#` comments are ignored.
+pub fn load() -> Result<Vec<Alias>> {
+    let content = fsutil::read_optional(&LocalFile::Aliases.path()?)?;
+    Ok(parse(&content))
+}
+
+fn parse(content: &str) -> Vec<Alias> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split('\t').map(str::to_string);
+            let name = fields.next()?;
+            Some(Alias {
+                name,
+                args: fields.collect(),
+            })
+        })
+        .collect()
+}
+
+pub fn find(name: &str) -> Result<Option<Alias>> {
+    Ok(load()?.into_iter().find(|alias| alias.name == name))
+}
+
+/// Adds or replaces an alias.
+pub fn add(alias: Alias) -> Result<()> {
+    validate_name(&alias.name)?;
+    if let Some(arg) = alias
+        .args
+        .iter()
+        .find(|arg| arg.contains(['\t', '\n', '\r']))
+    {
+        bail!("Alias arguments cannot contain tabs or newlines: {:?}", arg);
+    }
+
+    update(|aliases| {
+        aliases.retain(|existing| existing.name != alias.name);
+        aliases.push(alias);
+        Ok(())
+    })
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    update(|aliases| {
+        let before = aliases.len();
+        aliases.retain(|existing| existing.name != name);
+        if aliases.len() == before {
+            bail!("No such alias: {}", name);
+        }
+        Ok(())
+    })
+}
+
+/// Read-modify-write under the file lock so concurrent edits aren't lost.
+fn update(change: impl FnOnce(&mut Vec<Alias>) -> Result<()>) -> Result<()> {
+    let path = LocalFile::Aliases.path()?;
+    let _lock = FileLock::acquire(&path)?;
+
+    let mut aliases = parse(&fsutil::read_optional(&path)?);
+    change(&mut aliases)?;
+    aliases.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut contents = String::new();
+    for alias in &aliases {
+        contents.push_str(&alias.name);
+        for arg in &alias.args {
+            contents.push('\t');
+            contents.push_str(arg);
+        }
+        contents.push('\n');
+    }
+    fsutil::write_atomic(&path, contents.as_bytes())
+}
+
+/// Names must not be mistaken for a real destination or a flag.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with('-')
+        || name.contains(['@', ':', '/', '\\', '#'])
+        || name.contains(char::is_whitespace)
+    {
+        bail!(
+            "Invalid alias name {:?}: it must not start with '-' or contain whitespace, '@', ':', '/', '\\\\' or '#'.",
+            name
+        );
+    }
+    Ok(())
+}
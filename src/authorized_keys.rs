@@ -0,0 +1,519 @@
+//! authorized_keys lines as sshd reads them (see AUTHORIZED_KEYS FILE
+//! FORMAT in sshd(8)): blank lines, `#` comments, and keys preceded by
+//! options such as `from="10.0.0.0/8",command="echo \"a, b\"",no-pty`.
+//!
+//! The options end at the first space or tab outside double quotes, and
+//! inside them `\"` is a quote. An entry with an option sshd doesn't know,
+//! or a value it can't read, is skipped by sshd as a whole, so it counts
+//! as invalid here too rather than as an installed key.
+
+use crate::key::{self, PublicKey};
+use crate::keyblob;
+
+/// Options that take no value.
+const FLAGS: &[&str] = &[
+    "agent-forwarding",
+    "cert-authority",
+    "no-agent-forwarding",
+    "no-port-forwarding",
+    "no-pty",
+    "no-touch-required",
+    "no-user-rc",
+    "no-x11-forwarding",
+    "port-forwarding",
+    "pty",
+    "restrict",
+    "user-rc",
+    "verify-required",
+    "x11-forwarding",
+];
+
+/// Options that take a value in double quotes.
+const VALUED: &[&str] = &[
+    "command",
+    "environment",
+    "expiry-time",
+    "from",
+    "permitlisten",
+    "permitopen",
+    "principals",
+    "tunnel",
+];
+
+/// An entry's options: the lower-cased name and unquoted value of each, in
+/// order.
+type KeyOptions = Vec<(String, Option<String>)>;
+
+/// One line of an authorized_keys file.
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    Blank,
+    Comment,
+    Key(Entry),
+    /// A line sshd would skip, and why
+    Invalid(String),
+}
+
+/// A key with its options.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub options: KeyOptions,
+    pub key: PublicKey,
+}
+
+impl Entry {
+    /// Whether the key is trusted to sign user certificates rather than to
+    /// log in with itself.
+    pub fn is_cert_authority(&self) -> bool {
+        self.options
+            .iter()
+            .any(|(name, _)| name == "cert-authority")
+    }
+}
+
+/// The keys of a file that can be logged in with, and the lines sshd
+/// would skip, by line number with the reason.
+pub struct Parsed {
+    pub keys: Vec<PublicKey>,
+    /// The same keys, with their options
+    pub entries: Vec<Entry>,
+    pub invalid: Vec<(usize, String)>,
+}
+
+pub fn parse(content: &str) -> Parsed {
+    let mut parsed = Parsed {
+        keys: Vec::new(),
+        entries: Vec::new(),
+        invalid: Vec::new(),
+    };
+    for (number, line) in content.lines().enumerate() {
+        match parse_line(line) {
+            Line::Key(entry) if !entry.is_cert_authority() => {
+                parsed.keys.push(entry.key.clone());
+                parsed.entries.push(entry);
+            }
+            Line::Invalid(reason) => parsed.invalid.push((number + 1, reason)),
+            _ => {}
+        }
+    }
+    parsed
+}
+
+/// `options` as they are written in front of a key, e.g.
+/// `no-pty,from="10.0.0.0/8"`.
+pub fn options_text(options: &KeyOptions) -> String {
+    let written: Vec<String> = options
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{}=\"{}\"", name, value.replace('"', "\\\"")),
+            None => name.clone(),
+        })
+        .collect();
+    written.join(",")
+}
+
+/// The key of a line, after any options; None for anything else.
+pub fn key(line: &str) -> Option<PublicKey> {
+    match parse_line(line) {
+        Line::Key(entry) => Some(entry.key),
+        _ => None,
+    }
+}
+
+/// The keys of those of `lines`, such as the lines a remote reports
+/// appending, that are key lines.
+pub fn keys_in(lines: &[impl AsRef<str>]) -> Vec<PublicKey> {
+    lines.iter().filter_map(|line| key(line.as_ref())).collect()
+}
+
+/// Each key line of `payload` with every spelling of its key's blob, for
+/// [`crate::remote_script::InstallScript::known`].
+pub fn spellings_by_line(payload: &str) -> Vec<(String, Vec<String>)> {
+    payload
+        .lines()
+        .filter_map(|line| Some((line.to_string(), keyblob::spellings(&[key(line)?]))))
+        .collect()
+}
+
+/// `existing` with each line of `payload` that it doesn't have yet
+/// appended, as the install scripts append them, and those lines. A key
+/// line counts as there when a line has the same key, whatever its
+/// options, comment or spelling.
+pub fn append_missing(existing: &str, payload: &str) -> (String, Vec<String>) {
+    let mut merged = existing.to_string();
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+    let mut added = Vec::new();
+    for new in payload.lines().filter(|line| !line.is_empty()) {
+        let installed = merged.lines().any(|line| line == new)
+            || key(new).is_some_and(|key| keyblob::contains(&parse(&merged).keys, &key));
+        if !installed {
+            merged.push_str(new);
+            merged.push('\n');
+            added.push(new.to_string());
+        }
+    }
+    (merged, added)
+}
+
+/// The line installing `key` with `options` (each `name`, `name="value"`
+/// or several of them separated by commas) in front, or why sshd would
+/// skip it.
+pub fn with_options(key: &PublicKey, options: &[String]) -> Result<String, String> {
+    if options.is_empty() {
+        return Ok(key.to_line());
+    }
+    if let Some(option) = options
+        .iter()
+        .find(|option| option.contains(char::is_control))
+    {
+        return Err(format!("{:?} contains a control character", option));
+    }
+    let line = format!("{} {}", options.join(","), key.to_line());
+    match parse_line(&line) {
+        Line::Key(entry) if !entry.options.is_empty() && entry.key.blob == key.blob => Ok(line),
+        Line::Invalid(reason) => Err(reason),
+        _ => Err(format!("{:?} aren't options", options.join(","))),
+    }
+}
+
+pub fn parse_line(line: &str) -> Line {
+    let line = line.trim();
+    if line.is_empty() {
+        return Line::Blank;
+    }
+    if line.starts_with('#') {
+        return Line::Comment;
+    }
+    // As sshd does, a line is first read as a key, then as options and a key
+    let error = match PublicKey::parse(line) {
+        Ok(key) => {
+            return Line::Key(Entry {
+                options: Vec::new(),
+                key,
+            });
+        }
+        Err(e) => e,
+    };
+    let first = line.split_whitespace().next().unwrap_or_default();
+    if key::is_known_type(first) {
+        return Line::Invalid(error.to_string());
+    }
+    match split_options(line) {
+        Ok((options, rest)) => match PublicKey::parse(rest) {
+            Ok(key) => Line::Key(Entry { options, key }),
+            Err(e) => Line::Invalid(e.to_string()),
+        },
+        Err(reason) => Line::Invalid(reason),
+    }
+}
+
+/// Splits the options off the start of `line`, returning them and the rest.
+fn split_options(line: &str) -> Result<(KeyOptions, &str), String> {
+    let mut options = Vec::new();
+    let mut rest = line;
+    loop {
+        let end = rest.find(['=', ',', ' ', '\t']).unwrap_or(rest.len());
+        let name = rest[..end].to_lowercase();
+        rest = &rest[end..];
+        let value = match rest.strip_prefix('=') {
+            Some(quoted) => {
+                let (value, after) = unquote(quoted).ok_or_else(|| {
+                    format!(
+                        "the value of {:?} isn't a complete double-quoted string",
+                        name
+                    )
+                })?;
+                rest = after;
+                Some(value)
+            }
+            None => None,
+        };
+        check(&name, value.as_deref())?;
+        options.push((name, value));
+        match rest.chars().next() {
+            Some(',') => rest = &rest[1..],
+            Some(' ' | '\t') => return Ok((options, rest.trim_start())),
+            Some(c) => {
+                return Err(format!(
+                    "unexpected {:?} after the option {}",
+                    c,
+                    options[options.len() - 1].0
+                ));
+            }
+            None => return Err("no key after the options".to_string()),
+        }
+    }
+}
+
+/// The value of a `"..."` string at the start of `s`, and what follows it.
+fn unquote(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &s[i + 2..])),
+            '\\' if chars.as_str().starts_with('"') => {
+                chars.next();
+                value.push('"');
+            }
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Whether sshd would accept the option `name` with `value`.
+fn check(name: &str, value: Option<&str>) -> Result<(), String> {
+    match value {
+        _ if name.is_empty() => Err("an empty option".to_string()),
+        None if FLAGS.contains(&name) => Ok(()),
+        Some(_) if FLAGS.contains(&name) => Err(format!("{} takes no value", name)),
+        None if VALUED.contains(&name) => Err(format!("{} needs a value", name)),
+        Some(value) if name == "environment" => match value.split_once('=') {
+            Some((variable, _)) if is_variable_name(variable) => Ok(()),
+            _ => Err(format!("environment={:?} isn't NAME=value", value)),
+        },
+        Some(_) if VALUED.contains(&name) => Ok(()),
+        _ => Err(format!("unknown option {:?}", name)),
+    }
+}
+
+/// What `options` allow, one `(option, meaning)` row each, for deciding
+/// whether to keep a key; empty for a key without any.
+pub fn describe(options: &KeyOptions) -> Vec<(String, String)> {
+    options
+        .iter()
+        .map(|(name, value)| {
+            let value = value.as_deref().unwrap_or_default();
+            let meaning = match name.as_str() {
+                "command" => match tool(value) {
+                    Some(tool) => format!("only runs {} ({})", value, tool),
+                    None => format!("only runs {}", value),
+                },
+                "from" => format!("only from {}", value),
+                "restrict" => {
+                    "no forwarding, terminal or ~/.ssh/rc unless allowed here".to_string()
+                }
+                "cert-authority" => "signs user certificates; not a login key itself".to_string(),
+                "principals" => format!("certificates for {}", value),
+                "expiry-time" => format!("valid until {}", value),
+                "environment" => format!("sets {}", value),
+                "permitopen" => format!("may only forward to {}", value),
+                "permitlisten" => format!("may only listen on {}", value),
+                "tunnel" => format!("tunnel device {}", value),
+                "pty" => "terminal allowed".to_string(),
+                "no-pty" => "no terminal".to_string(),
+                "user-rc" => "runs ~/.ssh/rc".to_string(),
+                "no-user-rc" => "doesn't run ~/.ssh/rc".to_string(),
+                "verify-required" => "FIDO user verification (PIN) required".to_string(),
+                "no-touch-required" => "FIDO touch not required".to_string(),
+                forwarding => match forwarding.strip_prefix("no-") {
+                    Some(kind) => format!("no {}", kind.replace('-', " ").replace("x11", "X11")),
+                    None => format!(
+                        "{} allowed",
+                        forwarding.replace('-', " ").replace("x11", "X11")
+                    ),
+                },
+            };
+            (name.clone(), meaning)
+        })
+        .collect()
+}
+
+/// What a forced command is for, if it is a well-known one: rsync and
+/// backup servers, git and SFTP.
+fn tool(command: &str) -> Option<&'static str> {
+    let program = command.split_whitespace().next()?.rsplit('/').next()?;
+    Some(match program {
+        "rsync" => "rsync",
+        "rrsync" => "rsync, limited to a directory",
+        "borg" | "borgbackup" => "Borg backup",
+        "restic" | "rest-server" => "restic backup",
+        "duplicity" => "duplicity backup",
+        "git-shell" | "gitolite-shell" | "git-upload-pack" | "git-receive-pack" => "git",
+        "internal-sftp" | "sftp-server" => "SFTP only",
+        "scp" => "scp",
+        _ => return None,
+    })
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAID88xfz5ysGpCkSpKg8GLUE87P6l9caTMy+rAM+aYnqF";
+
+    fn entry(line: &str) -> Entry {
+        match parse_line(line) {
+            Line::Key(entry) => entry,
+            other => panic!("{:?}: {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn options_are_read_as_sshd_reads_them() {
+        let key = PublicKey::parse(ED25519).unwrap();
+        for prefixed in [
+            ED25519.to_string(),
+            format!("no-pty,command=\"echo a b\" {}", ED25519),
+            format!("from=\"10.0.0.1\" {}", ED25519),
+        ] {
+            let parsed = entry(&prefixed).key;
+            assert_eq!(
+                (parsed.algorithm.as_str(), &parsed.blob),
+                (key.algorithm.as_str(), &key.blob)
+            );
+        }
+
+        // A quoted key, commas and escaped quotes don't end the options
+        let line = format!(
+            "command=\"echo \\\"ssh-rsa AAAA, x\\\"\",environment=\"PATH=/bin:/usr/bin\",No-Pty\t{} ops, 2026",
+            ED25519
+        );
+        let parsed = entry(&line);
+        assert_eq!(
+            parsed.options,
+            [
+                (
+                    "command".to_string(),
+                    Some("echo \"ssh-rsa AAAA, x\"".to_string())
+                ),
+                (
+                    "environment".to_string(),
+                    Some("PATH=/bin:/usr/bin".to_string())
+                ),
+                ("no-pty".to_string(), None),
+            ]
+        );
+        assert_eq!(parsed.key.blob, key.blob);
+        assert_eq!(parsed.key.comment.as_deref(), Some("ops, 2026"));
+        assert_eq!(
+            options_text(&parsed.options),
+            "command=\"echo \\\"ssh-rsa AAAA, x\\\"\",environment=\"PATH=/bin:/usr/bin\",no-pty"
+        );
+        let written = format!("{} {}", options_text(&parsed.options), ED25519);
+        assert_eq!(entry(&written).options, parsed.options);
+
+        assert_eq!(parse_line("# ssh-ed25519 AAAA"), Line::Comment);
+        assert_eq!(parse_line("   \r"), Line::Blank);
+    }
+
+    #[test]
+    fn entries_sshd_would_skip_are_invalid() {
+        for line in [
+            format!("no-ptty {}", ED25519),
+            format!("command=echo {}", ED25519),
+            format!("command=\"echo {}", ED25519),
+            format!("no-pty=\"yes\" {}", ED25519),
+            format!("from {}", ED25519),
+            format!("environment=\"1 2=x\" {}", ED25519),
+            format!("environment=\"PATH\" {}", ED25519),
+            format!("no-pty,,command=\"x\" {}", ED25519),
+            format!("command=\"x\"y {}", ED25519),
+            "no-pty".to_string(),
+            "ssh-rsa broken".to_string(),
+        ] {
+            assert!(matches!(parse_line(&line), Line::Invalid(_)), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn options_are_prepended_when_valid() {
+        let key = PublicKey::parse(ED25519).unwrap();
+        let options = |options: &[&str]| -> Vec<String> {
+            options.iter().map(|option| option.to_string()).collect()
+        };
+        assert_eq!(with_options(&key, &[]).unwrap(), key.to_line());
+        assert_eq!(
+            with_options(
+                &key,
+                &options(&[
+                    "from=\"10.0.0.0/8\"",
+                    "command=\"/usr/bin/rrsync /srv\"",
+                    "no-pty,no-agent-forwarding"
+                ])
+            )
+            .unwrap(),
+            format!(
+                "from=\"10.0.0.0/8\",command=\"/usr/bin/rrsync /srv\",no-pty,no-agent-forwarding {}",
+                key.to_line()
+            )
+        );
+        assert_eq!(
+            with_options(&key, &options(&["no-ptty"])),
+            Err("unknown option \"no-ptty\"".to_string())
+        );
+        for invalid in [
+            &["command=\"a\nb\""][..],
+            &["no-pty ssh-rsa"],
+            &["command=/bin/true"],
+            &[""],
+        ] {
+            assert!(
+                with_options(&key, &options(invalid)).is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn options_are_described() {
+        let parsed = entry(&format!(
+            "restrict,command=\"/usr/bin/rrsync -ro /srv/backup\",from=\"10.0.0.5\",no-X11-forwarding,port-forwarding {}",
+            ED25519
+        ));
+        let rows: Vec<String> = describe(&parsed.options)
+            .into_iter()
+            .map(|(name, meaning)| format!("{}: {}", name, meaning))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "restrict: no forwarding, terminal or ~/.ssh/rc unless allowed here",
+                "command: only runs /usr/bin/rrsync -ro /srv/backup (rsync, limited to a directory)",
+                "from: only from 10.0.0.5",
+                "no-x11-forwarding: no X11 forwarding",
+                "port-forwarding: port forwarding allowed",
+            ]
+        );
+        assert!(describe(&entry(ED25519).options).is_empty());
+    }
+
+    #[test]
+    fn keys_already_there_are_not_appended_again() {
+        let existing = format!("no-pty {} old-comment\n", ED25519);
+        let other = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICSyhJxX6s91OzmeRhVlPEzwdln2ifnCxM7ToHGOQO1S";
+        let payload = format!("{} laptop\n{}\n", ED25519, other);
+        let (merged, added) = append_missing(&existing, &payload);
+        assert_eq!(added, [other]);
+        assert_eq!(append_missing(&merged, &payload), (merged, Vec::new()));
+    }
+
+    #[test]
+    fn certificate_authorities_are_not_login_keys() {
+        let content = format!(
+            "# keys\r\n{key} laptop\r\ncert-authority,principals=\"deploy\" {key} ca\nrestrict,pty {key} ci\nno-ptty {key}\n",
+            key = ED25519
+        );
+        let parsed = parse(&content);
+        let comments: Vec<_> = parsed
+            .keys
+            .iter()
+            .map(|key| key.comment.as_deref())
+            .collect();
+        assert_eq!(comments, [Some("laptop"), Some("ci")]);
+        assert_eq!(
+            parsed.invalid,
+            [(5, "unknown option \"no-ptty\"".to_string())]
+        );
+        assert!(key(&format!("cert-authority {}", ED25519)).is_some());
+    }
+}
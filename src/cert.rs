@@ -0,0 +1,170 @@
+//! OpenSSH certificate fields (PROTOCOL.certkeys in the OpenSSH sources).
+
+use crate::base64;
+use crate::key;
+use crate::timeutil;
+use crate::wire::Reader;
+use anyhow::{Result, bail};
+
+/// `valid_before` value meaning the certificate never expires.
+const FOREVER: u64 = u64::MAX;
+
+pub struct Certificate {
+    pub serial: u64,
+    pub is_host: bool,
+    pub key_id: String,
+    pub principals: Vec<String>,
+    pub valid_after: u64,
+    pub valid_before: u64,
+    /// `name` or `name value` for each critical option
+    pub critical_options: Vec<String>,
+    pub extensions: Vec<String>,
+    /// Blob of the signing CA's public key
+    pub signature_key: Vec<u8>,
+}
+
+impl Certificate {
+    /// Reads the certificate fields that follow the nonce and key fields.
+    pub fn read(reader: &mut Reader) -> Result<Certificate> {
+        let serial = reader.u64()?;
+        let is_host = reader.u32()? == 2;
+        let key_id = reader.string()?;
+
+        let mut principals = Vec::new();
+        let mut packed = Reader::new(reader.bytes()?);
+        while !packed.is_empty() {
+            principals.push(packed.string()?);
+        }
+
+        let valid_after = reader.u64()?;
+        let valid_before = reader.u64()?;
+        let critical_options = read_options(reader.bytes()?)?;
+        let extensions = read_options(reader.bytes()?)?;
+        reader.bytes()?; // reserved
+        let signature_key = reader.bytes()?.to_vec();
+
+        Ok(Certificate {
+            serial,
+            is_host,
+            key_id,
+            principals,
+            valid_after,
+            valid_before,
+            critical_options,
+            extensions,
+            signature_key,
+        })
+    }
+
+    /// SHA256 fingerprint of the CA key, prefixed with its type label.
+    pub fn ca_description(&self) -> String {
+        let ca_type = Reader::new(&self.signature_key)
+            .string()
+            .unwrap_or_default();
+        format!(
+            "{} {}",
+            key::type_label(&ca_type),
+            key::fingerprint_sha256(&self.signature_key)
+        )
+    }
+
+    /// The CA key, as it goes on a `cert-authority` line.
+    pub fn ca_key(&self) -> Result<key::PublicKey> {
+        let algorithm = Reader::new(&self.signature_key).string()?;
+        if !key::is_known_type(&algorithm) || algorithm.ends_with(key::CERT_SUFFIX) {
+            bail!("the CA key has an unknown type {:?}", algorithm);
+        }
+        Ok(key::PublicKey {
+            algorithm,
+            blob: base64::encode(&self.signature_key),
+            comment: None,
+        })
+    }
+
+    /// Why a host whose clock reads `remote_now` would reject the
+    /// certificate, valid here at `local_now`, if it would. Differences of
+    /// a few seconds are put down to the connection.
+    pub fn clock_problem(&self, remote_now: u64, local_now: u64) -> Option<String> {
+        let valid = |now| self.valid_after <= now && now < self.valid_before;
+        let skew = remote_now as i64 - local_now as i64;
+        if skew.abs() <= 5 || !valid(local_now) || valid(remote_now) {
+            return None;
+        }
+        Some(if skew < 0 {
+            format!(
+                "the remote clock is {}s behind, so it takes certificate {:?} (valid {}) as not valid yet",
+                -skew,
+                self.key_id,
+                self.validity()
+            )
+        } else {
+            format!(
+                "the remote clock is {}s ahead, so it takes certificate {:?} (valid {}) as expired",
+                skew,
+                self.key_id,
+                self.validity()
+            )
+        })
+    }
+
+    pub fn validity(&self) -> String {
+        match (self.valid_after, self.valid_before) {
+            (0, FOREVER) => "forever".to_string(),
+            (0, before) => format!("before {}", timeutil::format_utc(before)),
+            (after, FOREVER) => format!("from {}", timeutil::format_utc(after)),
+            (after, before) => format!(
+                "from {} to {}",
+                timeutil::format_utc(after),
+                timeutil::format_utc(before)
+            ),
+        }
+    }
+}
+
+/// Reads a packed list of (name, data) pairs. Option data is itself an SSH
+/// string when non-empty (e.g. the command of `force-command`).
+fn read_options(data: &[u8]) -> Result<Vec<String>> {
+    let mut reader = Reader::new(data);
+    let mut options = Vec::new();
+    while !reader.is_empty() {
+        let name = reader.string()?;
+        let value = reader.bytes()?;
+        if value.is_empty() {
+            options.push(name);
+        } else {
+            let inner = Reader::new(value).string().unwrap_or_default();
+            options.push(format!("{} {}", name, inner));
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn certificate(valid_after: u64, valid_before: u64) -> Certificate {
+        Certificate {
+            serial: 1,
+            is_host: false,
+            key_id: "alice".to_string(),
+            principals: vec!["alice".to_string()],
+            valid_after,
+            valid_before,
+            critical_options: Vec::new(),
+            extensions: Vec::new(),
+            signature_key: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn clock_problems() {
+        let now = 1_700_000_000;
+        let fresh = certificate(now - 60, now + 3600);
+        assert!(fresh.clock_problem(now - 600, now).unwrap().contains("600s behind"));
+        assert!(fresh.clock_problem(now + 7200, now).unwrap().contains("expired"));
+        assert_eq!(fresh.clock_problem(now - 30, now), None);
+        assert_eq!(fresh.clock_problem(now - 63, now - 59), None);
+        assert_eq!(certificate(0, FOREVER).clock_problem(0, now), None);
+    }
+}
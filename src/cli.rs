@@ -0,0 +1,1980 @@
+//! The command-line interface: arguments, and the commands they run.
+
+use anyhow::{Context, Result, bail};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use crate::{
+    aliases, authorized_keys, cert, connection, consul, destination, fsutil, harden, history, hooks, hostkey,
+    identity, inspect, inventory, key, keyblob, keycache, keyformat, keygen, lockout, netbox, pager,
+    paths, ping, plan, porcelain, probe, proxy, remote_script, reporter, rotate, sftp, ssh_config,
+    sshfp, status, timeutil, token, transcript, usage, vault, zabbix,
+};
+use crate::{Reply, TimedOut, wait_with_timeout};
+
+use destination::Destination;
+use inventory::Target;
+use remote_script::{AnnounceTo, Announcement, BecomeMethod, InstallScript, Profile};
+use reporter::{HostResult, KeyOutcome, Reporter, Status};
+
+#[derive(Parser, Debug)]
+#[command(name = "ssh-copy-id-rs")]
+#[command(about = "A Rust implementation of ssh-copy-id", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(args_override_self = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    #[command(flatten)]
+    source: identity::SourceArgs,
+
+    #[command(flatten)]
+    connection: connection::ConnectionArgs,
+
+    /// Stable, versioned output for scripts (install summaries, `alias list`)
+    #[arg(long, value_enum, value_name = "VERSION", num_args = 0..=1, require_equals = true, default_missing_value = "v1", global = true)]
+    porcelain: Option<porcelain::Version>,
+
+    /// Use the ssh agent listening on SOCKET for this run, in place of $SSH_AUTH_SOCK
+    #[arg(long, value_name = "SOCKET", global = true)]
+    agent_socket: Option<PathBuf>,
+
+    /// Read keys and ssh config from DIR instead of ~/.ssh, and keep known hosts there
+    #[arg(long, value_name = "DIR", global = true)]
+    ssh_dir: Option<PathBuf>,
+
+    /// Use this ssh config file instead of ~/.ssh/config and /etc/ssh/ssh_config, as with `ssh -F`
+    #[arg(short = 'F', long = "config", value_name = "FILE", global = true)]
+    config_file: Option<PathBuf>,
+
+    /// Encrypt the key cache and history written, under a passphrase kept in the OS keychain (the default) or with age to your SSH key
+    #[arg(long, value_enum, value_name = "METHOD", num_args = 0..=1, require_equals = true, default_missing_value = "keychain", global = true)]
+    encrypt_state: Option<vault::Method>,
+
+    /// Append what is run on each host and what it prints, with passwords and private keys redacted, to FILE
+    #[arg(long, value_name = "FILE", global = true)]
+    transcript: Option<PathBuf>,
+
+    /// Never pipe long reports through $PAGER
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// Install into USER's authorized_keys instead of the login account's, becoming USER on the remote with --become-method, which must not ask for a password
+    #[arg(long, value_name = "USER", conflicts_with_all = ["sftp", "harden", "rotate", "skip_if_cached", "abort_harden"])]
+    sudo_user: Option<String>,
+
+    /// How --sudo-user becomes the other user
+    #[arg(long, value_enum, value_name = "METHOD", default_value_t = BecomeMethod::Sudo, requires = "sudo_user")]
+    become_method: BecomeMethod,
+
+    /// Kind of remote environment to generate the install command for
+    #[arg(long, value_enum, default_value_t = Profile::Posix)]
+    remote_profile: Profile,
+
+    /// The remote's operating system; `windows` installs with the powershell profile
+    #[arg(long, value_enum, value_name = "OS", conflicts_with = "remote_profile")]
+    remote_os: Option<remote_script::RemoteOs>,
+
+    /// Look for .ssh in PATH on the remote instead of the account's home directory, for accounts whose sessions start elsewhere (ForceCommand wrappers, ChrootDirectory)
+    #[arg(long, value_name = "PATH")]
+    remote_home: Option<String>,
+
+    /// Install to the key file at PATH instead of .ssh/authorized_keys, as the remote's AuthorizedKeysFile has it: relative to the home directory unless absolute, with sshd's %u, %U, %h and %% tokens (e.g. .ssh/authorized_keys2 or /etc/ssh/keys/%u)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["sftp", "rotate", "skip_if_cached"])]
+    authorized_keys_path: Option<String>,
+
+    /// Only show which keys would be added and which are already installed; nothing on the remote is changed
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Make no changes, but record the keys each host would get (or lose, with --remove) in FILE, for --apply-plan to carry out once reviewed
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["rotate", "skip_if_cached", "harden", "announce", "emit_sshfp"])]
+    plan_file: Option<PathBuf>,
+
+    /// Make exactly the changes recorded in FILE with --plan-file, refusing hosts whose authorized_keys have changed since
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "source", "generate", "destination", "hosts_file", "discovery", "rotate", "remove", "dry_run", "plan_file"
+        ]
+    )]
+    apply_plan: Option<PathBuf>,
+
+    /// With --apply-plan, plan the hosts whose keys changed again, against what they hold now, and write that plan to FILE for review
+    #[arg(long, value_name = "FILE", requires = "apply_plan")]
+    replan_on_drift: Option<PathBuf>,
+
+    /// Don't connect to hosts that, according to the local cache, already have all the keys
+    #[arg(long, conflicts_with_all = ["harden", "emit_sshfp", "dry_run", "force"])]
+    skip_if_cached: bool,
+
+    /// Remove the keys from the remote authorized_keys instead of adding them, matching them by key whatever their options and comment
+    #[arg(long, conflicts_with_all = ["harden", "announce", "skip_if_cached", "force", "emit_sshfp"])]
+    remove: bool,
+
+    /// Replace the key in OLD with the one in NEW in one session: NEW is installed, a login offering only NEW is tried, and OLD is removed once it has worked; otherwise NEW is taken out again
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["OLD", "NEW"],
+        conflicts_with_all = [
+            "source", "remove", "dry_run", "force", "sftp", "harden", "announce", "skip_if_cached", "emit_sshfp"
+        ]
+    )]
+    rotate: Vec<String>,
+
+    /// When no identity is found, generate a key of TYPE in ~/.ssh with ssh-keygen and install it
+    #[arg(long, value_enum, value_name = "TYPE", conflicts_with_all = ["source", "rotate"])]
+    generate: Option<keygen::KeyType>,
+
+    /// Append the keys without checking whether they are already installed, even if the remote authorized_keys looks implausibly large
+    #[arg(short = 'f', long)]
+    force: bool,
+
+    /// Before the first key is appended, copy the remote authorized_keys to a timestamped authorized_keys.bak.YYYYMMDDTHHMMSS beside it, and print the copy's path
+    #[arg(long, conflicts_with_all = ["remove", "rotate"])]
+    backup: bool,
+
+    /// Install the keys with this authorized_keys option in front, e.g. `from="10.0.0.0/8"`, `command="/usr/bin/rrsync /srv"` or `no-pty`; repeatable. A key already installed is left as it is, whatever its options; remove it first (--remove) to change them
+    #[arg(long, value_name = "OPTION", conflicts_with_all = ["remove", "rotate", "skip_if_cached", "apply_plan"])]
+    key_option: Vec<String>,
+
+    /// For a certificate (-i ...-cert.pub), install the CA that signed it as a `cert-authority` line, so the account accepts the certificates it signs that name it as a principal, instead of the key the certificate was issued for
+    #[arg(long, conflicts_with_all = ["remove", "rotate", "skip_if_cached", "apply_plan", "dry_run", "plan_file"])]
+    as_ca: bool,
+
+    /// Install over SFTP, downloading authorized_keys, adding the keys locally and uploading it, instead of running a command on the remote (for forced commands, sftp-only accounts and hosts without a POSIX shell)
+    #[arg(short = 's', long, conflicts_with_all = ["harden", "announce", "abort_harden"])]
+    sftp: bool,
+
+    /// After installing, verify that key authentication works, then disable password authentication and root login with an sshd_config.d drop-in (uses sudo)
+    #[arg(long)]
+    harden: bool,
+
+    /// With --harden, only disable password authentication once DURATION (e.g. 30m, 2h) has passed, via a systemd timer or `at` on the remote
+    #[arg(long, value_name = "DURATION", requires = "harden", value_parser = timeutil::parse_duration)]
+    after: Option<Duration>,
+
+    /// Cancel a hardening scheduled with --harden --after on the targets, instead of installing
+    #[arg(long, conflicts_with = "harden")]
+    abort_harden: bool,
+
+    /// When keys are added, leave a dated note of which and by whom in the remote's ~/.ssh/README, or with `=wall` broadcast it to logged-in sessions
+    #[arg(long, value_enum, value_name = "HOW", num_args = 0..=1, require_equals = true, default_missing_value = "readme")]
+    announce: Option<AnnounceTo>,
+
+    /// After installing, print SSHFP DNS records for the remote's host keys
+    #[arg(long)]
+    emit_sshfp: bool,
+
+    /// Install to every host listed in FILE (one destination per line) instead of a single destination
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["destination", "hostkey_alias"])]
+    hosts_file: Option<PathBuf>,
+
+    /// Install to the devices and virtual machines in NetBox at URL (their primary IPs), filtered with --netbox-site/-role/-tag
+    #[arg(long, value_name = "URL", group = "discovery", conflicts_with_all = ["destination", "hosts_file"])]
+    netbox_url: Option<String>,
+
+    /// NetBox API token (default: $NETBOX_TOKEN)
+    #[arg(long, value_name = "TOKEN", requires = "netbox_url")]
+    netbox_token: Option<String>,
+
+    /// Only NetBox objects at this site (slug); may be repeated
+    #[arg(long, value_name = "SLUG", requires = "netbox_url")]
+    netbox_site: Vec<String>,
+
+    /// Only NetBox objects with this role (slug); may be repeated
+    #[arg(long, value_name = "SLUG", requires = "netbox_url")]
+    netbox_role: Vec<String>,
+
+    /// Only NetBox objects with this tag (slug); may be repeated
+    #[arg(long, value_name = "SLUG", requires = "netbox_url")]
+    netbox_tag: Vec<String>,
+
+    /// Install to the nodes providing this service in the Consul catalog
+    #[arg(long, value_name = "SERVICE", group = "discovery", conflicts_with_all = ["destination", "hosts_file"])]
+    consul_service: Option<String>,
+
+    /// Consul agent to ask (default: $CONSUL_HTTP_ADDR, else http://127.0.0.1:8500)
+    #[arg(long, value_name = "URL", requires = "consul_service")]
+    consul_url: Option<String>,
+
+    /// Consul ACL token (default: $CONSUL_HTTP_TOKEN)
+    #[arg(long, value_name = "TOKEN", requires = "consul_service")]
+    consul_token: Option<String>,
+
+    /// Only Consul service instances with this tag
+    #[arg(long, value_name = "TAG", requires = "consul_service")]
+    consul_tag: Option<String>,
+
+    /// Consul datacenter to query (default: the agent's)
+    #[arg(long, value_name = "DC", requires = "consul_service")]
+    consul_datacenter: Option<String>,
+
+    /// Install to the enabled hosts monitored by the Zabbix frontend at URL (their main interfaces)
+    #[arg(long, value_name = "URL", group = "discovery", conflicts_with_all = ["destination", "hosts_file"])]
+    zabbix_url: Option<String>,
+
+    /// Zabbix API token (default: $ZABBIX_API_TOKEN)
+    #[arg(long, value_name = "TOKEN", requires = "zabbix_url")]
+    zabbix_token: Option<String>,
+
+    /// Only Zabbix hosts in this host group; may be repeated
+    #[arg(long, value_name = "NAME", requires = "zabbix_url")]
+    zabbix_group: Vec<String>,
+
+    /// User to log in as on hosts whose destination doesn't name one (e.g. discovered hosts)
+    #[arg(short = 'l', long, value_name = "USER")]
+    login: Option<String>,
+
+    /// When a host turns the login away with a temporary ban (fail2ban, PerSourcePenalties), wait DURATION (e.g. 10m) and try once more
+    #[arg(long, value_name = "DURATION", value_parser = timeutil::parse_duration)]
+    retry_lockout: Option<Duration>,
+
+    /// With --hosts-file, flag hosts that took longer than SECONDS in the summary
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    slow_after: u64,
+
+    /// Only check that every target accepts TCP connections and sends an SSH banner; nothing is authenticated or installed
+    #[arg(long)]
+    ping: bool,
+
+    /// Run COMMAND (with sh) before installing to each host; the host is skipped if it fails. Host details are in SSH_COPY_ID_* variables
+    #[arg(long, value_name = "COMMAND")]
+    pre_hook: Option<String>,
+
+    /// Run COMMAND (with sh) after each host, with its outcome in SSH_COPY_ID_STATUS, _KEYS_ADDED, _DURATION_MS and _ERROR
+    #[arg(long, value_name = "COMMAND")]
+    post_hook: Option<String>,
+
+    /// How to report progress and results
+    #[arg(long, value_enum, default_value_t = reporter::Format::Human)]
+    output: reporter::Format,
+
+    /// Make the output the same from run to run, for comparing it with a golden file: durations are reported as 0, times as 1970-01-01T00:00:00Z, SSHFP records sorted, and no host is flagged slow
+    #[arg(long, global = true)]
+    deterministic: bool,
+
+    /// Print one JSON object with the result on stdout, the same as --output json
+    #[arg(long, conflicts_with = "output")]
+    json: bool,
+
+    /// Finish with a single `RESULT hosts=N ok=N failed=N keys_added=N` line
+    #[arg(long)]
+    trailer: bool,
+
+    /// The remote destination (user@host), or the name of an alias
+    #[arg(required_unless_present_any = ["hosts_file", "discovery", "apply_plan"])]
+    destination: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Manage locally stored destination aliases
+    Alias {
+        #[command(subcommand)]
+        action: AliasAction,
+    },
+    /// Show type, size, fingerprints and certificate details of a key
+    /// without contacting any host
+    Inspect {
+        #[command(flatten)]
+        source: identity::SourceArgs,
+    },
+    /// Translate public keys between OpenSSH, RFC 4716 and PEM encodings
+    Convert {
+        #[command(flatten)]
+        source: identity::SourceArgs,
+
+        /// Encoding to write
+        #[arg(long, value_enum)]
+        to: keyformat::Format,
+
+        /// Write to this file instead of stdout
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+    /// Report the remote's OS, shell, sshd version, offered auth methods,
+    /// whether authorized_keys is writable and SELinux status, without
+    /// installing anything
+    Probe {
+        /// Port to connect to on the remote host, as a number or a service name from /etc/services
+        #[arg(short = 'p', long, value_parser = destination::parse_port_arg)]
+        port: Option<u16>,
+
+        /// The remote destination (user@host)
+        destination: String,
+    },
+    /// Run a command on the remote, connecting as installs do (ssh config,
+    /// jump hosts, proxies, host key checks, timeouts)
+    Exec {
+        #[command(flatten)]
+        connection: connection::ConnectionArgs,
+
+        /// The remote destination (user@host)
+        destination: String,
+
+        /// The command to run, after `--`; as with ssh, its words are joined with spaces for the remote shell
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// List the remote account's authorized_keys with when each was last
+    /// used to log in, from sshd's log (read with sudo)
+    Usage {
+        #[command(flatten)]
+        connection: connection::ConnectionArgs,
+
+        /// The remote destination (user@host)
+        destination: String,
+
+        /// How far back to look in the log
+        #[arg(long, value_name = "DAYS", default_value_t = 90)]
+        days: u32,
+    },
+    /// List, for every host of an inventory, the authorized_keys nobody has
+    /// logged in with recently, e.g. `stale-report --days 180 --hosts-file hosts.txt`
+    StaleReport {
+        /// How far back to look in each host's log
+        #[arg(long, value_name = "DAYS", default_value_t = 90)]
+        days: u32,
+
+        /// The destination (or --hosts-file, etc.) and options, as for an install
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    /// Show, from the local key cache, which of your keys each host is
+    /// believed to have, e.g. `status web1 web2` or `status --refresh`
+    Status {
+        #[command(flatten)]
+        source: identity::SourceArgs,
+
+        #[command(flatten)]
+        connection: connection::ConnectionArgs,
+
+        /// Kind of remote environment, for reading authorized_keys with --refresh
+        #[arg(long, value_enum, default_value_t = Profile::Posix)]
+        remote_profile: Profile,
+
+        /// Read each host's authorized_keys again first, updating the cache
+        #[arg(long)]
+        refresh: bool,
+
+        /// The destinations to report on (default: every one in the cache)
+        #[arg(value_name = "HOST")]
+        hosts: Vec<String>,
+    },
+    /// Copy a local file to the remote, choosing hosts and connecting as an
+    /// install does, e.g. `push-file ca.pem /etc/ssl/ca.pem --hosts-file hosts.txt`
+    PushFile {
+        /// The file to copy
+        local: PathBuf,
+
+        /// Where to write it, relative to the remote home directory unless absolute; an existing file is replaced atomically
+        remote_path: String,
+
+        /// Permissions of the remote file, in octal (default: the local file's)
+        #[arg(long, value_name = "MODE")]
+        mode: Option<String>,
+
+        /// The destination (or --hosts-file, etc.) and options, as for an install
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    /// Relays stdin/stdout to HOST:PORT through a proxy; used as ssh's ProxyCommand
+    #[command(hide = true)]
+    Proxy {
+        #[arg(value_enum)]
+        kind: proxy::Kind,
+        address: String,
+        host: String,
+        port: u16,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AliasAction {
+    /// Save a destination and its flags under a name, e.g.
+    /// `alias add prod-web user@10.1.2.3 -p 2202 -i ~/.ssh/prod.pub`
+    Add {
+        /// Name used in place of the destination
+        name: String,
+        /// The destination followed by any options to use with it
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Delete an alias
+    Remove {
+        name: String,
+    },
+    /// Show all aliases
+    List,
+}
+
+/// Runs the command given on the command line.
+pub fn run() -> Result<()> {
+    if let Err(e) = paths::migrate_legacy() {
+        eprintln!("Warning: could not migrate local data to the new location: {:#}", e);
+    }
+
+    let args = parse_args()?.with_shorthands();
+    apply_environment(&args)?;
+    let paging = !args.no_pager;
+
+    match args.command {
+        Some(Commands::Alias { action }) => run_alias(action, paging, args.porcelain),
+        Some(Commands::Inspect { source }) => run_inspect(&source, paging),
+        Some(Commands::Convert { source, to, output }) => run_convert(&source, to, output),
+        Some(Commands::Probe { port, destination }) => run_probe(port, &destination),
+        Some(Commands::Exec { connection, destination, command }) => run_exec(&connection, &destination, &command),
+        Some(Commands::Usage { connection, destination, days }) => usage::run(&connection, &destination, days),
+        Some(Commands::StaleReport { days, args }) => run_stale_report(days, &args),
+        Some(Commands::Status { source, connection, remote_profile, refresh, hosts }) => {
+            status::run(&source, &connection, remote_profile, &hosts, refresh, args.deterministic)
+        }
+        Some(Commands::PushFile { local, remote_path, mode, args }) => {
+            run_push_file(&local, &remote_path, mode.as_deref(), &args)
+        }
+        Some(Commands::Proxy { kind, address, host, port }) => proxy::run(kind, &address, &host, port),
+        None if args.ping => run_ping(args),
+        None if args.abort_harden => run_abort_harden(args),
+        None if args.apply_plan.is_some() => run_apply_plan(args),
+        None => run_install(args),
+    }
+}
+
+/// Applies `--agent-socket`, `--ssh-dir` and `-F` to ssh, ssh-add and our
+/// own lookups alike, and `--encrypt-state` to the files written.
+fn apply_environment(args: &Args) -> Result<()> {
+    if let Some(socket) = &args.agent_socket {
+        if !socket.exists() {
+            bail!("The agent socket {:?} does not exist", socket);
+        }
+        std::env::set_var("SSH_AUTH_SOCK", socket);
+    }
+    if let Some(dir) = &args.ssh_dir {
+        if !dir.is_dir() {
+            bail!("The ssh directory {:?} does not exist", dir);
+        }
+        paths::set_ssh_dir(dir.clone());
+    }
+    if let Some(method) = args.encrypt_state {
+        vault::set_method(method);
+    }
+    if let Some(path) = &args.transcript {
+        transcript::set_path(path.clone());
+    }
+    match &args.config_file {
+        Some(file) if file.as_os_str() != "none" && !file.is_file() => {
+            bail!("The ssh config file {:?} does not exist", file)
+        }
+        Some(file) => ssh_config::set_config_file(file.clone()),
+        None => {
+            let config = args.ssh_dir.as_ref().map(|dir| dir.join("config"));
+            if let Some(config) = config.filter(|config| config.is_file()) {
+                ssh_config::set_config_file(config);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Args {
+    /// Who the remote scripts run as with --sudo-user.
+    fn run_as(&self) -> Option<remote_script::Become> {
+        let user = self.sudo_user.clone()?;
+        Some(remote_script::Become { method: self.become_method, user })
+    }
+
+    /// Folds `--json` into `--output json`, and `--remote-os` into
+    /// `--remote-profile`.
+    fn with_shorthands(mut self) -> Args {
+        if self.json {
+            self.output = reporter::Format::Json;
+        }
+        if let Some(os) = self.remote_os {
+            self.remote_profile = os.profile();
+        }
+        if self.plan_file.is_some() {
+            self.dry_run = true;
+        }
+        self
+    }
+}
+
+/// Parses the command line, expanding the destination if it names an alias.
+///
+/// The alias arguments are spliced in ahead of everything the user typed, so
+/// flags given explicitly on the command line take precedence.
+fn parse_args() -> Result<Args> {
+    let argv: Vec<String> = std::env::args().collect();
+    let matches = Args::command().get_matches_from(&argv);
+    let args = Args::from_arg_matches(&matches)?;
+
+    let (Some(destination), Some(index)) = (&args.destination, matches.index_of("destination")) else {
+        return Ok(args);
+    };
+    let Some(alias) = aliases::find(destination)? else {
+        return Ok(args);
+    };
+
+    let mut expanded = vec![argv[0].clone()];
+    expanded.extend(alias.args);
+    expanded.extend(
+        argv.into_iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(i, _)| *i != index)
+            .map(|(_, arg)| arg),
+    );
+    Ok(Args::parse_from(expanded))
+}
+
+fn run_alias(action: AliasAction, paging: bool, porcelain: Option<porcelain::Version>) -> Result<()> {
+    match action {
+        AliasAction::Add { name, args } => {
+            // Make sure the alias expands to a valid invocation before saving it
+            let mut check = vec!["ssh-copy-id-rs".to_string()];
+            check.extend(args.iter().cloned());
+            let parsed = Args::try_parse_from(check)
+                .map_err(|e| anyhow::anyhow!("Invalid alias arguments:\n{}", e))?;
+            match (&parsed.command, &parsed.destination) {
+                (None, Some(destination)) => {
+                    destination.parse::<Destination>()?;
+                }
+                _ => bail!("An alias must expand to a destination, optionally followed by options."),
+            }
+
+            let summary = format!("Alias {} -> {}", name, remote_script::sh_join(&args));
+            aliases::add(aliases::Alias { name, args })?;
+            println!("{}", summary);
+            Ok(())
+        }
+        AliasAction::Remove { name } => {
+            aliases::remove(&name)?;
+            println!("Removed alias {}", name);
+            Ok(())
+        }
+        AliasAction::List => {
+            let all = aliases::load()?;
+            // alias<TAB>NAME<TAB>ARGUMENTS (shell-quoted, as for `alias add`)
+            if let Some(version) = porcelain {
+                println!("{}", version.header());
+                for alias in &all {
+                    let args = remote_script::sh_join(&alias.args);
+                    println!("{}", porcelain::line(&["alias", &alias.name, &args]));
+                }
+                return Ok(());
+            }
+            if all.is_empty() {
+                println!("No aliases defined. Add one with: ssh-copy-id-rs alias add NAME DESTINATION [OPTIONS]");
+            }
+            let width = all.iter().map(|alias| alias.name.len()).max().unwrap_or(0);
+            let listing: String = all
+                .iter()
+                .map(|alias| format!("{:width$}  {}\n", alias.name, remote_script::sh_join(&alias.args)))
+                .collect();
+            pager::show(&listing, paging);
+            Ok(())
+        }
+    }
+}
+
+/// Reads and validates the public keys of an identity, in any supported encoding.
+fn identity_keys(identity: &identity::Identity) -> Result<Vec<key::PublicKey>> {
+    let keys = keyformat::read_any(&identity.content)
+        .with_context(|| format!("Invalid public key in {}", identity.source))?;
+    if keys.is_empty() {
+        bail!("Identity content is empty.");
+    }
+    Ok(keyblob::dedupe(keys))
+}
+
+/// The keys to install for `keys`, read from `source`: sshd never matches a
+/// certificate against an authorized_keys line, so each one is replaced
+/// with the key it was issued for or, with --as-ca, the CA that signed it.
+fn certificate_keys(args: &Args, source: &str, keys: Vec<key::PublicKey>) -> Result<Vec<key::PublicKey>> {
+    let mut installed = Vec::new();
+    for key in keys {
+        let Some(certificate) = key.decode()?.cert else {
+            if args.as_ca {
+                bail!("--as-ca: {} holds a key that isn't a certificate", source);
+            }
+            installed.push(key);
+            continue;
+        };
+        if certificate.is_host {
+            bail!("{} holds host certificate {:?}, which identifies a server and doesn't belong in authorized_keys",
+                source, certificate.key_id);
+        }
+        if args.as_ca {
+            eprintln!(
+                "Info: Installing the CA that signed certificate {:?} ({}) as a cert-authority line",
+                certificate.key_id,
+                certificate.ca_description()
+            );
+            installed.push(certificate.ca_key()?);
+        } else {
+            eprintln!(
+                "Warning: {} holds certificate {:?}, which sshd doesn't look for in authorized_keys; installing the key \
+                 it was issued for instead. To trust its CA ({}) instead, use --as-ca.",
+                source,
+                certificate.key_id,
+                certificate.ca_description()
+            );
+            installed.push(key.underlying()?);
+        }
+    }
+    Ok(keyblob::dedupe(installed))
+}
+
+fn run_inspect(source: &identity::SourceArgs, paging: bool) -> Result<()> {
+    let identity = identity::resolve(source)?;
+    let keys = identity_keys(&identity)?;
+    pager::show(&inspect::describe(&identity.source, &keys)?, paging);
+    Ok(())
+}
+
+fn run_convert(source: &identity::SourceArgs, to: keyformat::Format, output: Option<PathBuf>) -> Result<()> {
+    let identity = identity::resolve(source)?;
+    let converted = keyformat::write(&identity_keys(&identity)?, to)?;
+    match output {
+        Some(path) => {
+            fs::write(&path, converted).with_context(|| format!("Failed to write {:?}", path))?;
+            eprintln!("Wrote {:?}", path);
+        }
+        None => print!("{}", converted),
+    }
+    Ok(())
+}
+
+fn run_probe(port: Option<u16>, destination: &str) -> Result<()> {
+    let destination: Destination = destination.parse()?;
+    if port.is_some() && destination.port.is_some() {
+        bail!("The port is given both with -p and in the destination {}", destination);
+    }
+    let mut ssh_args = ssh_config::ssh_args();
+    if let Some(port) = port {
+        ssh_args.extend(["-p".to_string(), port.to_string()]);
+    }
+    ssh_args.extend(destination.ssh_args());
+    let tcp_port = port.or(destination.port).unwrap_or(22);
+
+    for (label, value) in probe::run(&destination, &ssh_args, tcp_port) {
+        println!("{:<14} {}", format!("{}:", label), value);
+    }
+    Ok(())
+}
+
+/// Runs `command` on the destination, exiting with its status.
+fn run_exec(connection: &connection::ConnectionArgs, destination: &str, command: &[String]) -> Result<()> {
+    let mut destination: Destination = destination.parse()?;
+    connection.jump.apply(&mut destination)?;
+    let timeout = connection.timeout.map(Duration::from_secs);
+    let mut ssh = connection.ssh(&destination, timeout, &mut |message| eprintln!("Info: {}", message))?;
+    let mut child = ssh
+        .with_remote(command)
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    let Some(status) = wait_with_timeout(&mut child, timeout)? else {
+        return Err(TimedOut(timeout.unwrap_or_default()).into());
+    };
+    if !status.success() {
+        // As ssh does: the remote command's status, or 255 if ssh itself failed
+        std::process::exit(status.code().unwrap_or(255));
+    }
+    Ok(())
+}
+
+/// The targets of an install (or ping) run, with jump hosts applied and
+/// duplicate endpoints dropped.
+fn targets(args: &Args) -> Result<Vec<Target>> {
+    let mut targets = match (&args.hosts_file, &args.destination) {
+        (Some(path), _) => inventory::load(path)?,
+        (None, Some(destination)) => vec![Target::new(destination.parse()?)],
+        (None, None) => discover(args)?
+            .iter()
+            .map(|address| Ok(Target::new(address.parse()?)))
+            .collect::<Result<Vec<Target>>>()?,
+    };
+    for target in &mut targets {
+        if target.destination.user.is_none() {
+            target.destination.user = args.login.clone();
+        }
+        args.connection.jump.apply(&mut target.destination)?;
+    }
+    // Resolving names locally would bypass the proxy's own DNS
+    let resolve = !args.connection.proxied();
+    let (targets, duplicates) = inventory::dedupe(targets, args.connection.port, resolve);
+    for (dropped, kept) in duplicates {
+        eprintln!("Warning: skipping {}, the same endpoint as {}", dropped, kept);
+    }
+    Ok(targets)
+}
+
+/// The key source of an install: where none was given, the first
+/// IdentityFile in the targets' ssh config that has a `.pub` next to it,
+/// the key `ssh` would log in with. Targets naming different files get the
+/// default keys, with a warning.
+fn configured_source(args: &Args, targets: &[Target]) -> Result<identity::SourceArgs> {
+    if !args.source.is_default() {
+        return Ok(args.source.clone());
+    }
+    let mut chosen: Vec<Option<String>> = Vec::new();
+    for target in targets {
+        let destination = &target.destination;
+        let port = args.connection.port.or(destination.port);
+        let config = ssh_config::resolve(&destination.host, destination.user.as_deref(), port)?;
+        let file = config
+            .all("IdentityFile")
+            .filter_map(|args| args.first())
+            .find(|file| {
+                let public = if file.ends_with(".pub") { file.to_string() } else { format!("{}.pub", file) };
+                Path::new(&public).is_file()
+            })
+            .cloned();
+        if !chosen.contains(&file) {
+            chosen.push(file);
+        }
+    }
+    match chosen.as_slice() {
+        [Some(file)] => Ok(identity::SourceArgs {
+            identity_file: vec![file.clone()],
+            ..args.source.clone()
+        }),
+        [_, _, ..] => {
+            eprintln!("Warning: the ssh config names different IdentityFiles for these hosts; installing the default key(s), choose others with -i");
+            Ok(args.source.clone())
+        }
+        _ => Ok(args.source.clone()),
+    }
+}
+
+/// Resolves `source`, generating a key when none is found and `--generate`
+/// asks for one or the user accepts the offer of one.
+fn resolve_or_generate(args: &Args, source: &identity::SourceArgs) -> Result<identity::Identity> {
+    let e = match identity::resolve(source) {
+        Err(e) if e.is::<identity::NoIdentity>() => e,
+        resolved => return resolved,
+    };
+    let kind = match args.generate {
+        Some(kind) => kind,
+        None if !args.dry_run && keygen::offer(keygen::KeyType::Ed25519)? => keygen::KeyType::Ed25519,
+        None => return Err(e),
+    };
+    let public = keygen::generate(kind)?;
+    identity::resolve(&identity::SourceArgs {
+        identity_file: vec![public.to_string_lossy().into_owned()],
+        ..source.clone()
+    })
+}
+
+/// The addresses of the hosts found by the selected discovery backend.
+fn discover(args: &Args) -> Result<Vec<String>> {
+    let token = |given: &Option<String>, variable: &str, option: &str| match given {
+        Some(token) => Ok(token.clone()),
+        None => std::env::var(variable).with_context(|| format!("{} or ${} is needed", option, variable)),
+    };
+    if let Some(url) = &args.netbox_url {
+        return netbox::discover(&netbox::Query {
+            url: url.clone(),
+            token: token(&args.netbox_token, "NETBOX_TOKEN", "--netbox-token")?,
+            sites: args.netbox_site.clone(),
+            roles: args.netbox_role.clone(),
+            tags: args.netbox_tag.clone(),
+        });
+    }
+    if let Some(service) = &args.consul_service {
+        return consul::discover(&consul::Query {
+            service: service.clone(),
+            url: args.consul_url.clone(),
+            token: args.consul_token.clone(),
+            tag: args.consul_tag.clone(),
+            datacenter: args.consul_datacenter.clone(),
+        });
+    }
+    if let Some(url) = &args.zabbix_url {
+        return zabbix::discover(&zabbix::Query {
+            url: url.clone(),
+            token: token(&args.zabbix_token, "ZABBIX_API_TOKEN", "--zabbix-token")?,
+            groups: args.zabbix_group.clone(),
+        });
+    }
+    // Guaranteed by clap unless a subcommand was given
+    bail!("No destination given")
+}
+
+fn run_ping(args: Args) -> Result<()> {
+    let targets = targets(&args)?;
+    let timeout = Duration::from_secs(args.connection.timeout.unwrap_or(10));
+    let destinations: Vec<Destination> = targets.into_iter().map(|t| t.destination).collect();
+    let mut outcomes = ping::run(&destinations, timeout, |destination| banner_route(&args, destination));
+    if args.deterministic {
+        outcomes.iter_mut().for_each(|outcome| outcome.duration = Duration::ZERO);
+    }
+    ping::report(&outcomes, args.output, args.porcelain);
+
+    let failed = outcomes.iter().filter(|o| matches!(o.status, ping::Status::Unreachable(_))).count();
+    if failed > 0 {
+        bail!("{} of {} hosts unreachable", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+/// `--abort-harden`: cancels a scheduled hardening on every target.
+fn run_abort_harden(args: Args) -> Result<()> {
+    if !matches!(args.remote_profile, Profile::Posix | Profile::Busybox) {
+        bail!("--abort-harden needs a POSIX shell and sudo on the remote (--remote-profile posix or busybox)");
+    }
+    let targets = targets(&args)?;
+    let mut failed = 0;
+    for target in &targets {
+        let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+        let outcome = harden::abort(&args.connection, &target.destination, timeout, false, &mut |message| {
+            eprintln!("{}", message)
+        });
+        match outcome {
+            Ok(true) => println!("{}: cancelled the scheduled hardening", target.destination),
+            Ok(false) => println!("{}: no hardening was scheduled", target.destination),
+            Err(e) => {
+                eprintln!("{}: {:#}", target.destination, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} hosts failed", failed, targets.len());
+    }
+    Ok(())
+}
+
+fn run_install(args: Args) -> Result<()> {
+    if args.remote_home.is_some() && args.remote_profile == Profile::Powershell {
+        bail!("--remote-home doesn't apply to Windows, where the key file is found through the account's profile");
+    }
+    if let Some(path) = &args.authorized_keys_path {
+        if matches!(args.remote_profile, Profile::Powershell | Profile::Device) {
+            bail!("--authorized-keys-path needs a POSIX shell on the remote (--remote-profile posix, busybox or csh)");
+        }
+        remote_script::check_key_path(path)
+            .map_err(|reason| anyhow::anyhow!("Invalid --authorized-keys-path: {}", reason))?;
+    }
+    if let Some(user) = &args.sudo_user {
+        if matches!(args.remote_profile, Profile::Powershell | Profile::Device) {
+            bail!("--sudo-user needs a POSIX shell on the remote (--remote-profile posix, busybox or csh)");
+        }
+        if user.is_empty() || user.starts_with('-') || user.chars().any(char::is_whitespace) {
+            bail!("Invalid user name for --sudo-user: {:?}", user);
+        }
+    }
+    let prepared = targets(&args).and_then(|targets| {
+        if let [old, new] = args.rotate.as_slice() {
+            let source = |path: &str| identity::SourceArgs {
+                identity_file: vec![path.to_string()],
+                ..args.source.clone()
+            };
+            let identity = identity::resolve(&source(new))?;
+            let keys = certificate_keys(&args, &identity.source, identity_keys(&identity)?)?;
+            if keys.len() != 1 {
+                bail!("--rotate: {} holds {} keys; NEW has to be a single key", identity.source, keys.len());
+            }
+            let old = identity_keys(&identity::resolve(&source(old))?)?;
+            if keyblob::contains(&old, &keys[0]) {
+                bail!("--rotate: OLD and NEW are the same key");
+            }
+            return Ok((targets, identity, keys, old));
+        }
+        // 1. Resolve identity (file, the ssh config's IdentityFile or ssh-agent)
+        let identity = resolve_or_generate(&args, &configured_source(&args, &targets)?)?;
+
+        // Validate that we are sending well-formed public keys, one per line
+        let keys = identity_keys(&identity)?;
+        let keys = if args.remove { keys } else { certificate_keys(&args, &identity.source, keys)? };
+        Ok((targets, identity, keys, Vec::new()))
+    });
+    let (targets, identity, keys, old) = match prepared {
+        Err(e) if args.porcelain.is_none() && args.output == reporter::Format::Json => {
+            reporter::print_json_error(&e);
+            return Err(e);
+        }
+        prepared => prepared?,
+    };
+    let options = key_options(&args);
+    let clean_key_content = keys
+        .iter()
+        .map(|key| authorized_keys::with_options(key, &options).map(|line| line + "\n"))
+        .collect::<Result<String, String>>()
+        .map_err(|reason| anyhow::anyhow!("Invalid --key-option: {}", reason))?;
+    run_each(&args, &targets, &identity.source, &keys, None, |target, reporter, findings| {
+        if args.remove {
+            remove_host(&args, target, &identity, &keys, reporter, findings)
+        } else if !args.rotate.is_empty() {
+            rotate_host(&args, target, &identity, &keys, &old, reporter, findings)
+        } else {
+            install_host(&args, target, &identity, &keys, &clean_key_content, reporter, findings)
+        }
+    })
+}
+
+/// The options put in front of each key installed: `cert-authority` with
+/// --as-ca, then the --key-options.
+fn key_options(args: &Args) -> Vec<String> {
+    args.as_ca.then(|| "cert-authority".to_string()).into_iter().chain(args.key_option.iter().cloned()).collect()
+}
+
+/// `--apply-plan`: makes the changes recorded with `--plan-file`, on each
+/// host whose authorized_keys still hold the keys they held then.
+fn run_apply_plan(mut args: Args) -> Result<()> {
+    let path = args.apply_plan.clone().expect("--apply-plan is given");
+    let plan = plan::Plan::load(&path)?;
+    args.remove = plan.action == plan::Action::Remove;
+    // So that hosts planned again get them as well
+    args.key_option = plan.options.clone();
+    let mut targets = Vec::new();
+    for host in &plan.hosts {
+        let mut destination: Destination = host.destination.parse()?;
+        args.connection.jump.apply(&mut destination)?;
+        targets.push(Target::new(destination));
+    }
+    // Plans from before `want` lines were recorded only have the changes
+    let keys = match plan.wanted.is_empty() {
+        true => keyblob::dedupe(plan.hosts.iter().flat_map(|host| host.keys.clone()).collect()),
+        false => plan.wanted.clone(),
+    };
+    let source = format!("plan {}", path.display());
+    run_each(&args, &targets, &source, &keys, None, |target, reporter, findings| {
+        let destination = &target.destination;
+        let host = plan
+            .hosts
+            .iter()
+            .find(|host| host.destination == destination.to_string())
+            .expect("targets are the plan's hosts");
+        let (label, _) = account_id(&args, destination)?;
+        if label != host.label {
+            bail!("{} was planned as {}; give the connection options the plan was made with", label, host.label);
+        }
+        let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+        let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+        let installed = read_installed(&args, destination, &mut ssh, timeout)?.keys;
+        let state = plan::state(&installed)?;
+        if state != host.state {
+            if args.replan_on_drift.is_some() {
+                let wanted = if plan.wanted.is_empty() { &host.keys } else { &plan.wanted };
+                findings.plan = Some(plan::HostPlan {
+                    label,
+                    destination: host.destination.clone(),
+                    state,
+                    keys: plan::changes(plan.action, &installed, wanted),
+                });
+            }
+            return Err(plan::Drift(destination.to_string()).into());
+        }
+        if host.keys.is_empty() {
+            reporter.info("Nothing to change, as planned");
+            findings.key_outcome = Some(KeyOutcome { added: Vec::new(), skipped: Vec::new(), removed: Vec::new() });
+            return Ok(());
+        }
+        let identity = identity::Identity { source: source.clone(), content: key::payload(&host.keys) };
+        if args.remove {
+            remove_host(&args, target, &identity, &host.keys, reporter, findings)
+        } else {
+            // The lines exactly as the plan records them
+            let lines: String = host.keys.iter().map(|key| plan.line(key) + "\n").collect();
+            install_host(&args, target, &identity, &host.keys, &lines, reporter, findings)
+        }
+    })
+}
+
+/// What a job learned about its host, for the report.
+#[derive(Default)]
+struct Findings {
+    /// SSHFP records, with --emit-sshfp
+    sshfp: Vec<String>,
+    /// The host key ssh was shown, as `TYPE SHA256:...`
+    host_key: Option<String>,
+    /// Which keys the host reported adding
+    key_outcome: Option<KeyOutcome>,
+    /// What --plan-file records for the host
+    plan: Option<plan::HostPlan>,
+    /// What --dry-run found would happen to each key
+    changes: Vec<reporter::Change>,
+}
+
+/// Runs `job` on every target with hooks and reporting; `job` notes what
+/// it learns in the [`Findings`], even when it fails. `keys` are the keys
+/// being installed, if any, and `file` the remote path being written by
+/// `push-file`.
+fn run_each(
+    args: &Args,
+    targets: &[Target],
+    source: &str,
+    keys: &[key::PublicKey],
+    file: Option<&str>,
+    mut job: impl FnMut(&Target, &mut dyn Reporter, &mut Findings) -> Result<()>,
+) -> Result<()> {
+    let slow_after = Duration::from_secs(args.slow_after);
+    let mut reporter: Box<dyn Reporter> = match args.porcelain {
+        Some(version) => Box::new(reporter::Porcelain(version)),
+        None => reporter::new(args.output, slow_after),
+    };
+    reporter.start(source, targets.len());
+
+    let mut results = Vec::new();
+    let mut plans = Vec::new();
+    let mut single_error = None;
+    for target in targets {
+        let destination = target.destination.to_string();
+        reporter.host_started(&destination);
+        let banner = if args.porcelain.is_some() || args.output.reports_banner() {
+            server_banner(args, &target.destination)
+        } else {
+            None
+        };
+        let hook_env = hooks::env(&target.destination, args.connection.port, keys)?;
+        let hook_output_to_stderr = args.porcelain.is_some() || args.output.is_machine_readable();
+        let started = Instant::now();
+        let mut findings = Findings::default();
+        // Held until the job is done, so runs against the same host queue
+        let outcome = lock_destination(args, &target.destination, reporter.as_mut()).and_then(|_lock| {
+            if let Some(command) = &args.pre_hook {
+                hooks::run("pre-hook", command, &hook_env, hook_output_to_stderr)?;
+            }
+            match (job(target, reporter.as_mut(), &mut findings), args.retry_lockout) {
+                (Err(e), Some(delay)) if e.downcast_ref::<lockout::Lockout>().is_some_and(|l| l.is_temporary()) => {
+                    reporter.info(&format!(
+                        "The server dropped the connection, likely a temporary ban; trying again in {}s...",
+                        delay.as_secs()
+                    ));
+                    std::thread::sleep(delay);
+                    job(target, reporter.as_mut(), &mut findings)
+                }
+                (outcome, _) => outcome,
+            }
+        });
+        let duration = if args.deterministic { Duration::ZERO } else { started.elapsed() };
+
+        let status = match outcome {
+            Ok(()) => Status::Ok,
+            Err(e) => {
+                let status = match e.downcast_ref::<TimedOut>() {
+                    Some(TimedOut(after)) => Status::TimedOut(*after),
+                    None if e.is::<plan::Drift>() => Status::DriftDetected(format!("{:#}", e)),
+                    None => Status::Failed(format!("{:#}", e)),
+                };
+                if targets.len() == 1 {
+                    single_error = Some(e);
+                }
+                status
+            }
+        };
+        plans.extend(findings.plan);
+        let result = HostResult {
+            destination,
+            status,
+            duration,
+            slow: duration > slow_after,
+            keys: if args.dry_run { 0 } else { keys.len() },
+            key_outcome: findings.key_outcome,
+            login_command: remote_script::sh_join(&args.connection.login_args(&account(args, &target.destination))?),
+            sshfp: findings.sshfp,
+            banner,
+            host_key: findings.host_key,
+            file: file.map(str::to_string),
+            dry_run: args.dry_run,
+            changes: findings.changes,
+            remove: args.remove,
+        };
+        reporter.host_finished(&result);
+        if let Some(command) = &args.post_hook {
+            let env = [hook_env, hooks::result_env(&result)].concat();
+            if let Err(e) = hooks::run("post-hook", command, &env, hook_output_to_stderr) {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+        results.push(result);
+    }
+    reporter.finish(&results);
+    if args.trailer {
+        println!("{}", reporter::trailer(&results));
+    }
+    let failed = results.iter().filter(|r| r.status != Status::Ok).count();
+    let action = if args.remove { plan::Action::Remove } else { plan::Action::Add };
+    let options = key_options(args);
+    let wanted = keys.to_vec();
+    if let Some(path) = &args.plan_file {
+        if failed == 0 {
+            plan::Plan { action, options, wanted, hosts: plans }.save(path)?;
+            eprintln!("Info: Wrote the plan for {} host(s) to {:?}; carry it out with --apply-plan", results.len(), path);
+        } else {
+            eprintln!("Warning: no plan was written, as not every host could be read");
+        }
+    } else if let Some(path) = args.replan_on_drift.as_ref().filter(|_| !plans.is_empty()) {
+        let drifted = plans.len();
+        plan::Plan { action, options, wanted, hosts: plans }.save(path)?;
+        eprintln!(
+            "Info: Wrote a new plan for the {} host(s) whose keys changed to {:?}; review it and carry it out with --apply-plan",
+            drifted, path
+        );
+    }
+
+    if let Some(e) = single_error {
+        return Err(e);
+    }
+    if failed > 0 {
+        bail!("{} of {} hosts failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// Takes the local lock for `user@host:port`, waiting while another run on
+/// this machine holds it.
+fn lock_destination(args: &Args, destination: &Destination, reporter: &mut dyn Reporter) -> Result<fsutil::FileLock> {
+    let (key, name) = args.connection.destination_id(destination)?;
+    let path = paths::state_dir()?.join("locks").join(name);
+    fsutil::FileLock::acquire_within(&path, fsutil::LOCK_STALE_AFTER, &mut || {
+        reporter.info(&format!("Waiting for another run against {} to finish...", key))
+    })
+}
+
+/// `push-file`: copies `local` to `remote_path` on the targets selected by
+/// `rest`, which takes the same destination and options as an install.
+fn run_push_file(local: &Path, remote_path: &str, mode: Option<&str>, rest: &[String]) -> Result<()> {
+    let args = Args::parse_from(std::iter::once("ssh-copy-id-rs".to_string()).chain(rest.iter().cloned())).with_shorthands();
+    if args.command.is_some() || args.ping || args.abort_harden {
+        bail!("push-file takes a destination and connection options, not another command");
+    }
+    apply_environment(&args)?;
+    if args.dry_run {
+        bail!("push-file has no --dry-run");
+    }
+    if !matches!(args.remote_profile, Profile::Posix | Profile::Busybox) {
+        bail!("push-file needs a POSIX shell on the remote (--remote-profile posix or busybox)");
+    }
+    let remote_path = remote_path.strip_prefix("~/").unwrap_or(remote_path);
+    if remote_path.is_empty() || remote_path.ends_with('/') {
+        bail!("The remote path must name a file, not a directory");
+    }
+    let content = fs::read(local).with_context(|| format!("Failed to read {:?}", local))?;
+    let mode = match mode {
+        Some(mode) => u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .with_context(|| format!("Invalid mode {:?}; expected octal, e.g. 644", mode))?,
+        None => local_mode(local)?,
+    };
+    let script = remote_script::push_file(remote_path, mode);
+
+    let targets = targets(&args)?;
+    let source = local.display().to_string();
+    run_each(&args, &targets, &source, &[], Some(remote_path), |target, reporter, findings| {
+        let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+        let mut ssh = args.connection.ssh(&target.destination, timeout, &mut |message| reporter.info(message))?;
+        ssh.observe_host_key(&target.destination);
+        let status = run_ssh(&args, &mut ssh, &script, &content, timeout);
+        findings.host_key = ssh.host_key();
+        match status?.0 {
+            None => Err(TimedOut(timeout.unwrap_or_default()).into()),
+            Some(status) if !status.success() => {
+                bail!("ssh process exited with error code: {:?}", status.code())
+            }
+            Some(_) => Ok(()),
+        }
+    })
+}
+
+/// Collects the key usage of every target and reports the stale keys.
+fn run_stale_report(days: u32, rest: &[String]) -> Result<()> {
+    let args = Args::parse_from(std::iter::once("ssh-copy-id-rs".to_string()).chain(rest.iter().cloned())).with_shorthands();
+    if args.command.is_some() || args.ping || args.abort_harden {
+        bail!("stale-report takes destinations and connection options, not another command");
+    }
+    apply_environment(&args)?;
+    if !matches!(args.remote_profile, Profile::Posix | Profile::Busybox) {
+        bail!("stale-report needs a POSIX shell and sudo on the remote (--remote-profile posix or busybox)");
+    }
+    let targets = targets(&args)?;
+    let hosts: Vec<usage::HostUsage> = targets
+        .iter()
+        .map(|target| {
+            eprintln!("Reading the sshd log of {}...", target.destination);
+            let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+            usage::HostUsage {
+                destination: target.destination.to_string(),
+                usage: usage::collect(&args.connection, &target.destination, timeout, days).map_err(|e| format!("{:#}", e)),
+            }
+        })
+        .collect();
+    usage::report_stale(&hosts, days, args.output);
+    let failed = hosts.iter().filter(|host| host.usage.is_err()).count();
+    if failed > 0 {
+        bail!("{} of {} hosts failed", failed, hosts.len());
+    }
+    Ok(())
+}
+
+/// The permission bits of a local file, for the remote copy.
+#[cfg(unix)]
+fn local_mode(path: &Path) -> Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn local_mode(_path: &Path) -> Result<u32> {
+    Ok(0o644)
+}
+
+/// The identification string of a directly reachable host, for reports.
+fn server_banner(args: &Args, destination: &Destination) -> Option<String> {
+    let timeout = Duration::from_secs(args.connection.timeout.unwrap_or(10));
+    banner_route(args, destination).ok()?.banner(timeout).ok()
+}
+
+/// Where ssh would connect for the destination, taking the ssh config into
+/// account, if it can be contacted without ssh.
+fn banner_route(args: &Args, destination: &Destination) -> Result<probe::Route> {
+    let unchecked = |reason: &str| Ok(probe::Route::Unchecked(format!("not checked: {}", reason)));
+    if !destination.via.is_empty() {
+        return unchecked("behind a jump host");
+    }
+    if args.connection.proxied() || proxy::is_onion(&destination.host) {
+        return unchecked("reached through a proxy");
+    }
+    let port = args.connection.port.or(destination.port);
+    let config = ssh_config::resolve(&destination.host, destination.user.as_deref(), port)?;
+    if config.proxy_jump().is_some() {
+        return unchecked("behind a jump host (ProxyJump in ssh config)");
+    }
+    if let Some(command) = config.proxy_command() {
+        return Ok(probe::Route::Command(command));
+    }
+    Ok(probe::Route::Direct {
+        host: config.host_name().to_string(),
+        port: config.port()?,
+    })
+}
+
+/// The `--announce` note naming each key by fingerprint and comment.
+fn announcement(to: AnnounceTo, keys: &[key::PublicKey]) -> Result<Announcement> {
+    let keys = keys
+        .iter()
+        .map(|key| {
+            let fingerprint = key.decode()?.fingerprint_sha256();
+            let name = match &key.comment {
+                Some(comment) => format!("{} ({})", fingerprint, comment),
+                None => fingerprint,
+            };
+            Ok((key.to_line(), name))
+        })
+        .collect::<Result<_>>()?;
+    let user = ssh_config::local_user();
+    let by = if user.is_empty() {
+        ssh_config::local_host_name()
+    } else {
+        format!("{}@{}", user, ssh_config::local_host_name())
+    };
+    Ok(Announcement {
+        to,
+        header: format!(
+            "{} {} added to authorized_keys with ssh-copy-id-rs:",
+            timeutil::format_utc(timeutil::now_unix()),
+            by
+        ),
+        keys,
+    })
+}
+
+/// Installs the keys on one host.
+fn install_host(
+    args: &Args,
+    target: &Target,
+    identity: &identity::Identity,
+    keys: &[key::PublicKey],
+    clean_key_content: &str,
+    reporter: &mut dyn Reporter,
+    findings: &mut Findings,
+) -> Result<()> {
+    let destination = &target.destination;
+    let port = args.connection.port(destination)?;
+    let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+    if args.harden && !matches!(args.remote_profile, Profile::Posix | Profile::Busybox) {
+        bail!("--harden needs a POSIX shell and sudo on the remote (--remote-profile posix or busybox)");
+    }
+
+    if args.announce.is_some() && matches!(args.remote_profile, Profile::Powershell | Profile::Device) {
+        bail!("--announce needs a POSIX shell on the remote (--remote-profile posix, busybox or csh)");
+    }
+
+    if args.backup && !args.sftp && !matches!(args.remote_profile, Profile::Posix | Profile::Csh) {
+        bail!("--backup needs a POSIX shell on the remote (--remote-profile posix or csh), or -s");
+    }
+
+    let (label, id) = account_id(args, destination)?;
+    if let Some(written) = cached_install(args, &id, keys) {
+        if args.skip_if_cached {
+            reporter.info(&format!(
+                "All key(s) were installed as of {} (cached); not connecting (--skip-if-cached)",
+                written
+            ));
+            let entry = history::Entry {
+                destination: &destination.to_string(),
+                source: &identity.source,
+                outcome: "skipped (cached)",
+                host_key: None,
+            };
+            if let Err(e) = history::record(&entry) {
+                eprintln!("Warning: could not update local history: {:#}", e);
+            }
+            findings.key_outcome = Some(KeyOutcome {
+                added: Vec::new(),
+                skipped: keys.iter().map(fingerprint).collect(),
+                removed: Vec::new(),
+            });
+            return Ok(());
+        }
+        reporter.info(&format!(
+            "All key(s) were installed as of {} (cached); --skip-if-cached would skip this host",
+            written
+        ));
+    }
+
+    let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+    if ssh.proxied && args.emit_sshfp {
+        bail!("--emit-sshfp contacts the host directly and cannot be used through a proxy.");
+    }
+    if args.dry_run {
+        return preview_host(args, destination, (&label, &id), &mut ssh, keys, timeout, findings);
+    }
+
+    // 2. Construct the remote command
+    let announcement = args.announce.map(|to| announcement(to, keys)).transpose()?;
+    // The keys are installed in place of the certificates, which are still what logs in
+    let certificates: Vec<cert::Certificate> = identity_keys(identity)?
+        .iter()
+        .map(|key| Ok(key.decode()?.cert))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let remote_cmd = InstallScript::new(args.remote_profile)
+        .size_guard(!args.force)
+        .dedupe(!args.force)
+        .known(authorized_keys::spellings_by_line(clean_key_content))
+        .announce(announcement)
+        .report_clock(!certificates.is_empty() && matches!(args.remote_profile, Profile::Posix | Profile::Csh))
+        .report_added(args.remote_profile != Profile::Device)
+        .verify_checksum(true)
+        .backup(args.backup)
+        .home(args.remote_home.clone())
+        .path(args.authorized_keys_path.clone())
+        .render();
+
+    // 3. Execute SSH
+    if args.sftp {
+        reporter.info("Installing the key(s) over SFTP (-s)...");
+    } else if args.force {
+        reporter.info("Appending the key(s) without checking for ones already installed (--force)...");
+    } else {
+        reporter.info("Attempting to log in with the new key(s) to filter out any that are already installed...");
+    }
+    let mut over_sftp = false;
+    ssh.observe_host_key(destination);
+    let status = match args.sftp {
+        true => Err(sftp::SftpOnly.into()),
+        false => run_ssh(args, &mut ssh, &remote_cmd, clean_key_content.as_bytes(), timeout),
+    };
+    findings.host_key = ssh.host_key();
+    let (outcome, result) = match status {
+        Err(e) if e.is::<sftp::SftpOnly>() && args.authorized_keys_path.is_some() => {
+            ("failed (sftp only)".to_string(), Err(sftp_only_key_path()))
+        }
+        Err(e) if e.is::<sftp::SftpOnly>() => {
+            if !args.sftp {
+                reporter.info(
+                    "The account only allows SFTP (ForceCommand internal-sftp); installing over SFTP instead...",
+                );
+            }
+            // run_ssh has added the destination to `ssh`
+            let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+            ssh.observe_host_key(destination);
+            let result = sftp::install(
+                &ssh,
+                clean_key_content,
+                !args.force,
+                args.backup,
+                args.remote_home.as_deref(),
+                timeout,
+            )
+            .map(|installed| {
+                report_backup(installed.backup.as_deref(), reporter);
+                findings.key_outcome = Some(report_added(keys, &installed.added, None, reporter));
+            });
+            findings.host_key = ssh.host_key().or(findings.host_key.take());
+            over_sftp = true;
+            let outcome = match &result {
+                Ok(()) => "ok (sftp)",
+                Err(e) if e.is::<TimedOut>() => "timed out",
+                Err(_) => "failed (sftp)",
+            };
+            (outcome.to_string(), result)
+        }
+        Err(e) if e.is::<lockout::Lockout>() => ("locked out".to_string(), Err(e)),
+        status => {
+            let (status, stdout) = status?;
+            check_clock(&certificates, &stdout);
+            report_backup(remote_script::backed_up(&stdout), reporter);
+            if status.is_some_and(|status| status.success()) {
+                findings.key_outcome = Some(match args.remote_profile {
+                    // A plain append: every key went in
+                    Profile::Device => {
+                        report_added(keys, &clean_key_content.lines().collect::<Vec<_>>(), None, reporter)
+                    }
+                    _ => report_added(
+                        keys,
+                        &remote_script::added(&stdout),
+                        Some(&remote_script::skipped(&stdout)),
+                        reporter,
+                    ),
+                });
+            }
+            let outcome = match status {
+                Some(status) if status.success() => "ok".to_string(),
+                Some(status) => format!("failed ({:?})", status.code()),
+                None => "timed out".to_string(),
+            };
+            let result = match status {
+                Some(status) if status.success() => Ok(()),
+                Some(status) => Err(anyhow::anyhow!("ssh process exited with error code: {:?}", status.code())),
+                None => Err(TimedOut(timeout.unwrap_or_default()).into()),
+            };
+            (outcome, result)
+        }
+    };
+    let entry = history::Entry {
+        destination: &destination.to_string(),
+        source: &identity.source,
+        outcome: &outcome,
+        host_key: findings.host_key.as_deref(),
+    };
+    if let Err(e) = history::record(&entry) {
+        eprintln!("Warning: could not update local history: {:#}", e);
+    }
+    result?;
+    if let Err(e) = args.connection.password.remember(&args.connection.destination_id(destination)?.0) {
+        eprintln!("Warning: could not keep the password in the OS keychain: {:#}", e);
+    }
+    // The cache is of the keys in .ssh/authorized_keys
+    if args.authorized_keys_path.is_none() {
+        if let Err(e) = keycache::add(&id, &label, keys) {
+            eprintln!("Warning: could not update the key cache: {:#}", e);
+        }
+    }
+
+    if over_sftp {
+        if args.harden {
+            bail!("--harden needs a shell on the remote, but the account only allows SFTP; the keys are installed");
+        }
+        if args.announce.is_some() {
+            eprintln!("Warning: --announce skipped, as the account only allows SFTP.");
+        }
+    }
+
+    if args.harden {
+        let output_to_stderr = args.porcelain.is_some() || args.output.is_machine_readable();
+        token::check(keys, &mut |message| reporter.info(message))?;
+        harden::run(&args.connection, destination, timeout, args.after, output_to_stderr, &mut |message| {
+            reporter.info(message)
+        })?;
+    }
+
+    if args.emit_sshfp {
+        findings.sshfp = emit_sshfp(destination, port.as_deref());
+        if args.deterministic {
+            // ssh-keyscan lists the keys in the order the host answers
+            findings.sshfp.sort();
+        }
+    }
+    Ok(())
+}
+
+/// Removes the keys from one host, with --remove.
+fn remove_host(
+    args: &Args,
+    target: &Target,
+    identity: &identity::Identity,
+    keys: &[key::PublicKey],
+    reporter: &mut dyn Reporter,
+    findings: &mut Findings,
+) -> Result<()> {
+    let destination = &target.destination;
+    let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+    let spellings = keyblob::spellings(keys);
+    let blobs: Vec<&str> = spellings.iter().map(String::as_str).collect();
+    let script = remote_script::remove_keys(
+        args.remote_profile,
+        &blobs,
+        args.remote_home.as_deref(),
+        args.authorized_keys_path.as_deref(),
+    );
+    if script.is_none() && !args.sftp {
+        bail!("--remove needs the posix, csh or powershell profile, or -s; edit authorized_keys by hand on this host");
+    }
+    let (label, id) = account_id(args, destination)?;
+    let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+    if args.dry_run {
+        return preview_host(args, destination, (&label, &id), &mut ssh, keys, timeout, findings);
+    }
+
+    reporter.info("Removing the key(s) from authorized_keys...");
+    ssh.observe_host_key(destination);
+    let status = match &script {
+        Some(script) if !args.sftp => run_ssh(args, &mut ssh, script, b"", timeout),
+        _ => Err(sftp::SftpOnly.into()),
+    };
+    findings.host_key = ssh.host_key();
+    let (outcome, result) = match status {
+        Err(e) if e.is::<sftp::SftpOnly>() && args.authorized_keys_path.is_some() => {
+            ("failed (sftp only)".to_string(), Err(sftp_only_key_path()))
+        }
+        Err(e) if e.is::<sftp::SftpOnly>() => {
+            if !args.sftp {
+                reporter.info("The account only allows SFTP (ForceCommand internal-sftp); removing over SFTP instead...");
+            }
+            let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+            ssh.observe_host_key(destination);
+            let result = sftp::remove(&ssh, &blobs, args.remote_home.as_deref(), timeout);
+            findings.host_key = ssh.host_key().or(findings.host_key.take());
+            let outcome = match &result {
+                Ok(_) => "removed (sftp)",
+                Err(e) if e.is::<TimedOut>() => "timed out",
+                Err(_) => "failed (sftp)",
+            };
+            (outcome.to_string(), result)
+        }
+        Err(e) if e.is::<lockout::Lockout>() => ("locked out".to_string(), Err(e)),
+        status => {
+            let (status, stdout) = status?;
+            match status {
+                Some(status) if status.success() => (
+                    "removed".to_string(),
+                    Ok(remote_script::removed(&stdout).into_iter().map(str::to_string).collect()),
+                ),
+                Some(status) => (
+                    format!("failed ({:?})", status.code()),
+                    Err(anyhow::anyhow!("ssh process exited with error code: {:?}", status.code())),
+                ),
+                None => ("timed out".to_string(), Err(TimedOut(timeout.unwrap_or_default()).into())),
+            }
+        }
+    };
+    let entry = history::Entry {
+        destination: &destination.to_string(),
+        source: &identity.source,
+        outcome: &outcome,
+        host_key: findings.host_key.as_deref(),
+    };
+    if let Err(e) = history::record(&entry) {
+        eprintln!("Warning: could not update local history: {:#}", e);
+    }
+    findings.key_outcome = Some(report_removed(keys, &result?, reporter));
+    if let Err(e) = keycache::remove(&id, keys) {
+        eprintln!("Warning: could not update the key cache: {:#}", e);
+    }
+    Ok(())
+}
+
+/// Swaps `old` for the key `new` on one host, with --rotate.
+fn rotate_host(
+    args: &Args,
+    target: &Target,
+    identity: &identity::Identity,
+    new: &[key::PublicKey],
+    old: &[key::PublicKey],
+    reporter: &mut dyn Reporter,
+    findings: &mut Findings,
+) -> Result<()> {
+    let destination = &target.destination;
+    let timeout = target.timeout.or(args.connection.timeout.map(Duration::from_secs));
+    let spellings = keyblob::spellings(old);
+    let blobs: Vec<&str> = spellings.iter().map(String::as_str).collect();
+    let known = authorized_keys::spellings_by_line(&key::payload(new));
+    let Some(script) = remote_script::rotate_keys(args.remote_profile, &known, &blobs, args.remote_home.as_deref()) else {
+        bail!("--rotate needs a POSIX shell on the remote (--remote-profile posix or csh)");
+    };
+    let script = remote_script::login_command(args.remote_profile, &script, None);
+    let (label, id) = args.connection.destination_id(destination)?;
+    let mut ssh = args.connection.ssh(destination, timeout, &mut |message| reporter.info(message))?;
+
+    token::check(&new[..1], &mut |message| reporter.info(message))?;
+    reporter.info("Installing the new key, to remove the old one(s) once a login with it works...");
+    ssh.observe_host_key(destination);
+    let login = rotate::login_identity(&args.rotate[1]);
+    let mut verified = None;
+    let reply = Reply {
+        after: remote_script::ROTATE_READY,
+        answer: Box::new(|| {
+            reporter.info("Verifying a login that offers only the new key...");
+            let ok = rotate::verify(&args.connection, destination, timeout, &login, &new[0], &mut |message| {
+                reporter.info(message)
+            })
+            .unwrap_or_else(|e| {
+                reporter.info(&format!("Could not verify the new key: {:#}", e));
+                false
+            });
+            verified = Some(ok);
+            if ok { b"commit\n".to_vec() } else { b"rollback\n".to_vec() }
+        }),
+    };
+    let input = format!("{}\n", key::payload(new));
+    let status = crate::converse(&mut ssh, &script, input.as_bytes(), Some(reply), timeout, remote_stdout(args));
+    findings.host_key = ssh.host_key();
+    let (outcome, result) = match (status, verified) {
+        (Err(e), _) if e.is::<sftp::SftpOnly>() => (
+            "failed (sftp only)".to_string(),
+            Err(anyhow::anyhow!("--rotate needs a shell on the remote, but the account only allows SFTP")),
+        ),
+        (Err(e), _) if e.is::<lockout::Lockout>() => ("locked out".to_string(), Err(e)),
+        (Err(e), _) => return Err(e),
+        (Ok((None, _)), _) => ("timed out".to_string(), Err(TimedOut(timeout.unwrap_or_default()).into())),
+        (Ok((_, stdout)), Some(false)) => match stdout.lines().any(|line| line == remote_script::ROTATE_ROLLED_BACK) {
+            true => (
+                "rolled back".to_string(),
+                Err(anyhow::anyhow!(
+                    "A login with the new key failed, so it was taken out again; the old key is still installed"
+                )),
+            ),
+            false => (
+                "failed (rollback)".to_string(),
+                Err(anyhow::anyhow!(
+                    "A login with the new key failed, and taking it out again failed too; both keys are installed"
+                )),
+            ),
+        },
+        (Ok((Some(status), stdout)), Some(true)) if status.success() => ("rotated".to_string(), Ok(stdout)),
+        (Ok((Some(status), _)), Some(true)) => (
+            format!("failed ({:?})", status.code()),
+            Err(anyhow::anyhow!(
+                "The new key works, but removing the old one failed (ssh exited with {:?}); both keys are installed",
+                status.code()
+            )),
+        ),
+        (Ok((Some(status), _)), None) => (
+            format!("failed ({:?})", status.code()),
+            Err(anyhow::anyhow!("ssh process exited with error code: {:?}", status.code())),
+        ),
+    };
+    let entry = history::Entry {
+        destination: &destination.to_string(),
+        source: &identity.source,
+        outcome: &outcome,
+        host_key: findings.host_key.as_deref(),
+    };
+    if let Err(e) = history::record(&entry) {
+        eprintln!("Warning: could not update local history: {:#}", e);
+    }
+    let stdout = result?;
+    if let Err(e) = args.connection.password.remember(&label) {
+        eprintln!("Warning: could not keep the password in the OS keychain: {:#}", e);
+    }
+
+    let mut key_outcome = report_added(new, &remote_script::added(&stdout), None, reporter);
+    key_outcome.removed = report_removed(old, &remote_script::removed(&stdout), reporter).removed;
+    findings.key_outcome = Some(key_outcome);
+    if let Err(e) = keycache::add(&id, &label, new).and_then(|()| keycache::remove(&id, old)) {
+        eprintln!("Warning: could not update the key cache: {:#}", e);
+    }
+    Ok(())
+}
+
+/// The account whose keys are changed on `destination`: the login
+/// account, or the one --sudo-user becomes.
+fn account(args: &Args, destination: &Destination) -> Destination {
+    Destination {
+        user: args.sudo_user.clone().or(destination.user.clone()),
+        ..destination.clone()
+    }
+}
+
+/// The `user@host:port` of [`account`], and its key cache name.
+fn account_id(args: &Args, destination: &Destination) -> Result<(String, String)> {
+    args.connection.destination_id(&account(args, destination))
+}
+
+/// When the key cache last saw all of `keys` on the destination `id`, if
+/// it did; never with --force or --dry-run, which always connect.
+fn cached_install(args: &Args, id: &str, keys: &[key::PublicKey]) -> Option<String> {
+    if args.force || args.dry_run || args.authorized_keys_path.is_some() {
+        return None;
+    }
+    let cached = keycache::load(id).unwrap_or_else(|e| {
+        eprintln!("Warning: could not read the key cache: {:#}", e);
+        None
+    })?;
+    cached.confirmed(keys).map(|written| timeutil::format_utc(if args.deterministic { 0 } else { written }))
+}
+
+/// Reads the remote authorized_keys and prints which of `keys` it lacks,
+/// noting each in the findings for the machine-readable reports.
+fn preview_host(
+    args: &Args,
+    destination: &Destination,
+    (label, id): (&str, &str),
+    ssh: &mut connection::Ssh,
+    keys: &[key::PublicKey],
+    timeout: Option<Duration>,
+    findings: &mut Findings,
+) -> Result<()> {
+    let parsed = read_installed(args, destination, ssh, timeout)?;
+    let installed = parsed.keys;
+    if args.authorized_keys_path.is_none() {
+        if let Err(e) = keycache::replace(id, label, &installed) {
+            eprintln!("Warning: could not update the key cache: {:#}", e);
+        }
+    }
+
+    let mut out: Box<dyn Write> = if args.porcelain.is_some() || args.output.is_machine_readable() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    };
+    let mut missing = 0;
+    for key in keys {
+        // Options and comments of the installed line don't matter
+        let present = keyblob::contains(&installed, key);
+        if !present {
+            missing += 1;
+        }
+        let (mark, action) = match (args.remove, present) {
+            (false, true) => ("=", "keep"),
+            (true, false) => ("=", "absent"),
+            (false, false) => ("+", "add"),
+            (true, true) => ("-", "remove"),
+        };
+        let fingerprint = key.decode()?.fingerprint_sha256();
+        let _ = writeln!(
+            out,
+            "{} {} {}{}",
+            mark,
+            fingerprint,
+            key.algorithm,
+            key.comment.as_deref().map(|c| format!(" {}", c)).unwrap_or_default()
+        );
+        let options = match action {
+            "add" => Some(args.key_option.join(",")),
+            _ => parsed
+                .entries
+                .iter()
+                .find(|entry| keyblob::same(&entry.key, key))
+                .map(|entry| authorized_keys::options_text(&entry.options)),
+        };
+        findings.changes.push(reporter::Change {
+            action,
+            fingerprint,
+            algorithm: key.algorithm.clone(),
+            comment: key.comment.clone(),
+            options: options.filter(|options| !options.is_empty()),
+        });
+    }
+    if args.plan_file.is_some() {
+        findings.plan = Some(plan::HostPlan {
+            label: label.to_string(),
+            destination: destination.to_string(),
+            state: plan::state(&installed)?,
+            keys: plan::changes(
+                if args.remove { plan::Action::Remove } else { plan::Action::Add },
+                &installed,
+                keys,
+            ),
+        });
+    }
+    let _ = match args.remove {
+        true => writeln!(
+            out,
+            "Dry run: {} of {} key(s) would be removed from {}; nothing was changed",
+            keys.len() - missing,
+            keys.len(),
+            account(args, destination)
+        ),
+        false => writeln!(
+            out,
+            "Dry run: {} of {} key(s) would be added to {}; nothing was changed",
+            missing,
+            keys.len(),
+            account(args, destination)
+        ),
+    };
+    Ok(())
+}
+
+/// The error of an install or removal with --authorized-keys-path on an
+/// account that only allows SFTP, where the path's tokens can't be expanded.
+fn sftp_only_key_path() -> anyhow::Error {
+    anyhow::anyhow!("The account only allows SFTP, and --authorized-keys-path needs a shell on the remote")
+}
+
+/// The login keys in the authorized_keys of `destination`, read over ssh or
+/// with -s over SFTP; lines sshd would skip are warned about.
+fn read_installed(
+    args: &Args,
+    destination: &Destination,
+    ssh: &mut connection::Ssh,
+    timeout: Option<Duration>,
+) -> Result<authorized_keys::Parsed> {
+    let parsed = if args.sftp {
+        authorized_keys::parse(&sftp::read(ssh, args.remote_home.as_deref(), timeout)?)
+    } else {
+        status::read_installed(
+            ssh,
+            args.remote_profile,
+            args.remote_home.as_deref(),
+            args.authorized_keys_path.as_deref(),
+            args.run_as().as_ref(),
+            timeout,
+        )?
+    };
+    for (number, reason) in &parsed.invalid {
+        eprintln!("Warning: sshd skips line {} of authorized_keys on {}: {}", number, destination, reason);
+    }
+    Ok(parsed)
+}
+
+/// Runs the ssh command with `remote_command`, feeding it `input` on stdin.
+/// Returns `None` if it had to be killed after `timeout`.
+fn run_ssh(
+    args: &Args,
+    ssh: &mut connection::Ssh,
+    remote_command: &str,
+    input: &[u8],
+    timeout: Option<Duration>,
+) -> Result<(Option<ExitStatus>, String)> {
+    let remote_command = remote_script::login_command(args.remote_profile, remote_command, args.run_as().as_ref());
+    crate::run_remote(ssh, &remote_command, input, timeout, remote_stdout(args))
+}
+
+/// Where the remote's output goes: stderr, to keep stdout clean for
+/// machine-readable reports.
+fn remote_stdout(args: &Args) -> Box<dyn Write + Send> {
+    if args.porcelain.is_some() || args.output.is_machine_readable() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+/// Tells where `--backup` copied authorized_keys, if it made a copy.
+fn report_backup(path: Option<&str>, reporter: &mut dyn Reporter) {
+    if let Some(path) = path {
+        reporter.info(&format!("Backed up authorized_keys to {}", path));
+    }
+}
+
+/// Reports each of `keys` as added, if it is in one of the `added` lines,
+/// or as already installed. With `skipped`, the lines the remote reported
+/// as already there, a key in neither is reported as unconfirmed instead,
+/// and counted as neither.
+fn report_added(
+    keys: &[key::PublicKey],
+    added: &[impl AsRef<str>],
+    skipped: Option<&[&str]>,
+    reporter: &mut dyn Reporter,
+) -> KeyOutcome {
+    let added = authorized_keys::keys_in(added);
+    let skipped = skipped.map(authorized_keys::keys_in);
+    let mut outcome = KeyOutcome::default();
+    for key in keys {
+        let fingerprint = fingerprint(key);
+        let (label, list) = if keyblob::contains(&added, key) {
+            ("Added:", Some(&mut outcome.added))
+        } else if skipped.as_ref().is_none_or(|skipped| keyblob::contains(skipped, key)) {
+            ("Already installed:", Some(&mut outcome.skipped))
+        } else {
+            ("Not confirmed by the remote:", None)
+        };
+        reporter.info(&format!(
+            "{} {} {}{}",
+            label,
+            fingerprint,
+            key.algorithm,
+            key.comment.as_deref().map(|c| format!(" {}", c)).unwrap_or_default()
+        ));
+        if let Some(list) = list {
+            list.push(fingerprint);
+        }
+    }
+    outcome
+}
+
+/// Reports each of `keys` as removed, if it is in one of the `removed`
+/// lines, or as not installed.
+fn report_removed(keys: &[key::PublicKey], removed: &[impl AsRef<str>], reporter: &mut dyn Reporter) -> KeyOutcome {
+    let removed = authorized_keys::keys_in(removed);
+    let mut outcome = KeyOutcome::default();
+    for key in keys {
+        let was_removed = keyblob::contains(&removed, key);
+        let fingerprint = fingerprint(key);
+        reporter.info(&format!(
+            "{} {} {}{}",
+            if was_removed { "Removed:" } else { "Not installed:" },
+            fingerprint,
+            key.algorithm,
+            key.comment.as_deref().map(|c| format!(" {}", c)).unwrap_or_default()
+        ));
+        if was_removed {
+            outcome.removed.push(fingerprint);
+        } else {
+            outcome.skipped.push(fingerprint);
+        }
+    }
+    outcome
+}
+
+/// The SHA256 fingerprint of `key`, which has been validated already.
+fn fingerprint(key: &key::PublicKey) -> String {
+    key.decode().map(|decoded| decoded.fingerprint_sha256()).unwrap_or_default()
+}
+
+/// Warns about the certificates the remote will reject because of its
+/// clock, as reported by the install script.
+fn check_clock(certificates: &[cert::Certificate], stdout: &str) {
+    let Some(remote_now) = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(remote_script::CLOCK))
+        .and_then(|time| time.trim().parse::<u64>().ok())
+    else {
+        return;
+    };
+    let local_now = timeutil::now_unix();
+    for certificate in certificates {
+        if let Some(problem) = certificate.clock_problem(remote_now, local_now) {
+            eprintln!("Warning: {}; check its time synchronization (NTP).", problem);
+        }
+    }
+}
+
+/// SSHFP records for the destination's host keys. Failures are only
+/// warnings, since the keys are installed by now.
+fn emit_sshfp(destination: &Destination, port: Option<&str>) -> Vec<String> {
+    if !destination.via.is_empty() {
+        eprintln!("Warning: --emit-sshfp cannot scan host keys through jump hosts; skipped.");
+        return Vec::new();
+    }
+    if destination.host.parse::<std::net::IpAddr>().is_ok() {
+        eprintln!(
+            "Warning: SSHFP records are looked up by host name, but {} is an IP address.",
+            destination.host
+        );
+    }
+    match hostkey::scan(&destination.host, port).and_then(|keys| sshfp::records(&destination.host, &keys)) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Warning: could not generate SSHFP records: {:#}", e);
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,323 @@
+//! How ssh reaches a destination: port, jump hosts, proxies, host key
+//! verification and agent forwarding. Shared by installs and `exec`.
+
+use crate::destination::Destination;
+use crate::digest;
+use crate::fsutil::TempFile;
+use crate::hostkey;
+use crate::jump::JumpArgs;
+use crate::key::PublicKey;
+use crate::password::PasswordArgs;
+use crate::proxy;
+use crate::ssh_config;
+use crate::sshfp;
+use anyhow::{Result, bail};
+use std::ffi::OsStr;
+use std::process::Command;
+use std::time::Duration;
+
+/// Connection options shared by the commands that log in to a host.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ConnectionArgs {
+    /// Port to connect to on the remote host, as a number or a service name from /etc/services
+    #[arg(short = 'p', long, value_parser = crate::destination::parse_port_arg)]
+    pub port: Option<u16>,
+
+    #[command(flatten)]
+    pub jump: JumpArgs,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+
+    /// Forward the ssh agent to the remote for the session (off by default)
+    #[arg(short = 'A', long, overrides_with = "no_forward_agent")]
+    pub forward_agent: bool,
+
+    /// Never forward the ssh agent, whatever the ssh config says (the default)
+    #[arg(long, overrides_with = "forward_agent")]
+    pub no_forward_agent: bool,
+
+    /// Look up and record the host key under NAME (ssh's HostKeyAlias), e.g. when connecting through a forwarded port
+    #[arg(long, value_name = "NAME")]
+    pub hostkey_alias: Option<String>,
+
+    /// Connect through the SOCKS5 proxy at HOST:PORT; `.onion` hosts use Tor's 127.0.0.1:9050 by default
+    #[arg(long, value_name = "HOST:PORT")]
+    pub socks5: Option<String>,
+
+    /// Connect through the HTTP CONNECT proxy at [http://][USER:PASSWORD@]HOST[:PORT]
+    #[arg(long, value_name = "URL", conflicts_with = "socks5")]
+    pub https_proxy: Option<String>,
+
+    /// Verify the host key against the host's SSHFP DNS records before connecting
+    #[arg(long)]
+    pub verify_sshfp: bool,
+
+    /// Connect only if the host key has this fingerprint (SHA256:..., as `ssh-keygen -l` prints it), which is then the only key accepted; repeat to allow any of several
+    #[arg(long, value_name = "FINGERPRINT", conflicts_with = "verify_sshfp")]
+    pub expect_fingerprint: Vec<String>,
+
+    /// What ssh does with a host key it doesn't know: refuse it (yes), add it to known_hosts (accept-new), or add it even in place of a changed one (no)
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub strict_host_key_checking: Option<HostKeyPolicy>,
+
+    /// Give up on a host after SECONDS; `timeout=` in the hosts file overrides it per host
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+}
+
+/// ssh's StrictHostKeyChecking, for `--strict-host-key-checking`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum HostKeyPolicy {
+    Yes,
+    AcceptNew,
+    No,
+}
+
+impl HostKeyPolicy {
+    fn ssh_value(self) -> &'static str {
+        match self {
+            HostKeyPolicy::Yes => "yes",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::No => "no",
+        }
+    }
+}
+
+/// An ssh command for one destination. Further ssh options can be added to
+/// `command` before [`Ssh::with_remote`] appends the destination.
+pub struct Ssh {
+    pub command: Command,
+    /// The jump host, port and `[user@]host` arguments
+    target: Vec<String>,
+    /// Whether the connection goes through a proxy
+    pub proxied: bool,
+    /// Host keys pinned by --verify-sshfp, kept until ssh has finished
+    _pinned: Option<TempFile>,
+    /// Where ssh notes the host key it is shown
+    observer: Option<TempFile>,
+    /// The SSH_ASKPASS helper answering password prompts
+    _askpass: Option<TempFile>,
+}
+
+impl Ssh {
+    /// Appends the destination and the words of the remote command.
+    pub fn with_remote<I, S>(&mut self, remote: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(&self.target).args(remote)
+    }
+
+    /// The `[user@]host` ssh connects to.
+    pub fn destination(&self) -> &str {
+        self.target.last().map_or("", String::as_str)
+    }
+
+    /// Has ssh note the host key it is shown, for [`Ssh::host_key`].
+    pub fn observe_host_key(&mut self, destination: &Destination) {
+        if let Some((file, options)) = hostkey::observer(destination) {
+            self.command.args(options);
+            self.observer = Some(file);
+        }
+    }
+
+    /// The type and fingerprint of the host key ssh was shown, once it has
+    /// connected.
+    pub fn host_key(&self) -> Option<String> {
+        self.observer.as_ref().and_then(hostkey::observed)
+    }
+
+    /// An `sftp` command for the same connection, reading its batch from
+    /// stdin. sftp takes ssh's options, except that the port is `-P`.
+    pub fn sftp(&self) -> Command {
+        let mut sftp = Command::new("sftp");
+        // ssh uses the first value given, so this keeps password prompts
+        // despite the BatchMode=yes that -b adds
+        sftp.args(["-o", "BatchMode=no", "-b", "-"]);
+        let (host, options) = self.target.split_last().expect("target ends with the host");
+        for arg in self.command.get_args().chain(options.iter().map(OsStr::new)) {
+            sftp.arg(if arg == "-p" { OsStr::new("-P") } else { arg });
+        }
+        // sftp would take the colons of an IPv6 address for a remote path
+        match host.rsplit_once('@') {
+            Some((user, address)) if address.contains(':') => sftp.arg(format!("{}@[{}]", user, address)),
+            None if host.contains(':') => sftp.arg(format!("[{}]", host)),
+            _ => sftp.arg(host),
+        };
+        for (name, value) in self.command.get_envs() {
+            match value {
+                Some(value) => sftp.env(name, value),
+                None => sftp.env_remove(name),
+            };
+        }
+        sftp
+    }
+}
+
+impl ConnectionArgs {
+    /// Whether the command line selects a proxy for every host.
+    pub fn proxied(&self) -> bool {
+        self.socks5.is_some() || self.https_proxy.is_some()
+    }
+
+    /// The port given with `-p` or in the destination.
+    pub fn port(&self, destination: &Destination) -> Result<Option<String>> {
+        if self.port.is_some() && destination.port.is_some() {
+            bail!("The port is given both with -p and in the destination {}", destination);
+        }
+        Ok(self.port.or(destination.port).map(|p| p.to_string()))
+    }
+
+    /// `user@host:port` for the destination, and a short hash of it to name
+    /// local files after.
+    pub fn destination_id(&self, destination: &Destination) -> Result<(String, String)> {
+        let user = destination.user.clone().unwrap_or_else(ssh_config::local_user);
+        let port = self.port(destination)?.unwrap_or_else(|| "22".to_string());
+        let key = format!("{}@{}:{}", user, destination.host.to_lowercase(), port);
+        let name: String = digest::sha256(key.as_bytes())[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Ok((key, name))
+    }
+
+    /// Builds the ssh command for `destination`, verifying its host key
+    /// first with --verify-sshfp or --expect-fingerprint. `info` receives
+    /// progress messages.
+    pub fn ssh(&self, destination: &Destination, timeout: Option<Duration>, info: &mut dyn FnMut(&str)) -> Result<Ssh> {
+        let port = self.port(destination)?;
+
+        let proxy = match (&self.socks5, &self.https_proxy) {
+            (Some(address), _) => Some(proxy::Proxy::new(proxy::Kind::Socks5, address)?),
+            (None, Some(url)) => Some(proxy::Proxy::new(proxy::Kind::Http, url)?),
+            (None, None) if proxy::is_onion(&destination.host) => {
+                info(&format!("Connecting to the onion service through Tor at {}", proxy::TOR_SOCKS));
+                Some(proxy::Proxy::new(proxy::Kind::Socks5, proxy::TOR_SOCKS)?)
+            }
+            (None, None) => None,
+        };
+        if proxy.is_some() {
+            if !destination.via.is_empty() {
+                bail!("A proxy cannot be combined with jump hosts.");
+            }
+            if self.verify_sshfp {
+                bail!("--verify-sshfp contacts the host directly and cannot be used through a proxy.");
+            }
+            if !self.expect_fingerprint.is_empty() {
+                bail!("--expect-fingerprint contacts the host directly and cannot be used through a proxy.");
+            }
+        }
+
+        let mut pinned = None;
+        let mut command = Command::new("ssh");
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        if self.verify_sshfp {
+            if !destination.via.is_empty() {
+                bail!("--verify-sshfp cannot check host keys through jump hosts.");
+            }
+            let keys = sshfp::verify(&destination.host, port.as_deref())?;
+            info(&format!("Host key verified against SSHFP records for {}", destination.host));
+            pinned = Some(self.pin(&mut command, destination, port.as_deref(), &keys)?);
+        }
+        if !self.expect_fingerprint.is_empty() {
+            if !destination.via.is_empty() {
+                bail!("--expect-fingerprint cannot check host keys through jump hosts.");
+            }
+            let keys = hostkey::expect(&destination.host, port.as_deref(), &self.expect_fingerprint)?;
+            info(&format!(
+                "Host key {} of {} has the expected fingerprint",
+                keys[0].decode()?.fingerprint_sha256(),
+                destination.host
+            ));
+            pinned = Some(self.pin(&mut command, destination, port.as_deref(), &keys)?);
+        }
+        if let Some(policy) = self.strict_host_key_checking {
+            // After a pin's StrictHostKeyChecking=yes, which ssh keeps as the first one given
+            command.arg("-o").arg(format!("StrictHostKeyChecking={}", policy.ssh_value()));
+        }
+        command.args(self.hostkey_alias_args()?);
+
+        command.args(self.forward_agent_args());
+        command.args(ssh_config::ssh_args());
+        command.args(ssh_dir_args());
+        if let Some(proxy) = &proxy {
+            command.args(proxy.ssh_args()?);
+            if let Some((name, value)) = proxy.env() {
+                command.env(name, value);
+            }
+        }
+        if let Some(timeout) = timeout {
+            command.arg("-o").arg(format!("ConnectTimeout={}", timeout.as_secs().max(1)));
+        }
+        let (label, _) = self.destination_id(destination)?;
+        let askpass = self.password.apply(&mut command, &label)?;
+        Ok(Ssh {
+            command,
+            target: self.jump.ssh_args(destination)?,
+            proxied: proxy.is_some(),
+            _pinned: pinned,
+            observer: None,
+            _askpass: askpass,
+        })
+    }
+
+    /// Has `command` accept only `keys` as the host key of `destination`;
+    /// the returned file holds them for as long as the command runs.
+    fn pin(
+        &self,
+        command: &mut Command,
+        destination: &Destination,
+        port: Option<&str>,
+        keys: &[PublicKey],
+    ) -> Result<TempFile> {
+        // ssh looks an alias up on its own, without the port
+        let (file, options) = match &self.hostkey_alias {
+            Some(alias) => hostkey::pin(alias, None, keys)?,
+            None => hostkey::pin(&destination.host.to_lowercase(), port, keys)?,
+        };
+        command.args(options);
+        Ok(file)
+    }
+
+    /// The ssh arguments to suggest for logging in by hand afterwards.
+    pub fn login_args(&self, destination: &Destination) -> Result<Vec<String>> {
+        let mut args = self.hostkey_alias_args()?;
+        // Only when asked for; the suggested login is the user's own session
+        if self.forward_agent {
+            args.push("-A".to_string());
+        }
+        args.extend(self.jump.ssh_args(destination)?);
+        Ok(args)
+    }
+
+    /// The ssh options for `--hostkey-alias`, if given.
+    fn hostkey_alias_args(&self) -> Result<Vec<String>> {
+        match &self.hostkey_alias {
+            Some(alias) if alias.is_empty() || alias.contains(char::is_whitespace) => {
+                bail!("Invalid host key alias {:?}", alias)
+            }
+            Some(alias) => Ok(vec!["-o".to_string(), format!("HostKeyAlias={}", alias)]),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The ssh options for -A; agent forwarding is turned off explicitly
+    /// otherwise, so a ForwardAgent in the ssh config doesn't apply.
+    fn forward_agent_args(&self) -> Vec<String> {
+        let value = if self.forward_agent { "yes" } else { "no" };
+        vec!["-o".to_string(), format!("ForwardAgent={}", value)]
+    }
+}
+
+/// The ssh options for `--ssh-dir`'s known_hosts. Pinned host keys come
+/// first, so they still take precedence.
+fn ssh_dir_args() -> Vec<String> {
+    match crate::paths::ssh_dir_override() {
+        Some(dir) => vec![
+            "-o".to_string(),
+            format!("UserKnownHostsFile={}", dir.join("known_hosts").display()),
+        ],
+        None => Vec::new(),
+    }
+}
@@ -0,0 +1,78 @@
+//! Target discovery from the Consul catalog (`--consul-service`): the
+//! nodes a service is registered on.
+
+use crate::http;
+use crate::json::Value;
+use anyhow::{Context, Result, bail};
+
+/// Where the agent listens unless `--consul-url` or $CONSUL_HTTP_ADDR say otherwise.
+const DEFAULT_ADDRESS: &str = "http://127.0.0.1:8500";
+
+#[derive(Debug, Default)]
+pub struct Query {
+    pub service: String,
+    /// Agent address; the Consul CLI's $CONSUL_HTTP_ADDR by default
+    pub url: Option<String>,
+    /// Consul's $CONSUL_HTTP_TOKEN is used if not given
+    pub token: Option<String>,
+    pub tag: Option<String>,
+    pub datacenter: Option<String>,
+}
+
+/// The addresses of the nodes providing the service.
+pub fn discover(query: &Query) -> Result<Vec<String>> {
+    let base = match &query.url {
+        Some(url) => url.clone(),
+        None => std::env::var("CONSUL_HTTP_ADDR").unwrap_or_else(|_| DEFAULT_ADDRESS.to_string()),
+    };
+    // CONSUL_HTTP_ADDR may be given without a scheme
+    let base = if base.contains("://") {
+        base
+    } else {
+        format!("http://{}", base)
+    };
+
+    let mut url = format!(
+        "{}/v1/catalog/service/{}",
+        base.trim_end_matches('/'),
+        http::encode(&query.service)
+    );
+    let mut filters = Vec::new();
+    if let Some(tag) = &query.tag {
+        filters.push(format!("tag={}", http::encode(tag)));
+    }
+    if let Some(datacenter) = &query.datacenter {
+        filters.push(format!("dc={}", http::encode(datacenter)));
+    }
+    if !filters.is_empty() {
+        url = format!("{}?{}", url, filters.join("&"));
+    }
+
+    let token = query
+        .token
+        .clone()
+        .or_else(|| std::env::var("CONSUL_HTTP_TOKEN").ok());
+    let headers: Vec<String> = token
+        .map(|token| format!("X-Consul-Token: {}", token))
+        .into_iter()
+        .collect();
+
+    let response = http::get_json(&url, &headers)?;
+    let entries = response
+        .as_array()
+        .with_context(|| format!("Unexpected Consul response from {}", url))?;
+    let addresses: Vec<String> = entries.iter().filter_map(address).collect();
+    if addresses.is_empty() {
+        bail!("No nodes provide the Consul service {:?}", query.service);
+    }
+    Ok(addresses)
+}
+
+/// The service's own address if it registered one, else its node's.
+fn address(entry: &Value) -> Option<String> {
+    ["ServiceAddress", "Address"]
+        .iter()
+        .filter_map(|field| entry.get(field)?.as_str())
+        .find(|address| !address.is_empty())
+        .map(str::to_string)
+}
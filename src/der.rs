@@ -0,0 +1,79 @@
+//! Just enough DER to read and write SubjectPublicKeyInfo and PKCS#1 keys.
+
+use anyhow::{Context, Result, bail};
+
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const NULL: u8 = 0x05;
+pub const OID: u8 = 0x06;
+pub const SEQUENCE: u8 = 0x30;
+
+pub fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&b| b == 0)
+            .collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// A BIT STRING with no unused bits.
+pub fn bit_string(content: &[u8]) -> Vec<u8> {
+    let mut inner = vec![0];
+    inner.extend_from_slice(content);
+    tlv(BIT_STRING, &inner)
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    /// Reads one element, which must have the given tag, and returns its content.
+    pub fn read(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let (&actual, rest) = self.data.split_first().context("truncated DER data")?;
+        if actual != tag {
+            bail!(
+                "unexpected DER tag 0x{:02x} (expected 0x{:02x})",
+                actual,
+                tag
+            );
+        }
+        let (&first, rest) = rest.split_first().context("truncated DER data")?;
+        let (len, rest) = if first < 0x80 {
+            (first as usize, rest)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 {
+                bail!("unsupported DER length");
+            }
+            let bytes = rest.get(..count).context("truncated DER data")?;
+            let len = bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            (len, &rest[count..])
+        };
+        let content = rest.get(..len).context("truncated DER data")?;
+        self.data = &rest[len..];
+        Ok(content)
+    }
+
+    /// Reads a BIT STRING without unused bits.
+    pub fn bit_string(&mut self) -> Result<&'a [u8]> {
+        match self.read(BIT_STRING)?.split_first() {
+            Some((0, content)) => Ok(content),
+            _ => bail!("unsupported BIT STRING"),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! The remote destination and its textual forms.
+//!
+//! Accepted forms, matching what ssh itself understands plus a `:port` suffix:
+//! `host`, `user@host`, `user@host:port`, `[v6addr]:port`, bare IPv6
+//! addresses, and `ssh://[user@]host[:port]` URIs.
+
+use anyhow::{Error, Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// A validated remote endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Destination {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Jump hosts to connect through, outermost first
+    pub via: Vec<Destination>,
+}
+
+impl Destination {
+    /// The `[user@]host` argument for ssh; the port is passed separately.
+    pub fn ssh_target(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Arguments selecting this destination on an ssh command line: port
+    /// and jump hosts, followed by the `[user@]host` target itself.
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(port) = self.port {
+            args.extend(["-p".to_string(), port.to_string()]);
+        }
+        if !self.via.is_empty() {
+            let jumps: Vec<String> = self.via.iter().map(Destination::to_string).collect();
+            args.extend(["-J".to_string(), jumps.join(",")]);
+        }
+        args.push(self.ssh_target());
+        args
+    }
+}
+
+/// Formats as `[user@]host[:port]`, bracketing IPv6 addresses when a port
+/// follows. Jump hosts are not part of the textual form.
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        match self.port {
+            Some(port) if self.host.contains(':') => write!(f, "[{}]:{}", self.host, port),
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => f.write_str(&self.host),
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Destination> {
+        let rest = match input.strip_prefix("ssh://") {
+            Some(uri) => uri.strip_suffix('/').unwrap_or(uri),
+            None => input,
+        };
+
+        // The user name may itself contain '@' (e.g. user@domain@host)
+        let (user, host_port) = match rest.rsplit_once('@') {
+            Some((user, host_port)) => {
+                if user.is_empty() {
+                    bail!("Empty user name in destination {:?}", input);
+                }
+                (Some(user), host_port)
+            }
+            None => (None, rest),
+        };
+
+        let (host, port) = split_port(host_port)
+            .ok_or_else(|| anyhow::anyhow!("Invalid port in destination {:?}", input))?;
+
+        if host.is_empty() {
+            bail!("Missing host name in destination {:?}", input);
+        }
+        // A leading '-' would be taken by ssh as an option
+        if host.starts_with('-') || user.is_some_and(|u| u.starts_with('-')) {
+            bail!("Destination must not start with '-': {:?}", input);
+        }
+        if let Some(c) = host_port
+            .chars()
+            .chain(user.unwrap_or_default().chars())
+            .find(|c| c.is_whitespace() || c.is_control() || *c == '/' || *c == ',')
+        {
+            bail!("Invalid character {:?} in destination {:?}", c, input);
+        }
+
+        Ok(Destination {
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+            via: Vec::new(),
+        })
+    }
+}
+
+/// Splits `host[:port]`, `[v6]:port` or a bare IPv6 address.
+/// Returns `None` when a port is present but not a valid number.
+fn split_port(s: &str) -> Option<(&str, Option<u16>)> {
+    if let Some(inner) = s.strip_prefix('[') {
+        let (host, after) = inner.split_once(']')?;
+        if !host.contains(':') {
+            return None;
+        }
+        return match after {
+            "" => Some((host, None)),
+            _ => Some((host, Some(parse_port(after.strip_prefix(':')?)?))),
+        };
+    }
+
+    match s.split_once(':') {
+        // More than one colon: an unbracketed IPv6 address without a port
+        Some((_, rest)) if rest.contains(':') => Some((s, None)),
+        Some((host, port)) => Some((host, Some(parse_port(port)?))),
+        None => Some((s, None)),
+    }
+}
+
+fn parse_port(s: &str) -> Option<u16> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok().filter(|&port| port != 0)
+}
+
+/// Parses a `-p` argument: a port number, or a service name looked up in
+/// the system's services database, as ssh does.
+pub fn parse_port_arg(s: &str) -> Result<u16, String> {
+    if let Some(port) = parse_port(s) {
+        return Ok(port);
+    }
+    if s.is_empty() || s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("{:?} is not a port number between 1 and 65535", s));
+    }
+    let services = services_file();
+    let content = std::fs::read_to_string(&services).unwrap_or_default();
+    service_port(&content, s)
+        .ok_or_else(|| format!("{:?} is neither a port number nor a TCP service in {}", s, services.display()))
+}
+
+/// The services(5) database.
+fn services_file() -> std::path::PathBuf {
+    if cfg!(windows) {
+        let root = std::env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+        std::path::Path::new(&root).join("System32\\drivers\\etc\\services")
+    } else {
+        std::path::PathBuf::from("/etc/services")
+    }
+}
+
+/// The TCP port of the service named `name` (or with it as an alias) in
+/// services(5) content.
+fn service_port(content: &str, name: &str) -> Option<u16> {
+    content.lines().find_map(|line| {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let service = fields.next()?;
+        let (port, protocol) = fields.next()?.split_once('/')?;
+        let names_it = service == name || fields.any(|alias| alias == name);
+        (names_it && protocol == "tcp").then(|| parse_port(port)).flatten()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::check;
+
+    #[test]
+    fn port_arguments() {
+        let services = "# comment\nssh\t\t22/tcp\nsyslog\t514/udp\nhttp-alt 8080/tcp webcache # WWW caching\n";
+        assert_eq!(service_port(services, "ssh"), Some(22));
+        assert_eq!(service_port(services, "webcache"), Some(8080));
+        assert_eq!(service_port(services, "syslog"), None);
+        assert_eq!(service_port(services, "WWW"), None);
+        assert_eq!(parse_port_arg("2222"), Ok(2222));
+        assert!(parse_port_arg("0").is_err());
+        assert!(parse_port_arg("65536").is_err());
+        assert!(parse_port_arg("").is_err());
+        assert!(parse_port_arg("no-such-service-anywhere").is_err());
+    }
+
+    fn dest(user: Option<&str>, host: &str, port: Option<u16>) -> Destination {
+        Destination {
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+            via: Vec::new(),
+        }
+    }
+
+    fn parse(input: &str) -> Result<Destination> {
+        input.parse()
+    }
+
+    #[test]
+    fn parses_common_forms() {
+        assert_eq!(parse("host").unwrap(), dest(None, "host", None));
+        assert_eq!(
+            parse("alice@host").unwrap(),
+            dest(Some("alice"), "host", None)
+        );
+        assert_eq!(
+            parse("alice@host:2222").unwrap(),
+            dest(Some("alice"), "host", Some(2222))
+        );
+        assert_eq!(
+            parse("a@corp@host").unwrap(),
+            dest(Some("a@corp"), "host", None)
+        );
+        assert_eq!(parse("::1").unwrap(), dest(None, "::1", None));
+        assert_eq!(
+            parse("bob@[fe80::1]:22").unwrap(),
+            dest(Some("bob"), "fe80::1", Some(22))
+        );
+        assert_eq!(
+            parse("ssh://bob@[2001:db8::2]:2202/").unwrap(),
+            dest(Some("bob"), "2001:db8::2", Some(2202))
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for input in [
+            "host",
+            "alice@host:2222",
+            "bob@[fe80::1]:22",
+            "::1",
+            "a@corp@host",
+        ] {
+            assert_eq!(parse(input).unwrap().to_string(), input);
+        }
+    }
+
+    #[test]
+    fn ssh_args_include_jump_hosts() {
+        let mut target = parse("alice@target:2222").unwrap();
+        target.via = vec![parse("jump@bastion").unwrap(), parse("[::1]:2200").unwrap()];
+        assert_eq!(
+            target.ssh_args(),
+            [
+                "-p",
+                "2222",
+                "-J",
+                "jump@bastion,[::1]:2200",
+                "alice@target"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_forms() {
+        for input in [
+            "",
+            "@host",
+            "user@",
+            "host:",
+            "host:0",
+            "host:65536",
+            "host:2x",
+            "-oProxyCommand=x",
+            "[host]:22",
+            "[::1",
+            "a b",
+            "user@-host",
+        ] {
+            assert!(parse(input).is_err(), "accepted {:?}", input);
+        }
+    }
+
+    #[test]
+    fn arbitrary_input_is_safe_for_ssh_argv() {
+        let users = ["", "root", "a'b", "é"];
+        let hosts = [
+            "h",
+            "10.0.0.1",
+            "::1",
+            "[::1]",
+            "[fe80::1%eth0]",
+            "-x",
+            "a b",
+        ];
+        check(4, |g, case| {
+            let input = match g.below(2) {
+                0 => g.tricky_string(40),
+                _ => format!(
+                    "{}{}@{}{}{}",
+                    if g.below(4) == 0 { "ssh://" } else { "" },
+                    g.pick(&users),
+                    g.pick(&hosts),
+                    g.tricky_string(3),
+                    if g.below(2) == 0 {
+                        format!(":{}", g.next_u64() % 70000)
+                    } else {
+                        String::new()
+                    },
+                ),
+            };
+            let Ok(parsed) = parse(&input) else { return };
+            let target = parsed.ssh_target();
+            assert!(!parsed.host.is_empty(), "case {}: {:?}", case, input);
+            assert!(!target.starts_with('-'), "case {}: {:?}", case, input);
+            assert!(
+                !target.chars().any(|c| c.is_whitespace() || c.is_control()),
+                "case {}: {:?} gave {:?}",
+                case,
+                input,
+                target
+            );
+            assert_ne!(parsed.port, Some(0));
+            assert_eq!(
+                parse(&parsed.to_string()).unwrap(),
+                parsed,
+                "case {}: {:?}",
+                case,
+                input
+            );
+        });
+    }
+}
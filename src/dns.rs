@@ -0,0 +1,218 @@
+//! A minimal DNS stub resolver, just enough to fetch SSHFP records and
+//! learn whether the (validating) resolver authenticated them with DNSSEC.
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+pub const TYPE_SSHFP: u16 = 44;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Flags in the second header word
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+const FLAG_AD: u16 = 0x0020;
+
+pub struct Answer {
+    /// Whether the resolver set the AD bit, i.e. validated the answer with DNSSEC
+    pub authenticated: bool,
+    /// RDATA of every answer record of the requested type
+    pub records: Vec<Vec<u8>>,
+}
+
+/// Queries the nameservers from /etc/resolv.conf in turn.
+pub fn query(name: &str, qtype: u16) -> Result<Answer> {
+    let id = (std::process::id() as u16)
+        ^ std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u16)
+            .unwrap_or_default();
+    let request = build_query(id, name, qtype)?;
+
+    let mut last_error = None;
+    for server in nameservers() {
+        match exchange(server, &request).and_then(|response| parse_response(id, qtype, &response)) {
+            Ok(answer) => return Ok(answer),
+            Err(e) => last_error = Some(e.context(format!("nameserver {}", server))),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no nameservers configured")))
+        .with_context(|| format!("DNS lookup of {} failed", name))
+}
+
+fn nameservers() -> Vec<IpAddr> {
+    let servers: Vec<IpAddr> = fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        // Drop any IPv6 zone index such as %eth0
+        .filter_map(|addr| addr.trim().split('%').next()?.parse().ok())
+        .collect();
+    if servers.is_empty() {
+        vec![IpAddr::from([127, 0, 0, 1])]
+    } else {
+        servers
+    }
+}
+
+fn exchange(server: IpAddr, request: &[u8]) -> Result<Vec<u8>> {
+    let address = SocketAddr::new(server, 53);
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.send_to(request, address)?;
+    let mut buf = vec![0u8; 4096];
+    let len = socket.recv(&mut buf).context("no response")?;
+    buf.truncate(len);
+
+    let flags = u16::from_be_bytes([*buf.get(2).unwrap_or(&0), *buf.get(3).unwrap_or(&0)]);
+    if flags & FLAG_TC == 0 {
+        return Ok(buf);
+    }
+
+    // Truncated: retry over TCP, where messages carry a length prefix
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(&(request.len() as u16).to_be_bytes())?;
+    stream.write_all(request)?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend(id.to_be_bytes());
+    // Ask for recursion and for the AD bit in the answer (RFC 6840 5.7)
+    out.extend((FLAG_RD | FLAG_AD).to_be_bytes());
+    // One question and one additional record (the OPT record)
+    out.extend([0, 1, 0, 0, 0, 0, 0, 1]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid DNS name {:?}", name);
+        }
+        out.push(label.len() as u8);
+        out.extend(label.as_bytes());
+    }
+    out.push(0);
+    out.extend(qtype.to_be_bytes());
+    out.extend(CLASS_IN.to_be_bytes());
+
+    // EDNS0 OPT: root name, 1232 byte payload, DO bit set so DNSSEC is used
+    out.push(0);
+    out.extend(TYPE_OPT.to_be_bytes());
+    out.extend(1232u16.to_be_bytes());
+    out.extend([0, 0, 0x80, 0, 0, 0]);
+    Ok(out)
+}
+
+fn parse_response(id: u16, qtype: u16, message: &[u8]) -> Result<Answer> {
+    let mut reader = Cursor { data: message };
+    let header = reader.fixed(12).context("truncated DNS response")?;
+    let word = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+    if word(0) != id || word(2) & FLAG_QR == 0 {
+        bail!("unexpected DNS response");
+    }
+    let flags = word(2);
+    match flags & 0x000f {
+        0 => {}
+        // NXDOMAIN: no records, but still possibly authenticated denial
+        3 => {
+            return Ok(Answer {
+                authenticated: flags & FLAG_AD != 0,
+                records: Vec::new(),
+            });
+        }
+        rcode => bail!("DNS error (rcode {})", rcode),
+    }
+
+    for _ in 0..word(4) {
+        skip_name(&mut reader)?;
+        reader.fixed(4).context("truncated DNS question")?;
+    }
+    let mut records = Vec::new();
+    for _ in 0..word(6) {
+        skip_name(&mut reader)?;
+        let fixed = reader.fixed(10).context("truncated DNS record")?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata = reader.fixed(rdlength).context("truncated DNS record")?;
+        if rtype == qtype {
+            records.push(rdata.to_vec());
+        }
+    }
+    Ok(Answer {
+        authenticated: flags & FLAG_AD != 0,
+        records,
+    })
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn fixed(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(..len)?;
+        self.data = &self.data[len..];
+        Some(bytes)
+    }
+}
+
+/// Skips a possibly compressed domain name.
+fn skip_name(reader: &mut Cursor) -> Result<()> {
+    loop {
+        let len = reader.fixed(1).context("truncated DNS name")?[0];
+        match len {
+            0 => return Ok(()),
+            l if l & 0xc0 == 0xc0 => {
+                // A compression pointer ends the name
+                reader.fixed(1).context("truncated DNS name")?;
+                return Ok(());
+            }
+            l => {
+                reader.fixed(l as usize).context("truncated DNS name")?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_authenticated_sshfp_answer() {
+        let query = build_query(0x1234, "host.example.com", TYPE_SSHFP).unwrap();
+        // The question section of the query, as echoed back by the server
+        let question = &query[12..query.len() - 11];
+
+        let mut response = vec![0x12, 0x34, 0x81, 0xa0, 0, 1, 0, 2, 0, 0, 0, 0];
+        response.extend(question);
+        for fptype in [1u8, 2] {
+            // Owner name compressed to the question name at offset 12
+            response.extend([
+                0xc0, 12, 0, 44, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 4, fptype, 0xab, 0xcd,
+            ]);
+        }
+
+        let answer = parse_response(0x1234, TYPE_SSHFP, &response).unwrap();
+        assert!(answer.authenticated);
+        assert_eq!(
+            answer.records,
+            [vec![4, 1, 0xab, 0xcd], vec![4, 2, 0xab, 0xcd]]
+        );
+        assert!(parse_response(0x4321, TYPE_SSHFP, &response).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! A C ABI over [`crate::copy_id_with_progress`], with the `ffi` feature,
+//! for provisioning agents and GUIs that aren't written in Rust. Build the
+//! shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`; the
+//! declarations are in `include/ssh_copy_id_rs.h`.
+
+use crate::{
+    Destination, Identity, Options, Profile, ProgressObserver, Report, copy_id_with_progress,
+};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::panic::AssertUnwindSafe;
+
+/// The keys are installed.
+pub const SCI_OK: c_int = 0;
+/// An argument was missing or invalid; nothing was attempted.
+pub const SCI_ERR_INVALID: c_int = -1;
+/// The install failed.
+pub const SCI_ERR_FAILED: c_int = -2;
+
+/// ssh is being started; the message is the destination.
+pub const SCI_EVENT_CONNECTING: c_int = 1;
+/// The login worked and the install command is running.
+pub const SCI_EVENT_AUTHENTICATED: c_int = 2;
+/// A key line that was appended.
+pub const SCI_EVENT_KEY_ADDED: c_int = 3;
+/// A key line that was installed already.
+pub const SCI_EVENT_KEY_ALREADY_INSTALLED: c_int = 4;
+/// Done; the message is the host key ssh was shown, or empty.
+pub const SCI_EVENT_INSTALLED: c_int = 5;
+/// A progress message.
+pub const SCI_EVENT_INFO: c_int = 6;
+/// Why the call returned an error.
+pub const SCI_EVENT_ERROR: c_int = 7;
+
+/// Called with each event and its message, which is only valid during the
+/// call, and the options' `user_data`.
+pub type SciCallback =
+    Option<unsafe extern "C" fn(event: c_int, message: *const c_char, user_data: *mut c_void)>;
+
+/// How [`sci_install_key`] installs the key; all zero (or NULL) for the
+/// defaults.
+#[repr(C)]
+pub struct SciOptions {
+    /// The port, or 0 for ssh's default
+    pub port: u16,
+    /// Seconds before the attempt is given up, or 0 for no limit
+    pub timeout_secs: u64,
+    /// Non-zero to append without checking for keys already installed
+    pub force: c_int,
+    /// Non-zero to install over SFTP
+    pub sftp: c_int,
+    /// `posix`, `busybox`, `csh`, `powershell` or `device`; NULL for posix
+    pub profile: *const c_char,
+    /// The directory holding `.ssh` on the remote; NULL for the home
+    /// directory
+    pub remote_home: *const c_char,
+    /// Passed to the callback as it is
+    pub user_data: *mut c_void,
+}
+
+/// Forwards the progress to the C callback.
+struct Events {
+    callback: SciCallback,
+    user_data: *mut c_void,
+}
+
+impl Events {
+    fn send(&mut self, event: c_int, message: &str) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        let message = CString::new(message.replace('\0', "")).expect("NULs were removed");
+        // SAFETY: the caller of sci_install_key vouches for the callback
+        unsafe { callback(event, message.as_ptr(), self.user_data) }
+    }
+}
+
+impl ProgressObserver for Events {
+    fn connecting(&mut self, destination: &Destination, _sftp: bool) {
+        self.send(SCI_EVENT_CONNECTING, &destination.to_string());
+    }
+
+    fn authenticated(&mut self) {
+        self.send(SCI_EVENT_AUTHENTICATED, "");
+    }
+
+    fn installed(&mut self, report: &Report) {
+        for key in &report.added {
+            self.send(SCI_EVENT_KEY_ADDED, &key.to_line());
+        }
+        for key in &report.already_installed {
+            self.send(SCI_EVENT_KEY_ALREADY_INSTALLED, &key.to_line());
+        }
+        self.send(
+            SCI_EVENT_INSTALLED,
+            report.host_key.as_deref().unwrap_or(""),
+        );
+    }
+
+    fn info(&mut self, message: &str) {
+        self.send(SCI_EVENT_INFO, message);
+    }
+}
+
+/// Installs the public key(s) in `key` (the content of a `.pub` file) on
+/// `host` (`user@host[:port]` or an `ssh://` URI), through the system's
+/// OpenSSH client. Returns [`SCI_OK`], or an error code after sending
+/// [`SCI_EVENT_ERROR`] with the reason.
+///
+/// # Safety
+///
+/// `host` and `key` must be NUL-terminated strings, `opts` NULL or a valid
+/// [`SciOptions`] whose strings are NULL or NUL-terminated, and `callback`
+/// NULL or a function that is safe to call, from this thread, with
+/// `user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn sci_install_key(
+    host: *const c_char,
+    key: *const c_char,
+    opts: *const SciOptions,
+    callback: SciCallback,
+) -> c_int {
+    // SAFETY: `opts` is NULL or valid, as the caller vouches
+    let opts = unsafe { opts.as_ref() };
+    let mut events = Events {
+        callback,
+        user_data: opts.map_or(std::ptr::null_mut(), |opts| opts.user_data),
+    };
+    // SAFETY: the strings are valid, as the caller vouches
+    let arguments = match unsafe { arguments(host, key, opts) } {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            events.send(SCI_EVENT_ERROR, &format!("{:#}", e));
+            return SCI_ERR_INVALID;
+        }
+    };
+    let (destination, identity, options) = arguments;
+    // A panic must not unwind into C
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        copy_id_with_progress(&destination, &[identity], &options, &mut events)
+    }));
+    let error = match result {
+        Ok(Ok(_)) => return SCI_OK,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(_) => "internal error".to_string(),
+    };
+    events.send(SCI_EVENT_ERROR, &error);
+    SCI_ERR_FAILED
+}
+
+/// The arguments of [`sci_install_key`], as the library takes them.
+///
+/// # Safety
+///
+/// As for [`sci_install_key`].
+unsafe fn arguments(
+    host: *const c_char,
+    key: *const c_char,
+    opts: Option<&SciOptions>,
+) -> Result<(Destination, Identity, Options)> {
+    // SAFETY: passed on from the caller
+    let string = |s: *const c_char, what: &str| -> Result<Option<String>> {
+        if s.is_null() {
+            return Ok(None);
+        }
+        let s = unsafe { CStr::from_ptr(s) };
+        Ok(Some(
+            s.to_str()
+                .with_context(|| format!("{} is not UTF-8", what))?
+                .to_string(),
+        ))
+    };
+    let Some(host) = string(host, "host")? else {
+        bail!("host is NULL");
+    };
+    let Some(key) = string(key, "key")? else {
+        bail!("key is NULL");
+    };
+    let destination: Destination = host.parse().context("Invalid host")?;
+    let identity = Identity {
+        source: "sci_install_key".to_string(),
+        content: key,
+    };
+    let mut options = Options::default();
+    if let Some(opts) = opts {
+        options.connection.port = (opts.port != 0).then_some(opts.port);
+        options.connection.timeout = (opts.timeout_secs != 0).then_some(opts.timeout_secs);
+        options.force = opts.force != 0;
+        options.sftp = opts.sftp != 0;
+        if let Some(profile) = string(opts.profile, "profile")? {
+            options.profile = Profile::from_str(&profile, false)
+                .map_err(|_| anyhow::anyhow!("Unknown profile {:?}", profile))?;
+        }
+        options.remote_home = string(opts.remote_home, "remote_home")?;
+    }
+    Ok((destination, identity, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn record(event: c_int, message: *const c_char, user_data: *mut c_void) {
+        let events = unsafe { &mut *(user_data as *mut Vec<(c_int, String)>) };
+        let message = unsafe { CStr::from_ptr(message) };
+        events.push((event, message.to_string_lossy().into_owned()));
+    }
+
+    #[test]
+    fn invalid_arguments_are_reported_without_connecting() {
+        let mut events: Vec<(c_int, String)> = Vec::new();
+        let profile = c"windows";
+        let opts = SciOptions {
+            port: 0,
+            timeout_secs: 0,
+            force: 0,
+            sftp: 0,
+            profile: profile.as_ptr(),
+            remote_home: std::ptr::null(),
+            user_data: &mut events as *mut _ as *mut c_void,
+        };
+        let host = c"alice@web1";
+        let key =
+            c"ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        let code = unsafe { sci_install_key(host.as_ptr(), key.as_ptr(), &opts, Some(record)) };
+        assert_eq!(code, SCI_ERR_INVALID);
+        let code = unsafe { sci_install_key(std::ptr::null(), key.as_ptr(), &opts, Some(record)) };
+        assert_eq!(code, SCI_ERR_INVALID);
+        assert_eq!(
+            events,
+            [
+                (SCI_EVENT_ERROR, "Unknown profile \"windows\"".to_string()),
+                (SCI_EVENT_ERROR, "host is NULL".to_string())
+            ]
+        );
+    }
+}
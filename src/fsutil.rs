@@ -0,0 +1,170 @@
+use anyhow::{Context, Result, bail};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long to wait for another instance to release a lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Locks older than this are assumed to belong to a crashed process.
+pub const LOCK_STALE_AFTER: Duration = Duration::from_secs(300);
+
+/// An exclusive, cross-process lock on a local file.
+///
+/// The lock is a sibling `<file>.lock` created with `create_new`, which is
+/// atomic on every platform we support. It is released on drop.
+pub struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    pub fn acquire(target: &Path) -> Result<FileLock> {
+        FileLock::acquire_within(target, LOCK_TIMEOUT, &mut || {})
+    }
+
+    /// Like [`FileLock::acquire`], but waits up to `timeout` (at most until
+    /// the lock goes stale) and calls `waiting` once if it has to wait.
+    pub fn acquire_within(target: &Path, timeout: Duration, waiting: &mut dyn FnMut()) -> Result<FileLock> {
+        let path = sibling(target, ".lock");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+
+        let started = SystemTime::now();
+        let mut delay = Duration::from_millis(10);
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // The pid is informational only, to help debug a stuck lock
+                    let _ = writeln!(file, "{}", std::process::id());
+                    return Ok(FileLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if is_stale(&path) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if delay == Duration::from_millis(10) {
+                        waiting();
+                    }
+                    if started.elapsed().unwrap_or_default() > timeout {
+                        bail!(
+                            "Timed out waiting for lock {:?}. Remove it if no other instance is running.",
+                            path
+                        );
+                    }
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_millis(500));
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create lock file: {:?}", path));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > LOCK_STALE_AFTER)
+}
+
+/// Replaces `path` with `contents` so readers never observe a partial write.
+///
+/// Data goes to a temporary file in the same directory, is flushed to disk,
+/// and is then renamed over the target.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+
+    let tmp = sibling(path, &format!(".tmp.{}", std::process::id()));
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&tmp)
+            .with_context(|| format!("Failed to create temporary file: {:?}", tmp))?;
+        file.write_all(contents)
+            .with_context(|| format!("Failed to write temporary file: {:?}", tmp))?;
+        file.sync_all()
+            .with_context(|| format!("Failed to flush temporary file: {:?}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace {:?}", path))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// A private file in the system temporary directory, removed on drop; for
+/// handing generated config (e.g. known_hosts entries) to other tools.
+pub struct TempFile {
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    pub fn create(label: &str, contents: &[u8]) -> Result<TempFile> {
+        let dir = std::env::temp_dir();
+        let mut attempt = 0;
+        loop {
+            let path = dir.join(format!(
+                "ssh-copy-id-rs-{}.{}.{}",
+                label,
+                std::process::id(),
+                attempt
+            ));
+            // create_new so an existing file (or symlink) is never followed
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(mut file) => {
+                    let temp = TempFile { path };
+                    file.write_all(contents).with_context(|| {
+                        format!("Failed to write temporary file: {:?}", temp.path)
+                    })?;
+                    return Ok(temp);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create temporary file: {:?}", path));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads a file that may not exist yet, treating a missing file as empty.
+pub fn read_optional(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
@@ -0,0 +1,140 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, with the `fuzzing`
+//! feature. Each feeds arbitrary bytes to a parser that reads what a remote
+//! sends back, and panics if the result breaks what the rest of the crate
+//! relies on before it reports keys or writes the cache, the history or an
+//! uploaded authorized_keys. The tests run them on random input too.
+
+use crate::authorized_keys;
+use crate::key::{self, PublicKey};
+use crate::keyblob;
+use crate::remote_script;
+
+/// The keys the fuzzed runs pretend to have sent.
+const SENT: &[&str] = &[
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl alice@laptop",
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAID88xfz5ysGpCkSpKg8GLUE87P6l9caTMy+rAM+aYnqF",
+];
+
+fn sent() -> Vec<PublicKey> {
+    SENT.iter()
+        .map(|line| PublicKey::parse(line).expect("valid key"))
+        .collect()
+}
+
+/// Whether `line` stays one line wherever it is printed or written.
+fn is_one_line(line: &str) -> bool {
+    !line.contains(|c: char| c.is_control() && c != '\t')
+}
+
+/// Reads `data` as the stdout of an install, removal or rotation script.
+pub fn remote_output(data: &[u8]) {
+    let stdout = String::from_utf8_lossy(data);
+    let added = remote_script::added(&stdout);
+    let skipped = remote_script::skipped(&stdout);
+    let removed = remote_script::removed(&stdout);
+    let found = remote_script::backed_up(&stdout)
+        .into_iter()
+        .chain(remote_script::checksum_mismatch(&stdout));
+    for line in added
+        .iter()
+        .chain(&skipped)
+        .chain(&removed)
+        .copied()
+        .chain(found)
+    {
+        assert!(!line.contains('\n'), "marker value spans lines: {:?}", line);
+    }
+    for lines in [&added, &skipped, &removed] {
+        for reported in authorized_keys::keys_in(lines) {
+            let line = reported.to_line();
+            assert!(is_one_line(&line), "reported key {:?}", reported);
+            assert_eq!(PublicKey::parse(&line).ok(), Some(reported));
+        }
+    }
+}
+
+/// Reads `data` as an authorized_keys, as status, `--dry-run` and an
+/// install over SFTP do, and merges keys into it.
+pub fn authorized_keys(data: &[u8]) {
+    let existing = String::from_utf8_lossy(data);
+    let parsed = authorized_keys::parse(&existing);
+    let lines = existing.lines().count();
+    for (number, reason) in &parsed.invalid {
+        assert!((1..=lines).contains(number), "line {} of {}", number, lines);
+        assert!(is_one_line(reason), "reason {:?}", reason);
+    }
+    for found in &parsed.keys {
+        assert!(is_one_line(&found.to_line()), "parsed key {:?}", found);
+        let line = authorized_keys::with_options(found, &["no-pty".to_string()])
+            .unwrap_or_else(|reason| panic!("{:?} with no-pty: {}", found, reason));
+        assert!(authorized_keys::key(&line).is_some_and(|again| again.blob == found.blob));
+    }
+
+    let keys: Vec<PublicKey> = sent().into_iter().chain(parsed.keys).collect();
+    let payload = key::payload(&keys);
+    let (merged, appended) = authorized_keys::append_missing(&existing, &payload);
+    assert!(
+        merged.starts_with(&*existing),
+        "the existing lines were changed"
+    );
+    assert!(
+        merged[existing.len()..]
+            .lines()
+            .filter(|line| !line.is_empty())
+            .eq(appended.iter().map(String::as_str))
+    );
+    for key in &keys {
+        assert!(keyblob::contains(
+            &authorized_keys::parse(&merged).keys,
+            key
+        ));
+    }
+    let (again, appended) = authorized_keys::append_missing(&merged, &payload);
+    assert_eq!(again, merged);
+    assert!(appended.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{Gen, check};
+
+    /// Random lines, some of them markers or key lines, as a remote might
+    /// send them back.
+    fn random_output(g: &mut Gen) -> String {
+        let markers = [
+            remote_script::ADDED,
+            remote_script::SKIPPED,
+            remote_script::REMOVED,
+            remote_script::BACKUP,
+            remote_script::CHECKSUM,
+            "",
+            "no-pty ",
+            "cert-authority ",
+            "from=\"",
+        ];
+        (0..g.below(6))
+            .map(|_| {
+                let marker = g.pick(&markers).to_string();
+                let rest = match g.below(3) {
+                    0 => g.pick(SENT).to_string(),
+                    1 => format!("{}{}", g.pick(SENT), g.tricky_string(12)),
+                    _ => g.tricky_string(40),
+                };
+                marker + &rest + *g.pick(&["\n", "\r\n", ""])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arbitrary_remote_output_is_read_safely() {
+        check(11, |g, _case| {
+            let input = match g.below(2) {
+                0 => random_output(g).into_bytes(),
+                _ => g.bytes(120),
+            };
+            remote_output(&input);
+            authorized_keys(&input);
+        });
+    }
+}
@@ -0,0 +1,242 @@
+//! `--harden`: once the keys are installed and a key-only login has been
+//! verified, installs an sshd_config.d drop-in (with sudo) that disables
+//! password authentication and root login.
+
+use crate::connection::ConnectionArgs;
+use crate::destination::Destination;
+use crate::remote_script::sh_quote;
+use anyhow::{Context, Result, bail};
+use std::io::IsTerminal;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+/// sshd uses the first value it reads for an option, so the low number
+/// makes the drop-in win over later ones.
+pub const DROP_IN: &str = "/etc/ssh/sshd_config.d/10-ssh-copy-id-rs-harden.conf";
+
+const SETTINGS: &str = "\
+# Installed by ssh-copy-id-rs --harden after key authentication was verified.
+# Delete this file and reload sshd to undo.
+PasswordAuthentication no
+ChallengeResponseAuthentication no
+PermitRootLogin no
+";
+
+/// Where a drop-in scheduled with `--after` waits; deleting it cancels.
+const PENDING: &str = "/etc/ssh/ssh-copy-id-rs-harden.pending";
+
+/// The systemd unit that runs a scheduled hardening.
+const UNIT: &str = "ssh-copy-id-rs-harden";
+
+/// Verifies a key-only login to the destination, then installs the drop-in,
+/// or with `after`, schedules that for once the grace period is over.
+/// `output_to_stderr` keeps the remote's output off a machine-readable stdout.
+pub fn run(
+    connection: &ConnectionArgs,
+    destination: &Destination,
+    timeout: Option<Duration>,
+    after: Option<Duration>,
+    output_to_stderr: bool,
+    info: &mut dyn FnMut(&str),
+) -> Result<()> {
+    info("Verifying that key authentication works before hardening sshd...");
+    let mut ssh = connection.ssh(destination, timeout, info)?;
+    ssh.command
+        .args(["-o", "BatchMode=yes", "-o", "PasswordAuthentication=no"]);
+    ssh.command
+        .args(["-o", "PreferredAuthentications=publickey"]);
+    let status = ssh
+        .with_remote(["true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    if !status.success() {
+        bail!("A login with key authentication alone failed, so sshd was left unchanged");
+    }
+
+    let script = match after {
+        Some(after) => schedule_script(after),
+        None => script(),
+    };
+    let status = run_as_root(
+        connection,
+        destination,
+        timeout,
+        &script,
+        true,
+        output_to_stderr,
+        info,
+    )?;
+    if !status.success() {
+        bail!("Hardening sshd failed; the keys are installed");
+    }
+    match after {
+        Some(after) => info(&format!(
+            "Password authentication and root login will be disabled in {}s; cancel with --abort-harden",
+            after.as_secs()
+        )),
+        None => info(&format!(
+            "Disabled password authentication and root login in {}",
+            DROP_IN
+        )),
+    }
+    Ok(())
+}
+
+/// Cancels a hardening scheduled with `--after`. Returns whether one was
+/// pending.
+pub fn abort(
+    connection: &ConnectionArgs,
+    destination: &Destination,
+    timeout: Option<Duration>,
+    output_to_stderr: bool,
+    info: &mut dyn FnMut(&str),
+) -> Result<bool> {
+    // exits 3 when nothing was pending
+    let script = format!(
+        "systemctl stop {unit}.timer 2>/dev/null; systemctl reset-failed {unit}.timer {unit}.service 2>/dev/null; \
+         if [ -f {pending} ]; then rm -f {pending}; else exit 3; fi",
+        unit = UNIT,
+        pending = PENDING
+    );
+    let status = run_as_root(
+        connection,
+        destination,
+        timeout,
+        &script,
+        false,
+        output_to_stderr,
+        info,
+    )?;
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(3) => Ok(false),
+        _ => bail!("Cancelling the scheduled hardening failed"),
+    }
+}
+
+/// Runs `script` on the destination with sudo, refusing first if logged in
+/// as root when `refuse_root` is set.
+fn run_as_root(
+    connection: &ConnectionArgs,
+    destination: &Destination,
+    timeout: Option<Duration>,
+    script: &str,
+    refuse_root: bool,
+    output_to_stderr: bool,
+    info: &mut dyn FnMut(&str),
+) -> Result<ExitStatus> {
+    // sudo may need to prompt for a password, which needs a terminal
+    let interactive = std::io::stdin().is_terminal();
+    let sudo = if interactive { "sudo" } else { "sudo -n" };
+    let mut remote = format!("{} sh -c {}", sudo, sh_quote(script));
+    if refuse_root {
+        remote = format!(
+            "if [ \"$(id -u)\" = 0 ]; then echo 'ssh-copy-id-rs --harden: logged in as root, which would be locked out' >&2; exit 1; fi; {}",
+            remote
+        );
+    }
+    let mut ssh = connection.ssh(destination, timeout, info)?;
+    if interactive {
+        ssh.command.arg("-t");
+    }
+    let command = ssh.with_remote([remote]);
+    if output_to_stderr {
+        command.stdout(std::io::stderr());
+    }
+    command
+        .status()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")
+}
+
+/// Run as root: checks now that sshd reads the drop-in directory, then
+/// leaves `script()` in the pending file for a systemd timer or `at` job
+/// to run (and delete) after `after`.
+fn schedule_script(after: Duration) -> String {
+    let job = format!("if [ -f {p} ]; then sh {p}; rm -f {p}; fi", p = PENDING);
+    // at counts in whole minutes
+    let minutes = after.as_secs().div_ceil(60).max(1);
+    [
+        INCLUDE_CHECK.to_string(),
+        format!(
+            "printf '%s' {} > {p} && chmod 600 {p} || exit 1",
+            sh_quote(&script()),
+            p = PENDING
+        ),
+        format!(
+            "if command -v systemd-run >/dev/null 2>&1 && [ -d /run/systemd/system ]; then \
+             systemctl stop {unit}.timer 2>/dev/null; systemctl reset-failed {unit}.timer {unit}.service 2>/dev/null; \
+             systemd-run --quiet --unit={unit} --on-active={secs} /bin/sh -c {job}; \
+             elif command -v at >/dev/null 2>&1; then echo {job} | at now + {minutes} minutes 2>/dev/null; \
+             else false; fi || {{ rm -f {p}; echo 'ssh-copy-id-rs --harden: could not schedule with systemd-run or at; nothing changed' >&2; exit 1; }}",
+            unit = UNIT,
+            secs = after.as_secs().max(1),
+            job = sh_quote(&job),
+            minutes = minutes,
+            p = PENDING
+        ),
+    ]
+    .join("\n")
+}
+
+/// Refuses unless sshd_config includes the drop-in directory.
+const INCLUDE_CHECK: &str = "grep -Eqi '^[[:space:]]*Include[[:space:]]+\"?/etc/ssh/sshd_config\\.d/' /etc/ssh/sshd_config || \
+     { echo 'ssh-copy-id-rs --harden: /etc/ssh/sshd_config does not Include /etc/ssh/sshd_config.d; nothing changed' >&2; exit 1; }";
+
+/// Run as root. Refuses unless sshd_config includes the drop-in directory,
+/// and removes the drop-in again unless `sshd -t` accepts it and `sshd -T`
+/// shows it in effect, before reloading sshd.
+fn script() -> String {
+    let fail = |message: &str| {
+        format!(
+            "{{ echo 'ssh-copy-id-rs --harden: {}' >&2; exit 1; }}",
+            message
+        )
+    };
+    let undo = |message: &str| {
+        format!(
+            "{{ rm -f \"$f\"; echo 'ssh-copy-id-rs --harden: {}' >&2; exit 1; }}",
+            message
+        )
+    };
+    [
+        format!("f={}", DROP_IN),
+        INCLUDE_CHECK.to_string(),
+        "sshd=$(command -v sshd || echo /usr/sbin/sshd)".to_string(),
+        format!(
+            "mkdir -p /etc/ssh/sshd_config.d && printf '%s' {} > \"$f.tmp\" && chmod 644 \"$f.tmp\" && mv -f \"$f.tmp\" \"$f\" || exit 1",
+            sh_quote(SETTINGS)
+        ),
+        format!("\"$sshd\" -t || {}", undo("sshd rejected the drop-in; removed it")),
+        "effective=$(\"$sshd\" -T 2>/dev/null)".to_string(),
+        format!(
+            "for s in 'passwordauthentication no' 'permitrootlogin no'; do printf '%s\\n' \"$effective\" | grep -qx \"$s\" || {}; done",
+            undo("an earlier setting in sshd_config takes precedence; removed the drop-in")
+        ),
+        format!(
+            "if command -v systemctl >/dev/null 2>&1 && [ -d /run/systemd/system ]; then systemctl reload ssh 2>/dev/null || systemctl reload sshd; \
+             elif command -v service >/dev/null 2>&1; then service ssh reload 2>/dev/null || service sshd reload; \
+             else kill -HUP \"$(cat /var/run/sshd.pid)\"; fi || {}",
+            fail("could not reload sshd; the drop-in applies once it restarts")
+        ),
+    ]
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_drop_in_before_reloading() {
+        let script = script();
+        let write = script.find("mv -f \"$f.tmp\" \"$f\"").unwrap();
+        let check = script.find("\"$sshd\" -t ||").unwrap();
+        let effective = script.find("grep -qx \"$s\"").unwrap();
+        let reload = script.find("systemctl reload").unwrap();
+        assert!(script.find("Include").unwrap() < write);
+        assert!(write < check && check < effective && effective < reload);
+        assert!(script.contains("PasswordAuthentication no\n"));
+    }
+}
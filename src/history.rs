@@ -0,0 +1,48 @@
+use crate::fsutil::{self, FileLock};
+use crate::paths::LocalFile;
+use crate::timeutil;
+use crate::vault;
+use anyhow::Result;
+
+/// Number of entries kept in the history file; older ones are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+/// One line of the local run history.
+pub struct Entry<'a> {
+    pub destination: &'a str,
+    pub source: &'a str,
+    pub outcome: &'a str,
+    /// The host key ssh was shown, as `TYPE SHA256:...`
+    pub host_key: Option<&'a str>,
+}
+
+/// Appends an entry, holding the file lock so concurrent runs don't interleave.
+pub fn record(entry: &Entry) -> Result<()> {
+    let path = LocalFile::History.path()?;
+    let _lock = FileLock::acquire(&path)?;
+
+    let existing = String::from_utf8_lossy(&vault::read(&path)?).into_owned();
+    let mut lines: Vec<&str> = existing.lines().collect();
+
+    let timestamp = timeutil::now_unix();
+    let line = format!(
+        "{}\t{}\t{}\t{}\t{}",
+        timestamp,
+        sanitize(entry.destination),
+        sanitize(entry.source),
+        sanitize(entry.outcome),
+        sanitize(entry.host_key.unwrap_or("-"))
+    );
+    lines.push(&line);
+
+    let skip = lines.len().saturating_sub(MAX_ENTRIES);
+    let mut contents = lines[skip..].join("\n");
+    contents.push('\n');
+
+    fsutil::write_atomic(&path, &vault::seal(&path, contents.into_bytes())?)
+}
+
+/// Keeps each field on one line and free of the column separator.
+fn sanitize(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
@@ -0,0 +1,76 @@
+//! `--pre-hook` / `--post-hook`: local commands run before and after each
+//! host's install, with the host's details in `SSH_COPY_ID_*` variables.
+//!
+//! Both hooks get `SSH_COPY_ID_DESTINATION`, `_HOST`, `_USER`, `_PORT`,
+//! `_KEYS` (the number of keys being installed) and `_FINGERPRINTS` (their
+//! SHA256 fingerprints, space-separated). The post-hook also gets `_STATUS`
+//! (`ok`, `failed`, `timed_out` or `drift_detected`), `_KEYS_ADDED`, `_DURATION_MS` and
+//! `_ERROR`.
+
+use crate::destination::Destination;
+use crate::key::PublicKey;
+use crate::reporter::HostResult;
+use anyhow::{Context, Result, bail};
+use std::process::{Command, Stdio};
+
+pub type Env = Vec<(&'static str, String)>;
+
+/// Variables describing the host and keys, for either hook.
+pub fn env(destination: &Destination, port: Option<u16>, keys: &[PublicKey]) -> Result<Env> {
+    let fingerprints = keys
+        .iter()
+        .map(|key| Ok(key.decode()?.fingerprint_sha256()))
+        .collect::<Result<Vec<String>>>()?;
+    let port = port.or(destination.port).unwrap_or(22).to_string();
+    Ok(vec![
+        ("SSH_COPY_ID_DESTINATION", destination.to_string()),
+        ("SSH_COPY_ID_HOST", destination.host.clone()),
+        (
+            "SSH_COPY_ID_USER",
+            destination.user.clone().unwrap_or_default(),
+        ),
+        ("SSH_COPY_ID_PORT", port),
+        ("SSH_COPY_ID_KEYS", keys.len().to_string()),
+        ("SSH_COPY_ID_FINGERPRINTS", fingerprints.join(" ")),
+    ])
+}
+
+/// The post-hook's additional variables about the outcome.
+pub fn result_env(result: &HostResult) -> Env {
+    let (added, removed) = match result.error() {
+        None if result.remove => (0, result.key_outcome.as_ref().map_or(0, |outcome| outcome.removed.len())),
+        None => (result.keys, 0),
+        Some(_) => (0, 0),
+    };
+    vec![
+        ("SSH_COPY_ID_STATUS", result.status_name().to_string()),
+        ("SSH_COPY_ID_KEYS_ADDED", added.to_string()),
+        ("SSH_COPY_ID_KEYS_REMOVED", removed.to_string()),
+        (
+            "SSH_COPY_ID_DURATION_MS",
+            result.duration.as_millis().to_string(),
+        ),
+        ("SSH_COPY_ID_ERROR", result.error().unwrap_or_default()),
+    ]
+}
+
+/// Runs `command` with `sh -c`. Its output goes to stderr when stdout
+/// carries a machine-readable report.
+pub fn run(name: &str, command: &str, env: &Env, output_to_stderr: bool) -> Result<()> {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null());
+    if output_to_stderr {
+        process.stdout(std::io::stderr());
+    }
+    let status = process
+        .status()
+        .with_context(|| format!("Failed to run the {}", name))?;
+    if !status.success() {
+        bail!("The {} exited with {}", name, status);
+    }
+    Ok(())
+}
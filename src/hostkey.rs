@@ -0,0 +1,151 @@
+//! Learning a remote's host keys.
+
+use crate::destination::Destination;
+use crate::fsutil::TempFile;
+use crate::key::PublicKey;
+use crate::ssh_config;
+use anyhow::{Context, Result, bail};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// Host keys offered by `host`, as collected by `ssh-keyscan`. This is
+/// unauthenticated, like any first contact with ssh.
+pub fn scan(host: &str, port: Option<&str>) -> Result<Vec<PublicKey>> {
+    let mut cmd = Command::new("ssh-keyscan");
+    cmd.args(["-T", "10"]);
+    if let Some(port) = port {
+        cmd.arg("-p").arg(port);
+    }
+    let output = cmd
+        .arg(host)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run ssh-keyscan. Make sure it is in your PATH.")?;
+
+    // Lines are `host keytype base64`; comments report the server version
+    let keys: Vec<PublicKey> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(_, key)| PublicKey::parse(key).ok())
+        .collect();
+    if keys.is_empty() {
+        bail!(
+            "Could not retrieve host keys from {}: {}",
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(keys)
+}
+
+/// The host keys `host` offers whose SHA256 fingerprint is one of
+/// `fingerprints`, for `--expect-fingerprint`; an error if none is.
+pub fn expect(host: &str, port: Option<&str>, fingerprints: &[String]) -> Result<Vec<PublicKey>> {
+    let mut expected = Vec::new();
+    for fingerprint in fingerprints {
+        match fingerprint.strip_prefix("SHA256:") {
+            Some(hash) if !hash.is_empty() => expected.push(format!("SHA256:{}", hash.trim_end_matches('='))),
+            _ => bail!(
+                "Invalid fingerprint {:?}: expected SHA256:..., as `ssh-keygen -l` prints it",
+                fingerprint
+            ),
+        }
+    }
+    let offered = scan(host, port)?;
+    let mut seen = Vec::new();
+    let mut matching = Vec::new();
+    for key in offered {
+        let fingerprint = key.decode()?.fingerprint_sha256();
+        if expected.contains(&fingerprint) {
+            matching.push(key);
+        } else {
+            seen.push(format!("{} {}", key.algorithm, fingerprint));
+        }
+    }
+    if matching.is_empty() {
+        bail!(
+            "Host key mismatch: {} offers none of the expected host keys, only {}",
+            host,
+            seen.join(", ")
+        );
+    }
+    Ok(matching)
+}
+
+/// A known_hosts file accepting only `keys` for the host, and the ssh
+/// options that make ssh rely on it alone.
+pub fn pin(host: &str, port: Option<&str>, keys: &[PublicKey]) -> Result<(TempFile, Vec<String>)> {
+    let pattern = match port {
+        Some(port) if port != "22" => format!("[{}]:{}", host, port),
+        _ => host.to_string(),
+    };
+    let entries: String = keys
+        .iter()
+        .map(|key| format!("{} {} {}\n", pattern, key.algorithm, key.blob))
+        .collect();
+    let file = TempFile::create("known-hosts", entries.as_bytes())?;
+
+    let options = [
+        format!("UserKnownHostsFile={}", file.path.display()),
+        "GlobalKnownHostsFile=/dev/null".to_string(),
+        "StrictHostKeyChecking=yes".to_string(),
+        "CheckHostIP=no".to_string(),
+    ];
+    let args = options
+        .into_iter()
+        .flat_map(|option| ["-o".to_string(), option])
+        .collect();
+    Ok((file, args))
+}
+
+/// A file ssh writes the type and fingerprint of the host key it is shown
+/// to, and the ssh options for that: a KnownHostsCommand (OpenSSH 8.5+)
+/// that adds nothing to the known hosts. `None` where ssh can't, or the
+/// ssh config has a KnownHostsCommand of its own for the host.
+pub fn observer(destination: &Destination) -> Option<(TempFile, Vec<String>)> {
+    if !cfg!(unix) || !supports_known_hosts_command() {
+        return None;
+    }
+    let config = ssh_config::resolve(&destination.host, destination.user.as_deref(), destination.port).ok()?;
+    if config.get("KnownHostsCommand").is_some_and(|command| command != "none") {
+        return None;
+    }
+    let file = TempFile::create("host-key", b"").ok()?;
+    // The path ends up inside two levels of quoting
+    let path = file.path.to_str()?;
+    if !path.chars().all(|c| c.is_ascii_alphanumeric() || "/._-".contains(c)) {
+        return None;
+    }
+    // %t and %f are empty when ssh only asks which key types are known
+    let command = format!(
+        "KnownHostsCommand=/bin/sh -c 'test -z \"$1\" || echo \"$0 $1\" >> \"{}\"' %t %f",
+        path
+    );
+    Some((file, vec!["-o".to_string(), command]))
+}
+
+/// The last host key written to an [`observer`] file, as `TYPE SHA256:...`.
+pub fn observed(file: &TempFile) -> Option<String> {
+    let contents = std::fs::read_to_string(&file.path).ok()?;
+    contents.lines().last().map(str::to_string)
+}
+
+/// Whether ssh is OpenSSH 8.5 or later, which has KnownHostsCommand.
+fn supports_known_hosts_command() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        let Ok(output) = Command::new("ssh").arg("-V").stdin(Stdio::null()).output() else {
+            return false;
+        };
+        // e.g. "OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024", on stderr
+        let version = String::from_utf8_lossy(&output.stderr);
+        let Some(version) = version.trim().strip_prefix("OpenSSH_") else {
+            return false;
+        };
+        let mut numbers = version.split(|c: char| !c.is_ascii_digit()).map(|n| n.parse::<u32>().unwrap_or(0));
+        let major = numbers.next().unwrap_or(0);
+        let minor = numbers.next().unwrap_or(0);
+        (major, minor) >= (8, 5)
+    })
+}
@@ -0,0 +1,76 @@
+//! HTTP(S) requests to inventory APIs, made with `curl`.
+
+use crate::json::Value;
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// GETs `url` and parses the response as JSON. Headers are handed to curl
+/// on stdin rather than its command line, as they may carry tokens.
+pub fn get_json(url: &str, headers: &[String]) -> Result<Value> {
+    request(url, headers, None)
+}
+
+/// POSTs `body` as JSON to `url` and parses the JSON response.
+pub fn post_json(url: &str, headers: &[String], body: &Value) -> Result<Value> {
+    request(url, headers, Some(body))
+}
+
+fn request(url: &str, headers: &[String], body: Option<&Value>) -> Result<Value> {
+    let mut config = format!("url = \"{}\"\n", config_quote(url));
+    config.push_str("header = \"Accept: application/json\"\n");
+    for header in headers {
+        config.push_str(&format!("header = \"{}\"\n", config_quote(header)));
+    }
+    if let Some(body) = body {
+        config.push_str("header = \"Content-Type: application/json\"\n");
+        config.push_str(&format!(
+            "data-binary = \"{}\"\n",
+            config_quote(&body.to_string())
+        ));
+    }
+
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", "60", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl. Make sure it is installed and in your PATH.")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(config.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "Request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let body = String::from_utf8(output.stdout).context("Response is not UTF-8")?;
+    Value::parse(&body).with_context(|| format!("Invalid JSON from {}", url))
+}
+
+/// Percent-encodes a query string component.
+pub fn encode(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Escapes a value for a double-quoted curl config file string.
+fn config_quote(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
@@ -0,0 +1,322 @@
+//! Resolution of the public keys to install.
+
+use crate::key;
+use crate::keyblob;
+use crate::keyformat;
+use crate::keys_repo;
+use crate::ldap;
+use crate::openpgp;
+use crate::signature::TrustedKey;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct Identity {
+    /// Description of the source (e.g., file path or "ssh-agent")
+    pub source: String,
+    /// The actual public key content
+    pub content: String,
+}
+
+/// The error of a resolution that found neither a default key file nor
+/// keys in the agent, which `--generate` can remedy.
+#[derive(Debug)]
+pub struct NoIdentity;
+
+impl std::fmt::Display for NoIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("No identity file found in default locations and no keys in ssh-agent. Please specify one with -i, or create one with --generate.")
+    }
+}
+
+impl std::error::Error for NoIdentity {}
+
+/// Where to take the keys from; shared by every subcommand that reads keys.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct SourceArgs {
+    /// Identity file, e.g., ~/.ssh/id_rsa.pub, or a directory of .pub files; repeat to install several
+    #[arg(short = 'i', long, group = "source")]
+    pub identity_file: Vec<String>,
+
+    /// With a directory given to -i, also collect keys from subdirectories
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// Git repository of public keys to install, optionally at a branch, tag or commit
+    #[arg(long, value_name = "URL[#REF]", group = "source")]
+    pub keys_repo: Option<String>,
+
+    /// Refuse a --keys-repo checkout whose commit (or tag) isn't validly signed
+    #[arg(long, requires = "keys_repo")]
+    pub verify_signatures: bool,
+
+    /// Fetch the sshPublicKey attributes of the LDAP entries matching FILTER, e.g. 'uid=alice'
+    #[arg(long, value_name = "FILTER", group = "source")]
+    pub ldap: Option<String>,
+
+    /// LDAP server for --ldap (default: URI from ldap.conf)
+    #[arg(long, value_name = "URI", requires = "ldap")]
+    pub ldap_uri: Option<String>,
+
+    /// Search base for --ldap (default: BASE from ldap.conf)
+    #[arg(long, value_name = "DN", requires = "ldap")]
+    pub ldap_base: Option<String>,
+
+    /// Bind as DN for --ldap, prompting for the password (default: anonymous)
+    #[arg(long, value_name = "DN", requires = "ldap")]
+    pub ldap_bind_dn: Option<String>,
+
+    /// Install the SSH form of the OpenPGP authentication subkey of KEYID
+    #[arg(long, value_name = "KEYID", group = "source")]
+    pub gpg: Option<String>,
+
+    /// Install the authentication key of the inserted OpenPGP smartcard
+    #[arg(long, group = "source")]
+    pub openpgp_authcard: bool,
+
+    /// Only accept keys signed by KEY (an SSH or minisign public key, or a file holding one)
+    #[arg(long, value_name = "KEY")]
+    pub require_signature: Option<String>,
+}
+
+impl SourceArgs {
+    /// Whether no source was given, so the default keys are looked for.
+    pub fn is_default(&self) -> bool {
+        self.identity_file.is_empty()
+            && self.keys_repo.is_none()
+            && self.ldap.is_none()
+            && self.gpg.is_none()
+            && !self.openpgp_authcard
+    }
+}
+
+pub fn resolve(args: &SourceArgs) -> Result<Identity> {
+    let trusted = args
+        .require_signature
+        .as_deref()
+        .map(TrustedKey::load)
+        .transpose()?;
+
+    // These sources have no files that could carry a detached signature
+    if trusted.is_some() && (args.ldap.is_some() || args.gpg.is_some() || args.openpgp_authcard) {
+        bail!("--require-signature only applies to key files, directories and --keys-repo.");
+    }
+
+    if let Some(filter) = &args.ldap {
+        let keys = ldap::search(&ldap::Query {
+            filter,
+            uri: args.ldap_uri.as_deref(),
+            base: args.ldap_base.as_deref(),
+            bind_dn: args.ldap_bind_dn.as_deref(),
+        })?;
+        return Ok(Identity {
+            source: format!("LDAP {}", filter),
+            content: key::payload(&keys),
+        });
+    }
+
+    if let Some(key_id) = &args.gpg {
+        return Ok(Identity {
+            source: format!("gpg key {}", key_id),
+            content: openpgp::export(key_id)?,
+        });
+    }
+
+    if args.openpgp_authcard {
+        return Ok(Identity {
+            source: "OpenPGP card".to_string(),
+            content: openpgp::export_card()?,
+        });
+    }
+
+    if let Some(spec) = &args.keys_repo {
+        let checkout = keys_repo::checkout(spec, args.verify_signatures)?;
+        let label = format!("{} at {}", spec, checkout.commit);
+        // A signed commit covers the whole tree; otherwise check each file
+        let per_file = match &trusted {
+            Some(key) if !key.verify_git_head(&checkout.dir)? => Some(key),
+            _ => None,
+        };
+        return from_directory(&checkout.dir, &label, true, per_file);
+    }
+    match args.identity_file.as_slice() {
+        [] => resolve_path(None, args.recursive, trusted.as_ref()),
+        [single] => resolve_path(Some(single.clone()), args.recursive, trusted.as_ref()),
+        several => {
+            // Each file may be in a different encoding, so normalize them
+            // to OpenSSH lines before joining
+            let mut sources = Vec::new();
+            let mut keys = Vec::new();
+            for input in several {
+                let identity = resolve_path(Some(input.clone()), args.recursive, trusted.as_ref())?;
+                let parsed = keyformat::read_any(&identity.content)
+                    .with_context(|| format!("Invalid public key in {}", identity.source))?;
+                keys.extend(parsed);
+                sources.push(identity.source);
+            }
+            Ok(Identity {
+                source: sources.join(", "),
+                content: key::payload(&keyblob::dedupe(keys)),
+            })
+        }
+    }
+}
+
+/// Resolves `-i` (a key file, or a directory of `.pub` files) or, when
+/// omitted, the first default key in ~/.ssh or the agent's keys.
+fn resolve_path(input: Option<String>, recursive: bool, trusted: Option<&TrustedKey>) -> Result<Identity> {
+    if let Some(mut path_str) = input {
+        // Expand ~ to home directory
+        if path_str.starts_with("~/") || path_str.starts_with("~\\") {
+            let home = dirs::home_dir().context("Could not determine home directory for ~ expansion")?;
+            path_str = path_str.replacen('~', &home.to_string_lossy(), 1);
+        }
+
+        let path = PathBuf::from(&path_str);
+        if path.is_dir() {
+            return from_directory(&path, &path.display().to_string(), recursive, trusted);
+        }
+        
+        // Logic to find .pub file if private key path given
+        let final_path = if path.exists() {
+             if path_str.ends_with(".pub") {
+                path
+            } else {
+                let pub_path = PathBuf::from(format!("{}.pub", path_str));
+                if pub_path.exists() {
+                    pub_path
+                } else {
+                    path // Fallback to original
+                }
+            }
+        } else {
+             // Try appending .pub
+             let pub_path = PathBuf::from(format!("{}.pub", path_str));
+             if pub_path.exists() {
+                 pub_path
+             } else {
+                 bail!("Identity file not found: {}", path_str);
+             }
+        };
+
+        if let Some(key) = trusted {
+            key.verify_file(&final_path)?;
+        }
+        let content = fs::read_to_string(&final_path)
+            .with_context(|| format!("Failed to read identity file: {:?}", final_path))?;
+            
+        Ok(Identity {
+            source: final_path.to_string_lossy().into_owned(),
+            content,
+        })
+
+    } else {
+        // Auto-discovery
+        let ssh_dir = crate::paths::ssh_dir().context("Could not determine home directory")?;
+
+        let candidates = [
+            "id_rsa.pub",
+            "id_ed25519.pub",
+            "id_ecdsa.pub",
+            "id_dsa.pub",
+            "identity.pub",
+        ];
+
+        for filename in candidates {
+            let candidate = ssh_dir.join(filename);
+            if candidate.exists() {
+                if let Some(key) = trusted {
+                    key.verify_file(&candidate)?;
+                }
+                let content = fs::read_to_string(&candidate)
+                    .with_context(|| format!("Failed to read identity file: {:?}", candidate))?;
+                return Ok(Identity {
+                    source: candidate.to_string_lossy().into_owned(),
+                    content,
+                });
+            }
+        }
+
+        if trusted.is_some() {
+            bail!("Keys from ssh-agent cannot be verified with --require-signature. Please specify one with -i.");
+        }
+
+        // Try ssh-add -L
+        if let Ok(output) = Command::new("ssh-add").arg("-L").output() {
+            if output.status.success() {
+                let keys = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                if !keys.is_empty() && !keys.contains("The agent has no identities") {
+                     return Ok(Identity {
+                        source: "ssh-agent".to_string(),
+                        content: keys,
+                    });
+                }
+            }
+        }
+
+        Err(NoIdentity.into())
+    }
+}
+
+/// Collects every valid `.pub` file in a directory, e.g. a checkout of a
+/// team's keys. Files that fail validation are reported and skipped, but
+/// with a trusted key every file must carry a valid signature.
+fn from_directory(
+    dir: &Path,
+    label: &str,
+    recursive: bool,
+    trusted: Option<&TrustedKey>,
+) -> Result<Identity> {
+    let mut files = Vec::new();
+    collect_pub_files(dir, recursive, &mut files)?;
+    files.sort();
+    if let Some(key) = trusted {
+        for file in &files {
+            key.verify_file(file)?;
+        }
+    }
+
+    let mut keys = Vec::new();
+    let mut used = 0;
+    for file in &files {
+        let parsed = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read identity file: {:?}", file))
+            .and_then(|content| keyformat::read_any(&content));
+        match parsed {
+            Ok(file_keys) if !file_keys.is_empty() => {
+                keys.extend(file_keys);
+                used += 1;
+            }
+            Ok(_) => eprintln!("Warning: skipping {:?}: no keys found", file),
+            Err(e) => eprintln!("Warning: skipping {:?}: {:#}", file, e),
+        }
+    }
+
+    if keys.is_empty() {
+        bail!("No valid .pub files found in {:?}", dir);
+    }
+    Ok(Identity {
+        source: format!("{} ({} of {} .pub files)", label, used, files.len()),
+        content: key::payload(&keys),
+    })
+}
+
+fn collect_pub_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read directory: {:?}", dir))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            // Skip VCS metadata and other hidden directories
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if recursive && !hidden {
+                collect_pub_files(&path, recursive, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "pub") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Offline description of public keys and certificates (`inspect`).
+
+use crate::key::{self, PublicKey};
+use anyhow::Result;
+use std::fmt::Write;
+
+/// Describes each key as a block of `Label: value` lines.
+pub fn describe(source: &str, keys: &[PublicKey]) -> Result<String> {
+    let mut out = String::new();
+    for (index, public_key) in keys.iter().enumerate() {
+        let data = public_key.decode()?;
+        if index > 0 {
+            out.push('\n');
+        }
+
+        let mut field = |label: &str, value: &str| {
+            let _ = writeln!(out, "{:<12} {}", format!("{}:", label), value);
+        };
+        field("Source", source);
+        let label = key::type_label(&data.base_type);
+        field(
+            "Type",
+            &match data.cert {
+                Some(_) => format!("{}-CERT ({})", label, public_key.algorithm),
+                None => format!("{} ({})", label, public_key.algorithm),
+            },
+        );
+        field("Size", &format!("{} bits", data.bits));
+        field("SHA256", &data.fingerprint_sha256());
+        field("MD5", &data.fingerprint_md5());
+        field("Comment", public_key.comment.as_deref().unwrap_or("(none)"));
+
+        if let Some(cert) = &data.cert {
+            field(
+                "Certificate",
+                &format!(
+                    "{} certificate, serial {}, key ID {:?}",
+                    if cert.is_host { "host" } else { "user" },
+                    cert.serial,
+                    cert.key_id
+                ),
+            );
+            field("Signing CA", &cert.ca_description());
+            field("Principals", &list_or_none(&cert.principals, "(none: any)"));
+            field("Valid", &cert.validity());
+            field("Critical", &list_or_none(&cert.critical_options, "(none)"));
+            field("Extensions", &list_or_none(&cert.extensions, "(none)"));
+        }
+    }
+    Ok(out)
+}
+
+fn list_or_none(items: &[String], none: &str) -> String {
+    if items.is_empty() {
+        none.to_string()
+    } else {
+        items.join(", ")
+    }
+}
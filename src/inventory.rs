@@ -0,0 +1,223 @@
+//! Inventory files listing the hosts of a run (`--hosts-file`).
+//!
+//! One destination per line, optionally followed by `key=value` settings
+//! for that host:
+//!
+//! ```text
+//! # web tier
+//! deploy@web1.example.com
+//! deploy@web2.example.com:2222  timeout=60
+//! ```
+
+use crate::destination::Destination;
+use crate::proxy;
+use crate::ssh_config;
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+/// A host to install to, with its per-host settings.
+#[derive(Debug, PartialEq)]
+pub struct Target {
+    pub destination: Destination,
+    /// Overrides `--timeout` for this host
+    pub timeout: Option<Duration>,
+}
+
+impl Target {
+    pub fn new(destination: Destination) -> Target {
+        Target {
+            destination,
+            timeout: None,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Target>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read hosts file {:?}", path))?;
+    let targets = parse(&content).with_context(|| format!("Invalid hosts file {:?}", path))?;
+    if targets.is_empty() {
+        bail!("No hosts listed in {:?}", path);
+    }
+    Ok(targets)
+}
+
+/// Drops targets that reach the same account on the same endpoint as an
+/// earlier one, e.g. an alias, its IP address and its hostname. Hosts are
+/// compared by resolved address, hosts behind jump hosts by name. Returns the
+/// kept targets and `(dropped, kept)` destination pairs; without `resolve`,
+/// and always for `.onion` hosts, names are compared as given. `port` is
+/// the one given with `-p`, if any.
+pub fn dedupe(
+    targets: Vec<Target>,
+    port: Option<u16>,
+    resolve: bool,
+) -> (Vec<Target>, Vec<(String, String)>) {
+    let mut kept: Vec<(Endpoint, Target)> = Vec::new();
+    let mut dropped = Vec::new();
+    for target in targets {
+        let endpoint = Endpoint::of(&target.destination, port, resolve);
+        match kept.iter().find(|(seen, _)| seen.same_as(&endpoint)) {
+            Some((_, first)) => dropped.push((
+                target.destination.to_string(),
+                first.destination.to_string(),
+            )),
+            None => kept.push((endpoint, target)),
+        }
+    }
+    (
+        kept.into_iter().map(|(_, target)| target).collect(),
+        dropped,
+    )
+}
+
+/// What a destination connects to, for duplicate detection.
+struct Endpoint {
+    user: Option<String>,
+    port: u16,
+    /// The route through jump hosts, empty for direct connections
+    via: Vec<String>,
+    host: String,
+    addresses: BTreeSet<IpAddr>,
+}
+
+impl Endpoint {
+    fn of(destination: &Destination, port: Option<u16>, resolve: bool) -> Endpoint {
+        let port = destination.port.or(port);
+        // An alias in the ssh config stands for its HostName, User and Port
+        let (host, user, port) =
+            match ssh_config::resolve(&destination.host, destination.user.as_deref(), port) {
+                Ok(config) => (
+                    config.host_name().to_string(),
+                    config.get("User").map(str::to_string),
+                    config.port().unwrap_or(22),
+                ),
+                Err(_) => (
+                    destination.host.clone(),
+                    destination.user.clone(),
+                    port.unwrap_or(22),
+                ),
+            };
+        let via: Vec<String> = destination.via.iter().map(Destination::to_string).collect();
+        // Names behind a jump host may not resolve, or mean something else, here
+        let addresses = if resolve && via.is_empty() && !proxy::is_onion(&host) {
+            (host.as_str(), port)
+                .to_socket_addrs()
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .unwrap_or_default()
+        } else {
+            BTreeSet::new()
+        };
+        Endpoint {
+            user,
+            port,
+            via,
+            host: host.to_lowercase(),
+            addresses,
+        }
+    }
+
+    fn same_as(&self, other: &Endpoint) -> bool {
+        self.user == other.user
+            && self.port == other.port
+            && self.via == other.via
+            && (self.host == other.host || !self.addresses.is_disjoint(&other.addresses))
+    }
+}
+
+fn parse(content: &str) -> Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let destination = fields.next().unwrap_or_default();
+        let mut target = Target::new(
+            destination
+                .parse()
+                .with_context(|| format!("line {}", number + 1))?,
+        );
+        for field in fields {
+            match field.split_once('=') {
+                Some(("timeout", value)) => {
+                    let seconds: u64 = value.parse().with_context(|| {
+                        format!("line {}: invalid timeout {:?}", number + 1, value)
+                    })?;
+                    target.timeout = Some(Duration::from_secs(seconds));
+                }
+                _ => bail!("line {}: unknown setting {:?}", number + 1, field),
+            }
+        }
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hosts_and_settings() {
+        let targets = parse(
+            "# fleet\n\
+             deploy@web1.example.com\n\
+             \n\
+             deploy@web2.example.com:2222  timeout=60  # slow link\n",
+        )
+        .unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(
+            targets[0].destination.to_string(),
+            "deploy@web1.example.com"
+        );
+        assert_eq!(targets[0].timeout, None);
+        assert_eq!(targets[1].destination.port, Some(2222));
+        assert_eq!(targets[1].timeout, Some(Duration::from_secs(60)));
+
+        assert!(parse("web1 timeout=soon\n").is_err());
+        assert!(parse("web1 colour=blue\n").is_err());
+        assert!(parse("-oProxyCommand=x\n").is_err());
+    }
+
+    #[test]
+    fn dedupe_collapses_same_endpoint() {
+        let targets = parse(
+            "deploy@localhost\n\
+             deploy@127.0.0.1\n\
+             deploy@LOCALHOST:22\n\
+             deploy@127.0.0.1:2222\n\
+             root@127.0.0.1\n",
+        )
+        .unwrap();
+        let (kept, dropped) = dedupe(targets, None, true);
+        let kept: Vec<String> = kept.iter().map(|t| t.destination.to_string()).collect();
+        assert_eq!(
+            kept,
+            [
+                "deploy@localhost",
+                "deploy@127.0.0.1:2222",
+                "root@127.0.0.1"
+            ]
+        );
+        assert_eq!(
+            dropped,
+            [
+                (
+                    "deploy@127.0.0.1".to_string(),
+                    "deploy@localhost".to_string()
+                ),
+                (
+                    "deploy@LOCALHOST:22".to_string(),
+                    "deploy@localhost".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Connecting through jump hosts (`-J`), with their own login settings.
+//!
+//! `-J` takes a chain of hops, outermost first. Names are looked up by ssh,
+//! so `Host` entries in ssh_config apply to each hop as usual.
+//!
+//! Jump hosts that log in like the target are passed to ssh as `-J`. When
+//! `--jump-user`, `--jump-identity` or `--jump-auth` are given, each hop is
+//! instead reached by an `ssh -W` ProxyCommand carrying those settings,
+//! since `-J` can't set an identity or auth method per hop.
+
+use crate::destination::Destination;
+use crate::remote_script::sh_join;
+use anyhow::{Result, bail};
+use std::path::PathBuf;
+
+/// Jump host options shared by the commands that connect to a host.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct JumpArgs {
+    /// Connect to the destination through jump hosts (bastions), a comma-separated chain of [USER@]HOST[:PORT], outermost first
+    #[arg(short = 'J', long, value_name = "DESTINATION[,...]")]
+    pub jump: Option<String>,
+
+    /// Log in to the jump hosts as USER (unless -J names a user for the hop)
+    #[arg(long, value_name = "USER", requires = "jump")]
+    pub jump_user: Option<String>,
+
+    /// Log in to the jump hosts with this private key instead of the target's credentials; repeat once per hop to give each its own
+    #[arg(long, value_name = "FILE", requires = "jump")]
+    pub jump_identity: Vec<PathBuf>,
+
+    /// Authentication methods for the jump host, in ssh's PreferredAuthentications form, e.g. 'publickey' or 'keyboard-interactive'
+    #[arg(long, value_name = "METHODS", requires = "jump")]
+    pub jump_auth: Option<String>,
+}
+
+impl JumpArgs {
+    /// The jump hosts to reach `destination` through: the ones it already
+    /// carries, else `-J`.
+    pub fn apply(&self, destination: &mut Destination) -> Result<()> {
+        let Some(jump) = &self.jump else {
+            return Ok(());
+        };
+        if destination.via.is_empty() {
+            for hop in jump.split(',') {
+                let mut hop: Destination = hop.parse()?;
+                if hop.user.is_none() {
+                    hop.user = self.jump_user.clone();
+                }
+                destination.via.push(hop);
+            }
+        }
+        Ok(())
+    }
+
+    fn has_login_options(&self) -> bool {
+        !self.jump_identity.is_empty() || self.jump_auth.is_some()
+    }
+
+    /// The identity for each of `hops` jump hosts.
+    fn identities(&self, hops: usize) -> Result<Vec<Option<&PathBuf>>> {
+        match self.jump_identity.as_slice() {
+            [] => Ok(vec![None; hops]),
+            [identity] => Ok(vec![Some(identity); hops]),
+            identities if identities.len() == hops => Ok(identities.iter().map(Some).collect()),
+            identities => bail!(
+                "{} --jump-identity files given for {} jump hosts; give one for all or one per hop",
+                identities.len(),
+                hops
+            ),
+        }
+    }
+
+    /// Arguments selecting `destination` on an ssh command line, like
+    /// [`Destination::ssh_args`] but with the jump host login options.
+    pub fn ssh_args(&self, destination: &Destination) -> Result<Vec<String>> {
+        if destination.via.is_empty() || !self.has_login_options() {
+            return Ok(destination.ssh_args());
+        }
+        if let Some(methods) = &self.jump_auth {
+            if methods.is_empty() || methods.contains(char::is_whitespace) {
+                bail!("Invalid --jump-auth methods {:?}", methods);
+            }
+        }
+
+        // Innermost hop first: each hop's ProxyCommand reaches it through the previous one
+        let identities = self.identities(destination.via.len())?;
+        let mut proxy_command: Option<String> = None;
+        for (hop, identity) in destination.via.iter().zip(identities) {
+            let mut command = vec!["ssh".to_string()];
+            if let Some(inner) = &proxy_command {
+                // The hop's ssh expands %h/%p in the inner command, not ours
+                command.extend([
+                    "-o".to_string(),
+                    format!("ProxyCommand={}", inner.replace('%', "%%")),
+                ]);
+            }
+            if let Some(identity) = identity {
+                command.extend(["-i".to_string(), identity.display().to_string()]);
+                command.extend(["-o".to_string(), "IdentitiesOnly=yes".to_string()]);
+            }
+            if let Some(methods) = &self.jump_auth {
+                command.extend([
+                    "-o".to_string(),
+                    format!("PreferredAuthentications={}", methods),
+                ]);
+            }
+            if let Some(port) = hop.port {
+                command.extend(["-p".to_string(), port.to_string()]);
+            }
+            command.extend(["-W".to_string(), "%h:%p".to_string(), hop.ssh_target()]);
+            proxy_command = Some(sh_join(&command));
+        }
+
+        let direct = Destination {
+            via: Vec::new(),
+            ..destination.clone()
+        };
+        let mut args = vec![
+            "-o".to_string(),
+            format!("ProxyCommand={}", proxy_command.unwrap_or_default()),
+        ];
+        args.extend(direct.ssh_args());
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_options_use_proxy_commands() {
+        let mut target: Destination = "deploy@web1:2222".parse().unwrap();
+        let jump = JumpArgs {
+            jump: Some("bastion".to_string()),
+            jump_user: Some("ops".to_string()),
+            ..JumpArgs::default()
+        };
+        jump.apply(&mut target).unwrap();
+        assert_eq!(
+            jump.ssh_args(&target).unwrap(),
+            ["-p", "2222", "-J", "ops@bastion", "deploy@web1"]
+        );
+
+        let jump = JumpArgs {
+            jump_identity: vec![PathBuf::from("/keys/bastion key")],
+            jump_auth: Some("publickey".to_string()),
+            ..jump
+        };
+        target.via.push("admin@inner:2200".parse().unwrap());
+        assert_eq!(
+            jump.ssh_args(&target).unwrap(),
+            [
+                "-o",
+                "ProxyCommand=ssh -o 'ProxyCommand=ssh -i '\\''/keys/bastion key'\\'' -o IdentitiesOnly=yes \
+                 -o PreferredAuthentications=publickey -W %%h:%%p ops@bastion' -i '/keys/bastion key' \
+                 -o IdentitiesOnly=yes -o PreferredAuthentications=publickey -p 2200 -W %h:%p admin@inner",
+                "-p",
+                "2222",
+                "deploy@web1",
+            ]
+        );
+    }
+
+    #[test]
+    fn chains_take_one_identity_per_hop() {
+        let jump = JumpArgs {
+            jump: Some("a@bastion1:22,bastion2:2222".to_string()),
+            jump_user: Some("ops".to_string()),
+            jump_identity: vec![PathBuf::from("k1"), PathBuf::from("k2")],
+            ..JumpArgs::default()
+        };
+        let mut target: Destination = "web".parse().unwrap();
+        jump.apply(&mut target).unwrap();
+        let hops: Vec<String> = target.via.iter().map(Destination::to_string).collect();
+        assert_eq!(hops, ["a@bastion1:22", "ops@bastion2:2222"]);
+
+        let args = jump.ssh_args(&target).unwrap();
+        assert_eq!(
+            args[1],
+            "ProxyCommand=ssh -o 'ProxyCommand=ssh -i k1 -o IdentitiesOnly=yes -p 22 -W %%h:%%p a@bastion1' \
+             -i k2 -o IdentitiesOnly=yes -p 2222 -W %h:%p ops@bastion2"
+        );
+
+        let three = JumpArgs {
+            jump_identity: vec![
+                PathBuf::from("k1"),
+                PathBuf::from("k2"),
+                PathBuf::from("k3"),
+            ],
+            ..jump
+        };
+        assert!(three.ssh_args(&target).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! The canonical form of a public key, for telling whether two key lines
+//! hold the same key. Lines are compared by the bytes their base64 decodes
+//! to, not as text: base64 leaves the low bits of a padded blob's last
+//! character unused, so the same key can be spelled several ways, and
+//! options and comments don't change the key either.
+
+use crate::base64;
+use crate::key::PublicKey;
+
+/// A key's type and decoded blob.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyBlob {
+    pub algorithm: String,
+    pub data: Vec<u8>,
+}
+
+impl KeyBlob {
+    /// The blob of `key`, or None if it isn't valid base64.
+    pub fn of(key: &PublicKey) -> Option<KeyBlob> {
+        Some(KeyBlob {
+            algorithm: key.algorithm.clone(),
+            data: base64::decode(&key.blob)?,
+        })
+    }
+
+    /// The blob as base64, spelled the one way OpenSSH writes it.
+    pub fn encoded(&self) -> String {
+        base64::encode(&self.data)
+    }
+
+    /// Every base64 text that decodes to the blob, the canonical one first,
+    /// for matching lines on the remote, where they are compared as text.
+    pub fn spellings(&self) -> Vec<String> {
+        let canonical = self.encoded();
+        let unused = match self.data.len() % 3 {
+            1 => 4,
+            2 => 2,
+            _ => return vec![canonical],
+        };
+        let last = canonical.trim_end_matches('=').len() - 1;
+        let value = base64::decode_char(canonical.as_bytes()[last]).expect("just encoded");
+        (0..1u8 << unused)
+            .map(|low| {
+                let mut spelling = canonical.clone().into_bytes();
+                spelling[last] = base64::encode_char(value | low);
+                String::from_utf8(spelling).expect("base64 is ASCII")
+            })
+            .collect()
+    }
+}
+
+/// `key` with its blob spelled canonically; options aren't part of a
+/// [`PublicKey`], and the comment is kept.
+pub fn canonical(key: PublicKey) -> PublicKey {
+    match KeyBlob::of(&key) {
+        Some(blob) => PublicKey {
+            blob: blob.encoded(),
+            ..key
+        },
+        None => key,
+    }
+}
+
+/// Whether `a` and `b` are the same key, whatever their comments.
+pub fn same(a: &PublicKey, b: &PublicKey) -> bool {
+    match (KeyBlob::of(a), KeyBlob::of(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Whether `keys` has `key` in it.
+pub fn contains(keys: &[PublicKey], key: &PublicKey) -> bool {
+    keys.iter().any(|other| same(other, key))
+}
+
+/// `keys` in canonical form, each key only the first time it appears.
+pub fn dedupe(keys: Vec<PublicKey>) -> Vec<PublicKey> {
+    let mut unique: Vec<PublicKey> = Vec::with_capacity(keys.len());
+    for key in keys.into_iter().map(canonical) {
+        if !contains(&unique, &key) {
+            unique.push(key);
+        }
+    }
+    unique
+}
+
+/// Every spelling of the blobs of `keys`, for [`crate::remote_script`]'s
+/// and [`crate::sftp`]'s textual matches: the install scripts' check for
+/// keys already there, `--remove` and `--rotate`.
+pub fn spellings(keys: &[PublicKey]) -> Vec<String> {
+    keys.iter()
+        .flat_map(|key| match KeyBlob::of(key) {
+            Some(blob) => blob.spellings(),
+            None => vec![key.blob.clone()],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A line with a blob of `extra` bytes after the type, which is all
+    /// [`PublicKey::parse`] checks.
+    fn line(extra: &[u8], comment: &str) -> String {
+        let mut blob = 11u32.to_be_bytes().to_vec();
+        blob.extend_from_slice(b"ssh-ed25519");
+        blob.extend_from_slice(extra);
+        format!("ssh-ed25519 {} {}", base64::encode(&blob), comment)
+    }
+
+    #[test]
+    fn keys_are_compared_by_their_decoded_blob() {
+        // 16 bytes: the last character before "==" carries four unused bits
+        let key = PublicKey::parse(&line(b"k", "alice@laptop")).unwrap();
+        let spellings = KeyBlob::of(&key).unwrap().spellings();
+        assert_eq!(spellings.len(), 16);
+        assert_eq!(spellings[0], key.blob);
+        for spelling in &spellings {
+            assert_eq!(base64::decode(spelling), base64::decode(&key.blob));
+        }
+
+        let respelled = PublicKey::parse(&format!("ssh-ed25519 {} other", spellings[5])).unwrap();
+        assert_ne!(respelled.blob, key.blob);
+        assert!(same(&key, &respelled));
+        assert_eq!(canonical(respelled.clone()).blob, key.blob);
+        let unique = dedupe(vec![respelled, key.clone()]);
+        assert_eq!(unique.len(), 1);
+        assert_eq!(
+            (unique[0].blob.as_str(), unique[0].comment.as_deref()),
+            (key.blob.as_str(), Some("other"))
+        );
+
+        assert!(!same(
+            &key,
+            &PublicKey::parse(&line(b"j", "alice@laptop")).unwrap()
+        ));
+        // 17 bytes: two unused bits; 18 bytes: none
+        let spellings = |extra: &[u8]| {
+            let key = PublicKey::parse(&line(extra, "")).unwrap();
+            KeyBlob::of(&key).unwrap().spellings().len()
+        };
+        assert_eq!((spellings(b"kk"), spellings(b"kkk")), (4, 1));
+    }
+}
@@ -0,0 +1,171 @@
+//! The keys each destination is known to have, as last seen by
+//! `--dry-run` or after an install, for predicting "already installed"
+//! without connecting (`--skip-if-cached`).
+//!
+//! Only the SHA256 fingerprint of each key is kept, with when it was last
+//! confirmed. Keys removed on the remote other than with `--remove` aren't
+//! noticed, so the cache can only say a key was there, not that it still is.
+//!
+//! The files are encrypted with `--encrypt-state` (see [`crate::vault`]).
+
+use crate::fsutil::{self, FileLock};
+use crate::key::PublicKey;
+use crate::paths;
+use crate::timeutil;
+use crate::vault;
+use anyhow::{Context, Result};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// First line of a cache file; older files without it are ignored.
+const HEADER: &str = "ssh-copy-id-rs key cache 1";
+
+/// What the cache knows about one destination.
+pub struct Cached {
+    /// `user@host:port`
+    pub destination: String,
+    /// The fingerprint of each key, and when it was last seen there in Unix
+    /// time
+    pub keys: Vec<(String, u64)>,
+}
+
+impl Cached {
+    /// When every key in `keys` was last seen on the destination (the
+    /// oldest of those times), if they all were.
+    pub fn confirmed(&self, keys: &[PublicKey]) -> Option<u64> {
+        keys.iter()
+            .map(|key| self.seen(key))
+            .try_fold(u64::MAX, |oldest, seen| seen.map(|seen| oldest.min(seen)))
+            .filter(|_| !keys.is_empty())
+    }
+
+    /// When `key` was last seen on the destination, if it was.
+    pub fn seen(&self, key: &PublicKey) -> Option<u64> {
+        let fingerprint = fingerprint(key).ok()?;
+        self.keys.iter().find(|(known, _)| *known == fingerprint).map(|(_, seen)| *seen)
+    }
+
+    fn parse(content: &str) -> Option<Cached> {
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+        let destination = lines.next()?.strip_prefix("destination ")?.to_string();
+        let keys = lines
+            .filter_map(|line| {
+                let (fingerprint, seen) = line.split_once(' ')?;
+                Some((fingerprint.to_string(), seen.parse().ok()?))
+            })
+            .collect();
+        Some(Cached { destination, keys })
+    }
+
+    fn render(&self) -> String {
+        let mut content = format!("{}\ndestination {}\n", HEADER, self.destination);
+        for (fingerprint, seen) in &self.keys {
+            content.push_str(&format!("{} {}\n", fingerprint, seen));
+        }
+        content
+    }
+}
+
+/// The cache for the destination `id` (a hash of `user@host:port`).
+fn path(id: &str) -> Result<PathBuf> {
+    Ok(paths::cache_dir()?.join("remote-keys").join(id))
+}
+
+fn fingerprint(key: &PublicKey) -> Result<String> {
+    Ok(key.decode()?.fingerprint_sha256())
+}
+
+/// The cached keys of `id`, if there are any.
+pub fn load(id: &str) -> Result<Option<Cached>> {
+    let data = vault::read(&path(id)?)?;
+    Ok(String::from_utf8(data).ok().as_deref().and_then(Cached::parse))
+}
+
+/// Every destination in the cache, with its `id`, by destination.
+pub fn all() -> Result<Vec<(String, Cached)>> {
+    let dir = paths::cache_dir()?.join("remote-keys");
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+    };
+    let mut all = Vec::new();
+    for entry in entries {
+        let name = entry.with_context(|| format!("Failed to read {:?}", dir))?.file_name();
+        // Locks and temporary files have a suffix
+        let Some(id) = name.to_str().filter(|name| !name.contains('.')) else {
+            continue;
+        };
+        if let Some(cached) = load(id)? {
+            all.push((id.to_string(), cached));
+        }
+    }
+    all.sort_by(|(_, a), (_, b)| a.destination.cmp(&b.destination));
+    Ok(all)
+}
+
+/// Records `keys` as the destination's complete set, e.g. as just read.
+pub fn replace(id: &str, destination: &str, keys: &[PublicKey]) -> Result<()> {
+    let _lock = FileLock::acquire(&path(id)?)?;
+    let now = timeutil::now_unix();
+    let keys = keys.iter().map(|key| Ok((fingerprint(key)?, now))).collect::<Result<_>>()?;
+    write(id, &Cached { destination: destination.to_string(), keys })
+}
+
+/// Adds `keys` to what is known about the destination, e.g. once they have
+/// been installed.
+pub fn add(id: &str, destination: &str, keys: &[PublicKey]) -> Result<()> {
+    let _lock = FileLock::acquire(&path(id)?)?;
+    let now = timeutil::now_unix();
+    let mut cached = load(id)?.unwrap_or(Cached { destination: String::new(), keys: Vec::new() });
+    cached.destination = destination.to_string();
+    for key in keys {
+        let fingerprint = fingerprint(key)?;
+        match cached.keys.iter_mut().find(|(known, _)| *known == fingerprint) {
+            Some((_, seen)) => *seen = now,
+            None => cached.keys.push((fingerprint, now)),
+        }
+    }
+    write(id, &cached)
+}
+
+/// Forgets `keys` for the destination, once they have been removed there.
+pub fn remove(id: &str, keys: &[PublicKey]) -> Result<()> {
+    let _lock = FileLock::acquire(&path(id)?)?;
+    let Some(mut cached) = load(id)? else {
+        return Ok(());
+    };
+    let gone = keys.iter().map(fingerprint).collect::<Result<Vec<_>>>()?;
+    cached.keys.retain(|(known, _)| !gone.contains(known));
+    write(id, &cached)
+}
+
+fn write(id: &str, cached: &Cached) -> Result<()> {
+    let path = path(id)?;
+    let content = vault::seal(&path, cached.render().into_bytes())?;
+    fsutil::write_atomic(&path, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_files_round_trip() {
+        let cached = Cached {
+            destination: "alice@web1:22".to_string(),
+            keys: vec![
+                ("SHA256:Uorj1loiJliOOGXkC40FARqq38bOprN/wWWdEzlhYJs".to_string(), 1760400000),
+                ("SHA256:sydcTCUJiXIQqKeDGaoZg6VTpHCw+edHoHVGrw8AC10".to_string(), 1760486400),
+            ],
+        };
+        let parsed = Cached::parse(&cached.render()).unwrap();
+        assert_eq!(parsed.destination, cached.destination);
+        assert_eq!(parsed.keys, cached.keys);
+        // The blob-per-line files of earlier versions
+        assert!(Cached::parse("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5\n").is_none());
+    }
+}
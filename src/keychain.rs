@@ -0,0 +1,66 @@
+//! Secrets kept in the OS keychain, through its command-line tools: macOS
+//! `security`, and libsecret's `secret-tool` elsewhere on Unix. Windows'
+//! Credential Manager has no tool that hands stored secrets back, so
+//! [`lookup`] and [`store`] fail there.
+
+use crate::pipe;
+use anyhow::{Context, Result, bail};
+use std::process::Command;
+
+/// The secret stored for `account` of `service`, if there is one.
+pub fn lookup(service: &str, account: &str) -> Result<Option<String>> {
+    let found = if cfg!(target_os = "macos") {
+        let mut security = Command::new("security");
+        security.args(["find-generic-password", "-s", service, "-a", account, "-w"]);
+        pipe(&mut security, b"").ok()
+    } else if cfg!(unix) {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.args(["lookup", "service", service, "account", account]);
+        pipe(&mut secret_tool, b"").ok()
+    } else {
+        bail!("The OS keychain isn't supported on this platform")
+    };
+    // Both print the secret with a newline, and fail when there is none
+    Ok(found
+        .map(|secret| String::from_utf8_lossy(&secret).trim_end_matches(['\r', '\n']).to_string())
+        .filter(|secret| !secret.is_empty()))
+}
+
+/// Stores `secret` for `account` of `service`, replacing any there was;
+/// `label` is what keychain browsers show for it.
+pub fn store(service: &str, account: &str, label: &str, secret: &str) -> Result<()> {
+    if cfg!(target_os = "macos") {
+        // security takes the secret as an argument, where `ps` would show
+        // it, so the command is given on stdin in its interactive mode
+        if secret.contains(['\r', '\n']) {
+            bail!("The OS keychain can't store a secret that spans lines");
+        }
+        let command = [
+            "add-generic-password",
+            "-U",
+            "-s",
+            service,
+            "-a",
+            account,
+            "-l",
+            label,
+            "-w",
+            secret,
+        ]
+        .iter()
+        .map(|word| format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ");
+        let mut security = Command::new("security");
+        security.arg("-i");
+        pipe(&mut security, format!("{}\n", command).as_bytes())
+    } else if cfg!(unix) {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.args(["store", "--label", label, "service", service, "account", account]);
+        pipe(&mut secret_tool, secret.as_bytes())
+    } else {
+        bail!("The OS keychain isn't supported on this platform")
+    }
+    .context("Failed to store the secret in the OS keychain")?;
+    Ok(())
+}
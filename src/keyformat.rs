@@ -0,0 +1,267 @@
+//! Public key encodings: OpenSSH lines, RFC 4716 ("SSH2") blocks, and PEM
+//! (PKCS#1 for RSA, SubjectPublicKeyInfo otherwise).
+
+use crate::base64;
+use crate::der;
+use crate::key::{self, PublicKey};
+use crate::wire::{Reader, Writer};
+use anyhow::{Context, Result, bail};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Format {
+    /// One `type base64 comment` line per key, as in authorized_keys
+    Openssh,
+    /// `---- BEGIN SSH2 PUBLIC KEY ----` blocks
+    Rfc4716,
+    /// `-----BEGIN PUBLIC KEY-----` (SubjectPublicKeyInfo)
+    Pkcs8,
+    /// Like pkcs8, but `-----BEGIN RSA PUBLIC KEY-----` (PKCS#1) for RSA keys
+    Pem,
+}
+
+const RFC4716_BEGIN: &str = "---- BEGIN SSH2 PUBLIC KEY ----";
+const RFC4716_END: &str = "---- END SSH2 PUBLIC KEY ----";
+const SPKI_LABEL: &str = "PUBLIC KEY";
+const PKCS1_LABEL: &str = "RSA PUBLIC KEY";
+
+const OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_EC: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const CURVES: &[(&str, &[u8])] = &[
+    (
+        "nistp256",
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07],
+    ),
+    ("nistp384", &[0x2b, 0x81, 0x04, 0x00, 0x22]),
+    ("nistp521", &[0x2b, 0x81, 0x04, 0x00, 0x23]),
+];
+
+/// Reads keys in any supported encoding, detected from the content.
+pub fn read_any(content: &str) -> Result<Vec<PublicKey>> {
+    if content.contains(RFC4716_BEGIN) {
+        read_rfc4716(content)
+    } else if content.contains("-----BEGIN ") && !content.contains("PRIVATE KEY-----") {
+        read_pem(content)
+    } else {
+        key::parse_keys(content)
+    }
+}
+
+pub fn write(keys: &[PublicKey], format: Format) -> Result<String> {
+    let mut out = String::new();
+    for public_key in keys {
+        match format {
+            Format::Openssh => {
+                out.push_str(&public_key.to_line());
+                out.push('\n');
+            }
+            Format::Rfc4716 => out.push_str(&write_rfc4716(public_key)?),
+            Format::Pkcs8 => out.push_str(&pem_block(SPKI_LABEL, &to_spki(public_key)?)),
+            Format::Pem => {
+                let (label, der) = match public_key.algorithm.as_str() {
+                    "ssh-rsa" => (PKCS1_LABEL, to_pkcs1(public_key)?),
+                    _ => (SPKI_LABEL, to_spki(public_key)?),
+                };
+                out.push_str(&pem_block(label, &der));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn read_rfc4716(content: &str) -> Result<Vec<PublicKey>> {
+    let mut keys = Vec::new();
+    let mut lines = content.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line != RFC4716_BEGIN {
+            continue;
+        }
+
+        let mut comment = None;
+        let mut body = String::new();
+        let mut header = String::new();
+        for line in lines.by_ref() {
+            if line == RFC4716_END {
+                break;
+            }
+            // Headers are `Tag: value`, continued with a trailing backslash
+            if !header.is_empty() || (body.is_empty() && line.contains(':')) {
+                header.push_str(line.strip_suffix('\\').unwrap_or(line));
+                if !line.ends_with('\\') {
+                    if let Some((tag, value)) = header.split_once(':') {
+                        if tag.trim().eq_ignore_ascii_case("Comment") {
+                            let value = value.trim();
+                            let value = value
+                                .strip_prefix('"')
+                                .and_then(|v| v.strip_suffix('"'))
+                                .unwrap_or(value);
+                            comment = Some(value.to_string());
+                        }
+                    }
+                    header.clear();
+                }
+                continue;
+            }
+            body.push_str(line);
+        }
+
+        let blob = base64::decode(&body).context("RFC 4716 key data is not valid base64")?;
+        keys.push(from_blob(&blob, comment.as_deref())?);
+    }
+    Ok(keys)
+}
+
+fn write_rfc4716(public_key: &PublicKey) -> Result<String> {
+    let mut out = format!("{}\n", RFC4716_BEGIN);
+    if let Some(comment) = &public_key.comment {
+        let escaped = comment.replace('\\', "\\\\").replace('"', "\\\"");
+        out.push_str(&format!("Comment: \"{}\"\n", escaped));
+    }
+    for chunk in public_key.blob.as_bytes().chunks(70) {
+        out.push_str(std::str::from_utf8(chunk)?);
+        out.push('\n');
+    }
+    out.push_str(RFC4716_END);
+    out.push('\n');
+    Ok(out)
+}
+
+fn read_pem(content: &str) -> Result<Vec<PublicKey>> {
+    let mut keys = Vec::new();
+    let mut lines = content.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|l| l.strip_suffix("-----"))
+        else {
+            continue;
+        };
+        let end = format!("-----END {}-----", label);
+        let body: String = lines
+            .by_ref()
+            .take_while(|l| *l != end)
+            .filter(|l| !l.contains(':')) // RFC 1421 headers
+            .collect();
+        let der = base64::decode(&body).context("PEM data is not valid base64")?;
+
+        let blob = match label {
+            SPKI_LABEL => from_spki(&der)?,
+            PKCS1_LABEL => from_pkcs1(&der)?,
+            other => bail!("unsupported PEM block {:?}", other),
+        };
+        keys.push(from_blob(&blob, None)?);
+    }
+    Ok(keys)
+}
+
+fn pem_block(label: &str, der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+    let mut out = format!("-----BEGIN {}-----\n", label);
+    for chunk in encoded.as_bytes().chunks(64) {
+        out.push_str(&String::from_utf8_lossy(chunk));
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", label));
+    out
+}
+
+fn from_blob(blob: &[u8], comment: Option<&str>) -> Result<PublicKey> {
+    let algorithm = Reader::new(blob).string()?;
+    let line = format!(
+        "{} {} {}",
+        algorithm,
+        base64::encode(blob),
+        comment.unwrap_or("")
+    );
+    PublicKey::parse(&line)
+}
+
+/// SSH fields of an RSA key: (e, n) as unsigned big-endian integers.
+fn rsa_fields(public_key: &PublicKey) -> Result<(Vec<u8>, Vec<u8>)> {
+    let blob = base64::decode(&public_key.blob).context("key data is not valid base64")?;
+    let mut reader = Reader::new(&blob);
+    reader.string()?;
+    // SSH mpints and DER INTEGERs share the same minimal two's complement form
+    Ok((reader.bytes()?.to_vec(), reader.bytes()?.to_vec()))
+}
+
+fn to_pkcs1(public_key: &PublicKey) -> Result<Vec<u8>> {
+    let (e, n) = rsa_fields(public_key)?;
+    let mut seq = der::tlv(der::INTEGER, &n);
+    seq.extend(der::tlv(der::INTEGER, &e));
+    Ok(der::tlv(der::SEQUENCE, &seq))
+}
+
+fn from_pkcs1(data: &[u8]) -> Result<Vec<u8>> {
+    let mut outer = der::Reader::new(data);
+    let mut seq = der::Reader::new(outer.read(der::SEQUENCE)?);
+    let n = seq.read(der::INTEGER)?;
+    let e = seq.read(der::INTEGER)?;
+    Ok(Writer::default()
+        .string(b"ssh-rsa")
+        .string(e)
+        .string(n)
+        .into_bytes())
+}
+
+fn to_spki(public_key: &PublicKey) -> Result<Vec<u8>> {
+    let blob = base64::decode(&public_key.blob).context("key data is not valid base64")?;
+    let mut reader = Reader::new(&blob);
+    let algorithm = reader.string()?;
+
+    let (algorithm_id, key_bits) = match algorithm.as_str() {
+        "ssh-rsa" => {
+            let mut id = der::tlv(der::OID, OID_RSA);
+            id.extend(der::tlv(der::NULL, &[]));
+            (id, to_pkcs1(public_key)?)
+        }
+        "ssh-ed25519" => (der::tlv(der::OID, OID_ED25519), reader.bytes()?.to_vec()),
+        a if a.starts_with("ecdsa-sha2-") => {
+            let curve = reader.string()?;
+            let (_, curve_oid) = CURVES
+                .iter()
+                .find(|(name, _)| *name == curve)
+                .with_context(|| format!("unsupported curve {:?}", curve))?;
+            let mut id = der::tlv(der::OID, OID_EC);
+            id.extend(der::tlv(der::OID, curve_oid));
+            (id, reader.bytes()?.to_vec())
+        }
+        other => bail!("{} keys cannot be converted to PEM", other),
+    };
+
+    let mut spki = der::tlv(der::SEQUENCE, &algorithm_id);
+    spki.extend(der::bit_string(&key_bits));
+    Ok(der::tlv(der::SEQUENCE, &spki))
+}
+
+fn from_spki(data: &[u8]) -> Result<Vec<u8>> {
+    let mut outer = der::Reader::new(data);
+    let mut spki = der::Reader::new(outer.read(der::SEQUENCE)?);
+    let mut algorithm_id = der::Reader::new(spki.read(der::SEQUENCE)?);
+    let oid = algorithm_id.read(der::OID)?;
+    let key_bits = spki.bit_string()?;
+
+    if oid == OID_RSA {
+        from_pkcs1(key_bits)
+    } else if oid == OID_ED25519 {
+        Ok(Writer::default()
+            .string(b"ssh-ed25519")
+            .string(key_bits)
+            .into_bytes())
+    } else if oid == OID_EC {
+        let curve_oid = algorithm_id.read(der::OID)?;
+        let (curve, _) = CURVES
+            .iter()
+            .find(|(_, oid)| *oid == curve_oid)
+            .context("unsupported elliptic curve")?;
+        let algorithm = format!("ecdsa-sha2-{}", curve);
+        Ok(Writer::default()
+            .string(algorithm.as_bytes())
+            .string(curve.as_bytes())
+            .string(key_bits)
+            .into_bytes())
+    } else {
+        bail!("unsupported public key algorithm in PEM data")
+    }
+}
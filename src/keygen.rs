@@ -0,0 +1,108 @@
+//! `--generate TYPE`: creates a keypair with `ssh-keygen` when no identity
+//! is found, so a new machine needs a single command to get its key onto a
+//! server.
+
+use crate::paths;
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KeyType {
+    /// Ed25519, what current OpenSSH generates by default
+    Ed25519,
+    /// ECDSA on P-256
+    Ecdsa,
+    /// 3072-bit RSA, for servers too old to accept the others
+    Rsa,
+}
+
+impl KeyType {
+    /// The name `ssh-keygen -t` takes, which is also the suffix of the
+    /// default file name (`id_ed25519`).
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyType::Ed25519 => "ed25519",
+            KeyType::Ecdsa => "ecdsa",
+            KeyType::Rsa => "rsa",
+        }
+    }
+}
+
+/// Where ssh looks for a key of `kind` by default.
+pub fn default_path(kind: KeyType) -> Result<PathBuf> {
+    let dir = paths::ssh_dir().context("Could not determine home directory")?;
+    Ok(dir.join(format!("id_{}", kind.name())))
+}
+
+/// Asks on the terminal whether to generate a key of `kind`; false without
+/// a terminal to ask on.
+pub fn offer(kind: KeyType) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    let path = default_path(kind)?;
+    eprint!(
+        "No identity found. Generate an {} key in {:?} now? [y/N] ",
+        kind.name(),
+        path
+    );
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read the answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Generates a key of `kind` at its default path, and returns the path of
+/// its public half. ssh-keygen asks for the passphrase on a terminal;
+/// without one the key is left unencrypted.
+pub fn generate(kind: KeyType) -> Result<PathBuf> {
+    let path = default_path(kind)?;
+    let public = PathBuf::from(format!("{}.pub", path.display()));
+    if path.exists() || public.exists() {
+        bail!("{:?} already exists; pass it with -i instead", path);
+    }
+    if let Some(dir) = path.parent() {
+        create_private_dir(dir)?;
+    }
+    let mut command = Command::new("ssh-keygen");
+    command.args(["-q", "-t", kind.name(), "-f"]).arg(&path);
+    if kind == KeyType::Rsa {
+        command.args(["-b", "3072"]);
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!(
+            "Warning: no terminal to ask for a passphrase, so {:?} is generated without one",
+            path
+        );
+        command.args(["-N", ""]);
+    }
+    let status = command
+        .status()
+        .context("Failed to run ssh-keygen. Make sure it is in your PATH.")?;
+    if !status.success() {
+        bail!("ssh-keygen exited with error code: {:?}", status.code());
+    }
+    eprintln!("Info: Generated {:?}", public);
+    Ok(public)
+}
+
+/// Creates `dir` readable only by its owner, as ssh requires of `~/.ssh`;
+/// an existing directory is left as it is.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(dir)
+        .with_context(|| format!("Failed to create directory: {:?}", dir))
+}
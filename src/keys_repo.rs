@@ -0,0 +1,98 @@
+//! Git repositories of public keys (`--keys-repo URL[#ref]`).
+//!
+//! Each repository is cloned once into the cache directory and updated on
+//! every run, then the requested ref is checked out detached.
+
+use crate::digest;
+use crate::paths;
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub struct Checkout {
+    pub dir: PathBuf,
+    /// Abbreviated hash of the checked-out commit
+    pub commit: String,
+}
+
+pub fn checkout(spec: &str, verify_signatures: bool) -> Result<Checkout> {
+    let (url, reference) = match spec.rsplit_once('#') {
+        Some((url, reference)) if !reference.is_empty() => (url, Some(reference)),
+        _ => (spec.trim_end_matches('#'), None),
+    };
+    if url.is_empty() || url.starts_with('-') || reference.is_some_and(|r| r.starts_with('-')) {
+        bail!("Invalid --keys-repo value: {:?}", spec);
+    }
+
+    // One checkout per URL, named by its hash so any URL maps to a safe path
+    let hash: String = digest::sha256(url.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let dir = paths::cache_dir()?.join("keys-repos").join(hash);
+
+    if !dir.join(".git").is_dir() {
+        eprintln!("Cloning {}...", url);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {:?}", dir))?;
+        git(&dir, &["init", "--quiet"])?;
+        git(&dir, &["remote", "add", "origin", url])?;
+    } else {
+        git(&dir, &["remote", "set-url", "origin", url])?;
+    }
+
+    let fetch_ref = reference.unwrap_or("HEAD");
+    git(
+        &dir,
+        &["fetch", "--quiet", "--force", "--tags", "origin", fetch_ref],
+    )
+    .with_context(|| format!("Failed to fetch {:?} from {}", fetch_ref, url))?;
+    git(
+        &dir,
+        &["checkout", "--quiet", "--force", "--detach", "FETCH_HEAD"],
+    )?;
+    // Remove anything left over from a previous checkout
+    git(&dir, &["clean", "--quiet", "-d", "--force", "-x"])?;
+
+    if verify_signatures {
+        let is_tag = reference.is_some_and(|r| {
+            git(
+                &dir,
+                &[
+                    "rev-parse",
+                    "--quiet",
+                    "--verify",
+                    &format!("refs/tags/{}", r),
+                ],
+            )
+            .is_ok()
+        });
+        let result = match reference {
+            Some(tag) if is_tag => git(&dir, &["verify-tag", tag]),
+            _ => git(&dir, &["verify-commit", "HEAD"]),
+        };
+        result.with_context(|| format!("Signature verification failed for {}", spec))?;
+    }
+
+    let commit = git(&dir, &["rev-parse", "--short", "HEAD"])?;
+    Ok(Checkout { dir, commit })
+}
+
+/// Runs git in the checkout and returns its trimmed stdout.
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run git. Make sure 'git' is in your PATH.")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
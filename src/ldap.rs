@@ -0,0 +1,118 @@
+//! Public keys stored in LDAP / Active Directory (`--ldap FILTER`).
+//!
+//! The query is run with `ldapsearch`, so the server and search base
+//! default to the system's ldap.conf (`URI`, `BASE`) or `LDAPURI`/`LDAPBASE`.
+
+use crate::base64;
+use crate::key::{self, PublicKey};
+use anyhow::{Context, Result, bail};
+use std::process::{Command, Stdio};
+
+/// Attribute holding the keys, as defined by the openssh-lpk schema.
+const ATTRIBUTE: &str = "sshPublicKey";
+
+pub struct Query<'a> {
+    /// Search filter; a bare `uid=alice` is wrapped in parentheses
+    pub filter: &'a str,
+    pub uri: Option<&'a str>,
+    pub base: Option<&'a str>,
+    /// Bind DN for a simple bind; ldapsearch prompts for the password
+    pub bind_dn: Option<&'a str>,
+}
+
+/// Returns the valid keys of every entry matching the query.
+pub fn search(query: &Query) -> Result<Vec<PublicKey>> {
+    let filter = if query.filter.starts_with('(') {
+        query.filter.to_string()
+    } else {
+        format!("({})", query.filter)
+    };
+
+    let mut cmd = Command::new("ldapsearch");
+    cmd.args(["-x", "-LLL", "-o", "ldif-wrap=no"]);
+    if let Some(uri) = query.uri {
+        cmd.arg("-H").arg(uri);
+    }
+    if let Some(base) = query.base {
+        cmd.arg("-b").arg(base);
+    }
+    if let Some(bind_dn) = query.bind_dn {
+        cmd.arg("-D").arg(bind_dn).arg("-W");
+    }
+    // "--" keeps a filter starting with '-' from being taken as an option
+    cmd.arg("--").arg(&filter).arg(ATTRIBUTE);
+
+    let output = cmd
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run ldapsearch. Make sure the OpenLDAP client tools are installed.")?;
+    if !output.status.success() {
+        bail!("ldapsearch failed for {} ({})", filter, output.status);
+    }
+
+    let mut keys = Vec::new();
+    for value in attribute_values(&String::from_utf8_lossy(&output.stdout), ATTRIBUTE)? {
+        match key::parse_keys(&value) {
+            Ok(parsed) => keys.extend(parsed),
+            Err(e) => eprintln!("Warning: skipping invalid {} value: {:#}", ATTRIBUTE, e),
+        }
+    }
+    if keys.is_empty() {
+        bail!("No {} values found for {}", ATTRIBUTE, filter);
+    }
+    Ok(keys)
+}
+
+/// Collects the values of one attribute from LDIF, unfolding continuation
+/// lines and decoding `attr:: base64` values.
+fn attribute_values(ldif: &str, attribute: &str) -> Result<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ldif.lines() {
+        match (line.strip_prefix(' '), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut values = Vec::new();
+    for line in &lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !name.eq_ignore_ascii_case(attribute) {
+            continue;
+        }
+        match value.strip_prefix(':') {
+            Some(encoded) => {
+                let bytes = base64::decode(encoded.trim())
+                    .with_context(|| format!("{} value is not valid base64", attribute))?;
+                values.push(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            None => values.push(value.trim().to_string()),
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_plain_folded_and_base64_values() {
+        let ldif = "dn: uid=alice,ou=people,dc=example,dc=com\n\
+                    sshPublicKey: ssh-ed25519 AAAA alice@laptop\n\
+                    sshpublickey: ssh-ed25519 BB\n CC alice@desk\n\
+                    sshPublicKey:: c3NoLXJzYSBBQUFBIGFsaWNlQHfDtnJr\n\
+                    mail: alice@example.com\n";
+        assert_eq!(
+            attribute_values(ldif, ATTRIBUTE).unwrap(),
+            [
+                "ssh-ed25519 AAAA alice@laptop",
+                "ssh-ed25519 BBCC alice@desk",
+                "ssh-rsa AAAA alice@wörk",
+            ]
+        );
+    }
+}
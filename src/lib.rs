@@ -0,0 +1,622 @@
+//! A Rust implementation of ssh-copy-id, as a library.
+//!
+//! [`copy_id`] installs keys on one host the way the `ssh-copy-id-rs`
+//! binary does, through the system's OpenSSH client:
+//!
+//! ```no_run
+//! use ssh_copy_id_rs::{Destination, Identity, Options, copy_id};
+//!
+//! let destination: Destination = "deploy@web1.example.com".parse()?;
+//! let identity = Identity {
+//!     source: "provisioning".to_string(),
+//!     content: std::fs::read_to_string("/etc/provisioning/deploy.pub")?,
+//! };
+//! let report = copy_id(&destination, &[identity], &Options::default())?;
+//! println!("{} key(s) added", report.added.len());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`copy_id_with_progress`] does the same, telling a [`ProgressObserver`]
+//! how far it has got, for front ends that show it their own way.
+//!
+//! The binary itself is [`cli::run`].
+//!
+//! Both need the `cli` feature, on by default. Without it, [`offline`]
+//! parses keys and works out merges and plans on their own.
+
+// Without `cli`, much of the shared code has no caller left
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+#[cfg(feature = "cli")]
+use anyhow::{Context, Result, bail};
+#[cfg(feature = "cli")]
+use std::io::Write;
+#[cfg(feature = "cli")]
+use std::process::{Child, Command, ExitStatus, Stdio};
+#[cfg(feature = "cli")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "cli")]
+mod aliases;
+mod authorized_keys;
+mod base64;
+mod cert;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+mod connection;
+#[cfg(feature = "cli")]
+mod consul;
+mod der;
+#[cfg(feature = "cli")]
+mod destination;
+mod digest;
+#[cfg(feature = "cli")]
+mod dns;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fsutil;
+#[cfg(all(feature = "cli", any(test, feature = "fuzzing")))]
+#[doc(hidden)]
+pub mod fuzzing;
+#[cfg(feature = "cli")]
+mod harden;
+#[cfg(feature = "cli")]
+mod history;
+#[cfg(feature = "cli")]
+mod hooks;
+#[cfg(feature = "cli")]
+mod hostkey;
+#[cfg(feature = "cli")]
+mod http;
+#[cfg(feature = "cli")]
+mod identity;
+#[cfg(feature = "cli")]
+mod inspect;
+#[cfg(feature = "cli")]
+mod inventory;
+#[cfg(feature = "cli")]
+mod json;
+#[cfg(feature = "cli")]
+mod jump;
+mod key;
+mod keyblob;
+#[cfg(feature = "cli")]
+mod keycache;
+#[cfg(feature = "cli")]
+mod keychain;
+mod keyformat;
+#[cfg(feature = "cli")]
+mod keygen;
+#[cfg(feature = "cli")]
+mod keys_repo;
+#[cfg(feature = "cli")]
+mod ldap;
+#[cfg(feature = "cli")]
+mod lockout;
+#[cfg(feature = "cli")]
+mod net;
+#[cfg(feature = "cli")]
+mod netbox;
+pub mod offline;
+#[cfg(feature = "cli")]
+mod openpgp;
+#[cfg(feature = "cli")]
+mod pager;
+#[cfg(feature = "cli")]
+mod password;
+#[cfg(feature = "cli")]
+mod paths;
+#[cfg(feature = "cli")]
+mod ping;
+mod plan;
+#[cfg(feature = "cli")]
+mod porcelain;
+#[cfg(feature = "cli")]
+mod probe;
+#[cfg(feature = "cli")]
+mod proxy;
+#[cfg(feature = "cli")]
+mod remote_script;
+#[cfg(feature = "cli")]
+mod reporter;
+#[cfg(feature = "cli")]
+mod rotate;
+#[cfg(feature = "cli")]
+mod sftp;
+#[cfg(feature = "cli")]
+mod signature;
+#[cfg(feature = "cli")]
+mod ssh_config;
+#[cfg(feature = "cli")]
+mod sshfp;
+#[cfg(feature = "cli")]
+mod status;
+#[cfg(test)]
+mod testutil;
+mod timeutil;
+#[cfg(feature = "cli")]
+mod token;
+#[cfg(feature = "cli")]
+mod transcript;
+#[cfg(feature = "cli")]
+mod usage;
+#[cfg(feature = "cli")]
+mod vault;
+mod wire;
+#[cfg(feature = "cli")]
+mod zabbix;
+
+#[cfg(feature = "cli")]
+pub use connection::ConnectionArgs;
+#[cfg(feature = "cli")]
+pub use destination::Destination;
+#[cfg(feature = "cli")]
+pub use identity::Identity;
+pub use key::PublicKey;
+#[cfg(feature = "cli")]
+pub use remote_script::Profile;
+
+/// How [`copy_id`] installs the keys.
+#[cfg(feature = "cli")]
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// How to reach the host: port, jump hosts, proxies, host key
+    /// verification and the timeout
+    pub connection: ConnectionArgs,
+    /// The kind of remote environment to install to
+    pub profile: Profile,
+    /// Append every key, without checking for ones already installed or
+    /// for an implausibly large authorized_keys
+    pub force: bool,
+    /// Install over SFTP rather than with a remote command, as for accounts
+    /// that allow nothing else
+    pub sftp: bool,
+    /// The directory holding `.ssh` on the remote, if not the account's
+    /// home directory (not for [`Profile::Powershell`])
+    pub remote_home: Option<String>,
+    /// authorized_keys options to install the keys with, such as
+    /// `from="10.0.0.0/8"` or `no-pty`
+    pub key_options: Vec<String>,
+}
+
+/// What [`copy_id`] did on the host.
+#[cfg(feature = "cli")]
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// The keys appended to authorized_keys. With [`Profile::Device`],
+    /// which can't tell, all of them.
+    pub added: Vec<PublicKey>,
+    /// The keys that were installed already
+    pub already_installed: Vec<PublicKey>,
+    /// The host key ssh was shown, as `TYPE SHA256:...`
+    pub host_key: Option<String>,
+}
+
+/// Told how far [`copy_id_with_progress`] has got on the host, as it
+/// happens. Every method does nothing unless implemented.
+#[cfg(feature = "cli")]
+pub trait ProgressObserver {
+    /// ssh is being started for the install, or `sftp` for the install
+    /// over SFTP
+    fn connecting(&mut self, _destination: &Destination, _sftp: bool) {}
+    /// The login worked and the install command is running; not reported
+    /// with [`Profile::Device`] or over SFTP, which can't tell
+    fn authenticated(&mut self) {}
+    /// The keys are installed; `report` is what [`copy_id_with_progress`]
+    /// returns
+    fn installed(&mut self, _report: &Report) {}
+    /// A progress message, of the kind the binary prints after `Info:`
+    fn info(&mut self, _message: &str) {}
+}
+
+/// The observer that ignores everything, for [`copy_id`].
+#[cfg(feature = "cli")]
+impl ProgressObserver for () {}
+
+/// Installs the keys of `identities` on `destination` over one connection,
+/// falling back to SFTP for accounts that allow nothing else.
+///
+/// ssh's own messages and prompts go to stderr as usual. Unlike the binary,
+/// this keeps no history and doesn't touch the key cache.
+#[cfg(feature = "cli")]
+pub fn copy_id(
+    destination: &Destination,
+    identities: &[Identity],
+    options: &Options,
+) -> Result<Report> {
+    copy_id_with_progress(destination, identities, options, &mut ())
+}
+
+/// [`copy_id`], telling `observer` of each step as it is reached.
+#[cfg(feature = "cli")]
+pub fn copy_id_with_progress(
+    destination: &Destination,
+    identities: &[Identity],
+    options: &Options,
+    observer: &mut dyn ProgressObserver,
+) -> Result<Report> {
+    let mut keys: Vec<PublicKey> = Vec::new();
+    for identity in identities {
+        let parsed = keyformat::read_any(&identity.content)
+            .with_context(|| format!("Invalid public key in {}", identity.source))?;
+        keys.extend(parsed);
+    }
+    let keys = keyblob::dedupe(keys);
+    if keys.is_empty() {
+        bail!("No keys to install");
+    }
+    let payload = keys
+        .iter()
+        .map(|key| authorized_keys::with_options(key, &options.key_options).map(|line| line + "\n"))
+        .collect::<std::result::Result<String, String>>()
+        .map_err(|reason| anyhow::anyhow!("Invalid key option: {}", reason))?;
+    let timeout = options.connection.timeout.map(Duration::from_secs);
+    let script = remote_script::InstallScript::new(options.profile)
+        .size_guard(!options.force)
+        .dedupe(!options.force)
+        .known(authorized_keys::spellings_by_line(&payload))
+        .report_added(options.profile != Profile::Device)
+        .report_started(options.profile != Profile::Device)
+        .verify_checksum(true)
+        .home(options.remote_home.clone())
+        .render();
+
+    let mut ssh = options
+        .connection
+        .ssh(destination, timeout, &mut |message| observer.info(message))?;
+    ssh.observe_host_key(destination);
+    let stdout = Box::new(std::io::sink());
+    let status = match options.sftp {
+        true => Err(sftp::SftpOnly.into()),
+        false => {
+            observer.connecting(destination, false);
+            let script = remote_script::login_command(options.profile, &script, None);
+            // A plain `cat` reads stdin before it prints anything
+            let reply = (options.profile != Profile::Device).then(|| Reply {
+                after: remote_script::STARTED,
+                answer: Box::new(|| {
+                    observer.authenticated();
+                    Vec::new()
+                }),
+            });
+            converse(&mut ssh, &script, payload.as_bytes(), reply, timeout, stdout)
+        }
+    };
+    let (added, host_key) = match status {
+        Err(e) if e.is::<sftp::SftpOnly>() => {
+            let mut ssh = options
+                .connection
+                .ssh(destination, timeout, &mut |message| observer.info(message))?;
+            ssh.observe_host_key(destination);
+            observer.connecting(destination, true);
+            let installed = sftp::install(
+                &ssh,
+                &payload,
+                !options.force,
+                false,
+                options.remote_home.as_deref(),
+                timeout,
+            )?;
+            (installed.added, ssh.host_key())
+        }
+        result => {
+            let (status, stdout) = result?;
+            let added = match status {
+                None => return Err(TimedOut(timeout.unwrap_or_default()).into()),
+                Some(status) if !status.success() => {
+                    bail!("ssh process exited with error code: {:?}", status.code())
+                }
+                Some(_) if options.profile == Profile::Device => {
+                    payload.lines().map(str::to_string).collect()
+                }
+                Some(_) => remote_script::added(&stdout)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            };
+            (added, ssh.host_key())
+        }
+    };
+
+    let added = authorized_keys::keys_in(&added);
+    let (added, already_installed) = keys
+        .into_iter()
+        .partition(|key| keyblob::contains(&added, key));
+    let report = Report {
+        added,
+        already_installed,
+        host_key,
+    };
+    observer.installed(&report);
+    Ok(report)
+}
+
+/// Runs `ssh` with `remote_command`, feeding it `input` on stdin. Its
+/// stdout, without our marker lines, goes to `stdout` and its stderr to
+/// ours, as they arrive; both are watched for an sftp-only account's
+/// refusal ([`sftp::SftpOnly`]) and for lockouts, and go to the
+/// `--transcript`. Returns `None` for the
+/// status if ssh had to be killed after `timeout`.
+#[cfg(feature = "cli")]
+pub(crate) fn run_remote(
+    ssh: &mut connection::Ssh,
+    remote_command: &str,
+    input: &[u8],
+    timeout: Option<Duration>,
+    stdout: Box<dyn Write + Send>,
+) -> Result<(Option<ExitStatus>, String)> {
+    converse(ssh, remote_command, input, None, timeout, stdout)
+}
+
+/// What to write to a remote command's stdin once it has printed the line
+/// `after`.
+#[cfg(feature = "cli")]
+pub(crate) struct Reply<'a> {
+    pub after: &'static str,
+    pub answer: Box<dyn FnOnce() -> Vec<u8> + 'a>,
+}
+
+/// [`run_remote`], keeping stdin open after `input` until the command has
+/// printed `reply`'s line, and then writing its answer. A command that
+/// ends without printing the line gets no answer.
+#[cfg(feature = "cli")]
+pub(crate) fn converse(
+    ssh: &mut connection::Ssh,
+    remote_command: &str,
+    input: &[u8],
+    reply: Option<Reply>,
+    timeout: Option<Duration>,
+    stdout: Box<dyn Write + Send>,
+) -> Result<(Option<ExitStatus>, String)> {
+    let command = ssh.with_remote([remote_command]);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+
+    let (printed, seen) = std::sync::mpsc::channel();
+    let from = child.stdout.take().expect("stdout is piped");
+    let stdout = match &reply {
+        Some(reply) => relay(Watch::new(from, reply.after, printed), stdout, Some(remote_script::MARKER)),
+        None => relay(from, stdout, Some(remote_script::MARKER)),
+    };
+    let stderr = relay(
+        child.stderr.take().expect("stderr is piped"),
+        Box::new(std::io::stderr()),
+        None,
+    );
+
+    // Pipe the input to the SSH process
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .context("Failed to write to ssh stdin")?;
+        if let Some(reply) = reply {
+            if seen.recv_timeout(timeout.unwrap_or(Duration::MAX)).is_ok() {
+                // Fails only if the command is gone, which its status tells
+                let _ = stdin.write_all(&(reply.answer)());
+            }
+        }
+    }
+
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    transcript::record(&transcript::Session {
+        destination: ssh.destination(),
+        command: remote_command,
+        stdout: &stdout,
+        stderr: &stderr,
+        status,
+    });
+    if let Some(checksum) = remote_script::checksum_mismatch(&stdout) {
+        bail!(
+            "The remote shell received an altered install command (cksum {}), so it wasn't run; \
+             something between here and the shell (a forced command, a wrapper, a broken shell) rewrites it. \
+             Try -s or --remote-profile device",
+            checksum.trim_end()
+        );
+    }
+    match status.and_then(|status| status.code()) {
+        Some(1) if stdout.lines().any(|line| line.trim_end() == sftp::REFUSAL) => {
+            Err(sftp::SftpOnly.into())
+        }
+        Some(code) if code != 0 && remote_script::ran_on_windows(&format!("{}{}", stdout, stderr)) => {
+            bail!(
+                "The remote looks like Windows OpenSSH Server, whose shell can't run this install command; \
+                 use --remote-os windows"
+            )
+        }
+        Some(255) => match (lockout::detect(&stderr), token::fido_error(&stderr)) {
+            (Some(lockout), _) => Err(lockout.into()),
+            (None, Some(fido)) => Err(fido.into()),
+            (None, None) => Ok((status, stdout)),
+        },
+        _ => Ok((status, stdout)),
+    }
+}
+
+/// The error of a host that didn't finish within its timeout.
+#[cfg(feature = "cli")]
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+#[cfg(feature = "cli")]
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Timed out after {}s", self.0.as_secs())
+    }
+}
+
+#[cfg(feature = "cli")]
+impl std::error::Error for TimedOut {}
+
+/// Copies `from` to `to` as it arrives, in a thread that returns the text
+/// (up to 64 KiB of it). Lines starting with `hidden` are not passed on;
+/// the start of a line is held back only while it could still be one.
+#[cfg(feature = "cli")]
+pub(crate) fn relay(
+    from: impl std::io::Read + Send + 'static,
+    to: Box<dyn Write + Send>,
+    hidden: Option<&'static str>,
+) -> std::thread::JoinHandle<String> {
+    relay_up_to(from, to, hidden, 64 * 1024)
+}
+
+/// [`relay`], returning all of the text, for output that is parsed as a
+/// whole, such as a listing of authorized_keys.
+#[cfg(feature = "cli")]
+pub(crate) fn relay_all(
+    from: impl std::io::Read + Send + 'static,
+    to: Box<dyn Write + Send>,
+    hidden: Option<&'static str>,
+) -> std::thread::JoinHandle<String> {
+    relay_up_to(from, to, hidden, usize::MAX)
+}
+
+#[cfg(feature = "cli")]
+fn relay_up_to(
+    mut from: impl std::io::Read + Send + 'static,
+    mut to: Box<dyn Write + Send>,
+    hidden: Option<&'static str>,
+    limit: usize,
+) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let hidden = hidden.unwrap_or_default().as_bytes();
+        let mut seen = Vec::new();
+        // The undecided start of the current line, and whether it is hidden
+        let mut start = Vec::new();
+        let mut decided = hidden.is_empty().then_some(false);
+        let mut buffer = [0u8; 4096];
+        while let Ok(n @ 1..) = from.read(&mut buffer) {
+            let mut out = Vec::new();
+            for &byte in &buffer[..n] {
+                match decided {
+                    Some(true) => {}
+                    Some(false) => out.push(byte),
+                    None => {
+                        start.push(byte);
+                        if !hidden.starts_with(&start) || byte == b'\n' {
+                            out.append(&mut start);
+                            decided = Some(false);
+                        } else if start.len() == hidden.len() {
+                            start.clear();
+                            decided = Some(true);
+                        }
+                    }
+                }
+                if byte == b'\n' && !hidden.is_empty() {
+                    decided = None;
+                }
+            }
+            let _ = to.write_all(&out).and_then(|_| to.flush());
+            if seen.len() < limit {
+                seen.extend_from_slice(&buffer[..n]);
+            }
+        }
+        let _ = to.write_all(&start).and_then(|_| to.flush());
+        String::from_utf8_lossy(&seen).into_owned()
+    })
+}
+
+/// A reader that signals `printed` once it has passed on the line `line`.
+#[cfg(feature = "cli")]
+struct Watch<R> {
+    inner: R,
+    line: &'static [u8],
+    current: Vec<u8>,
+    printed: Option<std::sync::mpsc::Sender<()>>,
+}
+
+#[cfg(feature = "cli")]
+impl<R> Watch<R> {
+    fn new(inner: R, line: &'static str, printed: std::sync::mpsc::Sender<()>) -> Self {
+        Watch {
+            inner,
+            line: line.as_bytes(),
+            current: Vec::new(),
+            printed: Some(printed),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl<R: std::io::Read> std::io::Read for Watch<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buffer)?;
+        for &byte in &buffer[..n] {
+            if byte != b'\n' {
+                if self.current.len() <= self.line.len() {
+                    self.current.push(byte);
+                }
+                continue;
+            }
+            if self.current.strip_suffix(b"\r").unwrap_or(&self.current) == self.line {
+                if let Some(printed) = self.printed.take() {
+                    let _ = printed.send(());
+                }
+            }
+            self.current.clear();
+        }
+        Ok(n)
+    }
+}
+
+/// Waits for the child, killing it once `timeout` has passed. Returns
+/// `None` if it had to be killed.
+#[cfg(feature = "cli")]
+pub(crate) fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(child.wait().context("Failed to wait on ssh process")?));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait on ssh process")? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Runs `command` with `input` on stdin and returns its stdout.
+#[cfg(feature = "cli")]
+pub(crate) fn pipe(command: &mut Command, input: &[u8]) -> Result<Vec<u8>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Failed to spawn {}. Make sure '{}' is in your PATH.",
+                program, program
+            )
+        })?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(input));
+        child.wait_with_output()
+    })
+    .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} exited with error code: {:?}",
+            program,
+            output.status.code()
+        );
+    }
+    Ok(output.stdout)
+}
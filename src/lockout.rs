@@ -0,0 +1,87 @@
+//! Recognizing, from ssh's messages, a server that has stopped letting us
+//! in, so the cause isn't buried in the output of a failed run.
+
+/// Why the server turned the login away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lockout {
+    /// sshd's MaxAuthTries was reached, usually by keys offered before the
+    /// password
+    TooManyAuthFailures,
+    /// The connection was dropped before the handshake, as fail2ban, sshd's
+    /// PerSourcePenalties or MaxStartups do; usually temporary
+    Banned,
+}
+
+impl Lockout {
+    /// Whether waiting may help.
+    pub fn is_temporary(self) -> bool {
+        self == Lockout::Banned
+    }
+}
+
+impl std::fmt::Display for Lockout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lockout::TooManyAuthFailures => f.write_str(
+                "The server gave up after too many authentication attempts (MaxAuthTries), most likely \
+                 because ssh offered several keys before the password; set `IdentitiesOnly yes` for \
+                 the host in ~/.ssh/config, or run with SSH_AUTH_SOCK unset",
+            ),
+            Lockout::Banned => f.write_str(
+                "The server dropped the connection before the SSH handshake, as fail2ban, sshd's \
+                 PerSourcePenalties or MaxStartups do after repeated failed logins; wait a while \
+                 before trying again, or use --retry-lockout",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Lockout {}
+
+/// The lockout ssh's stderr shows, if any.
+pub fn detect(stderr: &str) -> Option<Lockout> {
+    let lines = || stderr.lines().map(str::trim);
+    if lines().any(|line| line.contains("Too many authentication failures")) {
+        return Some(Lockout::TooManyAuthFailures);
+    }
+    let banned = lines().any(|line| {
+        (line.starts_with("kex_exchange_identification:")
+            && (line.ends_with("Connection closed by remote host")
+                || line.ends_with("Connection reset by peer")))
+            || line == "Not allowed at this time"
+    });
+    banned.then_some(Lockout::Banned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_lockouts() {
+        assert_eq!(
+            detect(
+                "Received disconnect from 192.0.2.1 port 22:2: Too many authentication failures\r\nDisconnected from 192.0.2.1 port 22\r\n"
+            ),
+            Some(Lockout::TooManyAuthFailures)
+        );
+        assert_eq!(
+            detect(
+                "kex_exchange_identification: read: Connection reset by peer\nConnection reset by 192.0.2.1 port 22\n"
+            ),
+            Some(Lockout::Banned)
+        );
+        assert_eq!(
+            detect("kex_exchange_identification: Connection closed by remote host\n"),
+            Some(Lockout::Banned)
+        );
+        assert_eq!(
+            detect("ssh: connect to host h port 22: Connection refused\n"),
+            None
+        );
+        assert_eq!(
+            detect("user@h: Permission denied (publickey,password).\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! TCP connections made by this tool itself (probes, proxies).
+//!
+//! Dual-stack hosts are connected to Happy Eyeballs style (RFC 8305): the
+//! addresses are tried alternating between IPv6 and IPv4, each attempt
+//! starting [`ATTEMPT_DELAY`] after the previous one (or as soon as it
+//! fails) while earlier ones keep running, and the first to connect wins.
+//! A host with a broken AAAA record thus costs a quarter second instead of
+//! a full connect timeout.
+
+use anyhow::{Context, Result, bail};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// How long an attempt gets before the next address is tried alongside it.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to `host:port`, giving up after `timeout`.
+pub fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Could not resolve {}", host))?
+        .collect();
+    if addresses.is_empty() {
+        bail!("Could not resolve {}", host);
+    }
+    connect_any(interleave(addresses), timeout)
+        .with_context(|| format!("Could not connect to {}:{}", host, port))
+}
+
+/// Races connection attempts to `addresses`, in order.
+fn connect_any(addresses: Vec<SocketAddr>, timeout: Duration) -> Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    let several = addresses.len() > 1;
+    let (sender, receiver) = mpsc::channel();
+    let mut remaining = addresses.into_iter();
+    let mut running = 0;
+    let mut last_error = None;
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        if let Some(address) = remaining.next() {
+            let sender = sender.clone();
+            // Attempts that lose the race finish on their own and are dropped
+            std::thread::spawn(move || {
+                let _ = sender.send((address, TcpStream::connect_timeout(&address, left)));
+            });
+            running += 1;
+        } else if running == 0 {
+            break;
+        }
+
+        let wait = if remaining.len() > 0 {
+            ATTEMPT_DELAY.min(left)
+        } else {
+            left
+        };
+        match receiver.recv_timeout(wait) {
+            Ok((_, Ok(stream))) => return Ok(stream),
+            // Start the next attempt right away
+            Ok((address, Err(e))) => {
+                running -= 1;
+                last_error = Some(if several {
+                    format!("{}: {}", address, e)
+                } else {
+                    e.to_string()
+                });
+            }
+            Err(_) => {}
+        }
+    }
+    match last_error {
+        Some(error) if running == 0 => bail!("{}", error),
+        _ => bail!("timed out after {}s", timeout.as_secs_f64()),
+    }
+}
+
+/// Alternates address families, starting with the resolver's first choice.
+fn interleave(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addresses.first().is_some_and(SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|address| address.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    let mut other = other.into_iter();
+    for address in preferred {
+        ordered.push(address);
+        ordered.extend(other.next());
+    }
+    ordered.extend(other);
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn interleaves_families() {
+        let parse = |list: &[&str]| -> Vec<SocketAddr> {
+            list.iter().map(|s| s.parse().unwrap()).collect()
+        };
+        assert_eq!(
+            interleave(parse(&["[::1]:22", "[::2]:22", "[::3]:22", "10.0.0.1:22"])),
+            parse(&["[::1]:22", "10.0.0.1:22", "[::2]:22", "[::3]:22"])
+        );
+        assert_eq!(
+            interleave(parse(&[
+                "10.0.0.1:22",
+                "10.0.0.2:22",
+                "[::1]:22",
+                "[::2]:22",
+                "[::3]:22"
+            ])),
+            parse(&[
+                "10.0.0.1:22",
+                "[::1]:22",
+                "10.0.0.2:22",
+                "[::2]:22",
+                "[::3]:22"
+            ])
+        );
+    }
+
+    #[test]
+    fn unreachable_address_does_not_hold_up_the_next() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // A blackholed address (TEST-NET-1) first, as a broken AAAA would be
+        let addresses = vec![
+            SocketAddr::from(([192, 0, 2, 1], port)),
+            SocketAddr::from(([127, 0, 0, 1], port)),
+        ];
+        let started = Instant::now();
+        let stream = connect_any(addresses, Duration::from_secs(10)).unwrap();
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,103 @@
+//! Target discovery from NetBox (`--netbox-url`): the primary IPs of the
+//! devices and virtual machines matching the given filters.
+
+use crate::http;
+use crate::json::Value;
+use anyhow::{Context, Result, bail};
+
+/// Filters for the NetBox device and virtual machine lists; each may be
+/// given several times, and values of the same filter are OR-ed.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub url: String,
+    pub token: String,
+    pub sites: Vec<String>,
+    pub roles: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// Guards against a `next` link that never ends.
+const MAX_PAGES: usize = 1000;
+
+/// The primary IP addresses of the matching devices and virtual machines.
+pub fn discover(query: &Query) -> Result<Vec<String>> {
+    let base = query.url.trim_end_matches('/');
+    let mut filters = vec!["has_primary_ip=true".to_string(), "limit=1000".to_string()];
+    for (name, values) in [
+        ("site", &query.sites),
+        ("role", &query.roles),
+        ("tag", &query.tags),
+    ] {
+        filters.extend(
+            values
+                .iter()
+                .map(|v| format!("{}={}", name, http::encode(v))),
+        );
+    }
+    let headers = [format!("Authorization: Token {}", query.token)];
+
+    let mut addresses = Vec::new();
+    for list in ["dcim/devices", "virtualization/virtual-machines"] {
+        let mut next = Some(format!("{}/api/{}/?{}", base, list, filters.join("&")));
+        let mut pages = 0;
+        while let Some(url) = next.take() {
+            pages += 1;
+            if pages > MAX_PAGES {
+                bail!("NetBox returned more than {} pages for {}", MAX_PAGES, list);
+            }
+            let page = http::get_json(&url, &headers)?;
+            let results = page
+                .get("results")
+                .and_then(Value::as_array)
+                .with_context(|| format!("Unexpected NetBox response from {}", url))?;
+            for object in results {
+                if let Some(address) = primary_ip(object) {
+                    addresses.push(address);
+                }
+            }
+            next = page.get("next").and_then(Value::as_str).map(str::to_string);
+        }
+    }
+    if addresses.is_empty() {
+        bail!("No NetBox devices or virtual machines with a primary IP match the filters");
+    }
+    Ok(addresses)
+}
+
+/// The `primary_ip` of a device or VM, without its prefix length.
+fn primary_ip(object: &Value) -> Option<String> {
+    let cidr = object.get("primary_ip")?.get("address")?.as_str()?;
+    Some(cidr.split('/').next().unwrap_or(cidr).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_primary_ips() {
+        let page = Value::parse(
+            r#"{"results": [
+                {"name": "web1", "primary_ip": {"address": "10.0.0.1/24"}},
+                {"name": "web2", "primary_ip": {"address": "2001:db8::2/64"}},
+                {"name": "spare", "primary_ip": null}
+            ]}"#,
+        )
+        .unwrap();
+        let ips: Vec<Option<String>> = page
+            .get("results")
+            .and_then(Value::as_array)
+            .unwrap()
+            .iter()
+            .map(primary_ip)
+            .collect();
+        assert_eq!(
+            ips,
+            [
+                Some("10.0.0.1".to_string()),
+                Some("2001:db8::2".to_string()),
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! The parts of an install that need no connection, for callers that only
+//! have the text: web consoles validating keys and previewing what an
+//! install or a plan would do. They use the same code as the binary, and
+//! build without the `cli` feature, on targets that can't start processes
+//! such as `wasm32-wasip1`:
+//!
+//! ```
+//! use ssh_copy_id_rs::offline;
+//!
+//! let keys = offline::parse_keys(
+//!     "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl alice@laptop",
+//! )?;
+//! let merge = offline::merge("", &keys, &[])?;
+//! assert_eq!(merge.added.len(), 1);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::authorized_keys;
+use crate::key::PublicKey;
+use crate::keyblob;
+use crate::keyformat;
+use crate::plan::{self, Action};
+use anyhow::{Result, anyhow, bail};
+
+/// The keys in `content`: OpenSSH lines, RFC 4716 blocks or PEM, as `-i`
+/// reads them, without duplicates.
+pub fn parse_keys(content: &str) -> Result<Vec<PublicKey>> {
+    let keys = keyblob::dedupe(keyformat::read_any(content)?);
+    if keys.is_empty() {
+        bail!("No keys found");
+    }
+    Ok(keys)
+}
+
+/// What installing keys on an authorized_keys would do.
+#[derive(Clone, Debug, Default)]
+pub struct Merge {
+    /// The authorized_keys afterwards
+    pub content: String,
+    /// The keys that would be appended
+    pub added: Vec<PublicKey>,
+    /// The keys that are installed already
+    pub already_installed: Vec<PublicKey>,
+    /// The lines of the existing file sshd would skip, by line number with
+    /// the reason
+    pub invalid: Vec<(usize, String)>,
+}
+
+/// Installs `keys` with `options` (as `--key-option` takes them) in
+/// `existing`, the content of an authorized_keys, as an install over SFTP
+/// does: line by line, appending the lines it doesn't have.
+pub fn merge(existing: &str, keys: &[PublicKey], options: &[String]) -> Result<Merge> {
+    let payload = keys
+        .iter()
+        .map(|key| authorized_keys::with_options(key, options).map(|line| line + "\n"))
+        .collect::<std::result::Result<String, String>>()
+        .map_err(|reason| anyhow!("Invalid key option: {}", reason))?;
+    let (content, added) = authorized_keys::append_missing(existing, &payload);
+    let added = authorized_keys::keys_in(&added);
+    let (added, already_installed) = keys
+        .iter()
+        .cloned()
+        .partition(|key| keyblob::contains(&added, key));
+    Ok(Merge {
+        content,
+        added,
+        already_installed,
+        invalid: authorized_keys::parse(existing).invalid,
+    })
+}
+
+/// What a `--plan-file` records for a host.
+#[derive(Clone, Debug, Default)]
+pub struct HostPlan {
+    /// The digest of the keys installed, which `--apply-plan` checks
+    pub state: String,
+    /// The keys to add, or to remove
+    pub keys: Vec<PublicKey>,
+}
+
+/// The plan for adding `keys` to (or with `remove`, removing them from) a
+/// host whose authorized_keys is `existing`.
+pub fn plan(existing: &str, keys: &[PublicKey], remove: bool) -> Result<HostPlan> {
+    let installed = authorized_keys::parse(existing).keys;
+    let action = match remove {
+        true => Action::Remove,
+        false => Action::Add,
+    };
+    Ok(HostPlan {
+        state: plan::state(&installed)?,
+        keys: plan::changes(action, &installed, keys),
+    })
+}
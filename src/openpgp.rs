@@ -0,0 +1,49 @@
+//! SSH keys derived from OpenPGP authentication subkeys (`--gpg`,
+//! `--openpgp-authcard`), exported by gpg in the same form gpg-agent's
+//! ssh support offers them.
+
+use anyhow::{Context, Result, bail};
+use std::process::{Command, Stdio};
+
+/// Exports the authentication subkey of KEYID (a key ID, fingerprint or
+/// user ID known to the local keyring) as an OpenSSH public key line.
+pub fn export(key_id: &str) -> Result<String> {
+    if key_id.starts_with('-') {
+        bail!("Invalid --gpg key ID: {:?}", key_id);
+    }
+    gpg(&["--export-ssh-key", key_id])
+        .with_context(|| format!("No usable authentication subkey for {:?}", key_id))
+}
+
+/// Exports the authentication key of the OpenPGP card currently inserted.
+pub fn export_card() -> Result<String> {
+    let status = gpg(&["--card-status", "--with-colons"])
+        .context("No OpenPGP card found (is the card inserted and scdaemon running?)")?;
+    // `fpr:SIG:ENC:AUTH:` lists the fingerprints of the three card slots
+    let fingerprint = status
+        .lines()
+        .find_map(|line| line.strip_prefix("fpr:"))
+        .and_then(|slots| slots.split(':').nth(2))
+        .filter(|fpr| !fpr.is_empty())
+        .context("The OpenPGP card has no authentication key")?;
+    // The trailing '!' selects exactly this subkey
+    gpg(&["--export-ssh-key", &format!("{}!", fingerprint)]).with_context(|| {
+        format!(
+            "The card's authentication key {} is not in the local keyring; import it with `gpg --card-edit` then `fetch`",
+            fingerprint
+        )
+    })
+}
+
+fn gpg(args: &[&str]) -> Result<String> {
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run gpg. Make sure GnuPG is installed and in your PATH.")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,54 @@
+//! Paging of long reports through `$PAGER`, the way git does it: only when
+//! stdout is a terminal and the text doesn't fit on one screen.
+
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Prints `text`, through the pager when `enabled` and it is too long.
+pub fn show(text: &str, enabled: bool) {
+    if enabled && std::io::stdout().is_terminal() {
+        if let Some(rows) = terminal_rows() {
+            if text.lines().count() >= rows && page(text) {
+                return;
+            }
+        }
+    }
+    print!("{}", text);
+}
+
+/// Runs the pager on `text`; false if no pager could be started.
+fn page(text: &str) -> bool {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" || !cfg!(unix) {
+        return false;
+    }
+
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(&pager).stdin(Stdio::piped());
+    // Same defaults as git: quit if one screen, keep colors, don't clear
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let Ok(mut child) = command.spawn() else {
+        return false;
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user quitting the pager early closes the pipe; that's fine
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let _ = child.wait();
+    true
+}
+
+#[cfg(unix)]
+fn terminal_rows() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only writes a winsize struct to the given pointer.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_row > 0).then_some(size.ws_row as usize)
+}
+
+#[cfg(not(unix))]
+fn terminal_rows() -> Option<usize> {
+    None
+}
@@ -0,0 +1,207 @@
+//! Logging in with a password where keys aren't accepted yet, without
+//! sshpass or a terminal for ssh to prompt on: ssh is handed an
+//! `SSH_ASKPASS` helper that answers its password prompts.
+//!
+//! With `--remember-password`, each host's password is kept in the OS
+//! keychain (see [`crate::keychain`]) once it has worked, under
+//! `user@host:port`, and used from there on later runs.
+
+use crate::fsutil::TempFile;
+use crate::keychain;
+use crate::transcript;
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+use std::process::Command;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Where the login password comes from.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct PasswordArgs {
+    /// Log in with the password on the first line of FILE while keys aren't accepted yet
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["password_env", "ask_password"])]
+    pub password_file: Option<PathBuf>,
+
+    /// Log in with the password in the environment variable VAR
+    #[arg(long, value_name = "VAR", conflicts_with = "ask_password")]
+    pub password_env: Option<String>,
+
+    /// Prompt for the password once, before connecting, and answer ssh's password prompts with it
+    #[arg(long)]
+    pub ask_password: bool,
+
+    /// Keep each host's password in the OS keychain (macOS Keychain or libsecret; not Windows) once it has worked, and use it from there next time; hosts without one are prompted for unless a password is given
+    #[arg(long)]
+    pub remember_password: bool,
+}
+
+/// The keychain service the passwords are stored under.
+const SERVICE: &str = "ssh-copy-id-rs-password";
+
+/// The passwords typed during the run: by destination with
+/// `--remember-password` alone, or under "" for every host.
+static PROMPTED: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+impl PasswordArgs {
+    /// The password to log in to `destination` (`user@host:port`) with, if
+    /// any.
+    pub fn password(&self, destination: &str) -> Result<Option<String>> {
+        let mut password = None;
+        if self.remember_password {
+            password = keychain::lookup(SERVICE, destination)?;
+        }
+        if password.is_none() {
+            password = self.given(destination)?;
+        }
+        if let Some(password) = &password {
+            transcript::redact_secret(password);
+        }
+        Ok(password)
+    }
+
+    /// With `--remember-password`, stores the password `destination` was
+    /// logged in to with, unless the keychain had it already.
+    pub fn remember(&self, destination: &str) -> Result<()> {
+        if !self.remember_password || keychain::lookup(SERVICE, destination)?.is_some() {
+            return Ok(());
+        }
+        let Some(password) = self.given(destination)? else {
+            return Ok(());
+        };
+        keychain::store(
+            SERVICE,
+            destination,
+            &format!("ssh-copy-id-rs password for {}", destination),
+            &password,
+        )
+    }
+
+    /// The password from the command line's source, or typed.
+    fn given(&self, destination: &str) -> Result<Option<String>> {
+        if let Some(path) = &self.password_file {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the password file {:?}", path))?;
+            let password = content.lines().next().unwrap_or_default();
+            if password.is_empty() {
+                bail!("The password file {:?} is empty", path);
+            }
+            return Ok(Some(password.to_string()));
+        }
+        if let Some(name) = &self.password_env {
+            return match std::env::var(name) {
+                Ok(password) if !password.is_empty() => Ok(Some(password)),
+                _ => bail!("--password-env: ${} is not set", name),
+            };
+        }
+        if !self.ask_password && !self.remember_password {
+            return Ok(None);
+        }
+        let (key, message) = if self.ask_password {
+            (String::new(), "Password: ".to_string())
+        } else {
+            (destination.to_string(), format!("Password for {}: ", destination))
+        };
+        let mut prompted = PROMPTED.lock().unwrap_or_else(|e| e.into_inner());
+        let prompted = prompted.get_or_insert_with(HashMap::new);
+        if let Some(password) = prompted.get(&key) {
+            return Ok(Some(password.clone()));
+        }
+        let password = prompt(&message)?;
+        prompted.insert(key, password.clone());
+        Ok(Some(password))
+    }
+
+    /// Has `ssh` answer its password prompts with the password for
+    /// `destination`, if one was asked for. The returned helper must be kept
+    /// until ssh has finished.
+    pub fn apply(&self, ssh: &mut Command, destination: &str) -> Result<Option<TempFile>> {
+        let Some(password) = self.password(destination)? else {
+            return Ok(None);
+        };
+        let helper = askpass(&password)?;
+        ssh.env("SSH_ASKPASS", &helper.path).env("SSH_ASKPASS_REQUIRE", "force");
+        // OpenSSH before 8.4 ignores SSH_ASKPASS_REQUIRE, and only uses the
+        // helper with a DISPLAY and no terminal
+        if std::env::var_os("DISPLAY").is_none() {
+            ssh.env("DISPLAY", "none");
+        }
+        // A wrong password is tried once, not three times towards a lockout
+        ssh.args(["-o", "NumberOfPasswordPrompts=1"]);
+        Ok(Some(helper))
+    }
+}
+
+/// A script that prints `password` when ssh's prompt asks for a password,
+/// and declines anything else (e.g. confirming an unknown host key).
+#[cfg(unix)]
+fn askpass(password: &str) -> Result<TempFile> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let helper = TempFile::create("askpass", script(password).as_bytes())?;
+    std::fs::set_permissions(&helper.path, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to make {:?} executable", helper.path))?;
+    Ok(helper)
+}
+
+#[cfg(not(unix))]
+fn askpass(_password: &str) -> Result<TempFile> {
+    bail!("Password logins aren't supported on this platform yet; install the key with ssh's own prompt")
+}
+
+fn script(password: &str) -> String {
+    format!(
+        "#!/bin/sh\ncase \"$1\" in\n*[Pp]assword*) printf '%s\\n' '{}' ;;\n*) exit 1 ;;\nesac\n",
+        password.replace('\'', "'\\''")
+    )
+}
+
+/// Reads a line from the terminal with echo off.
+#[cfg(unix)]
+fn prompt(message: &str) -> Result<String> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("--ask-password needs a terminal; use --password-file or --password-env instead")?;
+    let terminal = tty.try_clone()?;
+    let stty = |setting: &str| {
+        Command::new("stty")
+            .arg(setting)
+            .stdin(terminal.try_clone()?)
+            .status()
+    };
+    write!(tty, "{}", message)?;
+    tty.flush()?;
+    let echo_off = stty("-echo").is_ok_and(|status| status.success());
+    let mut line = String::new();
+    let read = BufReader::new(tty.try_clone()?).read_line(&mut line);
+    if echo_off {
+        let _ = stty("echo");
+    }
+    writeln!(tty)?;
+    read.context("Failed to read the password")?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        bail!("No password given");
+    }
+    Ok(password)
+}
+
+#[cfg(not(unix))]
+fn prompt(_message: &str) -> Result<String> {
+    bail!("--ask-password isn't supported on this platform yet; use --password-file or --password-env")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn askpass_script_quotes_the_password() {
+        let script = script("it's $x");
+        assert!(script.contains("printf '%s\\n' 'it'\\''s $x'"));
+        assert!(script.contains("*) exit 1 ;;"));
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const APP_DIR: &str = "ssh-copy-id-rs";
+
+/// Local files the tool owns, and which base directory each belongs in.
+#[derive(Clone, Copy)]
+pub enum LocalFile {
+    History,
+    Aliases,
+}
+
+impl LocalFile {
+    const ALL: [LocalFile; 2] = [LocalFile::History, LocalFile::Aliases];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            LocalFile::History => "history",
+            LocalFile::Aliases => "aliases",
+        }
+    }
+
+    fn base_dir(self) -> Result<PathBuf> {
+        match self {
+            LocalFile::History => state_dir(),
+            LocalFile::Aliases => config_dir(),
+        }
+    }
+
+    pub fn path(self) -> Result<PathBuf> {
+        Ok(self.base_dir()?.join(self.file_name()))
+    }
+}
+
+/// User configuration, e.g. `$XDG_CONFIG_HOME/ssh-copy-id-rs`
+/// (`%APPDATA%` on Windows, `~/Library/Application Support` on macOS).
+pub fn config_dir() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|d| d.join(APP_DIR))
+        .context("Could not determine the configuration directory")
+}
+
+/// Run state such as history, e.g. `$XDG_STATE_HOME/ssh-copy-id-rs`.
+/// Platforms without a state directory fall back to the local data directory.
+pub fn state_dir() -> Result<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|d| d.join(APP_DIR))
+        .context("Could not determine the state directory")
+}
+
+/// Disposable data such as repository checkouts, e.g. `$XDG_CACHE_HOME/ssh-copy-id-rs`.
+pub fn cache_dir() -> Result<PathBuf> {
+    dirs::cache_dir()
+        .map(|d| d.join(APP_DIR))
+        .context("Could not determine the cache directory")
+}
+
+/// Set by `--ssh-dir`.
+static SSH_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses `dir` in place of `~/.ssh` from now on.
+pub fn set_ssh_dir(dir: PathBuf) {
+    let _ = SSH_DIR.set(dir);
+}
+
+/// The `--ssh-dir` directory, if one was given.
+pub fn ssh_dir_override() -> Option<&'static Path> {
+    SSH_DIR.get().map(PathBuf::as_path)
+}
+
+/// Where the user's ssh keys and config are: `~/.ssh` unless relocated
+/// with `--ssh-dir`.
+pub fn ssh_dir() -> Option<PathBuf> {
+    match ssh_dir_override() {
+        Some(dir) => Some(dir.to_path_buf()),
+        None => dirs::home_dir().map(|home| home.join(".ssh")),
+    }
+}
+
+/// The single dot-directory used before the XDG layout.
+fn legacy_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh-copy-id-rs"))
+}
+
+/// Moves files from the legacy `~/.ssh-copy-id-rs` directory into the XDG
+/// layout. Files already present at the new location are left untouched.
+pub fn migrate_legacy() -> Result<()> {
+    let Some(legacy) = legacy_dir() else {
+        return Ok(());
+    };
+    if !legacy.is_dir() {
+        return Ok(());
+    }
+
+    for file in LocalFile::ALL {
+        let old = legacy.join(file.file_name());
+        let new = file.path()?;
+        if old.exists() && !new.exists() {
+            move_file(&old, &new)?;
+        }
+    }
+
+    // Only succeeds once nothing we don't know about is left behind
+    let _ = fs::remove_dir(&legacy);
+    Ok(())
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    // rename fails across filesystems, so fall back to copy + remove
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).with_context(|| format!("Failed to migrate {:?} to {:?}", from, to))?;
+        fs::remove_file(from).with_context(|| format!("Failed to remove {:?}", from))?;
+    }
+    Ok(())
+}
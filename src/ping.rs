@@ -0,0 +1,183 @@
+//! `--ping`: a reachability check of every target before a rollout.
+//!
+//! Only the TCP connection and the server's identification string are
+//! checked; nothing is authenticated and nothing is changed. Hosts are
+//! checked in parallel.
+
+use crate::destination::Destination;
+use crate::json::Value;
+use crate::porcelain;
+use crate::probe::Route;
+use crate::reporter::{Format, csv_field};
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+/// Hosts checked at the same time.
+const PARALLEL: usize = 32;
+
+pub enum Status {
+    /// Answered with this identification string
+    Reachable(String),
+    Unreachable(String),
+    /// Not checked, e.g. because it is only reachable through a jump host
+    Skipped(String),
+}
+
+pub struct Outcome {
+    pub destination: String,
+    pub status: Status,
+    pub duration: Duration,
+}
+
+impl Outcome {
+    fn status_name(&self) -> &'static str {
+        match self.status {
+            Status::Reachable(_) => "ok",
+            Status::Unreachable(_) => "failed",
+            Status::Skipped(_) => "skipped",
+        }
+    }
+
+    fn banner(&self) -> Option<&str> {
+        match &self.status {
+            Status::Reachable(banner) => Some(banner),
+            _ => None,
+        }
+    }
+
+    fn error(&self) -> Option<&str> {
+        match &self.status {
+            Status::Reachable(_) => None,
+            Status::Unreachable(error) | Status::Skipped(error) => Some(error),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("destination", self.destination.as_str())
+            .with("status", self.status_name())
+            .with("duration_ms", self.duration.as_millis() as u64)
+            .with("banner", self.banner())
+            .with("error", self.error())
+    }
+}
+
+/// Checks every destination along the route `route` gives for it.
+pub fn run(
+    destinations: &[Destination],
+    timeout: Duration,
+    route: impl Fn(&Destination) -> Result<Route> + Sync,
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    for chunk in destinations.chunks(PARALLEL) {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|destination| {
+                    let route = &route;
+                    scope.spawn(move || {
+                        let started = Instant::now();
+                        let status = match route(destination) {
+                            Ok(Route::Unchecked(reason)) => Status::Skipped(reason),
+                            Ok(route) => match route.banner(timeout) {
+                                Ok(banner) => Status::Reachable(banner),
+                                Err(e) => Status::Unreachable(format!("{:#}", e)),
+                            },
+                            Err(e) => Status::Unreachable(format!("{:#}", e)),
+                        };
+                        Outcome {
+                            destination: destination.to_string(),
+                            status,
+                            duration: started.elapsed(),
+                        }
+                    })
+                })
+                .collect();
+            outcomes.extend(
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("ping thread panicked")),
+            );
+        });
+    }
+    outcomes
+}
+
+/// Prints the outcomes in the selected format.
+pub fn report(outcomes: &[Outcome], format: Format, porcelain: Option<porcelain::Version>) {
+    let reachable = outcomes.iter().filter(|o| o.banner().is_some()).count();
+    if let Some(version) = porcelain {
+        println!("{}", version.header());
+        for outcome in outcomes {
+            println!(
+                "{}",
+                porcelain::line(&[
+                    "ping",
+                    &outcome.destination,
+                    outcome.status_name(),
+                    &outcome.duration.as_millis().to_string(),
+                    outcome.banner().unwrap_or("-"),
+                    outcome.error().unwrap_or("-"),
+                ])
+            );
+        }
+        return;
+    }
+
+    match format {
+        Format::Human | Format::Tui => {
+            let width = outcomes
+                .iter()
+                .map(|o| o.destination.len())
+                .max()
+                .unwrap_or(0);
+            for outcome in outcomes {
+                let detail = match &outcome.status {
+                    Status::Reachable(banner) => {
+                        format!("{:>5}ms  {}", outcome.duration.as_millis(), banner)
+                    }
+                    Status::Unreachable(error) | Status::Skipped(error) => error.clone(),
+                };
+                println!(
+                    "{:<width$}  {:<7}  {}",
+                    outcome.destination,
+                    outcome.status_name(),
+                    detail,
+                    width = width
+                );
+            }
+            println!("\n{} of {} hosts reachable", reachable, outcomes.len());
+        }
+        Format::Json => {
+            let hosts: Vec<Value> = outcomes.iter().map(Outcome::to_json).collect();
+            let summary = Value::object()
+                .with("hosts", outcomes.len())
+                .with("reachable", reachable);
+            println!(
+                "{}",
+                Value::object()
+                    .with("hosts", hosts)
+                    .with("summary", summary)
+            );
+        }
+        Format::Jsonl => {
+            for outcome in outcomes {
+                println!("{}", outcome.to_json());
+            }
+        }
+        Format::Csv => {
+            println!("destination,status,duration_ms,banner,error");
+            for outcome in outcomes {
+                println!(
+                    "{},{},{},{},{}",
+                    csv_field(&outcome.destination),
+                    outcome.status_name(),
+                    outcome.duration.as_millis(),
+                    csv_field(outcome.banner().unwrap_or_default()),
+                    csv_field(outcome.error().unwrap_or_default())
+                );
+            }
+        }
+        Format::Quiet => {}
+    }
+}
@@ -0,0 +1,275 @@
+//! `--plan-file` and `--apply-plan`: a run in two phases, for changes that
+//! are reviewed before they are made. The first records, for each host,
+//! the keys a `--dry-run` shows would be added (or removed), as the lines
+//! that install them, options included, and a digest of the keys its
+//! authorized_keys held; the second makes exactly those changes, on the
+//! hosts whose keys are still the ones planned against.
+//!
+//! Plans are plain text, so they can be read and approved as they are:
+//!
+//! ```text
+//! ssh-copy-id-rs plan 1
+//! action add
+//! want no-pty ssh-ed25519 AAAA... alice@laptop
+//! host alice@web1:22 alice@web1
+//! state 3b5d...
+//! key no-pty ssh-ed25519 AAAA... alice@laptop
+//! ```
+
+use crate::authorized_keys::{self, Line};
+use crate::digest;
+use crate::fsutil;
+use crate::key::PublicKey;
+use crate::keyblob;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// First line of a plan file.
+const HEADER: &str = "ssh-copy-id-rs plan 1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Remove,
+}
+
+/// What is planned for one host.
+#[derive(Debug, PartialEq)]
+pub struct HostPlan {
+    /// `user@host:port`, with the defaults filled in, as the connection
+    /// options in effect resolved it
+    pub label: String,
+    /// The destination to connect to, as given
+    pub destination: String,
+    /// [`state`] of the host's authorized_keys when the plan was made
+    pub state: String,
+    /// The keys to add or remove; none when the host needs no change
+    pub keys: Vec<PublicKey>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    pub action: Action,
+    /// The options in front of each key line an add installs, as given
+    /// (`cert-authority`, the `--key-option`s); the same for every key
+    pub options: Vec<String>,
+    /// The keys the plan was made for, which a host that drifted is
+    /// planned again with
+    pub wanted: Vec<PublicKey>,
+    pub hosts: Vec<HostPlan>,
+}
+
+/// The error of a host whose authorized_keys no longer hold the keys the
+/// plan was made against.
+#[derive(Debug)]
+pub struct Drift(pub String);
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The keys in authorized_keys on {} have changed since the plan was made; make a new plan",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for Drift {}
+
+/// A digest of the keys `installed`, whatever their order, spelling,
+/// options or comments.
+pub fn state(installed: &[PublicKey]) -> Result<String> {
+    let mut fingerprints = keyblob::dedupe(installed.to_vec())
+        .iter()
+        .map(|key| Ok(key.decode()?.fingerprint_sha256()))
+        .collect::<Result<Vec<_>>>()?;
+    fingerprints.sort();
+    let digest = digest::sha256(fingerprints.join("\n").as_bytes());
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The keys of `wanted` that `action` changes on a host whose
+/// authorized_keys hold `installed`: the ones it lacks, or for a removal the
+/// ones it has.
+pub fn changes(action: Action, installed: &[PublicKey], wanted: &[PublicKey]) -> Vec<PublicKey> {
+    wanted
+        .iter()
+        .filter(|key| keyblob::contains(installed, key) == (action == Action::Remove))
+        .cloned()
+        .collect()
+}
+
+impl Plan {
+    /// Reads the plan in `path`.
+    pub fn load(path: &Path) -> Result<Plan> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan file: {:?}", path))?;
+        Plan::parse(&content).with_context(|| format!("Invalid plan file: {:?}", path))
+    }
+
+    /// Writes the plan to `path`, replacing any plan there.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fsutil::write_atomic(path, self.render().as_bytes())
+    }
+
+    /// The line that installs `key`, as the plan records it.
+    pub fn line(&self, key: &PublicKey) -> String {
+        match self.options.is_empty() {
+            true => key.to_line(),
+            false => format!("{} {}", self.options.join(","), key.to_line()),
+        }
+    }
+
+    fn render(&self) -> String {
+        let action = match self.action {
+            Action::Add => "add",
+            Action::Remove => "remove",
+        };
+        let mut content = format!("{}\naction {}\n", HEADER, action);
+        for key in &self.wanted {
+            content.push_str(&format!("want {}\n", self.line(key)));
+        }
+        for host in &self.hosts {
+            content.push_str(&format!(
+                "host {} {}\nstate {}\n",
+                host.label, host.destination, host.state
+            ));
+            for key in &host.keys {
+                content.push_str(&format!("key {}\n", self.line(key)));
+            }
+        }
+        content
+    }
+
+    fn parse(content: &str) -> Result<Plan> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            bail!("not a plan written by this version of ssh-copy-id-rs");
+        }
+        let action = match lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("action "))
+        {
+            Some("add") => Action::Add,
+            Some("remove") => Action::Remove,
+            _ => bail!("line 2: expected `action add` or `action remove`"),
+        };
+        let mut wanted = Vec::new();
+        let mut hosts: Vec<HostPlan> = Vec::new();
+        let mut options: Option<String> = None;
+        // The key of a `want` or `key` line, whose options must be those
+        // of the others, so that each line is exactly what is installed
+        let mut key_of = |value: &str, number: usize| -> Result<PublicKey> {
+            let key = match authorized_keys::parse_line(value) {
+                Line::Key(entry) => entry.key,
+                Line::Invalid(reason) => bail!("line {}: invalid key: {}", number, reason),
+                _ => bail!("line {}: expected a key", number),
+            };
+            let Some(given) = value.strip_suffix(&key.to_line()) else {
+                bail!("line {}: expected the options, a space and the key", number);
+            };
+            let given = given.trim_end();
+            match &options {
+                Some(options) if options != given => {
+                    bail!("line {}: the options differ from those of the other keys", number)
+                }
+                Some(_) => {}
+                None => options = Some(given.to_string()),
+            }
+            Ok(key)
+        };
+        for (number, line) in lines {
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            match (field, hosts.last_mut()) {
+                ("host", _) => {
+                    let Some((label, destination)) = value.split_once(' ') else {
+                        bail!("line {}: expected `host LABEL DESTINATION`", number);
+                    };
+                    hosts.push(HostPlan {
+                        label: label.to_string(),
+                        destination: destination.to_string(),
+                        state: String::new(),
+                        keys: Vec::new(),
+                    });
+                }
+                ("want", None) => wanted.push(key_of(value, number)?),
+                ("state", Some(host)) => host.state = value.to_string(),
+                ("key", Some(host)) => host.keys.push(key_of(value, number)?),
+                ("", _) => {}
+                _ => bail!("line {}: unexpected {:?}", number, field),
+            }
+        }
+        if let Some(host) = hosts.iter().find(|host| host.state.is_empty()) {
+            bail!("no state recorded for {}", host.label);
+        }
+        if action == Action::Remove && options.as_ref().is_some_and(|options| !options.is_empty()) {
+            bail!("a removal plan has no options");
+        }
+        Ok(Plan {
+            action,
+            options: options.into_iter().filter(|options| !options.is_empty()).collect(),
+            wanted,
+            hosts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl alice@laptop";
+    const OTHER: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICSyhJxX6s91OzmeRhVlPEzwdln2ifnCxM7ToHGOQO1S";
+
+    #[test]
+    fn plans_round_trip() {
+        let key = PublicKey::parse(KEY).unwrap();
+        let other = PublicKey::parse(OTHER).unwrap();
+        let mut plan = Plan {
+            action: Action::Add,
+            options: Vec::new(),
+            wanted: vec![key.clone(), other.clone()],
+            hosts: vec![
+                HostPlan {
+                    label: "alice@web1:22".to_string(),
+                    destination: "alice@web1".to_string(),
+                    state: state(std::slice::from_ref(&other)).unwrap(),
+                    keys: vec![key.clone()],
+                },
+                HostPlan {
+                    label: "root@[::1]:2222".to_string(),
+                    destination: "root@[::1]:2222".to_string(),
+                    state: state(&[other.clone(), key.clone()]).unwrap(),
+                    keys: Vec::new(),
+                },
+            ],
+        };
+        assert_eq!(Plan::parse(&plan.render()).unwrap(), plan);
+        // The lines installed keep the options the plan was made with
+        plan.options = vec!["cert-authority".to_string(), "from=\"10.0.0.0/8\",no-pty".to_string()];
+        let rendered = plan.render();
+        assert!(rendered.contains(&format!("\nkey cert-authority,from=\"10.0.0.0/8\",no-pty {}\n", KEY)));
+        let parsed = Plan::parse(&rendered).unwrap();
+        assert_eq!(parsed.options, ["cert-authority,from=\"10.0.0.0/8\",no-pty"]);
+        assert_eq!(parsed.line(&key), plan.line(&key));
+        assert!(Plan::parse(&rendered.replacen("key cert-authority,", "key ", 1)).is_err());
+        assert!(Plan::parse("ssh-copy-id-rs plan 1\naction add\nhost a@b:22 a@b\n").is_err());
+        assert!(Plan::parse("ssh-copy-id-rs plan 1\naction add\nkey x\n").is_err());
+    }
+
+    #[test]
+    fn state_ignores_order_and_comments() {
+        let key = PublicKey::parse(KEY).unwrap();
+        let other = PublicKey::parse(OTHER).unwrap();
+        let renamed = PublicKey::parse(&format!("{} renamed", OTHER)).unwrap();
+        assert_eq!(
+            state(&[key.clone(), other.clone()]).unwrap(),
+            state(&[renamed, key.clone()]).unwrap()
+        );
+        assert_ne!(state(&[key]).unwrap(), state(&[other]).unwrap());
+    }
+}
@@ -0,0 +1,44 @@
+//! Stable output for scripts (`--porcelain`), kept separate from the human
+//! output, which may change between releases.
+//!
+//! The v1 contract: a `# porcelain v1` header line, then one record per
+//! line, fields separated by tabs, the record type in the first field.
+//! Fields never contain tabs or line breaks. New fields are only ever
+//! appended at the end of a record; any other change gets a new version.
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Version {
+    V1,
+}
+
+impl Version {
+    pub fn header(self) -> &'static str {
+        match self {
+            Version::V1 => "# porcelain v1",
+        }
+    }
+}
+
+/// One record; tabs and line breaks inside fields become spaces.
+pub fn line(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| field.replace(['\t', '\n', '\r'], " "))
+        .collect();
+    fields.join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_cannot_break_records() {
+        assert_eq!(
+            line(&["host", "web1", "failed", "bad\tthing\nhappened"]),
+            "host\tweb1\tfailed\tbad thing happened"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Reconnaissance of a remote before rolling keys out to it (`probe`).
+
+use crate::destination::Destination;
+use crate::net;
+use anyhow::{Context, Result, bail};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run by `sh` on the remote, whatever the login shell is; prints
+/// `key=value` lines.
+const PROBE_SCRIPT: &str = r#"echo "os=$(uname -srm 2>/dev/null || echo unknown)"
+if [ -r /etc/os-release ]; then (. /etc/os-release; echo "distro=${PRETTY_NAME:-$NAME}"); fi
+echo "shell=${SHELL:-unknown}"
+d="$HOME/.ssh"; f="$d/authorized_keys"
+if [ -e "$f" ]; then
+  if [ -w "$f" ]; then w=yes; else w="no (not writable)"; fi
+elif [ -d "$d" ]; then
+  if [ -w "$d" ]; then w="yes (will be created)"; else w="no ($d not writable)"; fi
+elif [ -w "$HOME" ]; then w="yes (will be created)"
+else w="no ($HOME not writable)"; fi
+echo "authorized_keys=$w"
+if command -v getenforce >/dev/null 2>&1; then
+  echo "selinux=$(getenforce 2>/dev/null)"
+  if [ -e "$d" ]; then echo "selinux_context=$(ls -Zd "$d" 2>/dev/null | awk '{print $1}')"; fi
+elif [ -r /sys/fs/selinux/enforce ]; then
+  if [ "$(cat /sys/fs/selinux/enforce)" = 1 ]; then echo "selinux=Enforcing"; else echo "selinux=Permissive"; fi
+else
+  echo "selinux=not present"
+fi
+"#;
+
+/// Labels for the keys printed by the probe script, in display order.
+const FIELDS: &[(&str, &str)] = &[
+    ("os", "OS"),
+    ("distro", "Distribution"),
+    ("shell", "Login shell"),
+    ("authorized_keys", "Keys writable"),
+    ("selinux", "SELinux"),
+    ("selinux_context", "~/.ssh label"),
+];
+
+/// How a destination's banner can be read without ssh.
+pub enum Route {
+    Direct { host: String, port: u16 },
+    /// Through a ProxyCommand from the ssh config
+    Command(String),
+    /// Not reachable directly, for this reason
+    Unchecked(String),
+}
+
+impl Route {
+    /// The server's identification string, read along this route.
+    pub fn banner(&self, timeout: Duration) -> Result<String> {
+        match self {
+            Route::Direct { host, port } => banner(host, *port, timeout),
+            Route::Command(command) => banner_through(command, timeout),
+            Route::Unchecked(reason) => bail!("{}", reason),
+        }
+    }
+}
+
+/// The identification string the server sends on connect, e.g.
+/// `SSH-2.0-OpenSSH_9.6`.
+pub fn banner(host: &str, port: u16, timeout: Duration) -> Result<String> {
+    let mut stream = net::connect(host, port, timeout)?;
+    let address = stream.peer_addr()?;
+    stream.set_read_timeout(Some(timeout))?;
+    // Identifying ourselves keeps this out of sshd's "no identification" log lines
+    let _ = stream.write_all(IDENTIFICATION.as_bytes());
+
+    match read_identification(BufReader::new(stream))? {
+        Some(banner) => Ok(banner),
+        None => bail!("{} did not send an SSH identification string", address),
+    }
+}
+
+/// Like `banner`, but through a ProxyCommand, which speaks to the server on
+/// its stdin and stdout as it does for ssh.
+fn banner_through(command: &str, timeout: Duration) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run the ProxyCommand {:?}", command))?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        let _ = stdin.write_all(IDENTIFICATION.as_bytes());
+    }
+    let stdout = child.stdout.take().context("ProxyCommand has no stdout")?;
+
+    // A pipe has no read timeout, so read on another thread
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(read_identification(BufReader::new(stdout)));
+    });
+    let received = receiver.recv_timeout(timeout);
+    let _ = child.kill();
+    let status = child.wait();
+    match received {
+        Ok(Ok(Some(banner))) => Ok(banner),
+        Ok(Ok(None)) => match status {
+            Ok(status) if !status.success() => bail!("ProxyCommand {:?} exited with {}", command, status),
+            _ => bail!("No SSH identification string through the ProxyCommand {:?}", command),
+        },
+        Ok(Err(e)) => Err(e),
+        Err(_) => bail!("Timed out after {}s waiting for the server through the ProxyCommand", timeout.as_secs()),
+    }
+}
+
+/// What we send before reading the server's identification.
+const IDENTIFICATION: &str = concat!("SSH-2.0-ssh-copy-id-rs_", env!("CARGO_PKG_VERSION"), "\r\n");
+
+/// The server's identification line; servers may send other lines before
+/// it (RFC 4253 4.2).
+fn read_identification(reader: impl BufRead) -> Result<Option<String>> {
+    for line in reader.lines().take(20) {
+        let line = line.context("Failed to read the server identification")?;
+        if line.starts_with("SSH-") {
+            return Ok(Some(line.trim_end().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Authentication methods the server offers, learned by attempting the
+/// `none` method, which servers answer with the list of methods that can continue.
+pub fn auth_methods(ssh_args: &[String]) -> Result<String> {
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "-o", "PreferredAuthentications=none"])
+        .args(["-o", "ConnectTimeout=10"])
+        .args(ssh_args)
+        .arg("true")
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let methods = stderr
+        .lines()
+        .find_map(|line| line.split_once("Permission denied (")?.1.split_once(')'))
+        .map(|(methods, _)| methods.replace(',', ", "));
+    match methods {
+        Some(methods) => Ok(methods),
+        None if output.status.success() => Ok("none (no authentication required)".to_string()),
+        None => bail!("{}", stderr.trim().lines().collect::<Vec<_>>().join("; ")),
+    }
+}
+
+/// Logs in and runs the probe script, returning (label, value) pairs.
+pub fn remote_facts(ssh_args: &[String]) -> Result<Vec<(&'static str, String)>> {
+    let mut child = Command::new("ssh")
+        .args(ssh_args)
+        .arg("sh")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(PROBE_SCRIPT.as_bytes())
+            .context("Failed to write the probe script to ssh stdin")?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("Failed to wait on ssh process")?;
+    if !output.status.success() {
+        bail!(
+            "ssh process exited with error code: {:?}",
+            output.status.code()
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let values: Vec<(&str, &str)> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    Ok(FIELDS
+        .iter()
+        .filter_map(|(key, label)| {
+            let (_, value) = values.iter().find(|(k, _)| k == key)?;
+            Some((*label, value.to_string()))
+        })
+        .collect())
+}
+
+/// Probes everything it can, reporting each failed step in place of its value.
+pub fn run(
+    destination: &Destination,
+    ssh_args: &[String],
+    port: u16,
+) -> Vec<(&'static str, String)> {
+    let mut report = vec![("Destination", destination.to_string())];
+    if destination.via.is_empty() {
+        report.push((
+            "sshd",
+            banner(&destination.host, port, TIMEOUT).unwrap_or_else(|e| format!("unknown ({:#})", e)),
+        ));
+    } else {
+        report.push((
+            "sshd",
+            "unknown (not reachable directly through jump hosts)".to_string(),
+        ));
+    }
+    report.push((
+        "Auth methods",
+        auth_methods(ssh_args).unwrap_or_else(|e| format!("unknown ({:#})", e)),
+    ));
+    match remote_facts(ssh_args) {
+        Ok(facts) => report.extend(facts),
+        Err(e) => report.push((
+            "Login",
+            format!("failed ({:#}); remote details unavailable", e),
+        )),
+    }
+    report
+}
@@ -0,0 +1,292 @@
+//! Reaching the target through a proxy (`--socks5`, `--https-proxy`).
+//!
+//! ssh is pointed at this binary as its `ProxyCommand`: the hidden `proxy`
+//! subcommand dials the target through the proxy and relays ssh's
+//! stdin/stdout over the connection.
+
+use crate::base64;
+use crate::destination::Destination;
+use crate::net;
+use crate::remote_script::sh_quote;
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+/// Where Tor listens by default, used for `.onion` hosts without `--socks5`.
+pub const TOR_SOCKS: &str = "127.0.0.1:9050";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Carries HTTP proxy credentials to the `proxy` subcommand, so they don't
+/// show up in its command line.
+const AUTH_ENV: &str = "SSH_COPY_ID_RS_PROXY_AUTH";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    Socks5,
+    /// HTTP CONNECT
+    Http,
+}
+
+impl Kind {
+    fn default_port(self) -> u16 {
+        match self {
+            Kind::Socks5 => 1080,
+            Kind::Http => 3128,
+        }
+    }
+}
+
+/// A proxy to connect through, e.g. `--socks5 gateway:1080`.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub kind: Kind,
+    /// `host:port` of the proxy
+    pub address: String,
+    /// `user:password` for HTTP proxies
+    pub credentials: Option<String>,
+}
+
+impl Proxy {
+    /// Parses `[http://][user:password@]host[:port]` for HTTP proxies and
+    /// `host[:port]` for SOCKS5.
+    pub fn new(kind: Kind, address: &str) -> Result<Proxy> {
+        let spec = match kind {
+            Kind::Http if address.starts_with("https://") => {
+                bail!(
+                    "TLS connections to the proxy are not supported: {:?}",
+                    address
+                )
+            }
+            Kind::Http => address.strip_prefix("http://").unwrap_or(address),
+            Kind::Socks5 => address,
+        };
+        let spec = spec.strip_suffix('/').unwrap_or(spec);
+        let parsed: Destination = spec
+            .parse()
+            .with_context(|| format!("Invalid proxy address {:?}", address))?;
+        if parsed.user.is_some() && kind == Kind::Socks5 {
+            bail!("SOCKS5 proxy credentials are not supported: {:?}", address);
+        }
+        let port = parsed.port.unwrap_or(kind.default_port());
+        Ok(Proxy {
+            kind,
+            address: Destination {
+                user: None,
+                port: Some(port),
+                ..parsed.clone()
+            }
+            .to_string(),
+            credentials: parsed.user,
+        })
+    }
+
+    /// Environment the `proxy` subcommand needs, to be set on ssh.
+    pub fn env(&self) -> Option<(&'static str, String)> {
+        self.credentials
+            .as_ref()
+            .map(|credentials| (AUTH_ENV, base64::encode(credentials.as_bytes())))
+    }
+
+    /// The ssh options making ssh connect through this proxy.
+    pub fn ssh_args(&self) -> Result<Vec<String>> {
+        let exe = std::env::current_exe().context("Failed to locate the ssh-copy-id-rs binary")?;
+        let kind = self.kind.to_possible_value().expect("no skipped variants");
+        let command = format!(
+            "{} proxy {} {} %h %p",
+            sh_quote(&exe.to_string_lossy()),
+            kind.get_name(),
+            sh_quote(&self.address)
+        );
+        Ok(vec!["-o".to_string(), format!("ProxyCommand={}", command)])
+    }
+}
+
+/// Whether `host` is a Tor onion service, only reachable through Tor.
+pub fn is_onion(host: &str) -> bool {
+    host.to_ascii_lowercase().ends_with(".onion")
+}
+
+/// The `proxy` subcommand: connects to `host:port` through the proxy and
+/// relays stdin/stdout until either side closes.
+pub fn run(kind: Kind, address: &str, host: &str, port: u16) -> Result<()> {
+    let proxy: Destination = address.parse()?;
+    let mut stream = net::connect(
+        &proxy.host,
+        proxy.port.unwrap_or(kind.default_port()),
+        CONNECT_TIMEOUT,
+    )
+    .with_context(|| format!("Failed to connect to proxy {}", address))?;
+    match kind {
+        Kind::Socks5 => socks5_connect(&mut stream, host, port),
+        Kind::Http => http_connect(&mut stream, host, port, std::env::var(AUTH_ENV).ok()),
+    }
+    .with_context(|| format!("Proxy {} could not reach {}:{}", address, host, port))?;
+    relay(stream)
+}
+
+/// SOCKS5 CONNECT without authentication (RFC 1928). The host name is sent
+/// as is so the proxy resolves it, which `.onion` names require and which
+/// keeps DNS lookups off the local network.
+fn socks5_connect(stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+    stream.write_all(&[5, 1, 0])?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    match choice {
+        [5, 0] => {}
+        [5, 0xff] => bail!("the proxy requires authentication"),
+        _ => bail!("not a SOCKS5 proxy"),
+    }
+
+    let host = host.as_bytes();
+    if host.len() > 255 {
+        bail!("host name too long for SOCKS5");
+    }
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host);
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        bail!("{}", socks5_error(reply[1]));
+    }
+    // Skip the bound address the proxy reports
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        other => bail!("invalid SOCKS5 address type {}", other),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound)?;
+    Ok(())
+}
+
+/// HTTP CONNECT (RFC 9110). `auth` is the base64 of `user:password`.
+fn http_connect(stream: &mut TcpStream, host: &str, port: u16, auth: Option<String>) -> Result<()> {
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut request = format!("CONNECT {a} HTTP/1.1\r\nHost: {a}\r\n", a = authority);
+    if let Some(auth) = auth {
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", auth));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // Byte by byte, so nothing the target sends after the headers is lost
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 16 * 1024 {
+            bail!("response headers too long");
+        }
+        stream
+            .read_exact(&mut byte)
+            .context("the proxy closed the connection")?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some("407") => bail!("the proxy requires (other) credentials: {}", status_line),
+        Some(_) => bail!("{}", status_line),
+        None => bail!("not an HTTP proxy"),
+    }
+}
+
+fn socks5_error(code: u8) -> &'static str {
+    match code {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown SOCKS5 error",
+    }
+}
+
+/// Copies stdin to the connection and the connection to stdout.
+fn relay(stream: TcpStream) -> Result<()> {
+    let mut upstream = stream.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin().lock(), &mut upstream);
+        let _ = upstream.shutdown(Shutdown::Write);
+    });
+    let mut downstream = stream;
+    std::io::copy(&mut downstream, &mut std::io::stdout().lock())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn socks5_sends_host_name_to_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            client.write_all(&[5, 0]).unwrap();
+            let mut request = [0u8; 5 + 11 + 2];
+            client.read_exact(&mut request).unwrap();
+            client.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0, 22]).unwrap();
+            (greeting, request)
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        socks5_connect(&mut stream, "abc.example", 2222).unwrap();
+        let (greeting, request) = server.join().unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        assert_eq!(&request[..5], &[5, 1, 0, 3, 11]);
+        assert_eq!(&request[5..16], b"abc.example");
+        assert_eq!(&request[16..], &2222u16.to_be_bytes());
+    }
+
+    #[test]
+    fn http_connect_keeps_data_after_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                client.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nSSH-2.0-test\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        http_connect(&mut stream, "::1", 22, Some("dTpw".to_string())).unwrap();
+        let mut banner = [0u8; 14];
+        stream.read_exact(&mut banner).unwrap();
+        assert_eq!(&banner, b"SSH-2.0-test\r\n");
+        assert_eq!(
+            server.join().unwrap(),
+            "CONNECT [::1]:22 HTTP/1.1\r\nHost: [::1]:22\r\nProxy-Authorization: Basic dTpw\r\n\r\n"
+        );
+    }
+}
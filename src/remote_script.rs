@@ -0,0 +1,1607 @@
+//! Generation of the command run on the remote host.
+//!
+//! Every script reads the public keys to install from stdin, one per line,
+//! and appends each one that isn't already present to `authorized_keys`.
+//! The POSIX-like scripts first enter the account's home directory as the
+//! remote resolves it (`~`, or the passwd entry), or the `--remote-home`
+//! given, rather than trusting the directory the session starts in.
+//! With the size guard, it first refuses to touch an `authorized_keys` that
+//! is already implausibly large.
+//!
+//! The scripts for the Bourne-like profiles stick to POSIX sh and the
+//! utilities it specifies (no `local`, `[[`, `echo -e` or arrays), so that
+//! dash, BusyBox ash, ksh and yash run them as bash does; the tests run them
+//! under every one of those that is installed.
+
+use crate::base64;
+use crate::digest;
+use clap::ValueEnum;
+
+/// The kind of remote environment the script has to run in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Bourne-compatible login shell with the usual coreutils
+    #[default]
+    Posix,
+    /// BusyBox or similar minimal userland: shell builtins, mkdir and chmod only
+    Busybox,
+    /// csh/tcsh login shell; the POSIX script is handed to `sh`
+    Csh,
+    /// Windows OpenSSH Server
+    Powershell,
+    /// Appliances with a restricted shell: plain append, no duplicate check
+    Device,
+}
+
+/// The remote's operating system, for `--remote-os`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RemoteOs {
+    /// Unix-like, with a POSIX shell (the posix profile)
+    Unix,
+    /// Windows OpenSSH Server (the powershell profile)
+    Windows,
+}
+
+impl RemoteOs {
+    pub fn profile(self) -> Profile {
+        match self {
+            RemoteOs::Unix => Profile::Posix,
+            RemoteOs::Windows => Profile::Powershell,
+        }
+    }
+}
+
+/// Whether ssh's output shows the command being run by Windows' cmd.exe or
+/// PowerShell rather than a POSIX shell.
+pub fn ran_on_windows(output: &str) -> bool {
+    output.contains("is not recognized as an internal or external command")
+        || output.contains("is not recognized as the name of a cmdlet")
+}
+
+/// How `--sudo-user` becomes the other account on the remote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BecomeMethod {
+    #[default]
+    Sudo,
+    Doas,
+}
+
+/// Another account for the scripts to run as, with `--sudo-user`.
+#[derive(Clone, Debug)]
+pub struct Become {
+    pub method: BecomeMethod,
+    pub user: String,
+}
+
+impl Become {
+    /// The command that runs the rest of the line as the user: without
+    /// prompting, as stdin carries the keys, and with `$HOME` set to the
+    /// user's home, so that the scripts enter it and files are created
+    /// owned by the user.
+    fn prefix(&self) -> String {
+        match self.method {
+            BecomeMethod::Sudo => format!("sudo -n -H -u {} --", login_quote(&self.user)),
+            BecomeMethod::Doas => format!("doas -n -u {}", login_quote(&self.user)),
+        }
+    }
+}
+
+/// Where `--announce` leaves its note about newly installed keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AnnounceTo {
+    /// Append a dated line to ~/.ssh/README
+    Readme,
+    /// Broadcast it to logged-in sessions with wall
+    Wall,
+}
+
+/// A note for the account's other users, made only if keys were added.
+#[derive(Clone, Debug)]
+pub struct Announcement {
+    pub to: AnnounceTo,
+    /// Leads the note, e.g. the date and who installed the keys
+    pub header: String,
+    /// Each key line as sent on stdin, and how the note names it
+    pub keys: Vec<(String, String)>,
+}
+
+/// Builder for the install script.
+#[derive(Clone, Debug, Default)]
+pub struct InstallScript {
+    pub profile: Profile,
+    /// Refuse to append to an authorized_keys over MAX_BYTES or MAX_LINES
+    pub size_guard: bool,
+    /// Skip keys already in authorized_keys; otherwise every key is appended
+    pub dedupe: bool,
+    /// Each key line as sent on stdin, with every spelling of its blob
+    /// (see [`crate::keyblob::spellings`]): with `dedupe`, such a key counts
+    /// as installed when a line has one of them, whatever its options and
+    /// comment, as `--dry-run` tells it; other lines only when they are
+    /// there exactly (all but the device profile)
+    pub known: Vec<(String, Vec<String>)>,
+    /// Tell others about the keys added (POSIX-like profiles only)
+    pub announce: Option<Announcement>,
+    /// Print the remote clock after [`CLOCK`] (posix and csh only)
+    pub report_clock: bool,
+    /// Print [`STARTED`] before stdin is read (all but the device profile)
+    pub report_started: bool,
+    /// Print each key appended after [`ADDED`], and each found already
+    /// installed after [`SKIPPED`] (all but the device profile)
+    pub report_added: bool,
+    /// Check that the script arrived intact before running it (posix and
+    /// csh only); a mismatch is printed after [`CHECKSUM`]
+    pub verify_checksum: bool,
+    /// Copy a non-empty authorized_keys aside before the first key is
+    /// appended, and print the copy's path after [`BACKUP`] (posix and csh
+    /// only)
+    pub backup: bool,
+    /// Where `.ssh` is, if not in the account's home directory
+    pub home: Option<String>,
+    /// The key file, if not `.ssh/authorized_keys`: relative to the home
+    /// directory unless absolute, with sshd's tokens (see
+    /// [`check_key_path`]; POSIX-like profiles only)
+    pub path: Option<String>,
+}
+
+const SSH_DIR: &str = ".ssh";
+const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+const README: &str = ".ssh/README";
+/// Leads the lines the scripts print for us; they aren't passed on.
+pub const MARKER: &str = "ssh-copy-id-rs-";
+/// Leads the line with the remote's Unix time.
+pub const CLOCK: &str = "ssh-copy-id-rs-clock: ";
+/// Printed once the command runs, i.e. the login has worked.
+pub const STARTED: &str = "ssh-copy-id-rs-started";
+/// Leads the line with a key that was appended.
+pub const ADDED: &str = "ssh-copy-id-rs-added: ";
+/// Leads the line with a key that was already installed.
+pub const SKIPPED: &str = "ssh-copy-id-rs-skipped: ";
+/// Leads the line with the path of the copy `--backup` made.
+pub const BACKUP: &str = "ssh-copy-id-rs-backup: ";
+/// Leads the line with a key line that `--remove` took out.
+pub const REMOVED: &str = "ssh-copy-id-rs-removed: ";
+/// Printed by the `--rotate` command once the new keys are in, and once
+/// it has taken them out again.
+pub const ROTATE_READY: &str = "ssh-copy-id-rs-rotate: ready";
+pub const ROTATE_ROLLED_BACK: &str = "ssh-copy-id-rs-rotate: rolled back";
+/// Leads the line with the checksum of a script that arrived altered.
+pub const CHECKSUM: &str = "ssh-copy-id-rs-checksum: ";
+/// Sizes beyond which authorized_keys most likely grew from a runaway loop.
+pub const MAX_BYTES: u64 = 1024 * 1024;
+pub const MAX_LINES: u64 = 10_000;
+
+impl InstallScript {
+    pub fn new(profile: Profile) -> Self {
+        InstallScript {
+            profile,
+            size_guard: false,
+            dedupe: true,
+            known: Vec::new(),
+            announce: None,
+            report_clock: false,
+            report_started: false,
+            report_added: false,
+            verify_checksum: false,
+            backup: false,
+            home: None,
+            path: None,
+        }
+    }
+
+    pub fn home(mut self, home: Option<String>) -> Self {
+        self.home = home;
+        self
+    }
+
+    pub fn path(mut self, path: Option<String>) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn size_guard(mut self, enabled: bool) -> Self {
+        self.size_guard = enabled;
+        self
+    }
+
+    pub fn dedupe(mut self, enabled: bool) -> Self {
+        self.dedupe = enabled;
+        self
+    }
+
+    pub fn known(mut self, keys: Vec<(String, Vec<String>)>) -> Self {
+        self.known = keys;
+        self
+    }
+
+    pub fn report_clock(mut self, enabled: bool) -> Self {
+        self.report_clock = enabled;
+        self
+    }
+
+    pub fn report_started(mut self, enabled: bool) -> Self {
+        self.report_started = enabled;
+        self
+    }
+
+    pub fn report_added(mut self, enabled: bool) -> Self {
+        self.report_added = enabled;
+        self
+    }
+
+    pub fn verify_checksum(mut self, enabled: bool) -> Self {
+        self.verify_checksum = enabled;
+        self
+    }
+
+    pub fn backup(mut self, enabled: bool) -> Self {
+        self.backup = enabled;
+        self
+    }
+
+    pub fn announce(mut self, announcement: Option<Announcement>) -> Self {
+        self.announce = announcement;
+        self
+    }
+
+    /// Run after appending "$key": reports it, and adds its name to "$new".
+    fn record_added(&self) -> Option<String> {
+        let report = self
+            .report_added
+            .then(|| format!("printf '%s%s\\n' '{}' \"$key\"", ADDED));
+        let announce = self.announce.as_ref().map(|announcement| {
+            let arms: String = announcement
+                .keys
+                .iter()
+                .map(|(line, name)| {
+                    format!("{}) new=\"${{new:+$new, }}\"{};; ", sh_quote(line), sh_quote(name))
+                })
+                .collect();
+            format!("case \"$key\" in {}esac", arms)
+        });
+        let steps: Vec<String> = report.into_iter().chain(announce).collect();
+        (!steps.is_empty()).then(|| steps.join("; "))
+    }
+
+    /// Run after the loop: leaves the note if any key was added.
+    fn announcement(&self) -> Option<String> {
+        let announcement = self.announce.as_ref()?;
+        let note = format!("printf '%s %s\\n' {} \"$new\"", sh_quote(&announcement.header));
+        let deliver = match announcement.to {
+            AnnounceTo::Readme => format!("{} >> {}", note, README),
+            // Best effort: wall may be missing or refused
+            AnnounceTo::Wall => format!("{{ {} | wall; }} 2>/dev/null || :", note),
+        };
+        Some(format!("if [ -n \"$new\" ]; then {}; fi", deliver))
+    }
+
+    pub fn refusal(size: &str) -> String {
+        Self::refusal_of(AUTHORIZED_KEYS, size)
+    }
+
+    /// [`Self::refusal`] for the key file at `path`.
+    fn refusal_of(path: &str, size: &str) -> String {
+        format!(
+            "ssh-copy-id-rs: refusing to append to {}, which already has {}; \
+             check for a runaway automation or pass --force",
+            path, size
+        )
+    }
+
+    /// The complete remote command, ready to be passed to ssh as one argument.
+    pub fn render(&self) -> String {
+        match self.profile {
+            Profile::Posix => self.checked(self.posix()),
+            Profile::Busybox => self.busybox(),
+            Profile::Csh => format!("sh -c {}", csh_quote(&self.checked(self.posix()))),
+            Profile::Powershell => self.powershell(),
+            Profile::Device => self.device(),
+        }
+    }
+
+    /// With `verify_checksum`, `script` behind [`checksummed`].
+    fn checked(&self, script: String) -> String {
+        match self.verify_checksum {
+            true => checksummed(&script),
+            false => script,
+        }
+    }
+
+    /// Steps shared by the POSIX-like profiles:
+    /// - umask 077: ensures created files are private
+    /// - mkdir -p .ssh && chmod 700 .ssh: ensures the dir exists with right perms
+    ///
+    /// A directory outside the home directory, as for `/etc/ssh/keys/%u`, is
+    /// only created if missing, and readable by all, as sshd reads it as
+    /// the account.
+    fn prepare_dir(path: Option<&str>) -> String {
+        let Some(path) = path else {
+            return format!(
+                "umask 077; mkdir -p {dir} && chmod 700 {dir}",
+                dir = SSH_DIR
+            );
+        };
+        match path.rsplit_once('/') {
+            // In the home directory, or at the root
+            None | Some(("", _)) => "umask 077".to_string(),
+            Some((dir, _)) if is_absolute(path) => format!(
+                "umask 077; [ -d {dir} ] || (umask 022; mkdir -p {dir}) || exit 1",
+                dir = key_path_word(dir)
+            ),
+            Some((dir, _)) => format!(
+                "umask 077; mkdir -p {dir} && chmod 700 {dir}",
+                dir = key_path_word(dir)
+            ),
+        }
+    }
+
+    /// The key file as a shell word.
+    fn file(&self) -> String {
+        match &self.path {
+            Some(path) => key_path_word(path),
+            None => AUTHORIZED_KEYS.to_string(),
+        }
+    }
+
+    /// The key file as it is shown in messages.
+    fn file_name(&self) -> &str {
+        self.path.as_deref().unwrap_or(AUTHORIZED_KEYS)
+    }
+
+    fn posix(&self) -> String {
+        // - loop over stdin lines to handle multiple keys (e.g. from ssh-add -L)
+        // - installed(): checks if the key, or the exact key line, already exists
+        let file = self.file();
+        let mut steps = vec![enter_home(self.home.as_deref())];
+        if self.report_started {
+            steps.push(format!("echo {}", STARTED));
+        }
+        if self.report_clock {
+            steps.push(format!("echo \"{}$(date +%s)\"", CLOCK));
+        }
+        steps.extend([
+            Self::prepare_dir(self.path.as_deref()),
+            format!(
+                "if [ ! -f {f} ]; then touch {f} && chmod 600 {f}; fi",
+                f = file
+            ),
+        ]);
+        if self.size_guard {
+            // $((...)) strips the padding some wc implementations print
+            steps.push(format!(
+                "s=$(wc -c < {f}); l=$(wc -l < {f}); \
+                 if [ $((s)) -gt {bytes} ] || [ $((l)) -gt {lines} ]; then \
+                 echo \"{refusal}\" >&2; exit 1; fi",
+                f = file,
+                bytes = MAX_BYTES,
+                lines = MAX_LINES,
+                refusal = Self::refusal_of(self.file_name(), "$((l)) lines ($((s)) bytes)"),
+            ));
+        }
+        let record = self.record_added().map(|r| format!(" {};", r)).unwrap_or_default();
+        // Once, before the first append; "$b" is set either way
+        let copy = match self.path.as_deref().is_some_and(is_absolute) {
+            true => "\"$b\"",
+            false => "\"$PWD/$b\"",
+        };
+        let backup = match self.backup {
+            true => format!(
+                "if [ -z \"$b\" ]; then b=-; if [ -s {f} ]; then \
+                 b={f}.bak.$(date -u +%Y%m%dT%H%M%S); cp -p {f} \"$b\" || exit 1; \
+                 printf '%s%s\\n' '{marker}' {copy}; fi; fi; ",
+                f = file,
+                marker = BACKUP,
+            ),
+            false => String::new(),
+        };
+        // Not echo, which turns backslashes in the key's options into escapes in dash
+        let append = format!("{}printf '%s\\n' \"$key\" >> {};{}", backup, file, record);
+        let installed = installed(&self.known, &file);
+        let append = match (self.dedupe, self.report_added) {
+            (true, true) => format!(
+                "if {}; then printf '%s%s\\n' '{}' \"$key\"; else {} fi;",
+                installed, SKIPPED, append
+            ),
+            (true, false) => format!("if ! {}; then {} fi;", installed, append),
+            (false, _) => append,
+        };
+        steps.push(format!(
+            "while read -r key; do if [ -n \"$key\" ]; then {} fi; done",
+            append
+        ));
+        steps.extend(self.announcement());
+        steps.join("; ")
+    }
+
+    fn busybox(&self) -> String {
+        // No grep/touch: compare lines with the shell's own `read` and `[`
+        let file = self.file();
+        let mut steps = vec![enter_home(self.home.as_deref())];
+        if self.report_started {
+            steps.push(format!("echo {}", STARTED));
+        }
+        steps.extend([
+            Self::prepare_dir(self.path.as_deref()),
+            format!(
+                "if [ ! -f {f} ]; then : > {f} && chmod 600 {f}; fi",
+                f = file
+            ),
+        ]);
+        if self.size_guard {
+            // Without wc only the line count can be checked
+            steps.push(format!(
+                "l=0; while read -r line; do l=$((l + 1)); done < {f}; \
+                 if [ \"$l\" -gt {lines} ]; then echo \"{refusal}\" >&2; exit 1; fi",
+                f = file,
+                lines = MAX_LINES,
+                refusal = Self::refusal_of(self.file_name(), "$l lines"),
+            ));
+        }
+        let append = format!("printf '%s\\n' \"$key\" >> {}", file);
+        let append = match self.record_added() {
+            Some(record) => format!("{{ {}; {}; }}", append, record),
+            None => append,
+        };
+        let check = if self.dedupe {
+            let report = match self.report_added {
+                true => format!("[ -z \"$found\" ] || printf '%s%s\\n' '{}' \"$key\"; ", SKIPPED),
+                false => String::new(),
+            };
+            let (which, by_blob) = match known_blobs(&self.known) {
+                Some((which, patterns)) => (
+                    format!("{} ", which),
+                    format!(" case \"$p $line \" in {}) found=1;; esac;", patterns),
+                ),
+                None => (String::new(), String::new()),
+            };
+            format!(
+                "found=; {}\
+                 while read -r line; do [ \"$line\" = \"$key\" ] && found=1;{} done < {}; \
+                 {}[ -n \"$found\" ] || ",
+                which, by_blob, file, report
+            )
+        } else {
+            String::new()
+        };
+        steps.push(format!(
+            "while read -r key; do [ -n \"$key\" ] || continue; {}{}; done",
+            check, append
+        ));
+        steps.extend(self.announcement());
+        steps.join("; ")
+    }
+
+    /// A plain append; too little of a shell to honour the size guard, or
+    /// to look up the home directory.
+    fn device(&self) -> String {
+        match &self.home {
+            Some(home) => format!(
+                "mkdir -p {}; cat >> {}",
+                sh_quote(&format!("{}/{}", home.trim_end_matches('/'), SSH_DIR)),
+                sh_quote(&format!("{}/{}", home.trim_end_matches('/'), AUTHORIZED_KEYS))
+            ),
+            None => format!("mkdir -p {}; cat >> {}", SSH_DIR, AUTHORIZED_KEYS),
+        }
+    }
+
+    fn powershell(&self) -> String {
+        let guard = format!(
+            "$size = (Get-Item $f).Length; \
+             if ($size -gt {bytes} -or $existing.Count -gt {lines}) {{ \
+             [Console]::Error.WriteLine(\"{refusal}\"); exit 1 }}",
+            bytes = MAX_BYTES,
+            lines = MAX_LINES,
+            refusal = Self::refusal("$($existing.Count) lines ($size bytes)"),
+        );
+        let (report, skip) = if self.report_added {
+            (
+                format!("; $out.WriteLine('{}' + $key)", ADDED),
+                format!(" elseif ($key) {{ $out.WriteLine('{}' + $key) }}", SKIPPED),
+            )
+        } else {
+            (String::new(), String::new())
+        };
+        let script = [
+            "$ErrorActionPreference = 'Stop'",
+            POWERSHELL_LOCATE,
+            POWERSHELL_UTF8,
+            &if self.report_started { format!("$out.WriteLine('{}')", STARTED) } else { String::new() },
+            "New-Item -ItemType Directory -Force -Path $d | Out-Null",
+            "if (-not (Test-Path $f)) { New-Item -ItemType File -Path $f | Out-Null }",
+            "$existing = @(Get-Content -Encoding UTF8 -Path $f)",
+            if self.size_guard { &guard } else { "" },
+            &if self.dedupe {
+                // A case-sensitive table, unlike @{}
+                let known: String = self
+                    .known
+                    .iter()
+                    .map(|(line, blobs)| {
+                        let blobs: Vec<String> = blobs.iter().map(|blob| format!("'{}'", blob)).collect();
+                        format!("$known['{}'] = @({}); ", line.replace('\'', "''"), blobs.join(", "))
+                    })
+                    .collect();
+                format!(
+                    "$known = New-Object Collections.Hashtable; {}\
+                     foreach ($key in ((New-Object IO.StreamReader([Console]::OpenStandardInput(), $utf8)).ReadToEnd() -split '\\r?\\n')) {{ \
+                     $installed = $existing -ccontains $key; \
+                     if (-not $installed -and $known.ContainsKey($key)) {{ $blobs = $known[$key]; \
+                     $installed = @($existing | Where-Object {{ @($_ -split '\\s+' | Where-Object {{ $blobs -ccontains $_ }}).Count -gt 0 }}).Count -gt 0 }}; \
+                     if ($key -and -not $installed) {{ \
+                     [IO.File]::AppendAllText($f, $key + [Environment]::NewLine); $existing += $key{} \
+                     }}{} }}",
+                    known, report, skip
+                )
+            } else {
+                format!(
+                    "foreach ($key in ((New-Object IO.StreamReader([Console]::OpenStandardInput(), $utf8)).ReadToEnd() -split '\\r?\\n')) {{ \
+                     if ($key) {{ [IO.File]::AppendAllText($f, $key + [Environment]::NewLine){} }} }}",
+                    report
+                )
+            },
+            // sshd ignores a key file that others can write to. The SIDs are
+            // Administrators and SYSTEM, whatever the system's language
+            "if ($admin) { $owner = '*S-1-5-32-544:F' } else { $owner = '*' + $id.User.Value + ':F' }",
+            "icacls $f /inheritance:r /grant $owner /grant '*S-1-5-18:F' | Out-Null",
+        ]
+        .iter()
+        .filter(|step| !step.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join("; ");
+        powershell_encoded(&script)
+    }
+}
+
+/// Sets `$p` to the number of the line "$key" among `known`, and the
+/// patterns that find that key in `"$p $line "`: a line with one of the
+/// spellings of its blob. `None` when no blobs are known.
+fn known_blobs(known: &[(String, Vec<String>)]) -> Option<(String, String)> {
+    if known.is_empty() {
+        return None;
+    }
+    let arms: String = known
+        .iter()
+        .enumerate()
+        .map(|(i, (line, _))| format!("{}) p={};; ", sh_quote(line), i + 1))
+        .collect();
+    // Blobs are base64, so need no quoting in a pattern
+    let patterns: Vec<String> = known
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, blobs))| blobs.iter().map(move |blob| format!("\"{} \"*\" {} \"*", i + 1, blob)))
+        .collect();
+    Some((format!("case \"$key\" in {}*) p=;; esac;", arms), patterns.join("|")))
+}
+
+/// A command that succeeds if the key file `file` already has the key of
+/// the line "$key", as [`InstallScript::known`] tells it.
+fn installed(known: &[(String, Vec<String>)], file: &str) -> String {
+    let exact = format!("grep -qxF \"$key\" {}", file);
+    match known_blobs(known) {
+        Some((which, patterns)) => format!(
+            "{{ {} found=; while IFS= read -r line || [ -n \"$line\" ]; do \
+             case \"$p $line \" in {}) found=1;; esac; done < {}; [ -n \"$found\" ] || {}; }}",
+            which, patterns, file, exact
+        ),
+        None => exact,
+    }
+}
+
+/// Enters `home`, or the account's home directory: `~` as the shell expands
+/// it, or else the passwd entry's, or else (as before) wherever the session
+/// started.
+fn enter_home(home: Option<&str>) -> String {
+    match home {
+        Some(home) => format!("cd {} || exit 1", sh_quote(home)),
+        None => "h=$(printf %s ~); if [ ! -d \"$h\" ] && command -v getent >/dev/null 2>&1; then \
+                 h=$(getent passwd \"$(id -un)\" | cut -d: -f6); fi; cd \"${h:-.}\" || exit 1"
+            .to_string(),
+    }
+}
+
+/// Whether `path` can be given as `--authorized-keys-path`: a file
+/// relative to the home directory or absolute, as sshd's
+/// `AuthorizedKeysFile` takes it, with its tokens `%u` (the user name),
+/// `%U` (the user ID), `%h` (the home directory) and `%%`. Whitespace,
+/// which sshd_config separates paths with, glob characters and those that
+/// are special inside double quotes aren't allowed, so that the path can
+/// be used unquoted in a variable and shown in the scripts' messages.
+pub fn check_key_path(path: &str) -> Result<(), String> {
+    if path.is_empty() || path.ends_with('/') {
+        return Err(format!("{:?} isn't a file", path));
+    }
+    if let Some(c) = path.chars().find(|c| c.is_control() || c.is_whitespace() || "\"$`\\*?[".contains(*c)) {
+        return Err(format!("{:?} isn't allowed in the path", c));
+    }
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.next() {
+                Some('u' | 'U' | 'h' | '%') => {}
+                Some(token) => return Err(format!("unknown token %{}", token)),
+                None => return Err("a lone % at the end".to_string()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the key file at `path` is outside the home directory, as sshd
+/// tells it.
+fn is_absolute(path: &str) -> bool {
+    path.starts_with('/') || path.starts_with("%h")
+}
+
+/// `path`, as [`check_key_path`] allows it, as one shell word, its tokens
+/// expanded by the remote shell. The scripts run in the home directory.
+fn key_path_word(path: &str) -> String {
+    let mut word = String::new();
+    let mut literal = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        let expansion = match (c, chars.clone().next()) {
+            ('%', Some('u')) => "\"$(id -un)\"",
+            ('%', Some('U')) => "\"$(id -u)\"",
+            ('%', Some('h')) => "\"$PWD\"",
+            ('%', Some('%')) => "%",
+            _ => {
+                literal.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        if expansion == "%" {
+            literal.push('%');
+            continue;
+        }
+        if !literal.is_empty() {
+            word.push_str(&sh_quote(&literal));
+            literal.clear();
+        }
+        word.push_str(expansion);
+    }
+    if !literal.is_empty() || word.is_empty() {
+        word.push_str(&sh_quote(&literal));
+    }
+    word
+}
+
+/// `script` behind a check that it reached the shell as sent: its `cksum`
+/// is compared before it is `eval`ed. Hosts without cksum run it unchecked.
+fn checksummed(script: &str) -> String {
+    let (crc, len) = digest::cksum(script.as_bytes());
+    format!(
+        "s={script}; if command -v cksum >/dev/null 2>&1; then \
+         set -- $(printf '%s' \"$s\" | cksum); \
+         if [ \"$1 $2\" != '{crc} {len}' ]; then echo \"{marker}$1 $2\"; exit 1; fi; fi; \
+         eval \"$s\"",
+        script = sh_quote(script),
+        marker = CHECKSUM,
+    )
+}
+
+/// The checksum the install script's `stdout` reports for a script that
+/// arrived altered, if it did.
+pub fn checksum_mismatch(stdout: &str) -> Option<&str> {
+    stdout.lines().find_map(|line| line.strip_prefix(CHECKSUM))
+}
+
+/// The key lines that the install script's `stdout` reports as appended.
+pub fn added(stdout: &str) -> Vec<&str> {
+    stdout.lines().filter_map(|line| line.strip_prefix(ADDED)).collect()
+}
+
+/// The key lines that the install script's `stdout` reports as already
+/// installed.
+pub fn skipped(stdout: &str) -> Vec<&str> {
+    stdout.lines().filter_map(|line| line.strip_prefix(SKIPPED)).collect()
+}
+
+/// Where the install script's `stdout` reports `--backup` copied
+/// authorized_keys, if it made a copy.
+pub fn backed_up(stdout: &str) -> Option<&str> {
+    stdout.lines().find_map(|line| line.strip_prefix(BACKUP))
+}
+
+/// The key lines that the removal script's `stdout` reports as taken out.
+pub fn removed(stdout: &str) -> Vec<&str> {
+    stdout.lines().filter_map(|line| line.strip_prefix(REMOVED)).collect()
+}
+
+/// The command for `--remove`: takes every line with one of the key
+/// `blobs` out of authorized_keys, whatever its options and comment, and
+/// prints each after [`REMOVED`]. The file is only rewritten if a line
+/// goes, and then atomically. `None` for the profiles that can't do it.
+pub fn remove_keys(profile: Profile, blobs: &[&str], home: Option<&str>, path: Option<&str>) -> Option<String> {
+    let posix = format!("{}; {}", enter_home(home), remove_posix(blobs, path));
+    match profile {
+        Profile::Posix => Some(posix),
+        Profile::Csh => Some(format!("sh -c {}", csh_quote(&posix))),
+        Profile::Powershell => {
+            let blobs: Vec<String> = blobs.iter().map(|blob| format!("'{}'", blob)).collect();
+            Some(powershell_encoded(&format!(
+                "$ErrorActionPreference = 'Stop'; {locate}; {utf8}; \
+                 if (Test-Path $f) {{ $blobs = @({blobs}); $kept = @(); $removed = @(); \
+                 foreach ($line in @(Get-Content -Encoding UTF8 -Path $f)) {{ \
+                 if (@($line -split '\\s+' | Where-Object {{ $blobs -contains $_ }}).Count -gt 0) {{ $removed += $line }} \
+                 else {{ $kept += $line }} }}; \
+                 if ($removed.Count -gt 0) {{ [IO.File]::WriteAllLines($f, [string[]]$kept); \
+                 foreach ($line in $removed) {{ $out.WriteLine('{marker}' + $line) }} }} }}",
+                locate = POWERSHELL_LOCATE,
+                utf8 = POWERSHELL_UTF8,
+                blobs = blobs.join(", "),
+                marker = REMOVED,
+            )))
+        }
+        Profile::Busybox | Profile::Device => None,
+    }
+}
+
+/// The POSIX part of [`remove_keys`], for the key file at `path` if not
+/// the default.
+fn remove_posix(blobs: &[&str], path: Option<&str>) -> String {
+    // Blobs are base64, so need no quoting in a pattern or a string
+    let patterns: Vec<String> = blobs.iter().map(|blob| format!("*\" {} \"*", blob)).collect();
+    format!(
+        "f={f}; if [ -f $f ]; then umask 077; t=$f.ssh-copy-id-rs.$$; n=0; \
+         while IFS= read -r line || [ -n \"$line\" ]; do case \" $line \" in \
+         {patterns}) n=$((n + 1)); printf '%s%s\\n' '{marker}' \"$line\" >&3;; \
+         *) printf '%s\\n' \"$line\";; esac; done < $f 3>&1 > \"$t\" || {{ rm -f \"$t\"; exit 1; }}; \
+         if [ \"$n\" -gt 0 ]; then mv -f \"$t\" $f || {{ rm -f \"$t\"; exit 1; }}; else rm -f \"$t\"; fi; fi",
+        f = path.map(key_path_word).unwrap_or_else(|| AUTHORIZED_KEYS.to_string()),
+        patterns = patterns.join("|"),
+        marker = REMOVED,
+    )
+}
+
+/// The command for `--rotate`: appends the new key lines read from stdin
+/// up to an empty line, unless [`InstallScript::known`] finds them among
+/// `new` already there (reporting each after [`ADDED`]), prints
+/// [`ROTATE_READY`] and reads one more line. On `commit` the lines with
+/// the old key `blobs` are removed as by [`remove_keys`]; on anything else,
+/// or if the connection drops, the lines just appended are taken out again
+/// and [`ROTATE_ROLLED_BACK`] printed. `None` for the profiles without a
+/// POSIX shell.
+pub fn rotate_keys(
+    profile: Profile,
+    new: &[(String, Vec<String>)],
+    old_blobs: &[&str],
+    home: Option<&str>,
+) -> Option<String> {
+    let posix = format!(
+        "{enter}; {prepare}; f={f}; a=$f.ssh-copy-id-rs.added.$$; \
+         if [ ! -f $f ]; then touch $f && chmod 600 $f; fi; : > \"$a\" || exit 1; \
+         while read -r key && [ -n \"$key\" ]; do if ! {installed}; then \
+         printf '%s\\n' \"$key\" >> $f || {{ rm -f \"$a\"; exit 1; }}; printf '%s\\n' \"$key\" >> \"$a\"; \
+         printf '%s%s\\n' '{added}' \"$key\"; fi; done; \
+         echo '{ready}'; answer=; read -r answer; \
+         if [ \"$answer\" = commit ]; then rm -f \"$a\"; {remove}; else \
+         if [ -s \"$a\" ]; then t=$f.ssh-copy-id-rs.$$; grep -vxF -f \"$a\" $f > \"$t\"; \
+         if [ $? -le 1 ] && mv -f \"$t\" $f; then echo '{rolled_back}'; else rm -f \"$t\"; fi; \
+         else echo '{rolled_back}'; fi; rm -f \"$a\"; fi",
+        enter = enter_home(home),
+        installed = installed(new, "$f"),
+        prepare = InstallScript::prepare_dir(None),
+        f = AUTHORIZED_KEYS,
+        added = ADDED,
+        ready = ROTATE_READY,
+        remove = remove_posix(old_blobs, None),
+        rolled_back = ROTATE_ROLLED_BACK,
+    );
+    match profile {
+        Profile::Posix => Some(checksummed(&posix)),
+        Profile::Csh => Some(format!("sh -c {}", csh_quote(&checksummed(&posix)))),
+        Profile::Busybox | Profile::Powershell | Profile::Device => None,
+    }
+}
+
+/// Prints authorized_keys, or the key file at `path`, or nothing if there
+/// is none, without changing anything. For `--dry-run`.
+pub fn read_authorized_keys(profile: Profile, home: Option<&str>, path: Option<&str>) -> String {
+    let posix = format!(
+        "{}; if [ -f {f} ]; then cat {f}; fi",
+        enter_home(home),
+        f = path.map(key_path_word).unwrap_or_else(|| AUTHORIZED_KEYS.to_string())
+    );
+    match profile {
+        Profile::Posix | Profile::Busybox => posix,
+        Profile::Csh => format!("sh -c {}", csh_quote(&posix)),
+        Profile::Powershell => powershell_encoded(&format!(
+            "{}; {}; if (Test-Path $f) {{ foreach ($line in Get-Content -Encoding UTF8 -Path $f) {{ $out.WriteLine($line) }} }}",
+            POWERSHELL_LOCATE, POWERSHELL_UTF8
+        )),
+        // Its failing is taken to mean there is no file
+        Profile::Device => match home {
+            Some(home) => format!("cat {}", sh_quote(&format!("{}/{}", home.trim_end_matches('/'), AUTHORIZED_KEYS))),
+            None => format!("cat {}", AUTHORIZED_KEYS),
+        },
+    }
+}
+
+/// Writes stdin to `path` (relative to the login directory unless absolute)
+/// with permissions `mode`, replacing any existing file atomically, so a
+/// dropped connection never leaves it half-written. For `push-file`.
+pub fn push_file(path: &str, mode: u32) -> String {
+    let path = sh_quote(path);
+    format!(
+        "t={path}.ssh-copy-id-rs.$$; umask 077; \
+         if cat > \"$t\" && chmod {mode:o} \"$t\" && mv -f \"$t\" {path}; then :; \
+         else rm -f \"$t\"; exit 1; fi"
+    )
+}
+
+/// Sets `$d` and `$f` to the key file sshd reads for the account on Windows:
+/// the shared administrators_authorized_keys for members of Administrators
+/// (as the default sshd_config has it), the profile's own file otherwise.
+const POWERSHELL_LOCATE: &str = "$id = [Security.Principal.WindowsIdentity]::GetCurrent(); \
+     $admin = $id.Groups -contains (New-Object Security.Principal.SecurityIdentifier 'S-1-5-32-544'); \
+     if ($admin) { $d = Join-Path $env:ProgramData 'ssh'; $f = Join-Path $d 'administrators_authorized_keys' } \
+     else { $d = Join-Path $env:USERPROFILE '.ssh'; $f = Join-Path $d 'authorized_keys' }";
+
+/// Sets `$utf8`, and `$out` to a UTF-8 stdout, for key comments outside
+/// ASCII: PowerShell would otherwise use the console's code page for stdin
+/// and stdout, and read key files as ANSI.
+const POWERSHELL_UTF8: &str = "$utf8 = New-Object Text.UTF8Encoding $false; \
+     $out = New-Object IO.StreamWriter([Console]::OpenStandardOutput(), $utf8); $out.AutoFlush = $true";
+
+/// Wraps a script in `powershell -EncodedCommand`, which sidesteps quoting
+/// differences between cmd.exe and PowerShell as the sshd default shell.
+fn powershell_encoded(script: &str) -> String {
+    let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!(
+        "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+        base64::encode(&utf16)
+    )
+}
+
+/// Single-quotes a string for a Bourne shell.
+pub fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Joins arguments into a command line a user can paste into a shell,
+/// quoting only the ones that need it.
+pub fn sh_join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_~[]".contains(c));
+            if plain { arg.clone() } else { sh_quote(arg) }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `script`, as the command ssh has the account's login shell run: the
+/// POSIX-like profiles' scripts are handed to `sh`, so that they work
+/// whatever that shell is, csh, tcsh and fish included, and with `run_as`
+/// `sh` is run as that user. The other profiles' commands are returned as
+/// they are, and can't be run as another user.
+pub fn login_command(profile: Profile, script: &str, run_as: Option<&Become>) -> String {
+    let command = match profile {
+        Profile::Posix | Profile::Busybox => format!("sh -c {}", login_quote(script)),
+        Profile::Csh | Profile::Powershell | Profile::Device => script.to_string(),
+    };
+    match run_as {
+        Some(run_as) => format!("{} {}", run_as.prefix(), command),
+        None => command,
+    }
+}
+
+/// Quotes a string as one word that Bourne-like shells, csh/tcsh and fish
+/// all read back unchanged. Inside single quotes they only differ on `'`,
+/// `\` (fish) and `!` (csh), so those are escaped outside the quotes,
+/// where a backslash means the same to all of them. csh can't take a
+/// newline in quotes, and no script has one.
+fn login_quote(s: &str) -> String {
+    debug_assert!(!s.contains('\n'), "scripts are a single line");
+    let mut quoted = String::from("'");
+    for c in s.chars() {
+        if matches!(c, '\'' | '\\' | '!') {
+            quoted.push_str("'\\");
+            quoted.push(c);
+            quoted.push('\'');
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Single-quotes a string for csh, which also expands `!` inside quotes.
+fn csh_quote(s: &str) -> String {
+    sh_quote(s).replace('!', "\\!")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How the POSIX-like scripts enter the home directory.
+    const ENTER: &str = "h=$(printf %s ~); if [ ! -d \"$h\" ] && command -v getent >/dev/null 2>&1; then \
+                         h=$(getent passwd \"$(id -un)\" | cut -d: -f6); fi; cd \"${h:-.}\" || exit 1; ";
+
+    #[test]
+    fn posix_script() {
+        assert_eq!(
+            InstallScript::new(Profile::Posix).render(),
+            format!(
+                "{}umask 077; mkdir -p .ssh && chmod 700 .ssh; \
+                 if [ ! -f .ssh/authorized_keys ]; then touch .ssh/authorized_keys && chmod 600 .ssh/authorized_keys; fi; \
+                 while read -r key; do if [ -n \"$key\" ]; then if ! grep -qxF \"$key\" .ssh/authorized_keys; then \
+                 printf '%s\\n' \"$key\" >> .ssh/authorized_keys; fi; fi; done",
+                ENTER
+            )
+        );
+    }
+
+    #[test]
+    fn busybox_script() {
+        assert_eq!(
+            InstallScript::new(Profile::Busybox).render(),
+            format!(
+                "{}umask 077; mkdir -p .ssh && chmod 700 .ssh; \
+                 if [ ! -f .ssh/authorized_keys ]; then : > .ssh/authorized_keys && chmod 600 .ssh/authorized_keys; fi; \
+                 while read -r key; do [ -n \"$key\" ] || continue; found=; \
+                 while read -r line; do [ \"$line\" = \"$key\" ] && found=1; done < .ssh/authorized_keys; \
+                 [ -n \"$found\" ] || printf '%s\\n' \"$key\" >> .ssh/authorized_keys; done",
+                ENTER
+            )
+        );
+    }
+
+    #[test]
+    fn csh_script_escapes_history_expansion() {
+        assert_eq!(
+            InstallScript::new(Profile::Csh).render(),
+            format!(
+                "sh -c '{}umask 077; mkdir -p .ssh && chmod 700 .ssh; \
+                 if [ \\! -f .ssh/authorized_keys ]; then touch .ssh/authorized_keys && chmod 600 .ssh/authorized_keys; fi; \
+                 while read -r key; do if [ -n \"$key\" ]; then if \\! grep -qxF \"$key\" .ssh/authorized_keys; then \
+                 printf '\\''%s\\n'\\'' \"$key\" >> .ssh/authorized_keys; fi; fi; done'",
+                ENTER.replace('!', "\\!")
+            )
+        );
+    }
+
+    #[test]
+    fn added_keys_are_reported() {
+        let script = InstallScript::new(Profile::Posix).report_added(true).render();
+        assert!(script.contains(
+            "printf '%s\\n' \"$key\" >> .ssh/authorized_keys; printf '%s%s\\n' 'ssh-copy-id-rs-added: ' \"$key\"; fi;"
+        ));
+        let script = InstallScript::new(Profile::Busybox).report_added(true).render();
+        assert!(script.contains(
+            "[ -n \"$found\" ] || { printf '%s\\n' \"$key\" >> .ssh/authorized_keys; \
+             printf '%s%s\\n' 'ssh-copy-id-rs-added: ' \"$key\"; }; done"
+        ));
+        assert!([STARTED, ADDED, SKIPPED, BACKUP, CLOCK, CHECKSUM, REMOVED, ROTATE_READY, ROTATE_ROLLED_BACK].iter().all(|marker| marker.starts_with(MARKER)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn start_is_reported_before_stdin_is_read() {
+        use std::io::{BufRead, Write};
+        let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-started-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        for profile in [Profile::Posix, Profile::Busybox] {
+            let script = InstallScript::new(profile).report_started(true).report_added(true).render();
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", &script])
+                .env("HOME", &home)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            // Stdin is still open: the line has to come before the keys are read
+            let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+            let mut first = String::new();
+            stdout.read_line(&mut first).unwrap();
+            assert_eq!(first, format!("{}\n", STARTED), "{:?}", profile);
+            child.stdin.take().unwrap().write_all(b"ssh-ed25519 AAAA one\n").unwrap();
+            assert!(child.wait().unwrap().success());
+        }
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn size_guard_runs_before_appending() {
+        let script = InstallScript::new(Profile::Posix).size_guard(true).render();
+        let guard = script.find("wc -c < .ssh/authorized_keys").unwrap();
+        assert!(guard > script.find("touch").unwrap());
+        assert!(guard < script.find("while read -r key").unwrap());
+        assert!(script.contains("-gt 1048576 ] || [ $((l)) -gt 10000 ]"));
+    }
+
+    #[test]
+    fn without_dedupe_every_key_is_appended() {
+        assert_eq!(
+            InstallScript::new(Profile::Posix).dedupe(false).render(),
+            format!(
+                "{}umask 077; mkdir -p .ssh && chmod 700 .ssh; \
+                 if [ ! -f .ssh/authorized_keys ]; then touch .ssh/authorized_keys && chmod 600 .ssh/authorized_keys; fi; \
+                 while read -r key; do if [ -n \"$key\" ]; then printf '%s\\n' \"$key\" >> .ssh/authorized_keys; fi; done",
+                ENTER
+            )
+        );
+        assert_eq!(
+            InstallScript::new(Profile::Busybox).dedupe(false).render(),
+            format!(
+                "{}umask 077; mkdir -p .ssh && chmod 700 .ssh; \
+                 if [ ! -f .ssh/authorized_keys ]; then : > .ssh/authorized_keys && chmod 600 .ssh/authorized_keys; fi; \
+                 while read -r key; do [ -n \"$key\" ] || continue; printf '%s\\n' \"$key\" >> .ssh/authorized_keys; done",
+                ENTER
+            )
+        );
+    }
+
+    #[test]
+    fn push_file_replaces_atomically() {
+        assert_eq!(
+            push_file("/etc/ssh/sshd_config.d/50-it's.conf", 0o644),
+            "t='/etc/ssh/sshd_config.d/50-it'\\''s.conf'.ssh-copy-id-rs.$$; umask 077; \
+             if cat > \"$t\" && chmod 644 \"$t\" && mv -f \"$t\" '/etc/ssh/sshd_config.d/50-it'\\''s.conf'; then :; \
+             else rm -f \"$t\"; exit 1; fi"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn announces_only_the_keys_added() {
+        let announcement = Announcement {
+            to: AnnounceTo::Readme,
+            header: "2026-10-14T12:00:00Z alice@laptop added:".to_string(),
+            keys: vec![
+                ("ssh-ed25519 AAAA one".to_string(), "SHA256:one (one)".to_string()),
+                ("ssh-ed25519 BBBB two".to_string(), "SHA256:two (two)".to_string()),
+            ],
+        };
+        for profile in [Profile::Posix, Profile::Busybox] {
+            let home = std::env::temp_dir()
+                .join(format!("ssh-copy-id-rs-announce-{:?}-{}", profile, std::process::id()));
+            std::fs::create_dir_all(home.join(".ssh")).unwrap();
+            std::fs::write(home.join(".ssh/authorized_keys"), "ssh-ed25519 AAAA one\n").unwrap();
+            let script = InstallScript::new(profile).announce(Some(announcement.clone())).render();
+            for _ in 0..2 {
+                let mut child = std::process::Command::new("sh")
+                    .args(["-c", &script])
+                    .current_dir(&home)
+                    .env("HOME", &home)
+                    .stdin(std::process::Stdio::piped())
+                    .spawn()
+                    .unwrap();
+                use std::io::Write;
+                let keys = b"ssh-ed25519 AAAA one\nssh-ed25519 BBBB two\n";
+                child.stdin.take().unwrap().write_all(keys).unwrap();
+                assert!(child.wait().unwrap().success());
+            }
+            assert_eq!(
+                std::fs::read_to_string(home.join(".ssh/README")).unwrap(),
+                "2026-10-14T12:00:00Z alice@laptop added: SHA256:two (two)\n"
+            );
+            std::fs::remove_dir_all(&home).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn backs_up_only_before_appending() {
+        let script = InstallScript::new(Profile::Posix).backup(true).render();
+        let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-backup-{}", std::process::id()));
+        std::fs::create_dir_all(home.join(".ssh")).unwrap();
+        let run = |keys: &[u8]| {
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", &script])
+                .current_dir(&home)
+                .env("HOME", &home)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            use std::io::Write;
+            child.stdin.take().unwrap().write_all(keys).unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success());
+            backed_up(&String::from_utf8(output.stdout).unwrap()).map(str::to_string)
+        };
+        let backups = || std::fs::read_dir(home.join(".ssh")).unwrap().filter(|entry| {
+            entry.as_ref().unwrap().file_name().to_string_lossy().starts_with("authorized_keys.bak.")
+        }).count();
+
+        // Nothing to keep in a new file
+        assert_eq!(run(b"ssh-ed25519 AAAA one\n"), None);
+        assert_eq!(backups(), 0);
+
+        let path = run(b"ssh-ed25519 AAAA one\nssh-ed25519 BBBB two\nssh-ed25519 CCCC three\n").unwrap();
+        assert!(path.starts_with('/') && path.contains("/.ssh/authorized_keys.bak.20"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ssh-ed25519 AAAA one\n");
+        assert_eq!(backups(), 1);
+
+        // Nothing appended, nothing copied
+        assert_eq!(run(b"ssh-ed25519 BBBB two\n"), None);
+        assert_eq!(backups(), 1);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn installs_to_a_custom_key_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let user = String::from_utf8(std::process::Command::new("id").arg("-un").output().unwrap().stdout).unwrap();
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        for profile in [Profile::Posix, Profile::Busybox] {
+            let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-path-{:?}-{}", profile, std::process::id()));
+            std::fs::create_dir_all(&home).unwrap();
+            for (path, file, dir_mode) in [
+                (".ssh/authorized_keys2", home.join(".ssh/authorized_keys2"), 0o700),
+                ("keys/%u/100%%", home.join("keys").join(user.trim()).join("100%"), 0o700),
+                ("%h/etc/%u", home.join("etc").join(user.trim()), 0o755),
+            ] {
+                let script = InstallScript::new(profile).path(Some(path.to_string())).report_added(true).render();
+                let Some(output) = run_in(SHELLS[0], &script, &home, b"ssh-ed25519 AAAA one\n") else {
+                    return;
+                };
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+                assert_eq!(added(&String::from_utf8(output.stdout).unwrap()), ["ssh-ed25519 AAAA one"]);
+                let again = run_in(SHELLS[0], &script, &home, b"ssh-ed25519 AAAA one\n").unwrap();
+                assert_eq!(skipped(&String::from_utf8(again.stdout).unwrap()), ["ssh-ed25519 AAAA one"]);
+                assert_eq!(std::fs::read_to_string(&file).unwrap(), "ssh-ed25519 AAAA one\n", "{}", path);
+                assert_eq!(mode(&file), 0o600, "{}", path);
+                assert_eq!(mode(file.parent().unwrap()), dir_mode, "{}", path);
+            }
+            assert!(!home.join(".ssh/authorized_keys").exists());
+            std::fs::remove_dir_all(&home).unwrap();
+        }
+        assert!(check_key_path("/etc/ssh/keys/%u").is_ok());
+        for invalid in ["", ".ssh/", "keys/%k", "100%", "my keys", "$HOME/ak", "ak*"] {
+            assert!(check_key_path(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn altered_scripts_are_not_run() {
+        let script = InstallScript::new(Profile::Posix).verify_checksum(true).render();
+        let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-checksum-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        let run = |script: &str| {
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", script])
+                .current_dir(&home)
+                .env("HOME", &home)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            use std::io::Write;
+            child.stdin.take().unwrap().write_all(b"ssh-ed25519 AAAA one\n").unwrap();
+            child.wait_with_output().unwrap()
+        };
+
+        let tampered = run(&script.replacen("chmod 700", "chmod 755", 1));
+        assert!(!tampered.status.success());
+        let stdout = String::from_utf8(tampered.stdout).unwrap();
+        assert!(checksum_mismatch(&stdout).is_some());
+        assert!(!home.join(".ssh").exists());
+
+        assert!(run(&script).status.success());
+        assert_eq!(
+            std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(),
+            "ssh-ed25519 AAAA one\n"
+        );
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn removes_keys_by_blob() {
+        let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-remove-{}", std::process::id()));
+        std::fs::create_dir_all(home.join(".ssh")).unwrap();
+        std::fs::write(
+            home.join(".ssh/authorized_keys"),
+            "ssh-ed25519 AAAA one\nfrom=\"10.0.0.1\" ssh-ed25519 BBBB two\n  ssh-ed25519 CCCC three",
+        )
+        .unwrap();
+        let script = remove_keys(Profile::Posix, &["BBBB", "CCCC", "DDDD"], None, None).unwrap();
+        let output = std::process::Command::new("sh")
+            .args(["-c", &script])
+            .current_dir(&home)
+            .env("HOME", &home)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            removed(&String::from_utf8(output.stdout).unwrap()),
+            ["from=\"10.0.0.1\" ssh-ed25519 BBBB two", "  ssh-ed25519 CCCC three"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(),
+            "ssh-ed25519 AAAA one\n"
+        );
+        std::fs::remove_dir_all(&home).unwrap();
+        assert!(remove_keys(Profile::Device, &["AAAA"], None, None).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keys_installed_otherwise_are_found_by_their_blob() {
+        let line = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBEXTDoLCRNPWIlDsC2mf5ZS/\
+                    NyQiLMBz6kfwnZjraOvY7UEYrAM278eVgFDdw6hmIJmCH94GZSIm0Oc+8cck7f0= bob@desk";
+        let known = crate::authorized_keys::spellings_by_line(line);
+        // The same key, with other padding bits, options and comment
+        let existing = format!("no-pty ecdsa-sha2-nistp256 {} old-comment\n", known[0].1[3]);
+        let input = format!("{}\n", line);
+        for shell in SHELLS {
+            for profile in [Profile::Posix, Profile::Busybox] {
+                let home = std::env::temp_dir().join(format!(
+                    "ssh-copy-id-rs-blob-{}-{:?}-{}",
+                    shell[0],
+                    profile,
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(home.join(".ssh")).unwrap();
+                std::fs::write(home.join(".ssh/authorized_keys"), &existing).unwrap();
+                let script = InstallScript::new(profile).report_added(true).known(known.clone()).render();
+                let Some(output) = run_in(shell, &script, &home, input.as_bytes()) else {
+                    std::fs::remove_dir_all(&home).unwrap();
+                    break;
+                };
+                let context = format!("{} with {:?}", shell.join(" "), profile);
+                assert!(output.status.success(), "{}", context);
+                let stdout = String::from_utf8(output.stdout).unwrap();
+                assert!(added(&stdout).is_empty(), "{}", context);
+                assert_eq!(skipped(&stdout), [line], "{}", context);
+                if profile == Profile::Posix {
+                    let script = rotate_keys(profile, &known, &["CCCC"], None).unwrap();
+                    let output = run_in(shell, &script, &home, format!("{}\ncommit\n", input).as_bytes()).unwrap();
+                    assert!(added(&String::from_utf8(output.stdout).unwrap()).is_empty(), "{}", context);
+                }
+                assert_eq!(
+                    std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(),
+                    existing,
+                    "{}",
+                    context
+                );
+                std::fs::remove_dir_all(&home).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn comments_come_through_the_scripts_intact() {
+        let keys = [
+            "ssh-ed25519 AAAA alice's máquina (work)",
+            "ssh-ed25519 BBBB $(touch pwned) `id` ; * \"q\" \\n\ttab  two  spaces",
+            "ssh-rsa CCCC ~/!x && {a,b} | $HOME 日本",
+        ];
+        let input: String = keys.iter().map(|key| format!("{}\n", key)).collect();
+        let announcement = Announcement {
+            header: "New key(s):".to_string(),
+            keys: keys.iter().map(|key| (key.to_string(), key.rsplit(' ').next().unwrap().to_string())).collect(),
+            to: AnnounceTo::Readme,
+        };
+        for shell in SHELLS {
+            for profile in [Profile::Posix, Profile::Busybox] {
+                let home = std::env::temp_dir().join(format!(
+                    "ssh-copy-id-rs-comments-{}-{:?}-{}",
+                    shell[0],
+                    profile,
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(&home).unwrap();
+                let script = InstallScript::new(profile)
+                    .report_added(true)
+                    .announce(Some(announcement.clone()))
+                    .render();
+                let Some(output) = run_in(shell, &script, &home, input.as_bytes()) else {
+                    std::fs::remove_dir_all(&home).unwrap();
+                    break;
+                };
+                let context = format!("{} with {:?}", shell.join(" "), profile);
+                assert!(output.status.success(), "{}", context);
+                assert_eq!(added(&String::from_utf8(output.stdout).unwrap()), keys, "{}", context);
+                let again = run_in(shell, &script, &home, input.as_bytes()).unwrap();
+                assert!(added(&String::from_utf8(again.stdout).unwrap()).is_empty(), "{}", context);
+                let installed = std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap();
+                assert_eq!(installed, input, "{}", context);
+                assert_eq!(
+                    std::fs::read_to_string(home.join(README)).unwrap(),
+                    "New key(s): (work), spaces, 日本\n",
+                    "{}",
+                    context
+                );
+                assert!(!home.join("pwned").exists(), "{}", context);
+                std::fs::remove_dir_all(&home).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn installs_in_the_home_directory_wherever_the_session_starts() {
+        let base = std::env::temp_dir().join(format!("ssh-copy-id-rs-home-{}", std::process::id()));
+        let (home, landing, given) = (base.join("home"), base.join("landing"), base.join("given dir"));
+        for dir in [&home, &landing, &given] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let run = |script: &str| {
+            use std::io::Write;
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", script])
+                .current_dir(&landing)
+                .env("HOME", &home)
+                .stdin(std::process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(b"ssh-ed25519 AAAA one\n").unwrap();
+            assert!(child.wait().unwrap().success());
+        };
+        run(&InstallScript::new(Profile::Posix).render());
+        assert!(home.join(".ssh/authorized_keys").is_file());
+        let given_home = Some(given.to_string_lossy().into_owned());
+        run(&InstallScript::new(Profile::Busybox).home(given_home.clone()).render());
+        run(&InstallScript::new(Profile::Device).home(given_home).render());
+        assert_eq!(
+            std::fs::read_to_string(given.join(".ssh/authorized_keys")).unwrap(),
+            "ssh-ed25519 AAAA one\nssh-ed25519 AAAA one\n"
+        );
+        assert!(!landing.join(".ssh").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotation_rolls_back_unless_committed() {
+        let home = std::env::temp_dir().join(format!("ssh-copy-id-rs-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(home.join(".ssh")).unwrap();
+        let script = rotate_keys(Profile::Posix, &[], &["AAAA"], None).unwrap();
+        let before = "ssh-ed25519 AAAA old\nssh-ed25519 CCCC other\n";
+        // Rejected, and the connection dropping before an answer
+        for answer in ["ssh-ed25519 BBBB new\n\nrollback\n", "ssh-ed25519 BBBB new\n\n"] {
+            std::fs::write(home.join(".ssh/authorized_keys"), before).unwrap();
+            let output = run_in(&["sh"], &script, &home, answer.as_bytes()).unwrap();
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert!(output.status.success());
+            assert_eq!(added(&stdout), ["ssh-ed25519 BBBB new"]);
+            assert!(stdout.lines().any(|line| line == ROTATE_ROLLED_BACK));
+            assert_eq!(std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(), before);
+        }
+        let output = run_in(&["sh"], &script, &home, b"ssh-ed25519 BBBB new\n\ncommit\n").unwrap();
+        assert_eq!(removed(&String::from_utf8(output.stdout).unwrap()), ["ssh-ed25519 AAAA old"]);
+        assert_eq!(
+            std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(),
+            "ssh-ed25519 CCCC other\nssh-ed25519 BBBB new\n"
+        );
+        assert_eq!(std::fs::read_dir(home.join(".ssh")).unwrap().count(), 1);
+        std::fs::remove_dir_all(&home).unwrap();
+        assert!(rotate_keys(Profile::Busybox, &[], &["AAAA"], None).is_none());
+    }
+
+    /// The shells the POSIX scripts have to work in. Those that aren't
+    /// installed are skipped, unless `SSH_COPY_ID_RS_REQUIRE_SHELLS` is set
+    /// (as in CI).
+    const SHELLS: &[&[&str]] = &[&["sh"], &["dash"], &["busybox", "sh"], &["ksh"], &["yash"]];
+
+    fn run_in(
+        shell: &[&str],
+        script: &str,
+        home: &std::path::Path,
+        stdin: &[u8],
+    ) -> Option<std::process::Output> {
+        use std::io::Write;
+        let mut child = match std::process::Command::new(shell[0])
+            .args(&shell[1..])
+            .args(["-c", script])
+            .current_dir(home)
+            .env("HOME", home)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let required = std::env::var_os("SSH_COPY_ID_RS_REQUIRE_SHELLS").is_some();
+                assert!(!required, "{} is not installed", shell[0]);
+                return None;
+            }
+            Err(e) => panic!("{}: {}", shell[0], e),
+        };
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        Some(child.wait_with_output().unwrap())
+    }
+
+    #[test]
+    fn scripts_run_under_minimal_shells() {
+        // Backslashes and quotes in the options must come through as they are
+        let keys = "command=\"printf 'a\\\\nb'\" ssh-ed25519 AAAA one\nssh-ed25519 BBBB two\n";
+        for shell in SHELLS {
+            for profile in [Profile::Posix, Profile::Busybox] {
+                let home = std::env::temp_dir().join(format!(
+                    "ssh-copy-id-rs-shell-{}-{:?}-{}",
+                    shell[0],
+                    profile,
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(&home).unwrap();
+                let script = InstallScript::new(profile)
+                    .size_guard(true)
+                    .report_added(true)
+                    .verify_checksum(true)
+                    .render();
+                let Some(first) = run_in(shell, &script, &home, keys.as_bytes()) else {
+                    std::fs::remove_dir_all(&home).unwrap();
+                    break;
+                };
+                let context = format!(
+                "{} with {:?}: {}",
+                    shell.join(" "),
+                    profile,
+                    String::from_utf8_lossy(&first.stderr)
+                );
+                assert!(first.status.success(), "{}", context);
+                let stdout = String::from_utf8(first.stdout).unwrap();
+                assert_eq!(added(&stdout).len(), 2, "{}", context);
+                let again = run_in(shell, &script, &home, keys.as_bytes()).unwrap();
+                assert!(again.status.success(), "{}", context);
+                let stdout = String::from_utf8(again.stdout).unwrap();
+                assert!(added(&stdout).is_empty(), "{}", context);
+                assert_eq!(skipped(&stdout).len(), 2, "{}", context);
+                let installed = std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap();
+                assert_eq!(installed, keys, "{}", context);
+
+                let listed = run_in(shell, &read_authorized_keys(profile, None, None), &home, b"").unwrap();
+                assert_eq!(String::from_utf8(listed.stdout).unwrap(), keys, "{}", context);
+                if profile == Profile::Posix {
+                    let script = remove_keys(profile, &["AAAA"], None, None).unwrap();
+                    let output = run_in(shell, &script, &home, b"").unwrap();
+                    assert!(output.status.success(), "{}", context);
+                    let stdout = String::from_utf8(output.stdout).unwrap();
+                    assert_eq!(removed(&stdout), [keys.lines().next().unwrap()], "{}", context);
+                    assert_eq!(
+                        std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap(),
+                        "ssh-ed25519 BBBB two\n",
+                        "{}",
+                        context
+                    );
+                    let script = push_file("pushed", 0o640);
+                    let output = run_in(shell, &script, &home, b"x\\y\n").unwrap();
+                    assert!(output.status.success(), "{}", context);
+                    assert_eq!(std::fs::read_to_string(home.join("pushed")).unwrap(), "x\\y\n");
+                }
+                std::fs::remove_dir_all(&home).unwrap();
+            }
+        }
+    }
+
+    /// Login shells that can't run the POSIX scripts themselves, or not all
+    /// of them, and get them through [`login_command`].
+    const LOGIN_SHELLS: &[&[&str]] = &[&["bash"], &["zsh"], &["csh"], &["tcsh"], &["fish"]];
+
+    #[test]
+    fn scripts_run_under_any_login_shell() {
+        assert_eq!(login_quote("it's a\\b!"), "'it'\\''s a'\\\\'b'\\!''");
+        let keys = "command=\"printf 'a\\\\nb'\" ssh-ed25519 AAAA one!x\nssh-ed25519 BBBB two\n";
+        let announcement = Announcement {
+            header: "New key(s):".to_string(),
+            keys: vec![(keys.lines().next().unwrap().to_string(), "it's !x".to_string())],
+            to: AnnounceTo::Readme,
+        };
+        for shell in SHELLS.iter().chain(LOGIN_SHELLS) {
+            for profile in [Profile::Posix, Profile::Busybox] {
+                let home = std::env::temp_dir().join(format!(
+                    "ssh-copy-id-rs-login-{}-{:?}-{}",
+                    shell[0],
+                    profile,
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(&home).unwrap();
+                let script = InstallScript::new(profile)
+                    .size_guard(true)
+                    .report_added(true)
+                    .announce(Some(announcement.clone()))
+                    .verify_checksum(true)
+                    .render();
+                let command = login_command(profile, &script, None);
+                let Some(output) = run_in(shell, &command, &home, keys.as_bytes()) else {
+                    std::fs::remove_dir_all(&home).unwrap();
+                    break;
+                };
+                let context = format!(
+                    "{} with {:?}: {}",
+                    shell.join(" "),
+                    profile,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                assert!(output.status.success(), "{}", context);
+                assert_eq!(added(&String::from_utf8(output.stdout).unwrap()).len(), 2, "{}", context);
+                let installed = std::fs::read_to_string(home.join(".ssh/authorized_keys")).unwrap();
+                assert_eq!(installed, keys, "{}", context);
+                assert_eq!(std::fs::read_to_string(home.join(README)).unwrap(), "New key(s): it's !x\n");
+
+                let listed = login_command(profile, &read_authorized_keys(profile, None, None), None);
+                let listed = run_in(shell, &listed, &home, b"").unwrap();
+                assert_eq!(String::from_utf8(listed.stdout).unwrap(), keys, "{}", context);
+                if profile == Profile::Posix {
+                    let script = login_command(profile, &remove_keys(profile, &["AAAA"], None, None).unwrap(), None);
+                    let output = run_in(shell, &script, &home, b"").unwrap();
+                    assert_eq!(removed(&String::from_utf8(output.stdout).unwrap()).len(), 1, "{}", context);
+                }
+                std::fs::remove_dir_all(&home).unwrap();
+            }
+        }
+        assert_eq!(login_command(Profile::Device, "cat", None), "cat");
+        let run_as = |method| Become { method, user: "svc-backup".to_string() };
+        assert_eq!(
+            login_command(Profile::Posix, "cd", Some(&run_as(BecomeMethod::Sudo))),
+            "sudo -n -H -u 'svc-backup' -- sh -c 'cd'"
+        );
+        assert_eq!(
+            login_command(Profile::Csh, "sh -c 'cd'", Some(&run_as(BecomeMethod::Doas))),
+            "doas -n -u 'svc-backup' sh -c 'cd'"
+        );
+    }
+
+    #[test]
+    fn device_script() {
+        assert_eq!(
+            InstallScript::new(Profile::Device).render(),
+            "mkdir -p .ssh; cat >> .ssh/authorized_keys"
+        );
+    }
+
+    #[test]
+    fn powershell_script_is_encoded() {
+        assert_eq!(
+            powershell_encoded("Write-Output 'hi'"),
+            "powershell -NoProfile -NonInteractive -EncodedCommand VwByAGkAdABlAC0ATwB1AHQAcAB1AHQAIAAnAGgAaQAnAA=="
+        );
+        assert!(
+            InstallScript::new(Profile::Powershell)
+                .render()
+                .starts_with(
+                    "powershell -NoProfile -NonInteractive -EncodedCommand JABFAHIAcgBvAHIA"
+                )
+        );
+    }
+
+    #[test]
+    fn powershell_script_handles_administrators() {
+        let encoded = InstallScript::new(Profile::Powershell).render();
+        let utf16 = base64::decode(encoded.rsplit(' ').next().unwrap()).unwrap();
+        let units: Vec<u16> = utf16.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        let script = String::from_utf16(&units).unwrap();
+        assert!(script.contains("if ($admin) { $d = Join-Path $env:ProgramData 'ssh'; $f = Join-Path $d 'administrators_authorized_keys' }"));
+        assert!(script.ends_with("icacls $f /inheritance:r /grant $owner /grant '*S-1-5-18:F' | Out-Null"));
+        // Comments outside ASCII are read, compared and written as UTF-8
+        assert!(script.contains("(New-Object IO.StreamReader([Console]::OpenStandardInput(), $utf8)).ReadToEnd()"));
+        assert!(script.contains("$existing = @(Get-Content -Encoding UTF8 -Path $f)"));
+        assert!(ran_on_windows("'umask' is not recognized as an internal or external command,\n"));
+
+        // Keys already there are found by their blob, whatever the line
+        let known = vec![("no-pty ssh-ed25519 AAAA bob's".to_string(), vec!["AAAA".to_string(), "AAAB".to_string()])];
+        let encoded = InstallScript::new(Profile::Powershell).known(known).render();
+        let utf16 = base64::decode(encoded.rsplit(' ').next().unwrap()).unwrap();
+        let units: Vec<u16> = utf16.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        let script = String::from_utf16(&units).unwrap();
+        assert!(script.contains("$known['no-pty ssh-ed25519 AAAA bob''s'] = @('AAAA', 'AAAB'); "));
+        assert!(script.contains("Where-Object { $blobs -ccontains $_ }"));
+    }
+
+    #[test]
+    fn sh_join_quotes_only_when_needed() {
+        let args = [
+            "-i".to_string(),
+            "~/my keys/a.pub".to_string(),
+            "user@host".to_string(),
+        ];
+        assert_eq!(sh_join(&args), "-i '~/my keys/a.pub' user@host");
+    }
+
+    #[test]
+    fn sh_quote_escapes_single_quotes() {
+        assert_eq!(sh_quote("it's"), "'it'\\''s'");
+    }
+}
@@ -0,0 +1,582 @@
+//! Progress and result output of an install run, one [`Reporter`] per
+//! `--output` format.
+
+use crate::json::Value;
+use crate::porcelain;
+use clap::ValueEnum;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Readable progress messages and a summary (the default)
+    Human,
+    /// One JSON document with every host, written at the end
+    Json,
+    /// One JSON object per line as each host finishes, then a summary line
+    Jsonl,
+    /// A header and one row per host, written at the end
+    Csv,
+    /// Nothing; only the exit status tells the outcome
+    Quiet,
+    /// A live status line per host
+    Tui,
+}
+
+impl Format {
+    /// Whether the format owns stdout, so ssh's own output must go elsewhere.
+    pub fn is_machine_readable(self) -> bool {
+        matches!(self, Format::Json | Format::Jsonl | Format::Csv)
+    }
+
+    /// Whether the format reports each host's SSH banner.
+    pub fn reports_banner(self) -> bool {
+        self.is_machine_readable()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    Failed(String),
+    TimedOut(Duration),
+    /// With `--apply-plan`, left alone because its keys changed since the
+    /// plan was made
+    DriftDetected(String),
+}
+
+/// The fingerprints of the keys a host added, and of those it had already
+/// (or, with `--remove`, didn't have); and of those `--remove` or
+/// `--rotate` took out.
+#[derive(Clone, Debug, Default)]
+pub struct KeyOutcome {
+    pub added: Vec<String>,
+    pub skipped: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// What `--dry-run` found a run would do with one key on a host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// `add`, `keep` (installed, and left so), `remove` or, with
+    /// `--remove`, `absent`
+    pub action: &'static str,
+    pub fingerprint: String,
+    pub algorithm: String,
+    pub comment: Option<String>,
+    /// The options of the installed line, or those it would be added with
+    pub options: Option<String>,
+}
+
+impl Change {
+    fn to_json(&self) -> Value {
+        Value::object()
+            .with("action", self.action)
+            .with("fingerprint", self.fingerprint.as_str())
+            .with("algorithm", self.algorithm.as_str())
+            .with("comment", self.comment.clone())
+            .with("options", self.options.clone())
+    }
+}
+
+/// The outcome of installing to one host.
+#[derive(Clone, Debug)]
+pub struct HostResult {
+    pub destination: String,
+    pub status: Status,
+    pub duration: Duration,
+    /// Took longer than the `--slow-after` threshold
+    pub slow: bool,
+    /// Number of keys sent to the host
+    pub keys: usize,
+    /// Which of those the host reported adding, when it could tell
+    pub key_outcome: Option<KeyOutcome>,
+    /// ssh arguments for logging in with the new key
+    pub login_command: String,
+    /// SSHFP records, with `--emit-sshfp`
+    pub sshfp: Vec<String>,
+    /// The server's identification string, e.g. `SSH-2.0-OpenSSH_9.6`
+    pub banner: Option<String>,
+    /// The host key ssh was shown, as `TYPE SHA256:...`
+    pub host_key: Option<String>,
+    /// The remote path written, for `push-file`
+    pub file: Option<String>,
+    /// Only previewed with `--dry-run`; nothing was sent
+    pub dry_run: bool,
+    /// With `--dry-run`, what would happen to each key
+    pub changes: Vec<Change>,
+    /// The keys were being removed, with `--remove`
+    pub remove: bool,
+}
+
+impl HostResult {
+    /// How many keys were added: as reported, or else all that were sent.
+    pub fn added(&self) -> usize {
+        self.key_outcome.as_ref().map_or(self.keys, |outcome| outcome.added.len())
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        match self.status {
+            Status::Ok => "ok",
+            Status::Failed(_) => "failed",
+            Status::TimedOut(_) => "timed_out",
+            Status::DriftDetected(_) => "drift_detected",
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match &self.status {
+            Status::Ok => None,
+            Status::Failed(message) | Status::DriftDetected(message) => Some(message.clone()),
+            Status::TimedOut(after) => Some(format!("Timed out after {}s", after.as_secs())),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let json = Value::object()
+            .with("destination", self.destination.as_str())
+            .with("status", self.status_name())
+            .with("error", self.error())
+            .with("duration_ms", self.duration.as_millis() as u64)
+            .with("slow", self.slow)
+            .with("keys", self.keys)
+            .with("added", self.key_outcome.as_ref().map(|outcome| outcome.added.len()))
+            .with("keys_added", self.key_outcome.as_ref().map(|outcome| outcome.added.clone()))
+            .with("keys_skipped", self.key_outcome.as_ref().map(|outcome| outcome.skipped.clone()))
+            .with("sshfp", self.sshfp.clone())
+            .with("banner", self.banner.clone())
+            .with("host_key", self.host_key.clone());
+        let json = match &self.file {
+            Some(file) => json.with("file", file.as_str()),
+            None => json,
+        };
+        let removed = self.key_outcome.as_ref().is_some_and(|outcome| !outcome.removed.is_empty());
+        let json = match self.remove || removed {
+            true => json.with("keys_removed", self.key_outcome.as_ref().map(|outcome| outcome.removed.clone())),
+            false => json,
+        };
+        match self.dry_run {
+            true => json
+                .with("dry_run", true)
+                .with("changes", Value::Array(self.changes.iter().map(Change::to_json).collect())),
+            false => json,
+        }
+    }
+}
+
+pub trait Reporter {
+    /// Called once the keys to install are known.
+    fn start(&mut self, _source: &str, _hosts: usize) {}
+    fn host_started(&mut self, _destination: &str) {}
+    /// A progress message about the current host.
+    fn info(&mut self, _message: &str) {}
+    fn host_finished(&mut self, _result: &HostResult) {}
+    fn finish(&mut self, _results: &[HostResult]) {}
+}
+
+pub fn new(format: Format, slow_after: Duration) -> Box<dyn Reporter> {
+    match format {
+        Format::Human => Box::new(Human::new(slow_after)),
+        Format::Json => Box::new(Json::default()),
+        Format::Jsonl => Box::new(Jsonl),
+        Format::Csv => Box::new(Csv),
+        Format::Quiet => Box::new(Quiet),
+        Format::Tui => Box::new(Tui::new()),
+    }
+}
+
+fn summary(results: &[HostResult]) -> Value {
+    let count = |status: &str| results.iter().filter(|r| r.status_name() == status).count();
+    Value::object()
+        .with("hosts", results.len())
+        .with("ok", count("ok"))
+        .with("failed", results.len() - count("ok"))
+        .with("timed_out", count("timed_out"))
+        .with("drift_detected", count("drift_detected"))
+        .with("slow", results.iter().filter(|r| r.slow).count())
+}
+
+/// A single grep-able line summarizing the run, for `--trailer`, e.g.
+/// `RESULT hosts=10 ok=9 failed=1 keys_added=12`.
+pub fn trailer(results: &[HostResult]) -> String {
+    let ok: Vec<&HostResult> = results.iter().filter(|r| r.status == Status::Ok).collect();
+    format!(
+        "RESULT hosts={} ok={} failed={} keys_added={}",
+        results.len(),
+        ok.len(),
+        results.len() - ok.len(),
+        ok.iter().map(|r| r.added()).sum::<usize>()
+    )
+}
+
+struct Human {
+    slow_after: Duration,
+    multiple: bool,
+}
+
+impl Human {
+    fn new(slow_after: Duration) -> Human {
+        Human {
+            slow_after,
+            multiple: false,
+        }
+    }
+}
+
+impl Reporter for Human {
+    fn start(&mut self, source: &str, hosts: usize) {
+        println!("Source: {}", source);
+        self.multiple = hosts > 1;
+    }
+
+    fn host_started(&mut self, destination: &str) {
+        if self.multiple {
+            println!("\n==> {}", destination);
+        }
+        println!("Target: {}", destination);
+    }
+
+    fn info(&mut self, message: &str) {
+        println!("Info: {}", message);
+    }
+
+    fn host_finished(&mut self, result: &HostResult) {
+        if let (None, Some(host_key)) = (result.error(), &result.host_key) {
+            println!("Host key: {}", host_key);
+        }
+        match (result.error(), &result.file) {
+            // A single host's error is reported by main as the run's error
+            (Some(error), _) if self.multiple => eprintln!("Error: {}", error),
+            (Some(_), _) => {}
+            (None, Some(file)) => println!("Wrote {}", file),
+            (None, None) if result.dry_run => {}
+            (None, None) if result.remove => {
+                let removed = result.key_outcome.as_ref().map_or(0, |outcome| outcome.removed.len());
+                println!("\nNumber of key(s) removed: {}", removed);
+            }
+            (None, None) => {
+                let outcome = result.key_outcome.clone().unwrap_or_default();
+                match outcome.skipped.len() {
+                    0 => println!("\nNumber of key(s) added: {}", outcome.added.len()),
+                    skipped => println!(
+                        "\nNumber of key(s) added: {} ({} already installed)",
+                        outcome.added.len(),
+                        skipped
+                    ),
+                }
+                if let Some(outcome) = result.key_outcome.as_ref().filter(|outcome| !outcome.removed.is_empty()) {
+                    println!("Number of key(s) removed: {}", outcome.removed.len());
+                }
+                println!(
+                    "\nNow try logging into the machine, with:   \"ssh {}\"",
+                    result.login_command
+                );
+                println!("and check to make sure that only the key(s) you wanted were added.");
+                if !result.sshfp.is_empty() {
+                    println!("\nSSHFP records:");
+                    for record in &result.sshfp {
+                        println!("{}", record);
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, results: &[HostResult]) {
+        if !self.multiple {
+            return;
+        }
+        let failed: Vec<&HostResult> = results.iter().filter(|r| r.status != Status::Ok).collect();
+        println!(
+            "\nSummary: {} hosts, {} ok, {} failed",
+            results.len(),
+            results.len() - failed.len(),
+            failed.len()
+        );
+        for result in failed {
+            println!(
+                "  failed: {} ({})",
+                result.destination,
+                result.error().unwrap_or_default()
+            );
+        }
+        for result in results.iter().filter(|r| r.slow) {
+            println!(
+                "  slow:   {} ({:.1}s, over {}s)",
+                result.destination,
+                result.duration.as_secs_f64(),
+                self.slow_after.as_secs()
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct Json {
+    source: String,
+}
+
+impl Reporter for Json {
+    fn start(&mut self, source: &str, _hosts: usize) {
+        self.source = source.to_string();
+    }
+
+    fn finish(&mut self, results: &[HostResult]) {
+        let hosts: Vec<Value> = results.iter().map(HostResult::to_json).collect();
+        let failed = results.iter().any(|r| r.status != Status::Ok);
+        let report = Value::object()
+            .with("source", self.source.as_str())
+            .with("hosts", hosts)
+            .with("summary", summary(results))
+            .with("error", Value::Null)
+            .with("exit_reason", if failed { "hosts_failed" } else { "ok" });
+        println!("{}", report);
+    }
+}
+
+/// The `--output json` document of a run that failed before reaching any
+/// host, e.g. for want of keys.
+pub fn print_json_error(error: &anyhow::Error) {
+    let report = Value::object()
+        .with("source", Value::Null)
+        .with("hosts", Vec::<Value>::new())
+        .with("summary", summary(&[]))
+        .with("error", format!("{:#}", error))
+        .with("exit_reason", "error");
+    println!("{}", report);
+}
+
+struct Jsonl;
+
+impl Reporter for Jsonl {
+    fn host_finished(&mut self, result: &HostResult) {
+        println!(
+            "{}",
+            Value::object()
+                .with("type", "host")
+                .with("host", result.to_json())
+        );
+    }
+
+    fn finish(&mut self, results: &[HostResult]) {
+        println!(
+            "{}",
+            Value::object()
+                .with("type", "summary")
+                .with("summary", summary(results))
+        );
+    }
+}
+
+struct Csv;
+
+impl Reporter for Csv {
+    fn finish(&mut self, results: &[HostResult]) {
+        println!("destination,status,duration_ms,slow,error,banner,host_key");
+        for result in results {
+            println!(
+                "{},{},{},{},{},{},{}",
+                csv_field(&result.destination),
+                result.status_name(),
+                result.duration.as_millis(),
+                result.slow,
+                csv_field(&result.error().unwrap_or_default()),
+                csv_field(result.banner.as_deref().unwrap_or_default()),
+                csv_field(result.host_key.as_deref().unwrap_or_default())
+            );
+        }
+    }
+}
+
+/// Quotes a field if it contains a delimiter, quote or line break (RFC 4180).
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `--porcelain` records:
+///
+/// ```text
+/// host<TAB>DESTINATION<TAB>STATUS<TAB>DURATION_MS<TAB>KEYS<TAB>SLOW<TAB>ERROR<TAB>BANNER<TAB>HOST_KEY
+/// summary<TAB>HOSTS<TAB>OK<TAB>FAILED<TAB>KEYS_ADDED
+/// ```
+///
+/// STATUS is `ok`, `failed`, `timed_out` or (with `--apply-plan`)
+/// `drift_detected`; SLOW is `true`/`false`; ERROR is
+/// `-` for hosts that succeeded; BANNER and HOST_KEY (`TYPE SHA256:...`)
+/// are `-` when they couldn't be learned.
+pub struct Porcelain(pub porcelain::Version);
+
+impl Reporter for Porcelain {
+    fn start(&mut self, _source: &str, _hosts: usize) {
+        println!("{}", self.0.header());
+    }
+
+    fn host_finished(&mut self, result: &HostResult) {
+        println!(
+            "{}",
+            porcelain::line(&[
+                "host",
+                &result.destination,
+                result.status_name(),
+                &result.duration.as_millis().to_string(),
+                &result.keys.to_string(),
+                &result.slow.to_string(),
+                &result.error().unwrap_or_else(|| "-".to_string()),
+                result.banner.as_deref().unwrap_or("-"),
+                result.host_key.as_deref().unwrap_or("-"),
+            ])
+        );
+    }
+
+    fn finish(&mut self, results: &[HostResult]) {
+        let ok: Vec<&HostResult> = results.iter().filter(|r| r.status == Status::Ok).collect();
+        println!(
+            "{}",
+            porcelain::line(&[
+                "summary",
+                &results.len().to_string(),
+                &ok.len().to_string(),
+                &(results.len() - ok.len()).to_string(),
+                &ok.iter().map(|r| r.keys).sum::<usize>().to_string(),
+            ])
+        );
+    }
+}
+
+struct Quiet;
+
+impl Reporter for Quiet {}
+
+/// One status line per host, rewritten in place while the host runs.
+struct Tui {
+    color: bool,
+    total: usize,
+    done: usize,
+    current: String,
+}
+
+impl Tui {
+    fn new() -> Tui {
+        Tui {
+            color: std::io::stdout().is_terminal(),
+            total: 0,
+            done: 0,
+            current: String::new(),
+        }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn line(&self, status: &str, detail: &str) {
+        let counter = format!("[{}/{}]", self.done + 1, self.total);
+        let clear = if self.color { "\r\x1b[K" } else { "" };
+        print!(
+            "{}{} {} {} {}",
+            clear, counter, status, self.current, detail
+        );
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Reporter for Tui {
+    fn start(&mut self, source: &str, hosts: usize) {
+        self.total = hosts;
+        println!("Copying {} to {} host(s)", source, hosts);
+    }
+
+    fn host_started(&mut self, destination: &str) {
+        self.current = destination.to_string();
+        let status = self.paint("33", " .. ");
+        self.line(&status, "");
+        if !self.color {
+            println!();
+        }
+    }
+
+    fn host_finished(&mut self, result: &HostResult) {
+        let (status, detail) = match result.error() {
+            None => (
+                self.paint("32", " ok "),
+                format!("({:.1}s)", result.duration.as_secs_f64()),
+            ),
+            Some(error) => (self.paint("31", "FAIL"), error),
+        };
+        let detail = if result.slow {
+            format!("{} {}", detail, self.paint("33", "slow"))
+        } else {
+            detail
+        };
+        self.line(&status, &detail);
+        println!();
+        self.done += 1;
+    }
+
+    fn finish(&mut self, results: &[HostResult]) {
+        let ok = results.iter().filter(|r| r.status == Status::Ok).count();
+        println!("{} ok, {} failed", ok, results.len() - ok);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(destination: &str, status: Status, keys: usize) -> HostResult {
+        HostResult {
+            destination: destination.to_string(),
+            status,
+            duration: Duration::from_millis(10),
+            slow: false,
+            keys,
+            key_outcome: None,
+            login_command: destination.to_string(),
+            sshfp: Vec::new(),
+            banner: None,
+            host_key: None,
+            file: None,
+            dry_run: false,
+            changes: Vec::new(),
+            remove: false,
+        }
+    }
+
+    #[test]
+    fn trailer_counts_hosts_and_keys() {
+        let results = [
+            result("web1", Status::Ok, 2),
+            result("web2", Status::Failed("exit 255".to_string()), 2),
+            result("web3", Status::TimedOut(Duration::from_secs(5)), 2),
+            result("web4", Status::Ok, 2),
+        ];
+        assert_eq!(
+            trailer(&results),
+            "RESULT hosts=4 ok=2 failed=2 keys_added=4"
+        );
+        let mut partly_installed = result("web5", Status::Ok, 2);
+        partly_installed.key_outcome = Some(KeyOutcome {
+            added: vec!["SHA256:Uorj1loiJliOOGXkC40FARqq38bOprN/wWWdEzlhYJs".to_string()],
+            skipped: vec!["SHA256:sydcTCUJiXIQqKeDGaoZg6VTpHCw+edHoHVGrw8AC10".to_string()],
+            removed: Vec::new(),
+        });
+        assert_eq!(
+            trailer(&[partly_installed]),
+            "RESULT hosts=1 ok=1 failed=0 keys_added=1"
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("user@web1"), "user@web1");
+        assert_eq!(csv_field("exit 1, \"bad\""), "\"exit 1, \"\"bad\"\"\"");
+    }
+}
@@ -0,0 +1,93 @@
+//! `--rotate OLD NEW`: replaces a key in one session. The remote command
+//! (see [`crate::remote_script::rotate_keys`]) appends NEW and then waits
+//! while a second login, offering NEW alone, is tried; only once that has
+//! worked does it remove OLD, and otherwise it takes NEW out again.
+
+use crate::connection::ConnectionArgs;
+use crate::destination::Destination;
+use crate::key::PublicKey;
+use crate::token;
+use crate::{relay, wait_with_timeout};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// What ssh is given with `-i` to log in with NEW: its private key if it
+/// is next to the `.pub` file, or else the public key, which has ssh look
+/// for the private one in the agent.
+pub fn login_identity(new: &str) -> PathBuf {
+    let new = match new.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map_or_else(|| PathBuf::from(new), |home| home.join(rest)),
+        None => PathBuf::from(new),
+    };
+    match new.to_str().and_then(|path| path.strip_suffix(".pub")) {
+        Some(private) if std::path::Path::new(private).is_file() => PathBuf::from(private),
+        _ => new,
+    }
+}
+
+/// Logs in to `destination` offering only `identity`, and tells whether
+/// the server accepted `key` for it. `info` is told why a login failed.
+pub fn verify(
+    connection: &ConnectionArgs,
+    destination: &Destination,
+    timeout: Option<Duration>,
+    identity: &std::path::Path,
+    key: &PublicKey,
+    info: &mut dyn FnMut(&str),
+) -> Result<bool> {
+    let fingerprint = key.decode()?.fingerprint_sha256();
+    let mut ssh = connection.ssh(destination, timeout, info)?;
+    // A multiplexed session would log in with whatever key it was opened
+    // with, and the config's own IdentityFile may hold OLD, so ssh's debug
+    // output is checked for the key the server accepted
+    for option in [
+        "BatchMode=yes",
+        "PasswordAuthentication=no",
+        "KbdInteractiveAuthentication=no",
+        "PreferredAuthentications=publickey",
+        "ControlMaster=no",
+        "ControlPath=none",
+        "IdentitiesOnly=yes",
+    ] {
+        ssh.command.args(["-o", option]);
+    }
+    ssh.command.arg("-v").arg("-i").arg(identity);
+    let mut child = ssh
+        .with_remote(["true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    let stderr = relay(
+        child.stderr.take().expect("stderr is piped"),
+        Box::new(std::io::sink()),
+        None,
+    );
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let stderr = stderr.join().unwrap_or_default();
+    let accepted = stderr
+        .lines()
+        .rfind(|line| line.contains("Server accepts key:"))
+        .is_some_and(|line| line.split_whitespace().any(|word| word == fingerprint));
+    if status.is_some_and(|status| status.success()) && accepted {
+        return Ok(true);
+    }
+    let reason = match status {
+        None => "timed out".to_string(),
+        Some(status) if status.success() => "the server let ssh in with another key".to_string(),
+        Some(_) => match token::fido_error(&stderr) {
+            Some(fido) => fido.to_string(),
+            None => stderr
+                .lines()
+                .rfind(|line| !line.starts_with("debug"))
+                .unwrap_or("ssh failed")
+                .trim()
+                .to_string(),
+        },
+    };
+    info(&format!("A login with the new key failed: {}", reason));
+    Ok(false)
+}
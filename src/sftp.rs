@@ -0,0 +1,228 @@
+//! Key installs over SFTP, for accounts whose sshd runs nothing but
+//! `internal-sftp` (`ForceCommand internal-sftp`, usually together with a
+//! `ChrootDirectory`). Paths are relative to where the session starts,
+//! i.e. inside the chroot, unless `--remote-home` gives the directory.
+//!
+//! The current `authorized_keys` is downloaded, the missing keys are
+//! appended locally, and the result is uploaded next to it and renamed
+//! over it, so the file is never left half-written.
+
+use crate::authorized_keys;
+use crate::connection::Ssh;
+use crate::fsutil::TempFile;
+use crate::remote_script::{InstallScript, MAX_BYTES, MAX_LINES};
+use crate::timeutil;
+use crate::transcript;
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// What sshd prints, on stdout, for a command to an sftp-only account.
+pub const REFUSAL: &str = "This service allows sftp connections only.";
+
+const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+const UPLOAD: &str = ".ssh/authorized_keys.ssh-copy-id-rs";
+
+/// The error of an install whose command the account refused to run.
+#[derive(Debug)]
+pub struct SftpOnly;
+
+impl std::fmt::Display for SftpOnly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The account only allows SFTP, so no command can be run on it")
+    }
+}
+
+impl std::error::Error for SftpOnly {}
+
+/// What [`install`] did.
+pub struct Installed {
+    /// The lines that were added
+    pub added: Vec<String>,
+    /// Where the previous authorized_keys was copied, with `backup`
+    pub backup: Option<String>,
+}
+
+/// Appends the keys of `payload` (one per line) that `ssh`'s account
+/// doesn't have yet, in `.ssh` under `home` if given. With `backup`, a
+/// non-empty authorized_keys is first copied to a timestamped file next to
+/// it, if anything is to be added.
+pub fn install(
+    ssh: &Ssh,
+    payload: &str,
+    size_guard: bool,
+    backup: bool,
+    home: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Installed> {
+    let existing = download(ssh, true, home, timeout)?;
+
+    if size_guard {
+        let lines = existing.lines().count() as u64;
+        if existing.len() as u64 > MAX_BYTES || lines > MAX_LINES {
+            bail!(InstallScript::refusal(&format!(
+                "{} lines ({} bytes)",
+                lines,
+                existing.len()
+            )));
+        }
+    }
+
+    let (merged, added) = authorized_keys::append_missing(&existing, payload);
+    if added.is_empty() {
+        return Ok(Installed {
+            added,
+            backup: None,
+        });
+    }
+
+    let backup = match backup && !existing.is_empty() {
+        true => Some(save_backup(ssh, &existing, home, timeout)?),
+        false => None,
+    };
+    upload(ssh, &merged, home, timeout)?;
+    Ok(Installed { added, backup })
+}
+
+/// Uploads `content`, the current authorized_keys, as a timestamped copy
+/// beside it, and returns the copy's path.
+fn save_backup(
+    ssh: &Ssh,
+    content: &str,
+    home: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    let stamp: String = timeutil::format_utc(timeutil::now_unix())
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':' | 'Z'))
+        .collect();
+    let path = format!("{}.bak.{}", AUTHORIZED_KEYS, stamp);
+    let copy = TempFile::create("sftp-keys", content.as_bytes())?;
+    let batch = format!(
+        "{}put {} {path}\nchmod 600 {path}\n",
+        enter(home),
+        quote(&copy.path.to_string_lossy()),
+        path = quote(&path)
+    );
+    run(ssh, &batch, timeout).context("Failed to back up authorized_keys over SFTP")?;
+    Ok(match home {
+        Some(home) => format!("{}/{}", home.trim_end_matches('/'), path),
+        None => path,
+    })
+}
+
+/// Replaces authorized_keys with `content`, by uploading it alongside and
+/// renaming it over the old one.
+fn upload(ssh: &Ssh, content: &str, home: Option<&str>, timeout: Option<Duration>) -> Result<()> {
+    let upload = TempFile::create("sftp-keys", content.as_bytes())?;
+    let replace = format!(
+        "{}put {} {upload}\nchmod 600 {upload}\nrename {upload} {}\n",
+        enter(home),
+        quote(&upload.path.to_string_lossy()),
+        AUTHORIZED_KEYS,
+        upload = UPLOAD
+    );
+    run(ssh, &replace, timeout).context("Failed to write authorized_keys over SFTP")
+}
+
+/// Takes the lines with one of the key `blobs` out of `ssh`'s
+/// authorized_keys. Returns the lines that were removed.
+pub fn remove(
+    ssh: &Ssh,
+    blobs: &[&str],
+    home: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Vec<String>> {
+    let existing = download(ssh, false, home, timeout)?;
+    let (removed, kept): (Vec<&str>, Vec<&str>) = existing
+        .lines()
+        .partition(|line| line.split_whitespace().any(|field| blobs.contains(&field)));
+    if removed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let kept: String = kept.iter().map(|line| format!("{}\n", line)).collect();
+    upload(ssh, &kept, home, timeout)?;
+    Ok(removed.into_iter().map(str::to_string).collect())
+}
+
+/// The content of `ssh`'s authorized_keys, read without changing anything
+/// (empty if there is none).
+pub fn read(ssh: &Ssh, home: Option<&str>, timeout: Option<Duration>) -> Result<String> {
+    download(ssh, false, home, timeout)
+}
+
+/// The batch command entering `home`, if given; a missing directory ends
+/// the batch.
+fn enter(home: Option<&str>) -> String {
+    home.map(|home| format!("cd {}\n", quote(home)))
+        .unwrap_or_default()
+}
+
+/// Downloads authorized_keys; with `prepare`, creates `.ssh` first if need be.
+fn download(
+    ssh: &Ssh,
+    prepare: bool,
+    home: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    let current = TempFile::create("sftp-keys", b"")?;
+    // A missing .ssh or authorized_keys is fine; the '-' ignores the error
+    let mut batch = enter(home);
+    if prepare {
+        batch.push_str("-mkdir .ssh\n-chmod 700 .ssh\n");
+    }
+    batch.push_str(&format!(
+        "-get {} {}\n",
+        AUTHORIZED_KEYS,
+        quote(&current.path.to_string_lossy())
+    ));
+    run(ssh, &batch, timeout).context("Failed to read authorized_keys over SFTP")?;
+    std::fs::read_to_string(&current.path).context("authorized_keys is not UTF-8")
+}
+
+/// Runs one sftp session with `batch`, which stops at the first failing
+/// command not prefixed with `-`.
+fn run(ssh: &Ssh, batch: &str, timeout: Option<Duration>) -> Result<()> {
+    let mut child = ssh
+        .sftp()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn sftp process. Make sure 'sftp' is in your PATH.")?;
+    // Only kept for the transcript
+    let stdout = crate::relay(
+        child.stdout.take().expect("stdout is piped"),
+        Box::new(std::io::sink()),
+        None,
+    );
+    let stderr = crate::relay(
+        child.stderr.take().expect("stderr is piped"),
+        Box::new(std::io::stderr()),
+        None,
+    );
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(batch.as_bytes())
+            .context("Failed to write to sftp stdin")?;
+    }
+    let status = crate::wait_with_timeout(&mut child, timeout)?;
+    transcript::record(&transcript::Session {
+        destination: ssh.destination(),
+        command: batch,
+        stdout: &stdout.join().unwrap_or_default(),
+        stderr: &stderr.join().unwrap_or_default(),
+        status,
+    });
+    match status {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => bail!("sftp exited with error code: {:?}", status.code()),
+        None => Err(crate::TimedOut(timeout.unwrap_or_default()).into()),
+    }
+}
+
+/// Quotes a path for an sftp batch file.
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
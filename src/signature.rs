@@ -0,0 +1,135 @@
+//! Verification of key sources against a trusted signing key
+//! (`--require-signature KEY`).
+//!
+//! Files need a detached signature next to them: `FILE.sig` made with
+//! `ssh-keygen -Y sign -n file`, or `FILE.minisig` made with minisign.
+//! Repository checkouts need an SSH-signed commit instead.
+
+use crate::fsutil::TempFile;
+use crate::key::PublicKey;
+use crate::keyformat;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Namespace expected in SSH file signatures.
+const SSH_NAMESPACE: &str = "file";
+
+pub enum TrustedKey {
+    Ssh(PublicKey),
+    /// A minisign public key, as the base64 line from `minisign.pub`
+    Minisign(String),
+}
+
+impl TrustedKey {
+    /// Accepts a key file path or the key itself, in SSH or minisign format.
+    pub fn load(spec: &str) -> Result<TrustedKey> {
+        let content = if Path::new(spec).is_file() {
+            fs::read_to_string(spec).with_context(|| format!("Failed to read {:?}", spec))?
+        } else {
+            spec.to_string()
+        };
+
+        // minisign.pub is an "untrusted comment:" line followed by the key
+        let minisign = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .filter(|line| line.starts_with("RW") && !line.contains(' '));
+        if let Some(line) = minisign {
+            return Ok(TrustedKey::Minisign(line.to_string()));
+        }
+
+        match keyformat::read_any(&content)?.into_iter().next() {
+            Some(key) => Ok(TrustedKey::Ssh(key)),
+            None => bail!("--require-signature: no signing key found in {:?}", spec),
+        }
+    }
+
+    /// Checks the detached signature of a file.
+    pub fn verify_file(&self, path: &Path) -> Result<()> {
+        match self {
+            TrustedKey::Ssh(key) => {
+                let sig = with_suffix(path, ".sig");
+                if !sig.is_file() {
+                    bail!("{:?} has no signature: expected {:?}", path, sig);
+                }
+                let allowed = allowed_signers(key)?;
+                let data =
+                    fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+                let output = Command::new("ssh-keygen")
+                    .args(["-Y", "verify", "-I", "signer", "-n", SSH_NAMESPACE, "-f"])
+                    .arg(&allowed.path)
+                    .arg("-s")
+                    .arg(&sig)
+                    .stdin(data)
+                    .output()
+                    .context("Failed to run ssh-keygen. Make sure it is in your PATH.")?;
+                check(output, path)
+            }
+            TrustedKey::Minisign(public_key) => {
+                let sig = with_suffix(path, ".minisig");
+                if !sig.is_file() {
+                    bail!("{:?} has no signature: expected {:?}", path, sig);
+                }
+                let output = Command::new("minisign")
+                    .args(["-V", "-q", "-P", public_key, "-x"])
+                    .arg(&sig)
+                    .arg("-m")
+                    .arg(path)
+                    .stdin(Stdio::null())
+                    .output()
+                    .context("Failed to run minisign. Make sure it is in your PATH.")?;
+                check(output, path)
+            }
+        }
+    }
+
+    /// Checks that the commit checked out in a git repository is signed by
+    /// this (SSH) key. minisign keys fall back to per-file signatures.
+    pub fn verify_git_head(&self, dir: &Path) -> Result<bool> {
+        let TrustedKey::Ssh(key) = self else {
+            return Ok(false);
+        };
+        let allowed = allowed_signers(key)?;
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "gpg.format=ssh", "-c"])
+            .arg(format!(
+                "gpg.ssh.allowedSignersFile={}",
+                allowed.path.display()
+            ))
+            .args(["verify-commit", "HEAD"])
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to run git. Make sure 'git' is in your PATH.")?;
+        check(output, dir)?;
+        Ok(true)
+    }
+}
+
+fn check(output: std::process::Output, what: &Path) -> Result<()> {
+    if !output.status.success() {
+        // ssh-keygen reports some failures on stdout
+        let mut reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if reason.is_empty() {
+            reason = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        }
+        bail!("Signature verification failed for {:?}: {}", what, reason);
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// An allowed_signers file trusting a single key for any principal.
+fn allowed_signers(key: &PublicKey) -> Result<TempFile> {
+    let line = format!("* {} {}\n", key.algorithm, key.blob);
+    TempFile::create("allowed-signers", line.as_bytes())
+}
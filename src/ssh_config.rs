@@ -0,0 +1,663 @@
+//! ssh_config(5) resolution: the options ssh will use for a destination,
+//! for the checks made without ssh (`--ping`, banners in reports).
+//!
+//! Follows ssh's own rules: the first value obtained for an option wins,
+//! except for those that accumulate such as IdentityFile; `Host` matches the
+//! name as given and `Match host` the name after any HostName seen so far;
+//! `Include` reads files (globs allowed) relative to ~/.ssh or /etc/ssh; and
+//! `%` tokens are expanded in the options that take them.
+
+use crate::digest;
+use crate::fsutil;
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+const SYSTEM_CONFIG: &str = "/etc/ssh/ssh_config";
+
+/// ssh's limit on nested `Include`s.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Options whose every value is used rather than only the first.
+const ACCUMULATING: &[&str] = &[
+    "identityfile",
+    "certificatefile",
+    "localforward",
+    "remoteforward",
+    "dynamicforward",
+    "sendenv",
+];
+
+/// Options whose values take `%` tokens (and, for paths, `~`).
+const EXPANDED: &[&str] = &[
+    "proxycommand",
+    "localcommand",
+    "remotecommand",
+    "knownhostscommand",
+    "identityfile",
+    "certificatefile",
+    "controlpath",
+    "identityagent",
+    "userknownhostsfile",
+];
+
+/// The options in effect for one destination.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Lower-cased keyword and its arguments, in the order first seen
+    values: Vec<(String, Vec<String>)>,
+    host_name: String,
+}
+
+impl Options {
+    /// The first argument of an option, e.g. `get("Port")`.
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        self.all(keyword)
+            .next()
+            .and_then(|args| args.first())
+            .map(String::as_str)
+    }
+
+    /// Every value of an option, for those given several times.
+    pub fn all<'a>(&'a self, keyword: &str) -> impl Iterator<Item = &'a [String]> + 'a {
+        let keyword = keyword.to_lowercase();
+        self.values
+            .iter()
+            .filter(move |(k, _)| *k == keyword)
+            .map(|(_, args)| args.as_slice())
+    }
+
+    /// The host actually connected to, after HostName.
+    pub fn host_name(&self) -> &str {
+        &self.host_name
+    }
+
+    pub fn port(&self) -> Result<u16> {
+        match self.get("Port") {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("Invalid Port {:?} in ssh config", port)),
+            None => Ok(22),
+        }
+    }
+
+    /// ProxyJump, unless it is `none`.
+    pub fn proxy_jump(&self) -> Option<&str> {
+        self.get("ProxyJump")
+            .filter(|value| !value.eq_ignore_ascii_case("none"))
+    }
+
+    /// The whole ProxyCommand line, unless it is `none`.
+    pub fn proxy_command(&self) -> Option<String> {
+        let args = self.all("ProxyCommand").next()?;
+        let command = args.join(" ");
+        (!command.eq_ignore_ascii_case("none")).then_some(command)
+    }
+}
+
+/// The config file given with `-F` (or found in `--ssh-dir`).
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Reads `path`, and only it, from now on, as ssh does with `-F`; `none`
+/// means no config at all.
+pub fn set_config_file(path: PathBuf) {
+    let _ = CONFIG_FILE.set(path);
+}
+
+/// The `-F` arguments that make ssh read the same config as we do.
+pub fn ssh_args() -> Vec<String> {
+    match CONFIG_FILE.get() {
+        Some(path) => vec!["-F".to_string(), path.to_string_lossy().into_owned()],
+        None => Vec::new(),
+    }
+}
+
+/// Resolves the options for `host` from the user's and the system's config,
+/// or from the `-F` file alone. A user or port given on the command line
+/// take precedence over the config, as they do with ssh.
+pub fn resolve(host: &str, user: Option<&str>, port: Option<u16>) -> Result<Options> {
+    let mut files = Vec::new();
+    let dir = crate::paths::ssh_dir();
+    match CONFIG_FILE.get() {
+        Some(path) if path.as_os_str() == "none" => {}
+        // Relative Includes are still read from ~/.ssh
+        Some(path) => files.push((path.clone(), dir.unwrap_or_default())),
+        None => {
+            if let Some(dir) = dir {
+                files.push((dir.join("config"), dir));
+            }
+            files.push((PathBuf::from(SYSTEM_CONFIG), PathBuf::from("/etc/ssh")));
+        }
+    }
+    resolve_files(&files, host, user, port)
+}
+
+/// Resolves from `files`, each with the directory its relative `Include`s
+/// are read from.
+fn resolve_files(
+    files: &[(PathBuf, PathBuf)],
+    host: &str,
+    user: Option<&str>,
+    port: Option<u16>,
+) -> Result<Options> {
+    let mut resolver = Resolver {
+        original_host: host.to_lowercase(),
+        host: host.to_lowercase(),
+        values: Vec::new(),
+        final_pass: false,
+        wants_final: false,
+    };
+    if let Some(user) = user {
+        resolver.set("user", vec![user.to_string()]);
+    }
+    if let Some(port) = port {
+        resolver.set("port", vec![port.to_string()]);
+    }
+
+    for (path, dir) in files {
+        resolver.read(path, dir, 0)?;
+    }
+    // `Match final` and `canonical` blocks apply on a second pass, which
+    // matches `Host` against the name resolved by the first
+    if resolver.wants_final {
+        resolver.final_pass = true;
+        resolver.host = resolver.current_host_name();
+        // As ssh does, the expanded name replaces HostName for the second pass
+        if let Some((_, args)) = resolver.values.iter_mut().find(|(k, _)| k == "hostname") {
+            *args = vec![resolver.host.clone()];
+        }
+        for (path, dir) in files {
+            resolver.read(path, dir, 0)?;
+        }
+    }
+
+    let host_name = resolver.current_host_name();
+    let tokens = resolver.tokens(&host_name);
+    let mut values = resolver.values;
+    for (keyword, args) in &mut values {
+        if EXPANDED.contains(&keyword.as_str()) {
+            for arg in args.iter_mut() {
+                *arg = expand_tilde(&expand_tokens(arg, &tokens, keyword)?);
+            }
+        }
+    }
+    Ok(Options { values, host_name })
+}
+
+struct Resolver {
+    /// The name as given, for `Match originalhost` and `%n`
+    original_host: String,
+    /// The name `Host` lines are matched against
+    host: String,
+    values: Vec<(String, Vec<String>)>,
+    final_pass: bool,
+    wants_final: bool,
+}
+
+impl Resolver {
+    fn read(&mut self, path: &Path, include_dir: &Path, depth: usize) -> Result<()> {
+        let content = fsutil::read_optional(path)?;
+        let mut active = true;
+        for (number, line) in content.lines().enumerate() {
+            let at = || format!("{}:{}", path.display(), number + 1);
+            let (keyword, args) = match split_line(line) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => continue,
+                Err(e) => return Err(e.context(at())),
+            };
+            match keyword.as_str() {
+                "host" => active = self.host_matches(&args),
+                "match" => active = self.match_matches(&args).with_context(at)?,
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        bail!("{}: Include nested too deeply", at());
+                    }
+                    if !active {
+                        continue;
+                    }
+                    for pattern in &args {
+                        let pattern = expand_tilde(pattern);
+                        let pattern = if Path::new(&pattern).is_absolute() {
+                            PathBuf::from(pattern)
+                        } else {
+                            include_dir.join(pattern)
+                        };
+                        for included in glob(&pattern) {
+                            self.read(&included, include_dir, depth + 1)?;
+                        }
+                    }
+                }
+                _ if active => self.set(&keyword, args),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, keyword: &str, args: Vec<String>) {
+        let seen = self
+            .values
+            .iter()
+            .any(|(k, a)| k == keyword && (*a == args || !ACCUMULATING.contains(&keyword)));
+        if !seen {
+            self.values.push((keyword.to_string(), args));
+        }
+    }
+
+    fn get(&self, keyword: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == keyword)
+            .and_then(|(_, args)| args.first())
+            .map(String::as_str)
+    }
+
+    /// The target name given by HostName so far, with `%h` expanded.
+    fn current_host_name(&self) -> String {
+        match self.get("hostname") {
+            Some(name) => name
+                .replace("%h", &self.host)
+                .replace("%%", "%")
+                .to_lowercase(),
+            None => self.host.clone(),
+        }
+    }
+
+    fn remote_user(&self) -> String {
+        self.get("user")
+            .map(str::to_string)
+            .unwrap_or_else(local_user)
+    }
+
+    fn host_matches(&self, patterns: &[String]) -> bool {
+        let mut matched = false;
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildmatch(negated, &self.host) => return false,
+                Some(_) => {}
+                None => matched |= wildmatch(pattern, &self.host),
+            }
+        }
+        matched
+    }
+
+    fn match_matches(&mut self, args: &[String]) -> Result<bool> {
+        if args.is_empty() {
+            bail!("Match needs criteria");
+        }
+        let mut result = true;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (negate, criterion) = match arg.strip_prefix('!') {
+                Some(criterion) => (true, criterion),
+                None => (false, arg.as_str()),
+            };
+            let criterion = criterion.to_lowercase();
+            let matched = match criterion.as_str() {
+                "all" => true,
+                "canonical" | "final" => {
+                    self.wants_final = true;
+                    self.final_pass
+                }
+                _ => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("Match {} needs an argument", criterion))?;
+                    match criterion.as_str() {
+                        "host" => pattern_list_matches(value, &self.current_host_name(), true),
+                        "originalhost" => pattern_list_matches(value, &self.original_host, true),
+                        "user" => pattern_list_matches(value, &self.remote_user(), false),
+                        "localuser" => pattern_list_matches(value, &local_user(), false),
+                        "exec" => self.exec_succeeds(value)?,
+                        _ => {
+                            eprintln!(
+                                "Warning: ssh config `Match {}` is not supported here and never matches",
+                                criterion
+                            );
+                            false
+                        }
+                    }
+                }
+            };
+            // Every criterion is consumed, even once the result is known
+            result &= matched != negate;
+        }
+        Ok(result)
+    }
+
+    fn exec_succeeds(&self, command: &str) -> Result<bool> {
+        let command = expand_tokens(command, &self.tokens(&self.current_host_name()), "exec")?;
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to run Match exec {:?}", command))?;
+        Ok(status.success())
+    }
+
+    /// Values for the `%` tokens, as ssh_config(5) TOKENS describes them.
+    fn tokens(&self, host_name: &str) -> Vec<(char, String)> {
+        let port = self.get("port").unwrap_or("22").to_string();
+        let user = self.remote_user();
+        let local_host = local_host_name();
+        let short_host = local_host.split('.').next().unwrap_or_default().to_string();
+        let hash = digest::sha1(format!("{}{}{}{}", local_host, host_name, port, user).as_bytes());
+        let home = dirs::home_dir()
+            .map(|home| home.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        vec![
+            ('%', "%".to_string()),
+            ('h', host_name.to_string()),
+            ('n', self.original_host.clone()),
+            ('p', port),
+            ('r', user),
+            ('u', local_user()),
+            ('l', local_host),
+            ('L', short_host),
+            ('d', home),
+            ('i', local_uid()),
+            (
+                'k',
+                self.get("hostkeyalias").unwrap_or(host_name).to_string(),
+            ),
+            ('j', self.get("proxyjump").unwrap_or_default().to_string()),
+            ('C', hash.iter().map(|b| format!("{:02x}", b)).collect()),
+        ]
+    }
+}
+
+/// Splits a config line into its lower-cased keyword and arguments, or
+/// `None` for blank lines and comments.
+fn split_line(line: &str) -> Result<Option<(String, Vec<String>)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    // `Keyword value`, `Keyword=value` and `Keyword = value` are all allowed
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    Ok(Some((keyword.to_lowercase(), split_args(rest)?)))
+}
+
+/// Whitespace-separated arguments, with double quotes grouping and
+/// backslash escaping quotes and backslashes, as ssh's argv_split does.
+fn split_args(input: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        if first == '#' {
+            // A trailing comment
+            return Ok(args);
+        }
+        let mut arg = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some('"' | '\'' | '\\')) => {
+                    arg.extend(chars.next());
+                }
+                '"' => quoted = !quoted,
+                c if c.is_whitespace() && !quoted => break,
+                c => arg.push(c),
+            }
+        }
+        if quoted {
+            bail!("Unterminated quote");
+        }
+        args.push(arg);
+    }
+}
+
+/// Expands `%x` tokens; an unknown token is an error, as with ssh.
+fn expand_tokens(value: &str, tokens: &[(char, String)], keyword: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        let Some(token) = chars.next() else {
+            bail!("Trailing % in ssh config {} {:?}", keyword, value);
+        };
+        match tokens.iter().find(|(t, _)| *t == token) {
+            Some((_, replacement)) => expanded.push_str(replacement),
+            None => bail!(
+                "Unknown token %{} in ssh config {} {:?}",
+                token,
+                keyword,
+                value
+            ),
+        }
+    }
+    Ok(expanded)
+}
+
+fn expand_tilde(path: &str) -> String {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    }
+}
+
+/// Whether `name` matches a comma-separated list of patterns, any of which
+/// may be negated with `!`. Host name patterns are case-insensitive.
+fn pattern_list_matches(list: &str, name: &str, host: bool) -> bool {
+    let mut matched = false;
+    for pattern in list.split(',') {
+        let pattern = if host {
+            pattern.to_lowercase()
+        } else {
+            pattern.to_string()
+        };
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildmatch(negated, name) => return false,
+            Some(_) => {}
+            None => matched |= wildmatch(&pattern, name),
+        }
+    }
+    matched
+}
+
+/// ssh's patterns: `*` matches any run of characters and `?` any one.
+fn wildmatch(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and the name position it is trying to cover up to
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, covered)) => {
+                    p = star + 1;
+                    n = covered + 1;
+                    backtrack = Some((star, covered + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The files matching a path whose components may contain `*` and `?`,
+/// sorted within each directory as glob(3) does.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut matches = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !part.contains(['*', '?']) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+        let mut next = Vec::new();
+        for dir in &matches {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| !name.starts_with('.') && wildmatch(&part, name))
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| dir.join(name)));
+        }
+        matches = next;
+    }
+    matches.into_iter().filter(|path| path.is_file()).collect()
+}
+
+/// The local user name, as ssh's %u.
+pub fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+/// The local host name, as ssh's %L.
+#[cfg(unix)]
+pub fn local_host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let ok = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } == 0;
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    if ok {
+        String::from_utf8_lossy(&buffer[..end]).into_owned()
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(not(unix))]
+pub fn local_host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn local_uid() -> String {
+    unsafe { libc::getuid() }.to_string()
+}
+
+#[cfg(not(unix))]
+fn local_uid() -> String {
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Dir {
+            let dir =
+                std::env::temp_dir().join(format!("ssh-config-{}-{}", name, std::process::id()));
+            fs::create_dir_all(dir.join("config.d")).unwrap();
+            Dir(dir)
+        }
+
+        fn write(&self, name: &str, content: &str) {
+            fs::write(self.0.join(name), content).unwrap();
+        }
+
+        fn resolve(&self, host: &str, user: Option<&str>) -> Options {
+            let files = [(self.0.join("config"), self.0.clone())];
+            resolve_files(&files, host, user, None).unwrap()
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn first_value_wins_and_host_patterns_negate() {
+        let dir = Dir::new("host");
+        dir.write(
+            "config",
+            "Host *.prod !bastion.prod\n  User deploy\n  Port=2222\n\
+             Host *\n  User nobody\n  Port 22\n  IdentityFile ~/.ssh/a\n  IdentityFile \"/keys/with space\"\n",
+        );
+        let web = dir.resolve("WEB1.prod", None);
+        assert_eq!(web.get("User"), Some("deploy"));
+        assert_eq!(web.port().unwrap(), 2222);
+        let files: Vec<&[String]> = web.all("identityfile").collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1], ["/keys/with space"]);
+
+        let bastion = dir.resolve("bastion.prod", Some("admin"));
+        assert_eq!(bastion.get("User"), Some("admin"));
+        assert_eq!(bastion.port().unwrap(), 22);
+    }
+
+    #[test]
+    fn includes_match_blocks_and_tokens() {
+        let dir = Dir::new("match");
+        dir.write("config.d/10-lab", "Host lab\n  HostName %h.example.com\n");
+        dir.write(
+            "config.d/20-lab-jump",
+            "Match host *.example.com user ops,!root\n  ProxyJump jump.example.com\n",
+        );
+        dir.write(
+            "config",
+            "Include config.d/*\n\
+             Match originalhost lab !user root\n  ProxyCommand nc -X 5 %n %p # comment\n\
+             Match final host *.example.com\n  Port 2200\n",
+        );
+
+        let lab = dir.resolve("lab", Some("ops"));
+        assert_eq!(lab.host_name(), "lab.example.com");
+        assert_eq!(lab.proxy_jump(), Some("jump.example.com"));
+        assert_eq!(lab.proxy_command().as_deref(), Some("nc -X 5 lab 2200"));
+        assert_eq!(lab.port().unwrap(), 2200);
+
+        let root = dir.resolve("lab", Some("root"));
+        assert_eq!(root.proxy_jump(), None);
+        assert_eq!(root.proxy_command(), None);
+
+        let other = dir.resolve("other", Some("ops"));
+        assert_eq!(other.host_name(), "other");
+        assert_eq!(other.port().unwrap(), 22);
+    }
+
+    #[test]
+    fn wildmatch_backtracks() {
+        assert!(wildmatch("*.example.com", "a.b.example.com"));
+        assert!(wildmatch("db?", "db1"));
+        assert!(wildmatch("*a*b", "xaxxab"));
+        assert!(!wildmatch("db?", "db10"));
+        assert!(!wildmatch("*.example.com", "example.com"));
+    }
+
+    #[test]
+    fn rejects_unknown_tokens_and_open_quotes() {
+        assert!(expand_tokens("%z", &[], "ProxyCommand").is_err());
+        assert!(split_args("\"unterminated").is_err());
+        assert_eq!(split_args(r#"a "b c" d\"e"#).unwrap(), ["a", "b c", "d\"e"]);
+    }
+}
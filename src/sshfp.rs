@@ -0,0 +1,110 @@
+//! SSHFP DNS records (RFC 4255, RFC 6594, RFC 7479) for host keys.
+
+use crate::base64;
+use crate::digest;
+use crate::dns;
+use crate::hostkey;
+use crate::key::PublicKey;
+use anyhow::{Context, Result, bail};
+
+/// SSHFP algorithm number of a host key type, if it has one.
+fn algorithm_number(algorithm: &str) -> Option<u8> {
+    match algorithm {
+        "ssh-rsa" => Some(1),
+        "ssh-dss" => Some(2),
+        a if a.starts_with("ecdsa-sha2-") => Some(3),
+        "ssh-ed25519" => Some(4),
+        _ => None,
+    }
+}
+
+/// Zone file lines for the given host keys, one per key and fingerprint
+/// type (SHA-1, SHA-256), in the format of `ssh-keygen -r`.
+pub fn records(host: &str, keys: &[PublicKey]) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    for public_key in keys {
+        let Some(number) = algorithm_number(&public_key.algorithm) else {
+            continue;
+        };
+        let blob = base64::decode(&public_key.blob).context("key data is not valid base64")?;
+        out.push(format!(
+            "{} IN SSHFP {} 1 {}",
+            host,
+            number,
+            hex(&digest::sha1(&blob))
+        ));
+        out.push(format!(
+            "{} IN SSHFP {} 2 {}",
+            host,
+            number,
+            hex(&digest::sha256(&blob))
+        ));
+    }
+    Ok(out)
+}
+
+/// The host keys offered by `host` that match its SSHFP records. Fails if
+/// there are no records, or if none of the offered keys matches them.
+pub fn verify(host: &str, port: Option<&str>) -> Result<Vec<PublicKey>> {
+    let answer = dns::query(host, dns::TYPE_SSHFP)?;
+    if answer.records.is_empty() {
+        bail!("No SSHFP records found for {}", host);
+    }
+    if !answer.authenticated {
+        eprintln!(
+            "Warning: the SSHFP records for {} are not DNSSEC-authenticated; they are only as trustworthy as the network path to the resolver.",
+            host
+        );
+    }
+
+    let mut verified = Vec::new();
+    for public_key in hostkey::scan(host, port)? {
+        let Some(number) = algorithm_number(&public_key.algorithm) else {
+            continue;
+        };
+        let blob = base64::decode(&public_key.blob).context("key data is not valid base64")?;
+        let matches = answer.records.iter().any(|rdata| match rdata.as_slice() {
+            [algorithm, 1, fingerprint @ ..] => {
+                *algorithm == number && digest::sha1(&blob) == fingerprint
+            }
+            [algorithm, 2, fingerprint @ ..] => {
+                *algorithm == number && digest::sha256(&blob) == fingerprint
+            }
+            _ => false,
+        });
+        if matches {
+            verified.push(public_key);
+        }
+    }
+    if verified.is_empty() {
+        bail!(
+            "None of the host keys offered by {} match its SSHFP records. The host key may have changed, or someone may be intercepting the connection.",
+            host
+        );
+    }
+    Ok(verified)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_ssh_keygen() {
+        let key = PublicKey::parse(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAvLtd30OFoMTE4W6DMg7LCelqt64mYegofKaoSLb8tC",
+        )
+        .unwrap();
+        assert_eq!(
+            records("host.example.com", &[key]).unwrap(),
+            [
+                "host.example.com IN SSHFP 4 1 a604722086a64013ef24660fd879215407a24f5e",
+                "host.example.com IN SSHFP 4 2 528ae3d65a2226588e3865e40b8d05011aaadfc6cea6b37fc1659d133961609b",
+            ]
+        );
+    }
+}
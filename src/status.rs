@@ -0,0 +1,194 @@
+//! `status`: where your keys are believed to be installed, from the local
+//! key cache, without connecting; `--refresh` reads each authorized_keys
+//! again first.
+
+use crate::authorized_keys;
+use crate::connection::{ConnectionArgs, Ssh};
+use crate::destination::Destination;
+use crate::identity::{self, SourceArgs};
+use crate::keycache;
+use crate::keyformat;
+use crate::remote_script::{self, Become, Profile};
+use crate::sftp;
+use crate::timeutil;
+use crate::{TimedOut, relay_all, wait_with_timeout};
+use anyhow::{Context, Result, bail};
+use std::process::Stdio;
+use std::time::Duration;
+
+/// A destination to report on.
+struct Entry {
+    destination: Destination,
+    /// `user@host:port`
+    label: String,
+    /// The name of its cache file
+    id: String,
+}
+
+/// Prints, for each of `hosts` (or every destination in the cache), which
+/// of the keys of `source` it is known to have and when they were last
+/// seen there; with `deterministic`, not when.
+pub fn run(
+    source: &SourceArgs,
+    connection: &ConnectionArgs,
+    profile: Profile,
+    hosts: &[String],
+    refresh: bool,
+    deterministic: bool,
+) -> Result<()> {
+    let identity = identity::resolve(source)?;
+    let keys = keyformat::read_any(&identity.content)
+        .with_context(|| format!("Invalid public key in {}", identity.source))?;
+    if keys.is_empty() {
+        bail!("Identity content is empty.");
+    }
+
+    let mut entries = Vec::new();
+    if hosts.is_empty() {
+        for (id, cached) in keycache::all()? {
+            let Some(mut destination) = parse_label(&cached.destination) else {
+                continue;
+            };
+            connection.jump.apply(&mut destination)?;
+            entries.push(Entry {
+                destination,
+                label: cached.destination,
+                id,
+            });
+        }
+        if entries.is_empty() {
+            println!("The key cache is empty; installs and --dry-run fill it.");
+            return Ok(());
+        }
+    } else {
+        for host in hosts {
+            let mut destination: Destination = host.parse()?;
+            connection.jump.apply(&mut destination)?;
+            let (label, id) = connection.destination_id(&destination)?;
+            entries.push(Entry {
+                destination,
+                label,
+                id,
+            });
+        }
+    }
+
+    println!("Keys: {}", identity.source);
+    let timeout = connection.timeout.map(Duration::from_secs);
+    let mut failed = 0;
+    for entry in &entries {
+        println!("\n{}", entry.label);
+        if refresh {
+            let refreshed = connection
+                .ssh(&entry.destination, timeout, &mut |message| {
+                    eprintln!("Info: {}", message)
+                })
+                .and_then(|mut ssh| read_installed(&mut ssh, profile, None, None, None, timeout))
+                .and_then(|installed| keycache::replace(&entry.id, &entry.label, &installed.keys));
+            if let Err(e) = refreshed {
+                println!("  could not refresh: {:#}", e);
+                failed += 1;
+            }
+        }
+        let Some(cached) = keycache::load(&entry.id)? else {
+            println!("  not in the key cache");
+            continue;
+        };
+        for key in &keys {
+            let seen = match cached.seen(key) {
+                Some(seen) => format!(
+                    "confirmed {}",
+                    timeutil::format_utc(if deterministic { 0 } else { seen })
+                ),
+                None => "not seen".to_string(),
+            };
+            println!(
+                "  {}  {:<30}  {}{}",
+                key.decode()?.fingerprint_sha256(),
+                seen,
+                key.algorithm,
+                key.comment
+                    .as_deref()
+                    .map(|c| format!(" {}", c))
+                    .unwrap_or_default()
+            );
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "{} of {} hosts could not be refreshed",
+            failed,
+            entries.len()
+        );
+    }
+    Ok(())
+}
+
+/// The authorized_keys of `ssh`'s account, or of the one it becomes with
+/// `run_as`, or the key file at `path`, read without changing anything.
+/// Also for `--dry-run`.
+pub fn read_installed(
+    ssh: &mut Ssh,
+    profile: Profile,
+    home: Option<&str>,
+    path: Option<&str>,
+    run_as: Option<&Become>,
+    timeout: Option<Duration>,
+) -> Result<authorized_keys::Parsed> {
+    let mut child = ssh
+        .with_remote([remote_script::login_command(
+            profile,
+            &remote_script::read_authorized_keys(profile, home, path),
+            run_as,
+        )])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    // All of it: keys past a cut-off would look absent
+    let stdout = relay_all(
+        child.stdout.take().expect("stdout is piped"),
+        Box::new(std::io::sink()),
+        None,
+    );
+    let status = wait_with_timeout(&mut child, timeout)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let content = match status {
+        None => return Err(TimedOut(timeout.unwrap_or_default()).into()),
+        Some(_) if stdout.lines().any(|line| line.trim_end() == sftp::REFUSAL) => {
+            bail!("The account only allows SFTP, so its authorized_keys can't be listed")
+        }
+        Some(status) if status.success() => stdout,
+        Some(_) if profile == Profile::Device && status.and_then(|s| s.code()) != Some(255) => {
+            String::new()
+        }
+        Some(status) => bail!("ssh process exited with error code: {:?}", status.code()),
+    };
+    Ok(authorized_keys::parse(&content))
+}
+
+/// The destination a cache file records as `user@host:port`.
+fn parse_label(label: &str) -> Option<Destination> {
+    let (user, address) = label.rsplit_once('@')?;
+    let (host, port) = address.rsplit_once(':')?;
+    Some(Destination {
+        user: Some(user.to_string()),
+        host: host.to_string(),
+        port: Some(port.parse().ok()?),
+        via: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cache_labels() {
+        let destination = parse_label("alice@2001:db8::1:2222").unwrap();
+        assert_eq!(destination.user.as_deref(), Some("alice"));
+        assert_eq!(destination.host, "2001:db8::1");
+        assert_eq!(destination.port, Some(2222));
+        assert!(parse_label("web1:22").is_none());
+    }
+}
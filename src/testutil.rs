@@ -0,0 +1,70 @@
+//! Helpers for randomized (property-style) tests.
+//!
+//! A small seeded generator keeps failures reproducible: [`check`] gives
+//! every case a generator of its own, reports the seed and case of a
+//! failing one, and can rerun just that case. Inputs aren't shrunk.
+
+/// xorshift64* pseudo-random generator.
+pub struct Gen(u64);
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Gen(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    /// A string biased towards characters that tend to break parsers:
+    /// quotes, separators, whitespace, control characters and non-ASCII.
+    pub fn tricky_string(&mut self, max_len: usize) -> String {
+        const POOL: &[char] = &[
+            'a', 'Z', '0', '9', '-', '_', '.', ' ', '\t', '\n', '\r', '\0', '\'', '"', '`', '$',
+            '\\', ';', '|', '&', '@', ':', '[', ']', '/', '#', '=', ',', '!', '%', '*', 'é', 'á',
+            'ß', '中', '🔑', '\u{202e}', '\u{2028}', '\u{85}', '\u{feff}',
+        ];
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| *self.pick(POOL)).collect()
+    }
+}
+
+/// Number of random cases per property.
+pub const CASES: u64 = 2000;
+
+/// Runs `property` with each case number below [`CASES`] and a generator
+/// derived from `seed` and the case. A failing case is reported with both;
+/// `SSH_COPY_ID_RS_CASE=N` runs case N only, as it ran before.
+pub fn check(seed: u64, mut property: impl FnMut(&mut Gen, u64)) {
+    let only: Option<u64> = std::env::var("SSH_COPY_ID_RS_CASE")
+        .ok()
+        .and_then(|case| case.parse().ok());
+    for case in (0..CASES).filter(|case| only.is_none_or(|only| only == *case)) {
+        let mut g = Gen::new(seed ^ (case + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let outcome =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| property(&mut g, case)));
+        if let Err(panic) = outcome {
+            eprintln!(
+                "Property failed with seed {}, case {}; rerun it alone with SSH_COPY_ID_RS_CASE={}",
+                seed, case, case
+            );
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Keys held on hardware: FIDO security keys (the `sk-` types) and PKCS#11
+//! tokens. A login with one can't work while the token is unplugged, and
+//! that shows up only as a verification that times out or is refused, so
+//! the token is looked for before a login with it is verified. When ssh
+//! does fail to sign with a security key, [`fido_error`] picks out why.
+
+use crate::fsutil::TempFile;
+use crate::key::PublicKey;
+use anyhow::{Context, Result, bail};
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A FIDO/U2F security key, which is touched for every login
+    Fido,
+    /// A smartcard or HSM reached through ssh-agent's PKCS#11 provider
+    Pkcs11,
+}
+
+impl Token {
+    fn name(self) -> &'static str {
+        match self {
+            Token::Fido => "security key",
+            Token::Pkcs11 => "PKCS#11 token",
+        }
+    }
+}
+
+/// Why ssh couldn't sign a login with a security key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FidoError {
+    /// No security key was plugged in
+    Missing,
+    /// The key was made with verify-required, and ssh couldn't ask for the PIN
+    PinRequired,
+    /// The PIN given was wrong
+    WrongPin,
+    /// Too many wrong PINs were given
+    PinBlocked,
+}
+
+impl std::fmt::Display for FidoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FidoError::Missing => f.write_str(
+                "ssh found no security key to sign the login with; insert it and try again",
+            ),
+            FidoError::PinRequired => f.write_str(
+                "The security key wants its PIN (the key was made with verify-required), and ssh had \
+                 no way to ask for it; run from a terminal, or give ssh-agent an SSH_ASKPASS program",
+            ),
+            FidoError::WrongPin => f.write_str(
+                "The security key rejected the PIN; after three wrong tries it has to be unplugged \
+                 and plugged in again, and after eight it locks",
+            ),
+            FidoError::PinBlocked => f.write_str(
+                "The security key is locked after too many wrong PINs; only resetting it (e.g. \
+                 `ykman fido reset`) unlocks it, and that deletes the keys on it",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FidoError {}
+
+/// The reason ssh's stderr gives for failing to sign with a security key,
+/// if it does: the libfido2 error in its debug output, or the message
+/// after `signing failed for ...-SK`.
+pub fn fido_error(stderr: &str) -> Option<FidoError> {
+    let lines = || stderr.lines().map(str::trim);
+    let code = lines().find_map(|line| {
+        let code = &line[line.find("FIDO_ERR_")? + "FIDO_ERR_".len()..];
+        match code
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .next()?
+        {
+            "PIN_REQUIRED" | "UV_BLOCKED" => Some(FidoError::PinRequired),
+            "PIN_INVALID" => Some(FidoError::WrongPin),
+            "PIN_BLOCKED" | "PIN_AUTH_BLOCKED" => Some(FidoError::PinBlocked),
+            _ => None,
+        }
+    });
+    code.or_else(|| {
+        lines()
+            .filter(|line| line.contains("signing failed for ") && line.contains("-SK "))
+            .find_map(|line| {
+                let reason = line.rsplit(": ").next()?;
+                match reason {
+                    "device not found" => Some(FidoError::Missing),
+                    "incorrect passphrase supplied to decrypt private key" => {
+                        Some(FidoError::WrongPin)
+                    }
+                    // ssh-agent refuses a verify-required key it can't ask the PIN for
+                    "agent refused operation" => Some(FidoError::PinRequired),
+                    _ => None,
+                }
+            })
+    })
+}
+
+/// The hardware `key` is held on, if any. `ssh-add -L` gives the keys a
+/// PKCS#11 provider added the provider's path as their comment.
+pub fn of(key: &PublicKey) -> Option<Token> {
+    if key.algorithm.starts_with("sk-") {
+        return Some(Token::Fido);
+    }
+    let comment = key.comment.as_deref()?;
+    let provider = [".so", ".dylib", ".dll"]
+        .iter()
+        .any(|suffix| comment.ends_with(suffix))
+        || comment.contains("pkcs11");
+    provider.then_some(Token::Pkcs11)
+}
+
+/// Before a login offering `keys` is verified: when each of them is on
+/// hardware, makes sure one of the tokens is plugged in, asking for it on a
+/// terminal, and says a security key will want a touch. Where there's no
+/// telling whether a token is present the login is left to find out.
+pub fn check(keys: &[PublicKey], info: &mut dyn FnMut(&str)) -> Result<()> {
+    let tokens: Vec<(&PublicKey, Token)> = match keys
+        .iter()
+        .map(|key| of(key).map(|token| (key, token)))
+        .collect::<Option<_>>()
+    {
+        Some(tokens) => tokens,
+        // A key on disk or in the agent can verify the login on its own
+        None => return Ok(()),
+    };
+    let Some(&(_, token)) = tokens.first() else {
+        return Ok(());
+    };
+    let plugged_in = || {
+        tokens
+            .iter()
+            .map(|(key, token)| present(key, *token))
+            .try_fold(false, |any, present| Some(any || present?))
+    };
+    if plugged_in() == Some(false) {
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "The {} holding the key isn't plugged in, so a login with it can't be verified; insert it and try again",
+                token.name()
+            );
+        }
+        eprint!(
+            "Insert the {} holding the key, then press Enter: ",
+            token.name()
+        );
+        std::io::stderr().flush().ok();
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .context("Failed to read the answer")?;
+        if plugged_in() == Some(false) {
+            bail!(
+                "The {} holding the key still isn't plugged in",
+                token.name()
+            );
+        }
+    }
+    if token == Token::Fido {
+        info("The login that verifies the key uses a security key: touch it when it blinks.");
+    }
+    Ok(())
+}
+
+/// Whether the token holding `key` is plugged in, or None if that can't be
+/// told here.
+fn present(key: &PublicKey, token: Token) -> Option<bool> {
+    match token {
+        Token::Fido => fido_present(),
+        Token::Pkcs11 => agent_can_sign(key),
+    }
+}
+
+/// Whether a HID device that speaks FIDO is attached, by the FIDO usage
+/// page (0xF1D0) in the report descriptors Linux publishes in sysfs.
+fn fido_present() -> Option<bool> {
+    let devices = std::fs::read_dir("/sys/class/hidraw").ok()?;
+    Some(devices.flatten().any(|device| {
+        std::fs::read(device.path().join("device/report_descriptor"))
+            .is_ok_and(|descriptor| descriptor.windows(3).any(|item| item == [0x06, 0xd0, 0xf1]))
+    }))
+}
+
+/// Whether ssh-agent can sign with `key` (`ssh-add -T`), which a PKCS#11
+/// key can't while its token is out; None without an agent to ask.
+fn agent_can_sign(key: &PublicKey) -> Option<bool> {
+    std::env::var_os("SSH_AUTH_SOCK")?;
+    let file = TempFile::create("token.pub", format!("{}\n", key.to_line()).as_bytes()).ok()?;
+    let status = Command::new("ssh-add")
+        .arg("-T")
+        .arg(&file.path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()?;
+    Some(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardware_keys_are_recognised() {
+        let token = |line: &str| of(&PublicKey::parse(line).unwrap());
+        assert_eq!(
+            token(
+                "sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIHSn ssh:"
+            ),
+            Some(Token::Fido)
+        );
+        assert_eq!(
+            token(
+                "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC7 /usr/lib/x86_64-linux-gnu/opensc-pkcs11.so"
+            ),
+            Some(Token::Pkcs11)
+        );
+        assert_eq!(
+            token("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC7 /usr/local/lib/libykcs11.dylib"),
+            Some(Token::Pkcs11)
+        );
+        assert_eq!(
+            token(
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl alice@laptop"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn fido_failures_are_explained() {
+        assert_eq!(
+            fido_error(
+                "sign_and_send_pubkey: signing failed for ED25519-SK \"/home/alice/.ssh/id_ed25519_sk\": device not found\r\n"
+            ),
+            Some(FidoError::Missing)
+        );
+        assert_eq!(
+            fido_error(
+                "sign_and_send_pubkey: signing failed for ECDSA-SK \"alice@laptop\" from agent: agent refused operation\n"
+            ),
+            Some(FidoError::PinRequired)
+        );
+        assert_eq!(
+            fido_error(
+                "debug1: sk_sign: fido_dev_get_assert: FIDO_ERR_PIN_INVALID\n\
+                 sign_and_send_pubkey: signing failed for ED25519-SK \"k\": incorrect passphrase supplied to decrypt private key\n"
+            ),
+            Some(FidoError::WrongPin)
+        );
+        assert_eq!(
+            fido_error("debug1: sk_sign: fido_dev_get_assert: FIDO_ERR_PIN_AUTH_BLOCKED\n"),
+            Some(FidoError::PinBlocked)
+        );
+        assert_eq!(
+            fido_error(
+                "sign_and_send_pubkey: signing failed for RSA \"k\" from agent: agent refused operation\n"
+            ),
+            None
+        );
+        assert_eq!(fido_error("Permission denied (publickey).\n"), None);
+    }
+}
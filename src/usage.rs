@@ -0,0 +1,369 @@
+//! `usage`: which of an account's authorized_keys have actually been used to
+//! log in, from the "Accepted publickey" lines in sshd's log on the remote
+//! (the journal, or auth.log/secure), read with sudo. Meant to inform
+//! pruning: keys nobody has logged in with for months are candidates.
+
+use crate::authorized_keys;
+use crate::connection::ConnectionArgs;
+use crate::destination::Destination;
+use crate::json::Value;
+use crate::remote_script::sh_quote;
+use crate::reporter::{Format, csv_field};
+use anyhow::{Context, Result, bail};
+use std::io::IsTerminal;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Starts each line of the script's output that is meant for us; anything
+/// else (sudo's prompt, errors) is passed on to the user.
+const MARKER: &str = "ssh-copy-id-rs-usage: ";
+
+/// One authorized_keys entry and its logins.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub fingerprint: String,
+    /// Key type and comment
+    pub name: String,
+    /// What the entry's options allow, as `(option, meaning)` rows
+    pub access: Vec<(String, String)>,
+    /// The log's timestamp of the latest login
+    pub last_used: Option<String>,
+    pub logins: usize,
+}
+
+/// Lists the destination's authorized_keys with their last use within the
+/// past `days`.
+pub fn run(connection: &ConnectionArgs, destination: &str, days: u32) -> Result<()> {
+    let mut destination: Destination = destination.parse()?;
+    connection.jump.apply(&mut destination)?;
+    let timeout = connection.timeout.map(Duration::from_secs);
+    let usage = collect(connection, &destination, timeout, days)?;
+    let used = usage.iter().filter(|u| u.last_used.is_some()).count();
+    let width = usage
+        .iter()
+        .map(|u| u.last_used.as_deref().map_or(8, str::len))
+        .max()
+        .unwrap_or(0);
+    for key in &usage {
+        let logins = match key.logins {
+            0 => String::new(),
+            1 => "1 login".to_string(),
+            n => format!("{} logins", n),
+        };
+        println!(
+            "{}  {:<width$}  {:<10}  {}",
+            key.fingerprint,
+            key.last_used.as_deref().unwrap_or("not seen"),
+            logins,
+            key.name,
+            width = width
+        );
+        for (option, meaning) in &key.access {
+            println!("    {:<18}  {}", option, meaning);
+        }
+    }
+    println!(
+        "\n{} of {} keys used to log in as {} in the last {} days",
+        used,
+        usage.len(),
+        destination,
+        days
+    );
+    Ok(())
+}
+
+/// The destination's authorized_keys with their logins in the past `days`.
+pub fn collect(
+    connection: &ConnectionArgs,
+    destination: &Destination,
+    timeout: Option<Duration>,
+    days: u32,
+) -> Result<Vec<KeyUsage>> {
+    // sudo may need to prompt for a password, which needs a terminal
+    let interactive = std::io::stdin().is_terminal();
+    let mut ssh = connection.ssh(destination, timeout, &mut |message| {
+        eprintln!("Info: {}", message)
+    })?;
+    if interactive {
+        ssh.command.arg("-t");
+    }
+    let remote = format!("sh -c {}", sh_quote(&script(days, interactive)));
+    let mut child = ssh
+        .with_remote([remote])
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn ssh process. Make sure 'ssh' is in your PATH.")?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let output = crate::relay(stdout, Box::new(std::io::stderr()), Some(MARKER));
+    let status = crate::wait_with_timeout(&mut child, timeout)?;
+    let output = output.join().unwrap_or_default();
+    match status {
+        Some(status) if status.success() => {}
+        Some(status) => bail!("ssh exited with error code: {:?}", status.code()),
+        None => return Err(crate::TimedOut(timeout.unwrap_or_default()).into()),
+    }
+    match parse(&output) {
+        Some(usage) => Ok(usage),
+        None => bail!("Could not read sshd's log on the remote with sudo"),
+    }
+}
+
+/// The outcome of `stale-report` for one host.
+pub struct HostUsage {
+    pub destination: String,
+    pub usage: Result<Vec<KeyUsage>, String>,
+}
+
+impl HostUsage {
+    fn stale(&self) -> impl Iterator<Item = &KeyUsage> {
+        self.usage.iter().flatten().filter(|key| key.logins == 0)
+    }
+
+    fn to_json(&self) -> Value {
+        let stale: Vec<Value> = self
+            .stale()
+            .map(|key| {
+                let access: Vec<Value> = key
+                    .access
+                    .iter()
+                    .map(|(option, meaning)| Value::from(format!("{}: {}", option, meaning)))
+                    .collect();
+                Value::object()
+                    .with("fingerprint", key.fingerprint.as_str())
+                    .with("key", key.name.as_str())
+                    .with("access", access)
+            })
+            .collect();
+        Value::object()
+            .with("destination", self.destination.as_str())
+            .with("status", if self.usage.is_ok() { "ok" } else { "failed" })
+            .with("keys", self.usage.as_ref().map_or(0, Vec::len))
+            .with("stale", stale)
+            .with("error", self.usage.as_ref().err().map(String::as_str))
+    }
+}
+
+/// Prints the keys of each host that weren't used to log in within `days`,
+/// in the selected format.
+pub fn report_stale(hosts: &[HostUsage], days: u32, format: Format) {
+    let stale: usize = hosts.iter().map(|host| host.stale().count()).sum();
+    let failed = hosts.iter().filter(|host| host.usage.is_err()).count();
+    match format {
+        Format::Human | Format::Tui => {
+            for host in hosts {
+                match &host.usage {
+                    Ok(_) => {
+                        for key in host.stale() {
+                            println!("{}  {}  {}", host.destination, key.fingerprint, key.name);
+                        }
+                    }
+                    Err(e) => println!("{}  failed: {}", host.destination, e),
+                }
+            }
+            println!(
+                "\n{} keys not used in the last {} days on {} hosts ({} failed)",
+                stale,
+                days,
+                hosts.len(),
+                failed
+            );
+        }
+        Format::Json => {
+            let list: Vec<Value> = hosts.iter().map(HostUsage::to_json).collect();
+            let summary = Value::object()
+                .with("hosts", hosts.len())
+                .with("failed", failed)
+                .with("stale", stale)
+                .with("days", days as u64);
+            println!(
+                "{}",
+                Value::object().with("hosts", list).with("summary", summary)
+            );
+        }
+        Format::Jsonl => {
+            for host in hosts {
+                println!("{}", host.to_json());
+            }
+        }
+        Format::Csv => {
+            println!("destination,fingerprint,key,error");
+            for host in hosts {
+                match &host.usage {
+                    Ok(_) => {
+                        for key in host.stale() {
+                            println!(
+                                "{},{},{},",
+                                csv_field(&host.destination),
+                                key.fingerprint,
+                                csv_field(&key.name)
+                            );
+                        }
+                    }
+                    Err(e) => println!("{},,,{}", csv_field(&host.destination), csv_field(e)),
+                }
+            }
+        }
+        Format::Quiet => {}
+    }
+}
+
+/// Run by the account's POSIX shell: prints its authorized_keys, then the
+/// "Accepted publickey" lines for it from the past `days`, each line after
+/// MARKER and `key ` or `log `, or `nolog` if the log couldn't be read.
+/// Log files are picked by modification time, so may reach further back.
+fn script(days: u32, interactive: bool) -> String {
+    let log = format!(
+        "p=\"Accepted publickey for $0 from \"\n\
+         if command -v journalctl >/dev/null 2>&1 && [ -d /run/systemd/system ]; then\n\
+         journalctl -q --no-pager -o short-iso --since -{days}d _COMM=sshd _COMM=sshd-session | grep -F \"$p\"\n\
+         else\n\
+         files=$(find /var/log -maxdepth 1 \\( -name 'auth.log*' -o -name 'secure*' \\) -mtime -{days} 2>/dev/null)\n\
+         [ -n \"$files\" ] && for f in $(ls -tr $files); do case $f in *.gz) gzip -dc \"$f\" ;; *) cat \"$f\" ;; esac; done | grep -F \"$p\"\n\
+         fi\n\
+         exit 0",
+        days = days
+    );
+    let sudo = if interactive { "sudo" } else { "sudo -n" };
+    [
+        format!("m='{}'", MARKER),
+        "[ -f \"$HOME/.ssh/authorized_keys\" ] && sed \"s/^/${m}key /\" \"$HOME/.ssh/authorized_keys\"".to_string(),
+        format!(
+            "if [ \"$(id -u)\" = 0 ]; then sudo=; else sudo='{}'; fi",
+            sudo
+        ),
+        format!(
+            "if log=$($sudo sh -c {} \"$(id -un)\"); then printf '%s\\n' \"$log\" | sed \"s/^/${{m}}log /\"; else echo \"${{m}}nolog\"; fi",
+            sh_quote(&log)
+        ),
+    ]
+    .join("\n")
+}
+
+/// The keys and their usage from the script's output, or None if the log
+/// couldn't be read. Lines that aren't keys are skipped.
+fn parse(output: &str) -> Option<Vec<KeyUsage>> {
+    let mut keys = Vec::new();
+    let mut logins = Vec::new();
+    for line in output
+        .lines()
+        .filter_map(|line| line.trim_end_matches('\r').strip_prefix(MARKER))
+    {
+        if line == "nolog" {
+            return None;
+        } else if let Some(entry) = line.strip_prefix("key ") {
+            keys.extend(authorized_key(entry));
+        } else if let Some(entry) = line.strip_prefix("log ") {
+            logins.extend(login(entry));
+        }
+    }
+    Some(
+        keys.into_iter()
+            .map(|key| {
+                let seen = logins.iter().filter(|(_, f)| *f == key.fingerprint);
+                KeyUsage {
+                    last_used: seen.clone().next_back().map(|(time, _)| time.to_string()),
+                    logins: seen.count(),
+                    ..key
+                }
+            })
+            .collect(),
+    )
+}
+
+/// An authorized_keys line as a key not yet seen in the log.
+fn authorized_key(line: &str) -> Option<KeyUsage> {
+    let authorized_keys::Line::Key(entry) = authorized_keys::parse_line(line) else {
+        return None;
+    };
+    let key = &entry.key;
+    let fingerprint = key.decode().ok()?.fingerprint_sha256();
+    let name = match &key.comment {
+        Some(comment) => format!("{} {}", key.algorithm, comment),
+        None => key.algorithm.clone(),
+    };
+    Some(KeyUsage {
+        fingerprint,
+        name,
+        access: authorized_keys::describe(&entry.options),
+        last_used: None,
+        logins: 0,
+    })
+}
+
+/// The timestamp and key fingerprint of an "Accepted publickey" log line,
+/// e.g. `2026-10-14T12:00:00+0000 web1 sshd[812]: Accepted publickey for
+/// deploy from 192.0.2.1 port 50122 ssh2: ED25519 SHA256:...`.
+fn login(line: &str) -> Option<(&str, &str)> {
+    let (header, message) = line.split_once(": Accepted publickey for ")?;
+    // The header ends with the host and the process
+    let mut words = header.trim_end().rsplitn(3, ' ');
+    let time = words.nth(2)?.trim();
+    let (_, key) = message.split_once(" ssh2: ")?;
+    let fingerprint = key.split_whitespace().nth(1)?;
+    Some((time, fingerprint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAvLtd30OFoMTE4W6DMg7LCelqt64mYegofKaoSLb8tC";
+
+    #[test]
+    fn parses_logins() {
+        assert_eq!(
+            login(
+                "2026-10-14T12:00:00+0000 web1 sshd[812]: Accepted publickey for deploy from 192.0.2.1 port 50122 ssh2: ED25519 SHA256:abc"
+            ),
+            Some(("2026-10-14T12:00:00+0000", "SHA256:abc"))
+        );
+        assert_eq!(
+            login(
+                "Oct  4 09:15:02 web1 sshd-session[77]: Accepted publickey for deploy from 2001:db8::1 port 2 ssh2: ED25519-CERT SHA256:def ID deploy (serial 1) CA ED25519 SHA256:ca"
+            ),
+            Some(("Oct  4 09:15:02", "SHA256:def"))
+        );
+        assert_eq!(
+            login(
+                "Oct  4 09:15:02 web1 sshd[77]: Accepted password for deploy from 192.0.2.1 port 2 ssh2"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_the_latest_login_of_each_key() {
+        let fingerprint = key::PublicKey::parse(ED25519)
+            .unwrap()
+            .decode()
+            .unwrap()
+            .fingerprint_sha256();
+        let output = format!(
+            "{m}key {key} laptop\r\n\
+             {m}key command=\"echo hi there\",no-pty {key} backup\n\
+             {m}key ssh-rsa broken\n\
+             [sudo] password for deploy: \n\
+             {m}log Oct  1 08:00:00 web1 sshd[1]: Accepted publickey for deploy from 192.0.2.1 port 2 ssh2: ED25519 {fp}\n\
+             {m}log Oct  4 09:15:02 web1 sshd[2]: Accepted publickey for deploy from 192.0.2.1 port 2 ssh2: ED25519 {fp}\n",
+            m = MARKER,
+            key = ED25519,
+            fp = fingerprint
+        );
+        let usage = parse(&output).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].name, "ssh-ed25519 laptop");
+        assert_eq!(usage[0].last_used.as_deref(), Some("Oct  4 09:15:02"));
+        assert_eq!(usage[0].logins, 2);
+        assert_eq!(usage[1].name, "ssh-ed25519 backup");
+        assert_eq!(
+            usage[1].access[1],
+            ("no-pty".to_string(), "no terminal".to_string())
+        );
+        assert_eq!(
+            parse(&format!("{}key {}\n{}nolog\n", MARKER, ED25519, MARKER)),
+            None
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Encryption at rest for the local files that tell where your keys get you
+//! in: the key cache and the run history (`--encrypt-state`).
+//!
+//! Files are encrypted as they are written, with:
+//! - `keychain`: `openssl enc` (AES-256-CBC, PBKDF2) under a random
+//!   passphrase kept in the OS keychain (macOS `security`, libsecret's
+//!   `secret-tool`)
+//! - `age`: `age`, to your own SSH key (`id_ed25519` or `id_rsa` in the ssh
+//!   directory), which is then needed to read them
+//! - [`PASSPHRASE_ENV`], which takes precedence: `openssl enc` under that
+//!   passphrase, for machines without a keychain
+//!
+//! Encrypted files are recognized by their header whenever they are read,
+//! and stay encrypted when rewritten without the option; plain ones from
+//! before are encrypted the next time they are written with it.
+
+use crate::keychain;
+use crate::paths;
+use crate::pipe;
+use anyhow::{Context, Result, bail};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// The variable holding a passphrase to encrypt with instead.
+pub const PASSPHRASE_ENV: &str = "SSH_COPY_ID_RS_CACHE_PASSPHRASE";
+
+/// How `openssl enc` and age output start.
+const OPENSSL: &[u8] = b"Salted__";
+const AGE: &[u8] = b"age-encryption.org/v1\n";
+
+/// The keychain entry holding the passphrase.
+const SERVICE: &str = "ssh-copy-id-rs";
+const ACCOUNT: &str = "state";
+
+/// Where the encryption key is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Method {
+    /// A random passphrase in the OS keychain
+    Keychain,
+    /// age, to your SSH key
+    Age,
+}
+
+static METHOD: OnceLock<Method> = OnceLock::new();
+
+/// Has the files written from now on encrypted with `method`.
+pub fn set_method(method: Method) {
+    let _ = METHOD.set(method);
+}
+
+/// `plain`, to be written to `path`: encrypted if `--encrypt-state` or
+/// [`PASSPHRASE_ENV`] asks for it, or if `path` is encrypted now.
+pub fn seal(path: &Path, plain: Vec<u8>) -> Result<Vec<u8>> {
+    if let Some(passphrase) = env_passphrase() {
+        return openssl(&[], &plain, &passphrase).context("Failed to encrypt with openssl");
+    }
+    match METHOD.get().copied().or_else(|| current_method(path)) {
+        None => Ok(plain),
+        Some(Method::Keychain) => openssl(&[], &plain, &keychain_passphrase(true)?)
+            .context("Failed to encrypt with openssl"),
+        Some(Method::Age) => {
+            let (_, recipient) = age_key()?;
+            let mut age = Command::new("age");
+            age.arg("-e").arg("-R").arg(recipient);
+            pipe(&mut age, &plain).context("Failed to encrypt with age")
+        }
+    }
+}
+
+/// The content of `path`, decrypted if need be; empty if there is no file.
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    };
+    open(data).with_context(|| format!("Failed to decrypt {:?}", path))
+}
+
+/// How the file at `path` is encrypted, if it is.
+fn current_method(path: &Path) -> Option<Method> {
+    let mut header = [0u8; AGE.len()];
+    let read = std::fs::File::open(path)
+        .and_then(|mut file| file.read(&mut header))
+        .ok()?;
+    match &header[..read] {
+        data if data.starts_with(OPENSSL) => Some(Method::Keychain),
+        data if data.starts_with(AGE) => Some(Method::Age),
+        _ => None,
+    }
+}
+
+/// `data` as written by [`seal`], decrypted.
+fn open(data: Vec<u8>) -> Result<Vec<u8>> {
+    if data.starts_with(OPENSSL) {
+        let passphrase = match env_passphrase() {
+            Some(passphrase) => passphrase,
+            None => keychain_passphrase(false)?,
+        };
+        openssl(&["-d"], &data, &passphrase)
+            .context("openssl could not decrypt it (wrong passphrase?)")
+    } else if data.starts_with(AGE) {
+        let (identity, _) = age_key()?;
+        let mut age = Command::new("age");
+        age.arg("-d").arg("-i").arg(identity);
+        pipe(&mut age, &data).context("age could not decrypt it")
+    } else {
+        Ok(data)
+    }
+}
+
+fn env_passphrase() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Runs `openssl enc` with `passphrase` handed over in its environment, so
+/// it never shows up in the process list.
+fn openssl(extra: &[&str], input: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut openssl = Command::new("openssl");
+    openssl
+        .args([
+            "enc",
+            "-aes-256-cbc",
+            "-pbkdf2",
+            "-iter",
+            "100000",
+            "-md",
+            "sha256",
+        ])
+        .args(extra)
+        .args(["-pass", &format!("env:{}", PASSPHRASE_ENV)])
+        .env(PASSPHRASE_ENV, passphrase)
+        .stderr(Stdio::null());
+    pipe(&mut openssl, input)
+}
+
+/// The private key age decrypts with, and the public key it encrypts to.
+fn age_key() -> Result<(PathBuf, PathBuf)> {
+    let dir = paths::ssh_dir().context("No ssh directory to find the key for age in")?;
+    ["id_ed25519", "id_rsa"]
+        .iter()
+        .map(|name| (dir.join(name), dir.join(format!("{}.pub", name))))
+        .find(|(private, public)| private.is_file() && public.is_file())
+        .with_context(|| {
+            format!(
+                "--encrypt-state age needs an id_ed25519 or id_rsa key pair in {:?}",
+                dir
+            )
+        })
+}
+
+/// The passphrase in the OS keychain; with `create`, a new one is stored if
+/// there is none yet.
+fn keychain_passphrase(create: bool) -> Result<String> {
+    let found = keychain::lookup(SERVICE, ACCOUNT).with_context(|| {
+        format!(
+            "--encrypt-state keychain isn't supported here; use age or ${}",
+            PASSPHRASE_ENV
+        )
+    })?;
+    if let Some(passphrase) = found {
+        return Ok(passphrase);
+    }
+    if !create {
+        bail!(
+            "The OS keychain has no passphrase for ssh-copy-id-rs; set ${} if you know it",
+            PASSPHRASE_ENV
+        );
+    }
+
+    let mut random = [0u8; 32];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut random))
+        .context("Failed to read /dev/urandom")?;
+    let passphrase: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    keychain::store(SERVICE, ACCOUNT, "ssh-copy-id-rs state", &passphrase)?;
+    Ok(passphrase)
+}
@@ -0,0 +1,74 @@
+//! Reader for the SSH wire encoding (RFC 4251) used inside key blobs.
+
+use anyhow::{Context, Result};
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(..len).context("truncated key data")?;
+        self.data = &self.data[len..];
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).context("key data contains invalid UTF-8")
+    }
+
+    /// Size in bits of an mpint, ignoring the sign-padding zero byte.
+    pub fn mpint_bits(&mut self) -> Result<usize> {
+        let bytes = self.bytes()?;
+        let bytes = match bytes.iter().position(|&b| b != 0) {
+            Some(first) => &bytes[first..],
+            None => return Ok(0),
+        };
+        Ok(bytes.len() * 8 - bytes[0].leading_zeros() as usize)
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Builder for SSH wire-encoded data.
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn string(mut self, bytes: &[u8]) -> Self {
+        self.buf
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
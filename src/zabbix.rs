@@ -0,0 +1,128 @@
+//! Target discovery from Zabbix (`--zabbix-url`): the main agent interface
+//! of the monitored hosts in the given host groups.
+
+use crate::http;
+use crate::json::Value;
+use anyhow::{Context, Result, bail};
+
+#[derive(Debug, Default)]
+pub struct Query {
+    /// Frontend URL, e.g. `https://zabbix.example.com`
+    pub url: String,
+    /// API token (Zabbix 5.4 and later)
+    pub token: String,
+    /// Host group names; all hosts if empty
+    pub groups: Vec<String>,
+}
+
+/// The addresses of the hosts' main interfaces.
+pub fn discover(query: &Query) -> Result<Vec<String>> {
+    let endpoint = format!("{}/api_jsonrpc.php", query.url.trim_end_matches('/'));
+    let call = |method: &str, params: Value| -> Result<Value> {
+        let request = Value::object()
+            .with("jsonrpc", "2.0")
+            .with("method", method)
+            .with("params", params)
+            .with("id", 1u64);
+        let headers = [format!("Authorization: Bearer {}", query.token)];
+        let response = http::post_json(&endpoint, &headers, &request)?;
+        if let Some(error) = response.get("error") {
+            let detail = error
+                .get("data")
+                .or(error.get("message"))
+                .and_then(Value::as_str);
+            bail!(
+                "Zabbix {} failed: {}",
+                method,
+                detail.unwrap_or("unknown error")
+            );
+        }
+        response
+            .get("result")
+            .cloned()
+            .with_context(|| format!("Unexpected Zabbix response to {}", method))
+    };
+
+    let mut params = Value::object()
+        .with("output", vec!["host"])
+        .with("selectInterfaces", vec!["ip", "dns", "useip", "main"])
+        .with("filter", Value::object().with("status", "0"));
+    if !query.groups.is_empty() {
+        let groups = call(
+            "hostgroup.get",
+            Value::object()
+                .with("output", vec!["groupid"])
+                .with("filter", Value::object().with("name", query.groups.clone())),
+        )?;
+        let ids: Vec<&str> = groups
+            .as_array()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|group| group.get("groupid")?.as_str())
+            .collect();
+        if ids.is_empty() {
+            bail!(
+                "No Zabbix host group is named {}",
+                query.groups.join(" or ")
+            );
+        }
+        params = params.with("groupids", ids);
+    }
+
+    let hosts = call("host.get", params)?;
+    let addresses: Vec<String> = hosts
+        .as_array()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(address)
+        .collect();
+    if addresses.is_empty() {
+        bail!("No enabled Zabbix hosts found");
+    }
+    Ok(addresses)
+}
+
+/// The IP or DNS name of a host's main interface, as Zabbix connects to it.
+fn address(host: &Value) -> Option<String> {
+    let interfaces = host.get("interfaces")?.as_array()?;
+    let main = interfaces
+        .iter()
+        .find(|i| i.get("main").and_then(Value::as_str) == Some("1"))
+        .or(interfaces.first())?;
+    let field = match main.get("useip").and_then(Value::as_str) {
+        Some("0") => "dns",
+        _ => "ip",
+    };
+    let address = main.get(field)?.as_str()?;
+    (!address.is_empty()).then(|| address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_main_interface() {
+        let hosts = Value::parse(
+            r#"[
+                {"host": "db1", "interfaces": [
+                    {"ip": "10.0.0.7", "dns": "", "useip": "1", "main": "0"},
+                    {"ip": "10.0.1.7", "dns": "", "useip": "1", "main": "1"}]},
+                {"host": "web1", "interfaces": [
+                    {"ip": "", "dns": "web1.example.com", "useip": "0", "main": "1"}]},
+                {"host": "trapper", "interfaces": []}
+            ]"#,
+        )
+        .unwrap();
+        let addresses: Vec<Option<String>> =
+            hosts.as_array().unwrap().iter().map(address).collect();
+        assert_eq!(
+            addresses,
+            [
+                Some("10.0.1.7".to_string()),
+                Some("web1.example.com".to_string()),
+                None
+            ]
+        );
+    }
+}